@@ -1,9 +1,10 @@
-use ::zikeiretsu::{config::*, DBContext, DBContextError, Database};
+use ::zikeiretsu::{config::*, DBContext, DBContextError, Database, RetentionPolicy};
 
 use clap::Parser;
 use std::env;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Parser, Clone, PartialEq, Debug)]
@@ -12,6 +13,11 @@ pub enum Mode {
     Server,
     Client,
     Repair,
+    Import,
+    Export,
+    Compact,
+    Expire,
+    RetryUpload,
 }
 impl FromStr for Mode {
     type Err = String;
@@ -22,6 +28,11 @@ impl FromStr for Mode {
             "server" => Ok(Self::Server),
             "client" => Ok(Self::Client),
             "repair" => Ok(Self::Repair),
+            "import" => Ok(Self::Import),
+            "export" => Ok(Self::Export),
+            "compact" => Ok(Self::Compact),
+            "expire" => Ok(Self::Expire),
+            "retry_upload" => Ok(Self::RetryUpload),
             r => Err(format!("unknown mode {r}")),
         }
     }
@@ -36,7 +47,7 @@ pub struct Args {
     #[clap(
         long = "mode",
         short = 'm',
-        help = "running mode {adhoc,server,client}. default: adhoc"
+        help = "running mode {adhoc,server,client,repair,import,export,compact,expire,retry_upload}. default: adhoc"
     )]
     pub mode: Option<Mode>,
 
@@ -73,12 +84,141 @@ pub struct Args {
     #[clap(long = "port", help = "config for server and client. ")]
     pub port: Option<usize>,
 
+    #[clap(
+        long = "line_protocol_port",
+        help = "config for server. serve the influxdb line protocol on this port if passed. "
+    )]
+    pub line_protocol_port: Option<usize>,
+
+    #[clap(
+        long = "line_protocol_max_body_mb",
+        help = "config for server. upper limit of the body of the line protocol http request in mega bytes. 32 by default. "
+    )]
+    pub line_protocol_max_body_mb: Option<usize>,
+
     #[clap(long = "cache_num", help = "config for server. ")]
     pub cache_block_num: Option<usize>,
 
+    #[clap(long = "result_cache_num", help = "config for server. ")]
+    pub result_cache_num: Option<usize>,
+
+    #[clap(
+        long = "query_timeout_sec",
+        help = "config for server. default timeout of each query in seconds. "
+    )]
+    pub query_timeout_sec: Option<u64>,
+
+    #[clap(
+        long = "query_memory_limit_mb",
+        help = "config for server. upper limit of the memory that each query can use in mega bytes. "
+    )]
+    pub query_memory_limit_mb: Option<usize>,
+
+    #[clap(
+        long = "import_file",
+        help = "config for import. csv or parquet file to import. "
+    )]
+    pub import_file: Option<PathBuf>,
+
+    #[clap(
+        long = "import_format",
+        help = "config for import. {csv,parquet}. guessed by the extension of the file by default. "
+    )]
+    pub import_format: Option<String>,
+
+    #[clap(
+        long = "import_metrics",
+        help = "config for import. metrics to be imported into. "
+    )]
+    pub import_metrics: Option<String>,
+
+    #[clap(
+        long = "timestamp_column",
+        help = "config for import. name of the timestamp column. default: ts"
+    )]
+    pub timestamp_column: Option<String>,
+
+    #[clap(
+        long = "timestamp_format",
+        help = "config for import. {ns,us,ms,s,rfc3339} or strftime format such as '%Y-%m-%d %H:%M:%S'. guessed by the type of the column by default. "
+    )]
+    pub timestamp_format: Option<String>,
+
+    #[clap(
+        long = "import_block_size",
+        help = "config for import. max number of the datapoints in a block. "
+    )]
+    pub import_block_size: Option<usize>,
+
+    #[clap(
+        long = "metrics",
+        help = "config for export, compact and expire. metrics to be exported, compacted or expired. all the metrics are compacted or expired if not passed. "
+    )]
+    pub metrics: Option<String>,
+
+    #[clap(
+        long = "since",
+        help = "config for export. 'yyyy-MM-DD hh:mm:ss', 'yyyy-MM-DD' or rfc3339. inclusive. "
+    )]
+    pub since: Option<String>,
+
+    #[clap(
+        long = "until",
+        help = "config for export. 'yyyy-MM-DD hh:mm:ss', 'yyyy-MM-DD' or rfc3339. exclusive. "
+    )]
+    pub until: Option<String>,
+
+    #[clap(
+        long = "partition",
+        help = "config for export. {day,none}. write a file per day in UTC if day. default: day"
+    )]
+    pub partition: Option<String>,
+
+    #[clap(
+        long = "format",
+        help = "config for export. {parquet,parquet_snappy,csv}. default: parquet"
+    )]
+    pub export_format: Option<String>,
+
+    #[clap(long = "output_dir", help = "config for export. ")]
+    pub output_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "columns",
+        help = "config for export. names of the field columns separated by comma. "
+    )]
+    pub export_columns: Option<String>,
+
+    #[clap(
+        long = "compact_max_datapoints",
+        help = "config for compact. max number of the timestamps in a compacted block. default: 100000"
+    )]
+    pub compact_max_datapoints: Option<usize>,
+
+    #[clap(
+        long = "compact_max_span_sec",
+        help = "config for compact. max time span of a compacted block in seconds. default: 86400"
+    )]
+    pub compact_max_span_sec: Option<u64>,
+
+    #[clap(
+        long = "retention_days",
+        help = "config for expire. drop the blocks older than the days. the retention in the config file is used by default. "
+    )]
+    pub retention_days: Option<u64>,
+
+    #[clap(
+        long = "retry_upload_attempts",
+        help = "config for retry_upload. max number of the retries of the uploads failed before. default: 5"
+    )]
+    pub retry_upload_attempts: Option<u32>,
+
     #[clap(skip)]
     parsed_databases: Option<Vec<Database>>,
 
+    #[clap(skip)]
+    parsed_retention_policies: Vec<(String, RetentionPolicy)>,
+
     pub query: Option<String>,
 }
 
@@ -92,6 +232,10 @@ impl Args {
             let mut databases = Vec::new();
             for each_config in database_configs.into_iter() {
                 databases.push(each_config.as_database()?);
+                if let Some(retention_policy) = each_config.as_retention_policy()? {
+                    self.parsed_retention_policies
+                        .push((each_config.database_name().to_string(), retention_policy));
+                }
             }
 
             self.parsed_databases = Some(databases);
@@ -117,10 +261,30 @@ impl Args {
             self.port = Some(port);
         }
 
+        if let Some(line_protocol_port) = config.line_protocol_port {
+            self.line_protocol_port = Some(line_protocol_port);
+        }
+
+        if let Some(line_protocol_max_body_mb) = config.line_protocol_max_body_mb {
+            self.line_protocol_max_body_mb = Some(line_protocol_max_body_mb);
+        }
+
         if let Some(cache_block_num) = config.cache_block_num {
             self.cache_block_num = Some(cache_block_num);
         }
 
+        if let Some(result_cache_num) = config.result_cache_num {
+            self.result_cache_num = Some(result_cache_num);
+        }
+
+        if let Some(query_timeout_sec) = config.query_timeout_sec {
+            self.query_timeout_sec = Some(query_timeout_sec);
+        }
+
+        if let Some(query_memory_limit_mb) = config.query_memory_limit_mb {
+            self.query_memory_limit_mb = Some(query_memory_limit_mb);
+        }
+
         Ok(())
     }
 
@@ -145,6 +309,10 @@ impl Args {
             env::set_var("ZDB_BLOCK_CACHE_SIZE", block_cache_num.to_string());
         }
 
+        if let Some(result_cache_num) = self.result_cache_num.as_ref() {
+            env::set_var("ZDB_RESULT_CACHE_SIZE", result_cache_num.to_string());
+        }
+
         Ok(())
     }
 
@@ -167,7 +335,14 @@ impl Args {
             None => return Err(ArgsError::NoDataDir),
         };
 
-        let ctx = DBContext::new(data_dir, self.default_database.clone(), parsed_databases);
+        let mut ctx = DBContext::new(data_dir, self.default_database.clone(), parsed_databases)
+            .with_default_query_timeout(self.query_timeout_sec.map(Duration::from_secs))
+            .with_default_query_memory_limit_bytes(
+                self.query_memory_limit_mb.map(|mb| mb * 1024 * 1024),
+            );
+        for (database_name, retention_policy) in self.parsed_retention_policies.iter() {
+            ctx = ctx.with_retention_policy(database_name, retention_policy.clone());
+        }
         Ok(ctx)
     }
 }
@@ -183,6 +358,12 @@ pub enum ArgsError {
     #[error("default database must specified")]
     DefaultDatabaseMustSpecified,
 
+    #[error("{0} must be specified")]
+    MissingArg(String),
+
+    #[error("invalid argument. {0}")]
+    InvalidArg(String),
+
     #[error("config error. {0}")]
     ConfigError(#[from] ConfigError),
 
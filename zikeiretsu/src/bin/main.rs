@@ -5,6 +5,7 @@ use args::*;
 use clap::Parser;
 use dotenv::dotenv;
 use std::io;
+use std::time::Duration;
 
 use thiserror::Error;
 
@@ -27,6 +28,9 @@ pub enum ZikeiretsuBinError {
 
     #[error("arrow flight client error: {0}")]
     ArrowFlightClientError(#[from] ArrowFlightClientError),
+
+    #[error("line protocol serve error: {0}")]
+    LineProtocolServeError(#[from] LineProtocolServeError),
 }
 
 pub type Result<T> = std::result::Result<T, ZikeiretsuBinError>;
@@ -66,7 +70,31 @@ pub async fn main() -> Result<()> {
     match mode {
         Mode::Server => {
             setup_log(true);
-            arrow_flight_server(ctx, args.host.as_deref(), args.port).await?;
+            if ctx
+                .databases()
+                .any(|database| ctx.retention_policy(&database.database_name).is_some())
+            {
+                tokio::spawn(expire_periodically(ctx.clone()));
+            }
+            // kept until the server stops. the workers stop when dropped
+            let _upload_retry_workers = start_upload_retry_workers(&ctx);
+            match args.line_protocol_port {
+                Some(line_protocol_port) => {
+                    let flight_server =
+                        arrow_flight_server(ctx.clone(), args.host.as_deref(), args.port);
+                    let line_protocol_server = line_protocol_server(
+                        ctx,
+                        args.host.as_deref(),
+                        Some(line_protocol_port),
+                        args.line_protocol_max_body_mb.map(|mb| mb * 1024 * 1024),
+                    );
+                    tokio::try_join!(
+                        async { flight_server.await.map_err(ZikeiretsuBinError::from) },
+                        async { line_protocol_server.await.map_err(ZikeiretsuBinError::from) },
+                    )?;
+                }
+                None => arrow_flight_server(ctx, args.host.as_deref(), args.port).await?,
+            }
         }
         Mode::Adhoc | Mode::Client => {
             setup_log(false);
@@ -91,6 +119,26 @@ pub async fn main() -> Result<()> {
             setup_log(true);
             repair(ctx).await?;
         }
+        Mode::Import => {
+            setup_log(true);
+            import(ctx, &args).await?;
+        }
+        Mode::Export => {
+            setup_log(true);
+            export(ctx, &args).await?;
+        }
+        Mode::Compact => {
+            setup_log(true);
+            compact(ctx, &args).await?;
+        }
+        Mode::Expire => {
+            setup_log(true);
+            expire(ctx, &args).await?;
+        }
+        Mode::RetryUpload => {
+            setup_log(true);
+            retry_upload(ctx, &args).await?;
+        }
     };
 
     Ok(())
@@ -119,6 +167,190 @@ async fn repair(ctx: DBContext) -> Result<()> {
     }
 }
 
+async fn import(ctx: DBContext, args: &Args) -> Result<()> {
+    let database = match ctx.get_database(None) {
+        Err(e) => {
+            let e: ArgsError = e.into();
+            return Err(e.into());
+        }
+        Ok(None) => return Err(ArgsError::DefaultDatabaseMustSpecified.into()),
+        Ok(Some(database)) => database,
+    };
+
+    let file_path = args
+        .import_file
+        .as_ref()
+        .ok_or_else(|| ArgsError::MissingArg("import_file".to_string()))?;
+    let metrics = args
+        .import_metrics
+        .as_ref()
+        .ok_or_else(|| ArgsError::MissingArg("import_metrics".to_string()))?;
+    let metrics = Metrics::new(metrics).map_err(ArgsError::InvalidArg)?;
+
+    let file_format = match args.import_format.as_ref() {
+        Some(import_format) => import_format.parse::<ImportFileFormat>(),
+        None => ImportFileFormat::from_path(file_path),
+    }
+    .map_err(EngineError::from)?;
+    let timestamp_format = args
+        .timestamp_format
+        .as_ref()
+        .map(|timestamp_format| timestamp_format.parse::<ImportTimestampFormat>())
+        .transpose()
+        .map_err(ArgsError::InvalidArg)?;
+
+    let mut setting = ImportSetting::new(
+        file_format,
+        args.timestamp_column.as_deref().unwrap_or("ts"),
+    )
+    .with_timestamp_format(timestamp_format);
+    if let Some(import_block_size) = args.import_block_size {
+        setting = setting.with_block_size(import_block_size);
+    }
+
+    let db_dir = database.as_local_db_dir(&ctx.data_dir);
+    let summary = Engine::import(
+        db_dir,
+        &metrics,
+        file_path,
+        &setting,
+        database.cloud_storage.as_ref(),
+    )
+    .await?;
+    println!(
+        "imported {datapoint_num} datapoints into {block_num} blocks of {metrics}. fields: {fields}",
+        datapoint_num = summary.datapoint_num,
+        block_num = summary.block_num,
+        fields = summary
+            .field_names
+            .iter()
+            .zip(summary.field_types.iter())
+            .map(|(name, field_type)| format!("{name}({field_type})"))
+            .collect::<Vec<String>>()
+            .join(","),
+    );
+    Ok(())
+}
+
+async fn export(ctx: DBContext, args: &Args) -> Result<()> {
+    let database = match ctx.get_database(None) {
+        Err(e) => {
+            let e: ArgsError = e.into();
+            return Err(e.into());
+        }
+        Ok(None) => return Err(ArgsError::DefaultDatabaseMustSpecified.into()),
+        Ok(Some(database)) => database,
+    };
+
+    let metrics = args
+        .metrics
+        .as_ref()
+        .ok_or_else(|| ArgsError::MissingArg("metrics".to_string()))?;
+    let metrics = Metrics::new(metrics).map_err(ArgsError::InvalidArg)?;
+    let output_dir = args
+        .output_dir
+        .as_ref()
+        .ok_or_else(|| ArgsError::MissingArg("output_dir".to_string()))?;
+
+    let file_format = args
+        .export_format
+        .as_deref()
+        .unwrap_or("parquet")
+        .parse::<ExportFileFormat>()
+        .map_err(EngineError::from)?;
+    let partition = args
+        .partition
+        .as_deref()
+        .unwrap_or("day")
+        .parse::<ExportPartition>()
+        .map_err(EngineError::from)?;
+    let since = args
+        .since
+        .as_deref()
+        .map(parse_export_datetime)
+        .transpose()
+        .map_err(EngineError::from)?;
+    let until = args
+        .until
+        .as_deref()
+        .map(parse_export_datetime)
+        .transpose()
+        .map_err(EngineError::from)?;
+    let field_names = args.export_columns.as_ref().map(|columns| {
+        columns
+            .split(',')
+            .map(|column| column.trim().to_string())
+            .collect::<Vec<String>>()
+    });
+
+    let setting = ExportSetting::new(file_format, partition)
+        .with_range(DatapointsRange::new(since, until))
+        .with_field_names(field_names);
+
+    let mut db_config = DBConfig::builder_with_no_cache().build();
+    db_config.cloud_storage = database.cloud_storage.clone();
+
+    let db_dir = database.as_local_db_dir(&ctx.data_dir);
+    let summary = Engine::export(
+        &database.database_name,
+        db_dir,
+        &metrics,
+        output_dir,
+        &setting,
+        &db_config,
+    )
+    .await?;
+    println!(
+        "exported {datapoint_num} datapoints of {metrics} from {block_num} blocks",
+        datapoint_num = summary.datapoint_num,
+        block_num = summary.block_num,
+    );
+    for file in summary.files.iter() {
+        println!("{}", file.display());
+    }
+    Ok(())
+}
+
+async fn compact(ctx: DBContext, args: &Args) -> Result<()> {
+    let database = match ctx.get_database(None) {
+        Err(e) => {
+            let e: ArgsError = e.into();
+            return Err(e.into());
+        }
+        Ok(None) => return Err(ArgsError::DefaultDatabaseMustSpecified.into()),
+        Ok(Some(database)) => database,
+    };
+
+    let mut setting = CompactionSetting::default();
+    if let Some(max_datapoints) = args.compact_max_datapoints {
+        setting.max_timestamp_num = max_datapoints;
+    }
+    if let Some(max_span_sec) = args.compact_max_span_sec {
+        setting = setting.with_max_time_span_sec(Some(max_span_sec));
+    }
+
+    let db_dir = database.as_local_db_dir(&ctx.data_dir);
+    let metricses = match args.metrics.as_ref() {
+        Some(metrics) => vec![Metrics::new(metrics).map_err(ArgsError::InvalidArg)?],
+        None => {
+            let mut db_config = DBConfig::builder_with_no_cache().build();
+            db_config.cloud_storage = database.cloud_storage.clone();
+            Engine::list_metrics(Some(&db_dir), &db_config).await?
+        }
+    };
+
+    for metrics in metricses.iter() {
+        let summary =
+            Engine::compact(&db_dir, metrics, &setting, database.cloud_storage.as_ref()).await?;
+        println!(
+            "compacted {metrics}: {block_num_before} blocks -> {block_num_after} blocks",
+            block_num_before = summary.block_num_before,
+            block_num_after = summary.block_num_after,
+        );
+    }
+    Ok(())
+}
+
 pub async fn repl(
     ctx: &mut DBContext,
     executor_interface: Box<dyn ExecutorInterface>,
@@ -128,3 +360,140 @@ pub async fn repl(
     }
     Ok(())
 }
+
+async fn expire(ctx: DBContext, args: &Args) -> Result<()> {
+    let database = match ctx.get_database(None) {
+        Err(e) => {
+            let e: ArgsError = e.into();
+            return Err(e.into());
+        }
+        Ok(None) => return Err(ArgsError::DefaultDatabaseMustSpecified.into()),
+        Ok(Some(database)) => database,
+    };
+
+    let retention_policy = match args.retention_days {
+        Some(retention_days) => {
+            RetentionPolicy::new(Some(Duration::from_secs(retention_days * 60 * 60 * 24)))
+        }
+        None => ctx
+            .retention_policy(&database.database_name)
+            .cloned()
+            .ok_or_else(|| ArgsError::MissingArg("retention_days".to_string()))?,
+    };
+    let metrics = args
+        .metrics
+        .as_ref()
+        .map(Metrics::new)
+        .transpose()
+        .map_err(ArgsError::InvalidArg)?;
+
+    expire_database(&ctx, database, &retention_policy, metrics).await
+}
+
+async fn expire_database(
+    ctx: &DBContext,
+    database: &Database,
+    retention_policy: &RetentionPolicy,
+    metrics: Option<Metrics>,
+) -> Result<()> {
+    let db_dir = database.as_local_db_dir(&ctx.data_dir);
+    let metricses = match metrics {
+        Some(metrics) => vec![metrics],
+        None => {
+            let mut db_config = DBConfig::builder_with_no_cache().build();
+            db_config.cloud_storage = database.cloud_storage.clone();
+            Engine::list_metrics(Some(&db_dir), &db_config).await?
+        }
+    };
+
+    for metrics in metricses.iter() {
+        let retention = match retention_policy.retention_of(metrics) {
+            Some(retention) => retention,
+            None => continue,
+        };
+        let summary =
+            Engine::expire(&db_dir, metrics, retention, database.cloud_storage.as_ref()).await?;
+        if !summary.expired_blocks.is_empty() {
+            log::info!(
+                "expired {expired_block_num} blocks of {metrics} before {expired_before}",
+                expired_block_num = summary.expired_blocks.len(),
+                expired_before = summary.expired_before,
+            );
+        }
+    }
+    Ok(())
+}
+
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// drops the expired blocks of the all databases that have the retention policies.
+async fn expire_periodically(ctx: DBContext) {
+    let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        for database in ctx.databases() {
+            if let Some(retention_policy) = ctx.retention_policy(&database.database_name) {
+                if let Err(e) = expire_database(&ctx, database, retention_policy, None).await {
+                    log::error!(
+                        "failed to expire the blocks of {database_name}: {e}",
+                        database_name = database.database_name
+                    );
+                }
+            }
+        }
+    }
+}
+
+const DEFAULT_RETRY_UPLOAD_ATTEMPTS: u32 = 5;
+
+async fn retry_upload(ctx: DBContext, args: &Args) -> Result<()> {
+    let database = match ctx.get_database(None) {
+        Err(e) => {
+            let e: ArgsError = e.into();
+            return Err(e.into());
+        }
+        Ok(None) => return Err(ArgsError::DefaultDatabaseMustSpecified.into()),
+        Ok(Some(database)) => database,
+    };
+    let cloud_storage = database
+        .cloud_storage
+        .as_ref()
+        .ok_or_else(|| ArgsError::MissingArg(format!("bucket of {}", database.database_name)))?;
+
+    let db_dir = database.as_local_db_dir(&ctx.data_dir);
+    let summary = Engine::retry_uploads(
+        db_dir,
+        cloud_storage,
+        &UploadRetryBackoff::default(),
+        args.retry_upload_attempts
+            .unwrap_or(DEFAULT_RETRY_UPLOAD_ATTEMPTS),
+    )
+    .await?;
+    for (metrics, block_timestamp) in summary.uploaded.iter() {
+        println!("uploaded {metrics} {block_timestamp}");
+    }
+    for pending_upload in summary.pending.iter() {
+        println!(
+            "pending {error_file}: {reason}",
+            error_file = pending_upload.error_file_path.display(),
+            reason = pending_upload.reason,
+        );
+    }
+    Ok(())
+}
+
+/// retries the uploads failed before of the all databases that have the cloud storages.
+fn start_upload_retry_workers(ctx: &DBContext) -> Vec<UploadRetryWorkerShutdown> {
+    ctx.databases()
+        .filter_map(|database| {
+            database.cloud_storage.as_ref().map(|cloud_storage| {
+                start_upload_retry_worker(
+                    database.as_local_db_dir(&ctx.data_dir),
+                    cloud_storage.clone(),
+                    UPLOAD_RETRY_INTERVAL,
+                    UploadRetryBackoff::default(),
+                )
+            })
+        })
+        .collect()
+}
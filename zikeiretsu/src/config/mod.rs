@@ -1,8 +1,10 @@
-use crate::{CloudStorage, CloudStorageError, DBContext, Database};
+use crate::{CloudStorage, CloudStorageError, DBContext, Database, Metrics, RetentionPolicy};
 use dirs::home_dir;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, ConfigError>;
@@ -39,7 +41,12 @@ pub struct Config {
     pub https: Option<bool>,
     pub host: Option<String>,
     pub port: Option<usize>,
+    pub line_protocol_port: Option<usize>,
+    pub line_protocol_max_body_mb: Option<usize>,
     pub cache_block_num: Option<usize>,
+    pub result_cache_num: Option<usize>,
+    pub query_timeout_sec: Option<u64>,
+    pub query_memory_limit_mb: Option<usize>,
     pub default_database: Option<String>,
 }
 
@@ -47,6 +54,8 @@ pub struct Config {
 pub struct DatabaseConfig {
     database_name: String,
     cloud_storage_url: Option<String>,
+    retention_days: Option<u64>,
+    metrics_retention_days: Option<HashMap<String, u64>>,
 }
 
 impl DatabaseConfig {
@@ -61,6 +70,29 @@ impl DatabaseConfig {
             cloud_storage,
         })
     }
+
+    pub fn database_name(&self) -> &str {
+        &self.database_name
+    }
+
+    pub fn as_retention_policy(&self) -> Result<Option<RetentionPolicy>> {
+        let days = |days: u64| Duration::from_secs(days * 60 * 60 * 24);
+        let mut retention_policy = RetentionPolicy::new(self.retention_days.map(days));
+        if let Some(metrics_retention_days) = &self.metrics_retention_days {
+            for (metrics, retention_days) in metrics_retention_days.iter() {
+                let metrics =
+                    Metrics::new(metrics).map_err(ConfigError::InvalidDatabaseDefinition)?;
+                retention_policy =
+                    retention_policy.with_metrics_retention(metrics, days(*retention_days));
+            }
+        }
+
+        if retention_policy.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(retention_policy))
+        }
+    }
 }
 
 impl Config {
@@ -103,7 +135,16 @@ impl Config {
             None => return Err(ConfigError::NoDataDir),
         };
 
-        let ctx = DBContext::new(data_dir, self.default_database.clone(), parsed_databases);
+        let mut ctx = DBContext::new(data_dir, self.default_database.clone(), parsed_databases)
+            .with_default_query_timeout(self.query_timeout_sec.map(Duration::from_secs))
+            .with_default_query_memory_limit_bytes(
+                self.query_memory_limit_mb.map(|mb| mb * 1024 * 1024),
+            );
+        for database_config in self.databases.iter().flatten() {
+            if let Some(retention_policy) = database_config.as_retention_policy()? {
+                ctx = ctx.with_retention_policy(database_config.database_name(), retention_policy);
+            }
+        }
         Ok(ctx)
     }
 }
@@ -130,12 +171,21 @@ mod test {
             https = false
             host = "localhost"
             port = 1234
+            line_protocol_port = 8089
+            line_protocol_max_body_mb = 16
             cache_block_num = 100
+            result_cache_num = 10
+            query_timeout_sec = 30
+            query_memory_limit_mb = 512
             default_database = "default_db"
 
             [[databases]]
             database_name="test_db"
             cloud_storage_url ="gs://some/where"
+            retention_days = 90
+
+            [databases.metrics_retention_days]
+            trades = 30
 
             "#;
 
@@ -148,14 +198,48 @@ mod test {
 
                 host: Some("localhost".to_string()),
                 port: Some(1234),
+                line_protocol_port: Some(8089),
+                line_protocol_max_body_mb: Some(16),
                 https: Some(false),
                 databases: Some(vec![DatabaseConfig {
                     database_name: "test_db".to_string(),
                     cloud_storage_url: Some("gs://some/where".to_string()),
+                    retention_days: Some(90),
+                    metrics_retention_days: Some(HashMap::from([("trades".to_string(), 30)])),
                 }]),
                 cache_block_num: Some(100),
+                result_cache_num: Some(10),
+                query_timeout_sec: Some(30),
+                query_memory_limit_mb: Some(512),
                 default_database: Some("default_db".to_string()),
             }
         );
     }
+
+    #[test]
+    fn retention_policy_of_database_config() {
+        let database_config = DatabaseConfig {
+            database_name: "test_db".to_string(),
+            cloud_storage_url: None,
+            retention_days: Some(90),
+            metrics_retention_days: Some(HashMap::from([("trades".to_string(), 30)])),
+        };
+        let retention_policy = database_config.as_retention_policy().unwrap().unwrap();
+        assert_eq!(
+            retention_policy.retention_of(&Metrics::new("trades").unwrap()),
+            Some(Duration::from_secs(30 * 60 * 60 * 24))
+        );
+        assert_eq!(
+            retention_policy.retention_of(&Metrics::new("orders").unwrap()),
+            Some(Duration::from_secs(90 * 60 * 60 * 24))
+        );
+
+        let database_config = DatabaseConfig {
+            database_name: "test_db".to_string(),
+            cloud_storage_url: None,
+            retention_days: None,
+            metrics_retention_days: None,
+        };
+        assert_eq!(database_config.as_retention_policy().unwrap(), None);
+    }
 }
@@ -52,6 +52,12 @@ impl<'a> CloudBlockFilePath<'a> {
             CloudStorage::Gcp(_, _) => gcp::download_block_file(self, dest).await,
         }
     }
+
+    pub async fn remove(&self) -> Result<()> {
+        match self.cloud_storage {
+            CloudStorage::Gcp(_, _) => gcp::remove_block_file(self).await,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -102,6 +108,12 @@ impl<'a> CloudBlockListFilePath<'a> {
         }
     }
 
+    pub async fn remove(&self) -> Result<()> {
+        match self.cloud_storage {
+            CloudStorage::Gcp(_, _) => gcp::remove_block_list_file(self).await,
+        }
+    }
+
     pub async fn download(&self, dest: &Path) -> Result<Option<()>> {
         match self.cloud_storage {
             CloudStorage::Gcp(_, _) => gcp::download_block_list_file(self, dest).await,
@@ -70,6 +70,12 @@ pub async fn upload_block_file<'a>(src: &Path, dest: &CloudBlockFilePath<'a>) ->
     Ok(())
 }
 
+pub async fn remove_block_file<'a>(block_file_path: &CloudBlockFilePath<'a>) -> Result<()> {
+    let gcs_file = file_dougu::gcs::GcsFile::new(block_file_path.as_url())?;
+    gcs_file.delete_with_retry(None).await?;
+    Ok(())
+}
+
 pub async fn download_block_list_file<'a>(
     src: &CloudBlockListFilePath<'a>,
     dest: &Path,
@@ -135,6 +141,14 @@ pub async fn upload_block_list_file<'a>(
     Ok(())
 }
 
+pub async fn remove_block_list_file<'a>(
+    block_list_file_path: &CloudBlockListFilePath<'a>,
+) -> Result<()> {
+    let gcs_file = file_dougu::gcs::GcsFile::new(block_list_file_path.as_url())?;
+    gcs_file.delete_with_retry(None).await?;
+    Ok(())
+}
+
 pub async fn is_lock_file_exists<'a>(lock_file_path: &CloudLockfilePath<'a>) -> Result<bool> {
     let gcs_file = file_dougu::gcs::GcsFile::new(lock_file_path.as_url())?;
 
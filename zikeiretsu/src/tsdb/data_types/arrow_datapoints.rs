@@ -0,0 +1,299 @@
+use super::{DataPoint, FieldType, FieldValue};
+use crate::tsdb::{TimestampNano, TimestampSec};
+use thiserror::*;
+
+use arrow::array::{
+    Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray,
+    TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray,
+    TimestampSecondArray, UInt64Array,
+};
+use arrow::datatypes::{DataType, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+
+pub type Result<T> = std::result::Result<T, ArrowDatapointsError>;
+#[derive(Error, Debug)]
+pub enum ArrowDatapointsError {
+    #[error("timestamp column not found :{0}")]
+    TimestampColumnNotFound(String),
+
+    #[error("unsupported type of the timestamp column :{0}")]
+    UnsupportedTimestampType(String),
+
+    #[error("unsupported type of the field column. column:{0}, type:{1}")]
+    UnsupportedFieldType(String, String),
+
+    #[error("null value is not allowed. column:{0}, row:{1}")]
+    NullValue(String, usize),
+
+    #[error("negative timestamp is not allowed. row:{0}")]
+    NegativeTimestamp(usize),
+
+    #[error("the schema of the record batch differs from the first one")]
+    UnmatchedSchema,
+}
+
+/// Converts the arrow record batches into datapoints.
+/// The column named as `timestamp_column` will be the timestamp and the rest columns will be the fields in order.
+pub struct RecordBatchDatapointsConverter {
+    schema: Schema,
+    timestamp_column_idx: usize,
+    field_column_indices: Vec<usize>,
+    field_types: Vec<FieldType>,
+}
+
+impl RecordBatchDatapointsConverter {
+    pub fn new(schema: &Schema, timestamp_column: &str) -> Result<Self> {
+        let timestamp_column_idx = schema.index_of(timestamp_column).map_err(|_| {
+            ArrowDatapointsError::TimestampColumnNotFound(timestamp_column.to_string())
+        })?;
+
+        match schema.field(timestamp_column_idx).data_type() {
+            DataType::Timestamp(_, _) | DataType::UInt64 | DataType::Int64 => {}
+            invalid => {
+                return Err(ArrowDatapointsError::UnsupportedTimestampType(format!(
+                    "{invalid:?}"
+                )))
+            }
+        }
+
+        let mut field_column_indices = Vec::<usize>::new();
+        let mut field_types = Vec::<FieldType>::new();
+        for (idx, field) in schema.fields().iter().enumerate() {
+            if idx == timestamp_column_idx {
+                continue;
+            }
+            let field_type = match field.data_type() {
+                DataType::Float64 => FieldType::Float64,
+                DataType::UInt64 => FieldType::UInt64,
+                DataType::Boolean => FieldType::Bool,
+                DataType::Utf8 => FieldType::String,
+                DataType::Timestamp(TimeUnit::Nanosecond, _) => FieldType::TimestampNano,
+                DataType::Timestamp(TimeUnit::Second, _) => FieldType::TimestampSec,
+                invalid => {
+                    return Err(ArrowDatapointsError::UnsupportedFieldType(
+                        field.name().to_string(),
+                        format!("{invalid:?}"),
+                    ))
+                }
+            };
+            field_column_indices.push(idx);
+            field_types.push(field_type);
+        }
+
+        Ok(Self {
+            schema: schema.clone(),
+            timestamp_column_idx,
+            field_column_indices,
+            field_types,
+        })
+    }
+
+    pub fn field_types(&self) -> &[FieldType] {
+        &self.field_types
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        self.field_column_indices
+            .iter()
+            .map(|idx| self.schema.field(*idx).name().to_string())
+            .collect()
+    }
+
+    pub fn to_datapoints(&self, record_batch: &RecordBatch) -> Result<Vec<DataPoint>> {
+        if record_batch.schema().fields() != self.schema.fields() {
+            return Err(ArrowDatapointsError::UnmatchedSchema);
+        }
+
+        let timestamps = self.timestamps(record_batch.column(self.timestamp_column_idx))?;
+        let mut datapoints: Vec<DataPoint> = timestamps
+            .into_iter()
+            .map(|timestamp| DataPoint::new(timestamp, Vec::with_capacity(self.field_types.len())))
+            .collect();
+
+        for column_idx in self.field_column_indices.iter() {
+            let column = record_batch.column(*column_idx);
+            let column_name = self.schema.field(*column_idx).name();
+            if let Some(row_idx) = (0..column.len()).find(|row_idx| column.is_null(*row_idx)) {
+                return Err(ArrowDatapointsError::NullValue(
+                    column_name.to_string(),
+                    row_idx,
+                ));
+            }
+
+            macro_rules! push_values {
+                ($array_type:ty, $to_field_value:expr) => {{
+                    let array = column.as_any().downcast_ref::<$array_type>().unwrap();
+                    for (row_idx, datapoint) in datapoints.iter_mut().enumerate() {
+                        datapoint
+                            .field_values
+                            .push($to_field_value(array.value(row_idx)));
+                    }
+                }};
+            }
+
+            match column.data_type() {
+                DataType::Float64 => push_values!(Float64Array, FieldValue::Float64),
+                DataType::UInt64 => push_values!(UInt64Array, FieldValue::UInt64),
+                DataType::Boolean => push_values!(BooleanArray, FieldValue::Bool),
+                DataType::Utf8 => {
+                    push_values!(StringArray, |v: &str| FieldValue::String(v.to_string()))
+                }
+                DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+                    push_values!(TimestampNanosecondArray, |v: i64| {
+                        FieldValue::TimestampNano(TimestampNano::new(v as u64))
+                    })
+                }
+                DataType::Timestamp(TimeUnit::Second, _) => {
+                    push_values!(TimestampSecondArray, |v: i64| {
+                        FieldValue::TimestampSec(TimestampSec::new(v as u64))
+                    })
+                }
+                invalid => {
+                    return Err(ArrowDatapointsError::UnsupportedFieldType(
+                        column_name.to_string(),
+                        format!("{invalid:?}"),
+                    ))
+                }
+            }
+        }
+
+        Ok(datapoints)
+    }
+
+    fn timestamps(&self, column: &ArrayRef) -> Result<Vec<TimestampNano>> {
+        let column_name = self.schema.field(self.timestamp_column_idx).name();
+        if let Some(row_idx) = (0..column.len()).find(|row_idx| column.is_null(*row_idx)) {
+            return Err(ArrowDatapointsError::NullValue(
+                column_name.to_string(),
+                row_idx,
+            ));
+        }
+
+        macro_rules! signed_nanos {
+            ($array_type:ty, $nanos_per_unit:expr) => {{
+                let array = column.as_any().downcast_ref::<$array_type>().unwrap();
+                let mut timestamps = Vec::<TimestampNano>::with_capacity(array.len());
+                for row_idx in 0..array.len() {
+                    let value = array.value(row_idx);
+                    if value < 0 {
+                        return Err(ArrowDatapointsError::NegativeTimestamp(row_idx));
+                    }
+                    timestamps.push(TimestampNano::new(value as u64 * $nanos_per_unit));
+                }
+                timestamps
+            }};
+        }
+
+        let timestamps = match column.data_type() {
+            DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+                signed_nanos!(TimestampNanosecondArray, 1)
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, _) => {
+                signed_nanos!(TimestampMicrosecondArray, 1_000)
+            }
+            DataType::Timestamp(TimeUnit::Millisecond, _) => {
+                signed_nanos!(TimestampMillisecondArray, 1_000_000)
+            }
+            DataType::Timestamp(TimeUnit::Second, _) => {
+                signed_nanos!(TimestampSecondArray, 1_000_000_000)
+            }
+            DataType::Int64 => signed_nanos!(Int64Array, 1),
+            DataType::UInt64 => {
+                let array = column.as_any().downcast_ref::<UInt64Array>().unwrap();
+                array
+                    .values()
+                    .iter()
+                    .map(|v| TimestampNano::new(*v))
+                    .collect()
+            }
+            invalid => {
+                return Err(ArrowDatapointsError::UnsupportedTimestampType(format!(
+                    "{invalid:?}"
+                )))
+            }
+        };
+        Ok(timestamps)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::datatypes::Field;
+    use std::sync::Arc;
+
+    fn test_schema() -> Schema {
+        Schema::new(vec![
+            Field::new("price", DataType::Float64, false),
+            Field::new(
+                "ts",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("is_buy", DataType::Boolean, false),
+        ])
+    }
+
+    #[test]
+    fn record_batch_to_datapoints_test() {
+        let schema = test_schema();
+        let converter = RecordBatchDatapointsConverter::new(&schema, "ts").unwrap();
+        assert_eq!(
+            converter.field_types(),
+            &[FieldType::Float64, FieldType::Bool]
+        );
+        assert_eq!(
+            converter.field_names(),
+            vec!["price".to_string(), "is_buy".to_string()]
+        );
+
+        let record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Float64Array::from(vec![1.5, 2.5])),
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    1640962800_000,
+                    1640962801_000,
+                ])),
+                Arc::new(BooleanArray::from(vec![true, false])),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            converter.to_datapoints(&record_batch).unwrap(),
+            vec![
+                DataPoint::new(
+                    TimestampNano::new(1640962800_000000000),
+                    vec![FieldValue::Float64(1.5), FieldValue::Bool(true)]
+                ),
+                DataPoint::new(
+                    TimestampNano::new(1640962801_000000000),
+                    vec![FieldValue::Float64(2.5), FieldValue::Bool(false)]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_batch_to_datapoints_err_test() {
+        let schema = test_schema();
+        assert!(RecordBatchDatapointsConverter::new(&schema, "timestamp").is_err());
+        assert!(RecordBatchDatapointsConverter::new(&schema, "is_buy").is_err());
+
+        let schema = Schema::new(vec![
+            Field::new("ts", DataType::UInt64, false),
+            Field::new("price", DataType::Float64, true),
+        ]);
+        let converter = RecordBatchDatapointsConverter::new(&schema, "ts").unwrap();
+        let record_batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(UInt64Array::from(vec![1, 2])),
+                Arc::new(Float64Array::from(vec![Some(1.5), None])),
+            ],
+        )
+        .unwrap();
+        assert!(converter.to_datapoints(&record_batch).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use super::arrow_dataframe::*;
 use super::dataseries::*;
 use super::dataseries_ref::*;
+use super::duplicate_policy::DuplicatedTimestampError;
 use crate::tsdb::util::VecOpeError;
 
 use serde::{Deserialize, Serialize};
@@ -22,6 +23,9 @@ pub enum DataframeError {
 
     #[error("unmatched field number. This might be a by bug. {0}, {1}")]
     UnmatchedFieldNumError(usize, usize),
+
+    #[error("{0}")]
+    DuplicatedTimestamp(#[from] DuplicatedTimestampError),
 }
 
 pub type Result<T> = std::result::Result<T, DataframeError>;
@@ -78,10 +78,43 @@ pub enum SearchDatapointsLimit {
     Tail(usize),
 }
 
+/// pick a part of the datapoints instead of all.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SearchDatapointsSample {
+    /// random sampling. each datapoint is picked with the probability of the percentage.
+    Percent(f64),
+    /// every n-th datapoints
+    Every(u64),
+}
+
+/// pick the datapoints that have the largest (or smallest) values of the field.
+/// `field_idx` is the index of the field in the searched dataframe. (i.e. after the fields selected)
+#[derive(Debug, PartialEq, Clone)]
+pub enum SearchDatapointsRank {
+    Top { field_idx: usize, n: usize },
+    Bottom { field_idx: usize, n: usize },
+}
+
+impl SearchDatapointsRank {
+    pub fn field_idx(&self) -> usize {
+        match self {
+            Self::Top { field_idx, .. } | Self::Bottom { field_idx, .. } => *field_idx,
+        }
+    }
+
+    pub fn n(&self) -> usize {
+        match self {
+            Self::Top { n, .. } | Self::Bottom { n, .. } => *n,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct DatapointsSearchCondition {
     pub datapoints_range: DatapointsRange,
     pub limit: Option<SearchDatapointsLimit>,
+    pub sample: Option<SearchDatapointsSample>,
+    pub rank: Option<SearchDatapointsRank>,
 }
 
 impl DatapointsSearchCondition {
@@ -89,6 +122,8 @@ impl DatapointsSearchCondition {
         Self {
             datapoints_range: DatapointsRange::all(),
             limit: None,
+            sample: None,
+            rank: None,
         }
     }
 }
@@ -155,6 +190,21 @@ impl DatapointsRange {
         self
     }
 
+    pub fn contains(&self, timestamp: &TimestampNano) -> bool {
+        if let Some(since_inclusive) = self.inner_since_inclusive.as_ref() {
+            if timestamp < since_inclusive {
+                return false;
+            }
+        }
+
+        if let Some(until_exclusive) = self.inner_until_exclusive.as_ref() {
+            if timestamp >= until_exclusive {
+                return false;
+            }
+        }
+        true
+    }
+
     pub fn contains_whole(&self, since: &TimestampNano, until: &TimestampNano) -> bool {
         if let Some(since_inclusive) = self.inner_since_inclusive {
             if since < &since_inclusive {
@@ -236,4 +286,16 @@ mod test {
         assert!(DatapointsRange::new(None, Some(TimestampNano::new(21)))
             .contains_whole(&TimestampNano::new(9), &TimestampNano::new(20)));
     }
+
+    #[test]
+    fn test_contains() {
+        let range =
+            DatapointsRange::new(Some(TimestampNano::new(10)), Some(TimestampNano::new(20)));
+        assert!(!range.contains(&TimestampNano::new(9)));
+        assert!(range.contains(&TimestampNano::new(10)));
+        assert!(range.contains(&TimestampNano::new(19)));
+        assert!(!range.contains(&TimestampNano::new(20)));
+
+        assert!(DatapointsRange::all().contains(&TimestampNano::new(0)));
+    }
 }
@@ -0,0 +1,268 @@
+use super::dataframe::{DataframeError, Result};
+use super::datapoint::SearchDatapointsRank;
+use super::field::FieldValue;
+use super::time_series_dataframe::TimeSeriesDataFrame;
+use crate::tsdb::datetime::TimestampNano;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Keeps the top (or bottom) n rows of the dataframes pushed one by one,
+/// so that the all dataframes don't need to be merged into a single one.
+pub struct DatapointsRankHeap {
+    rank: SearchDatapointsRank,
+    // the worst row among the kept rows is on the top of the heap.
+    heap: BinaryHeap<RankedRow>,
+    // empty dataframe that has the same column types as the pushed one.
+    template: Option<TimeSeriesDataFrame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RankKey {
+    Float64(f64),
+    UInt64(u64),
+}
+
+impl RankKey {
+    fn from_field_value(field_value: &FieldValue) -> Result<Option<Self>> {
+        match field_value {
+            FieldValue::Float64(v) if v.is_nan() => Ok(None),
+            FieldValue::Float64(v) => Ok(Some(Self::Float64(*v))),
+            FieldValue::UInt64(v) => Ok(Some(Self::UInt64(*v))),
+            FieldValue::Vacant => Ok(None),
+            invalid => Err(DataframeError::UnmatchedSeriesTypeError(
+                "float64 or uint64".to_string(),
+                invalid.as_ref().to_string(),
+            )),
+        }
+    }
+
+    fn cmp_value(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Float64(l), Self::Float64(r)) => l.total_cmp(r),
+            (Self::UInt64(l), Self::UInt64(r)) => l.cmp(r),
+            // a column never has the both types
+            (Self::Float64(l), Self::UInt64(r)) => l.total_cmp(&(*r as f64)),
+            (Self::UInt64(l), Self::Float64(r)) => (*l as f64).total_cmp(r),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RankedRow {
+    key: RankKey,
+    timestamp: TimestampNano,
+    row: Vec<FieldValue>,
+    descending: bool,
+}
+
+impl RankedRow {
+    /// `Less` if self is ranked higher than other.
+    /// the earlier datapoint is ranked higher among the same values.
+    fn cmp_rank(&self, other: &Self) -> Ordering {
+        let value_order = if self.descending {
+            other.key.cmp_value(&self.key)
+        } else {
+            self.key.cmp_value(&other.key)
+        };
+        value_order.then_with(|| self.timestamp.cmp(&other.timestamp))
+    }
+}
+
+impl PartialEq for RankedRow {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp_rank(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedRow {}
+
+impl PartialOrd for RankedRow {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedRow {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_rank(other)
+    }
+}
+
+impl DatapointsRankHeap {
+    pub fn new(rank: SearchDatapointsRank) -> Self {
+        Self {
+            rank,
+            heap: BinaryHeap::new(),
+            template: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn push_dataframe(&mut self, dataframe: &TimeSeriesDataFrame) -> Result<()> {
+        if self.template.is_none() {
+            self.template = Some(dataframe.pick_rows(&[]));
+        }
+
+        let n = self.rank.n();
+        if n == 0 {
+            return Ok(());
+        }
+        let field_idx = self.rank.field_idx();
+        let series = dataframe
+            .get_series(field_idx)
+            .ok_or(DataframeError::DataSeriesIndexOutOfBound(field_idx, 0))?;
+        let descending = matches!(self.rank, SearchDatapointsRank::Top { .. });
+
+        for (row_idx, timestamp) in dataframe.timestamp_nanos.iter().enumerate() {
+            let key = match series.get(row_idx) {
+                None => {
+                    return Err(DataframeError::DataSeriesIndexOutOfBound(
+                        field_idx, row_idx,
+                    ))
+                }
+                Some(field_value) => match RankKey::from_field_value(&field_value)? {
+                    Some(key) => key,
+                    None => continue,
+                },
+            };
+
+            let mut ranked_row = RankedRow {
+                key,
+                timestamp: *timestamp,
+                row: vec![],
+                descending,
+            };
+
+            if self.heap.len() >= n {
+                match self.heap.peek() {
+                    Some(worst) if ranked_row.cmp_rank(worst) == Ordering::Less => {
+                        self.heap.pop();
+                    }
+                    _ => continue,
+                }
+            }
+
+            // materialize the row only when it's kept
+            for each_series in dataframe.columns.iter() {
+                match each_series.get(row_idx) {
+                    Some(field_value) => ranked_row.row.push(field_value),
+                    None => {
+                        return Err(DataframeError::DataSeriesIndexOutOfBound(
+                            field_idx, row_idx,
+                        ))
+                    }
+                }
+            }
+            self.heap.push(ranked_row);
+        }
+        Ok(())
+    }
+
+    /// the rows are ordered by the rank.
+    /// returns None if no dataframe has been pushed.
+    pub fn into_dataframe(self) -> Result<Option<TimeSeriesDataFrame>> {
+        let mut dataframe = match self.template {
+            Some(template) => template,
+            None => return Ok(None),
+        };
+
+        for ranked_row in self.heap.into_sorted_vec() {
+            if ranked_row.row.len() != dataframe.columns.len() {
+                return Err(DataframeError::UnmatchedFieldNumError(
+                    dataframe.columns.len(),
+                    ranked_row.row.len(),
+                ));
+            }
+            dataframe.timestamp_nanos.push(ranked_row.timestamp);
+            for (series, field_value) in dataframe.columns.iter_mut().zip(ranked_row.row.iter()) {
+                series.push(field_value)?;
+            }
+        }
+        Ok(Some(dataframe))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::*;
+
+    fn dataframe(timestamps: Vec<u64>, values: Vec<f64>) -> TimeSeriesDataFrame {
+        let flags = values.iter().map(|v| *v > 0f64).collect();
+        TimeSeriesDataFrame::new(
+            timestamps.into_iter().map(TimestampNano::new).collect(),
+            vec![
+                DataSeries::new(SeriesValues::Float64(values)),
+                DataSeries::new(SeriesValues::Bool(flags)),
+            ],
+            None,
+        )
+    }
+
+    #[test]
+    fn rank_heap_top_test() {
+        let mut heap = DatapointsRankHeap::new(SearchDatapointsRank::Top { field_idx: 0, n: 3 });
+        heap.push_dataframe(&dataframe(vec![1, 2, 3], vec![1f64, 5f64, -1f64]))
+            .unwrap();
+        heap.push_dataframe(&dataframe(vec![4, 5, 6], vec![f64::NAN, 3f64, 5f64]))
+            .unwrap();
+        heap.push_dataframe(&dataframe(vec![7], vec![2f64]))
+            .unwrap();
+        assert_eq!(heap.len(), 3);
+
+        let result = heap.into_dataframe().unwrap().unwrap();
+        assert_eq!(
+            result,
+            TimeSeriesDataFrame::new(
+                vec![
+                    TimestampNano::new(2),
+                    TimestampNano::new(6),
+                    TimestampNano::new(5)
+                ],
+                vec![
+                    DataSeries::new(SeriesValues::Float64(vec![5f64, 5f64, 3f64])),
+                    DataSeries::new(SeriesValues::Bool(vec![true, true, true])),
+                ],
+                None,
+            )
+        );
+    }
+
+    #[test]
+    fn rank_heap_bottom_test() {
+        let mut heap = DatapointsRankHeap::new(SearchDatapointsRank::Bottom { field_idx: 0, n: 2 });
+        heap.push_dataframe(&dataframe(vec![1, 2, 3], vec![1f64, 5f64, -1f64]))
+            .unwrap();
+        heap.push_dataframe(&dataframe(vec![4, 5], vec![0f64, 3f64]))
+            .unwrap();
+
+        let result = heap.into_dataframe().unwrap().unwrap();
+        assert_eq!(
+            result.timestamp_nanos,
+            vec![TimestampNano::new(3), TimestampNano::new(4)]
+        );
+        assert_eq!(
+            result.columns[0],
+            DataSeries::new(SeriesValues::Float64(vec![-1f64, 0f64]))
+        );
+    }
+
+    #[test]
+    fn rank_heap_invalid_type_test() {
+        let mut heap = DatapointsRankHeap::new(SearchDatapointsRank::Top { field_idx: 1, n: 2 });
+        assert!(heap
+            .push_dataframe(&dataframe(vec![1], vec![1f64]))
+            .is_err());
+
+        let heap = DatapointsRankHeap::new(SearchDatapointsRank::Top { field_idx: 0, n: 2 });
+        assert!(heap.into_dataframe().unwrap().is_none());
+    }
+}
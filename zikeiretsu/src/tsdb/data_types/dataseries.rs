@@ -41,6 +41,35 @@ impl SeriesValues {
             Self::Vacant(len) => *len == 0,
         }
     }
+
+    /// new values that consist of only the values at the indices.
+    pub fn pick_rows(&self, indices: &[usize]) -> Self {
+        match self {
+            Self::Float64(vs) => Self::Float64(pick_values(vs, indices)),
+            Self::UInt64(vs) => Self::UInt64(pick_values(vs, indices)),
+            Self::Bool(vs) => Self::Bool(pick_values(vs, indices)),
+            Self::String(vs) => Self::String(pick_values(vs, indices)),
+            Self::TimestampNano(vs) => Self::TimestampNano(pick_values(vs, indices)),
+            Self::TimestampSec(vs) => Self::TimestampSec(pick_values(vs, indices)),
+            Self::Vacant(len) => Self::Vacant(indices.iter().filter(|idx| **idx < *len).count()),
+        }
+    }
+
+    /// approximate heap size of the values. the contents of strings are included.
+    pub fn approximate_size_in_bytes(&self) -> usize {
+        match self {
+            Self::Float64(vs) => vs.capacity() * std::mem::size_of::<f64>(),
+            Self::UInt64(vs) => vs.capacity() * std::mem::size_of::<u64>(),
+            Self::Bool(vs) => vs.capacity() * std::mem::size_of::<bool>(),
+            Self::String(vs) => {
+                vs.capacity() * std::mem::size_of::<String>()
+                    + vs.iter().map(|v| v.capacity()).sum::<usize>()
+            }
+            Self::TimestampNano(vs) => vs.capacity() * std::mem::size_of::<TimestampNano>(),
+            Self::TimestampSec(vs) => vs.capacity() * std::mem::size_of::<TimestampSec>(),
+            Self::Vacant(_) => 0,
+        }
+    }
 }
 
 impl std::fmt::Display for SeriesValues {
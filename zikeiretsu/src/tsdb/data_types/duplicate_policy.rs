@@ -0,0 +1,144 @@
+use super::datapoint::DataPoint;
+use crate::tsdb::datetime::TimestampNano;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+#[error("duplicated timestamp: {0}")]
+pub struct DuplicatedTimestampError(pub TimestampNano);
+
+/// how the datapoints that have exactly the same timestamp are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    KeepAll,
+    /// keep the datapoint that came first.
+    KeepFirst,
+    /// keep the datapoint that came last.
+    KeepLast,
+    /// reject the datapoints whose timestamp already exists.
+    Error,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        Self::KeepAll
+    }
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keep_all" => Ok(Self::KeepAll),
+            "keep_first" => Ok(Self::KeepFirst),
+            "keep_last" => Ok(Self::KeepLast),
+            "error" => Ok(Self::Error),
+            unknown => Err(format!("unknown duplicate policy {unknown}")),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::KeepAll => "keep_all",
+            Self::KeepFirst => "keep_first",
+            Self::KeepLast => "keep_last",
+            Self::Error => "error",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl DuplicatePolicy {
+    /// returns the indices of the rows to be kept, or None if all the rows are kept.
+    /// the timestamps must be sorted, and the row at the smaller index is regarded as the one that came first.
+    pub fn indices_to_keep(
+        &self,
+        sorted_timestamps: &[TimestampNano],
+    ) -> Result<Option<Vec<usize>>, DuplicatedTimestampError> {
+        let duplicated = sorted_timestamps
+            .windows(2)
+            .position(|timestamps| timestamps[0] == timestamps[1]);
+        let duplicated = match (self, duplicated) {
+            (Self::KeepAll, _) | (_, None) => return Ok(None),
+            (Self::Error, Some(idx)) => {
+                return Err(DuplicatedTimestampError(sorted_timestamps[idx]))
+            }
+            (_, Some(idx)) => idx,
+        };
+
+        let mut indices: Vec<usize> = (0..duplicated).collect();
+        for idx in duplicated..sorted_timestamps.len() {
+            let is_duplicated = match self {
+                Self::KeepLast => sorted_timestamps.get(idx + 1) == Some(&sorted_timestamps[idx]),
+                _ => idx > 0 && sorted_timestamps[idx - 1] == sorted_timestamps[idx],
+            };
+            if !is_duplicated {
+                indices.push(idx);
+            }
+        }
+        Ok(Some(indices))
+    }
+
+    /// removes the duplicated datapoints of the sorted datapoints.
+    pub fn dedup_datapoints(
+        &self,
+        sorted_datapoints: &mut Vec<DataPoint>,
+    ) -> Result<(), DuplicatedTimestampError> {
+        if *self == Self::KeepAll {
+            return Ok(());
+        }
+
+        let timestamps: Vec<TimestampNano> = sorted_datapoints
+            .iter()
+            .map(|datapoint| datapoint.timestamp_nano)
+            .collect();
+        if let Some(indices) = self.indices_to_keep(&timestamps)? {
+            let mut indices = indices.into_iter().peekable();
+            let mut idx = 0;
+            sorted_datapoints.retain(|_| {
+                let keep = indices.peek() == Some(&idx);
+                if keep {
+                    indices.next();
+                }
+                idx += 1;
+                keep
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn timestamps(values: &[u64]) -> Vec<TimestampNano> {
+        values.iter().map(|v| TimestampNano::new(*v)).collect()
+    }
+
+    #[test]
+    fn indices_to_keep_test() {
+        let sorted = timestamps(&[1, 2, 2, 2, 3, 4, 4]);
+        assert_eq!(DuplicatePolicy::KeepAll.indices_to_keep(&sorted), Ok(None));
+        assert_eq!(
+            DuplicatePolicy::KeepFirst.indices_to_keep(&sorted),
+            Ok(Some(vec![0, 1, 4, 5]))
+        );
+        assert_eq!(
+            DuplicatePolicy::KeepLast.indices_to_keep(&sorted),
+            Ok(Some(vec![0, 3, 4, 6]))
+        );
+        assert_eq!(
+            DuplicatePolicy::Error.indices_to_keep(&sorted),
+            Err(DuplicatedTimestampError(TimestampNano::new(2)))
+        );
+        assert_eq!(
+            DuplicatePolicy::Error.indices_to_keep(&timestamps(&[1, 2, 3])),
+            Ok(None)
+        );
+    }
+}
@@ -122,6 +122,15 @@ impl fmt::Display for FieldType {
     }
 }
 
+/// the field types separated by comma. e.g. `Float64,Bool`
+pub(crate) fn join_field_types(field_types: &[FieldType]) -> String {
+    field_types
+        .iter()
+        .map(|field_type| field_type.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
 pub(crate) trait AsFieldValuesRefIterator<'data> {
     fn values_iter(&'data self) -> FieldValuesIter<'data>;
 }
@@ -1,17 +1,23 @@
 pub mod arrow_dataframe;
+pub mod arrow_datapoints;
 pub mod dataframe;
 pub mod datapoint;
+pub mod datapoints_rank;
 pub mod datapoints_searcher;
 pub mod dataseries;
 pub mod dataseries_ref;
+pub mod duplicate_policy;
 pub mod field;
 pub mod time_series_dataframe;
 
 pub use arrow_dataframe::*;
+pub use arrow_datapoints::*;
 pub use dataframe::*;
 pub use datapoint::*;
+pub use datapoints_rank::*;
 pub use datapoints_searcher::*;
 pub use dataseries::*;
 pub use dataseries_ref::*;
+pub use duplicate_policy::*;
 pub use field::*;
 pub use time_series_dataframe::*;
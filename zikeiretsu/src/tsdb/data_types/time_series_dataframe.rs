@@ -3,10 +3,11 @@ use super::dataframe::{DataframeError, Result};
 use super::datapoint::SearchDatapointsLimit;
 use super::dataseries::*;
 use super::dataseries_ref::*;
+use super::duplicate_policy::DuplicatePolicy;
 use super::field::*;
 use super::{datapoint::DataPoint, DatapointsRange};
 use crate::tsdb::datetime::*;
-use crate::tsdb::util::{prepend, trim_values};
+use crate::tsdb::util::{pick_values, prepend, trim_values};
 
 use std::cmp::Ordering;
 
@@ -205,6 +206,46 @@ impl TimeSeriesDataFrame {
         Ok(())
     }
 
+    /// merges the sorted dataframes keeping the order of timestamps.
+    /// the rows of self come before the ones of other if their timestamps are the same.
+    pub fn merge_sorted(&mut self, other: &mut TimeSeriesDataFrame) -> Result<()> {
+        if other.is_empty() {
+            return Ok(());
+        }
+        if self.is_empty() {
+            std::mem::swap(self, other);
+            return Ok(());
+        }
+        if self.timestamp_nanos.last() <= other.timestamp_nanos.first() {
+            return self.append(other);
+        }
+
+        let self_len = self.len();
+        let mut order = Vec::with_capacity(self_len + other.len());
+        let (mut self_idx, mut other_idx) = (0, 0);
+        while self_idx < self_len || other_idx < other.len() {
+            let take_self = match (
+                self.timestamp_nanos.get(self_idx),
+                other.timestamp_nanos.get(other_idx),
+            ) {
+                (Some(self_ts), Some(other_ts)) => self_ts <= other_ts,
+                (Some(_), None) => true,
+                _ => false,
+            };
+            if take_self {
+                order.push(self_idx);
+                self_idx += 1;
+            } else {
+                order.push(self_len + other_idx);
+                other_idx += 1;
+            }
+        }
+
+        self.append(other)?;
+        *self = self.pick_rows(&order);
+        Ok(())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -213,6 +254,37 @@ impl TimeSeriesDataFrame {
         self.timestamp_nanos.len()
     }
 
+    /// new dataframe that consists of only the rows at the indices.
+    pub fn pick_rows(&self, indices: &[usize]) -> Self {
+        Self {
+            timestamp_nanos: pick_values(&self.timestamp_nanos, indices),
+            columns: self
+                .columns
+                .iter()
+                .map(|column| DataSeries::new(column.values.pick_rows(indices)))
+                .collect(),
+            column_names: self.column_names.clone(),
+        }
+    }
+
+    /// removes the rows of the duplicated timestamps. the dataframe must be sorted.
+    pub fn dedup(&mut self, duplicate_policy: &DuplicatePolicy) -> Result<()> {
+        if let Some(indices) = duplicate_policy.indices_to_keep(&self.timestamp_nanos)? {
+            *self = self.pick_rows(&indices);
+        }
+        Ok(())
+    }
+
+    /// approximate heap size of the timestamps and the all columns.
+    pub fn approximate_size_in_bytes(&self) -> usize {
+        self.timestamp_nanos.capacity() * std::mem::size_of::<TimestampNano>()
+            + self
+                .columns
+                .iter()
+                .map(|column| column.values.approximate_size_in_bytes())
+                .sum::<usize>()
+    }
+
     pub fn column_len(&self) -> usize {
         self.columns.len()
     }
@@ -1357,4 +1429,54 @@ mod test {
             assert_eq!(df, multi_dataframe!([(10, 1010, true)]));
         }
     }
+
+    #[test]
+    fn test_dedup() {
+        let create_df = || dataframe!([(10, 1), (20, 2), (20, 3), (20, 4), (30, 5), (30, 6)]);
+
+        let mut df = create_df();
+        df.dedup(&DuplicatePolicy::KeepAll).unwrap();
+        assert_eq!(df, create_df());
+
+        let mut df = create_df();
+        df.dedup(&DuplicatePolicy::KeepFirst).unwrap();
+        assert_eq!(df, dataframe!([(10, 1), (20, 2), (30, 5)]));
+
+        let mut df = create_df();
+        df.dedup(&DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(df, dataframe!([(10, 1), (20, 4), (30, 6)]));
+
+        let mut df = create_df();
+        assert!(df.dedup(&DuplicatePolicy::Error).is_err());
+    }
+
+    #[test]
+    fn test_merge_sorted() {
+        let mut df = dataframe!([(10, 1), (20, 2), (20, 3), (40, 4)]);
+        let mut other = dataframe!([(5, 5), (20, 6), (30, 7), (50, 8)]);
+        df.merge_sorted(&mut other).unwrap();
+        assert_eq!(
+            df,
+            dataframe!([
+                (5, 5),
+                (10, 1),
+                (20, 2),
+                (20, 3),
+                (20, 6),
+                (30, 7),
+                (40, 4),
+                (50, 8)
+            ])
+        );
+
+        let mut df = dataframe!([(10, 1), (20, 2)]);
+        let mut other = dataframe!([(20, 3), (30, 4)]);
+        df.merge_sorted(&mut other).unwrap();
+        assert_eq!(df, dataframe!([(10, 1), (20, 2), (20, 3), (30, 4)]));
+
+        let mut df = TimeSeriesDataFrame::empty();
+        let mut other = dataframe!([(10, 1)]);
+        df.merge_sorted(&mut other).unwrap();
+        assert_eq!(df, dataframe!([(10, 1)]));
+    }
 }
@@ -1,6 +1,8 @@
+use crate::tsdb::storage::api::RetentionPolicy;
 use crate::tsdb::{cloudstorage::CloudStorageError, Bucket, CloudStorage, SubDir};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use thiserror::Error;
 
@@ -98,7 +100,12 @@ impl Database {
 pub struct DBContext {
     pub data_dir: PathBuf,
     pub default_database: Option<String>,
+    /// applied to the queries that have no `timeout` definition in the with clause.
+    pub default_query_timeout: Option<Duration>,
+    /// upper limit of the memory that the dataframes of a query can hold.
+    pub default_query_memory_limit_bytes: Option<usize>,
     databases: HashMap<String, Database>,
+    retention_policies: HashMap<String, RetentionPolicy>,
 }
 
 impl DBContext {
@@ -115,10 +122,44 @@ impl DBContext {
         Self {
             data_dir,
             default_database,
+            default_query_timeout: None,
+            default_query_memory_limit_bytes: None,
             databases,
+            retention_policies: HashMap::new(),
         }
     }
 
+    pub fn with_default_query_timeout(mut self, default_query_timeout: Option<Duration>) -> Self {
+        self.default_query_timeout = default_query_timeout;
+        self
+    }
+
+    pub fn with_default_query_memory_limit_bytes(
+        mut self,
+        default_query_memory_limit_bytes: Option<usize>,
+    ) -> Self {
+        self.default_query_memory_limit_bytes = default_query_memory_limit_bytes;
+        self
+    }
+
+    pub fn with_retention_policy(
+        mut self,
+        database_name: &str,
+        retention_policy: RetentionPolicy,
+    ) -> Self {
+        self.retention_policies
+            .insert(database_name.to_string(), retention_policy);
+        self
+    }
+
+    pub fn retention_policy(&self, database_name: &str) -> Option<&RetentionPolicy> {
+        self.retention_policies.get(database_name)
+    }
+
+    pub fn databases(&self) -> impl Iterator<Item = &Database> {
+        self.databases.values()
+    }
+
     pub fn get_database(&self, db_name: Option<&str>) -> Result<Option<&Database>> {
         match db_name {
             Some(db_name) => Ok(self.databases.get(db_name)),
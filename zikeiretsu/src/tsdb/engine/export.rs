@@ -0,0 +1,625 @@
+use crate::tsdb::cloudstorage::CloudStorage;
+use crate::tsdb::data_types::{
+    ArrowConvatibleDataFrame, ArrowConvatibleDataFrameError, DataframeError, DatapointsRange,
+};
+use crate::tsdb::datetime::{parse_datetime_str, TimestampNano};
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::query::executor::output::ParquetOutput;
+use crate::tsdb::storage::api::{
+    self, lockfile_path, CacheSetting, CloudStorageSetting, SearchBudget, StorageApiError,
+};
+use crate::tsdb::storage::block_list;
+use arrow::csv;
+use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use chrono::DateTime;
+use lockfile::Lockfile;
+use parquet::arrow::arrow_writer::ArrowWriter;
+use parquet::errors::ParquetError;
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+pub const EXPORT_TIMESTAMP_COLUMN: &str = "ts";
+
+pub type Result<T> = std::result::Result<T, ExportError>;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("arrow error: {0}")]
+    ArrowError(#[from] ArrowError),
+
+    #[error("parquet error: {0}")]
+    ParquetError(#[from] ParquetError),
+
+    #[error("{0}")]
+    ArrowConvatibleDataFrameError(#[from] ArrowConvatibleDataFrameError),
+
+    #[error("data frame error: {0}")]
+    DataframeError(#[from] DataframeError),
+
+    #[error("storage api error: {0}")]
+    StorageApiError(#[from] StorageApiError),
+
+    #[error("unknown export format: {0}")]
+    UnknownFileFormat(String),
+
+    #[error("unknown partition: {0}")]
+    UnknownPartition(String),
+
+    #[error("invalid datetime: {0}")]
+    InvalidDatetime(String),
+
+    #[error("the schema of the block differs from the others. expected:{0}, actual:{1}")]
+    UnmatchedSchema(String, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFileFormat {
+    Parquet,
+    ParquetSnappy,
+    Csv,
+}
+
+impl ExportFileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Parquet | Self::ParquetSnappy => "parquet",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ExportFileFormat {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "parquet" => Ok(Self::Parquet),
+            "parquet_snappy" => Ok(Self::ParquetSnappy),
+            "csv" => Ok(Self::Csv),
+            unknown => Err(ExportError::UnknownFileFormat(unknown.to_string())),
+        }
+    }
+}
+
+/// how the datapoints are split into the files. the days are in UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportPartition {
+    Day,
+    None,
+}
+
+impl ExportPartition {
+    /// returns the name of the partition that the timestamp belongs to
+    /// and the exclusive end of the partition.
+    fn partition_of(&self, timestamp: &TimestampNano) -> (String, Option<TimestampNano>) {
+        match self {
+            Self::Day => {
+                let date = timestamp.as_naive_datetime().date();
+                let next_day = date.succ().and_hms(0, 0, 0).timestamp_nanos();
+                (
+                    date.format("%Y-%m-%d").to_string(),
+                    Some(TimestampNano::new(next_day as u64)),
+                )
+            }
+            Self::None => ("all".to_string(), None),
+        }
+    }
+}
+
+impl FromStr for ExportPartition {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(Self::Day),
+            "none" => Ok(Self::None),
+            unknown => Err(ExportError::UnknownPartition(unknown.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSetting {
+    pub file_format: ExportFileFormat,
+    pub partition: ExportPartition,
+    pub range: DatapointsRange,
+    /// names of the field columns. `0`, `1`, ... if None.
+    pub field_names: Option<Vec<String>>,
+}
+
+impl ExportSetting {
+    pub fn new(file_format: ExportFileFormat, partition: ExportPartition) -> Self {
+        Self {
+            file_format,
+            partition,
+            range: DatapointsRange::all(),
+            field_names: None,
+        }
+    }
+
+    pub fn with_range(mut self, range: DatapointsRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn with_field_names(mut self, field_names: Option<Vec<String>>) -> Self {
+        self.field_names = field_names;
+        self
+    }
+
+    fn column_names(&self, field_num: usize) -> Vec<String> {
+        let mut column_names = vec![EXPORT_TIMESTAMP_COLUMN.to_string()];
+        match self.field_names.as_ref() {
+            Some(field_names) => column_names.extend(field_names.iter().cloned()),
+            None => column_names.extend((0..field_num).map(|idx| idx.to_string())),
+        }
+        column_names
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ExportSummary {
+    /// in the order of the partitions
+    pub files: Vec<PathBuf>,
+    pub datapoint_num: usize,
+    pub block_num: usize,
+}
+
+/// parses `yyyy-MM-DD hh:mm:ss`, `yyyy-MM-DD` or the rfc3339 as UTC.
+pub fn parse_export_datetime(datetime_str: &str) -> Result<TimestampNano> {
+    let datetime = DateTime::parse_from_rfc3339(datetime_str)
+        .map(|datetime| datetime.timestamp_nanos())
+        .or_else(|_| {
+            parse_datetime_str(&format!("'{datetime_str}'"))
+                .map(|datetime| datetime.timestamp_nanos())
+        })
+        .map_err(|_| ExportError::InvalidDatetime(datetime_str.to_string()))?;
+
+    if datetime < 0 {
+        return Err(ExportError::InvalidDatetime(datetime_str.to_string()));
+    }
+    Ok(TimestampNano::new(datetime as u64))
+}
+
+/// Writes the datapoints in the range into `{output_dir}/{metrics}/{partition}.{parquet|csv}`.
+/// The groups of the overlapping blocks are read one by one and merge-sorted with the duplicate policy
+/// as the search does, and each partition file is closed once the following groups no longer contain it,
+/// so the whole range is never loaded into memory.
+/// The lock file is held until the end so that the blocks are not removed while exporting.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_metrics<P: AsRef<Path>>(
+    database_name: &str,
+    db_dir: P,
+    metrics: &Metrics,
+    output_dir: &Path,
+    setting: &ExportSetting,
+    cache_setting: &CacheSetting,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+    duplicate_policy: &DuplicatePolicy,
+) -> Result<ExportSummary> {
+    let db_dir = db_dir.as_ref();
+    let lock_file_path = lockfile_path(db_dir, metrics);
+    let _lockfile = Lockfile::create(&lock_file_path)
+        .map_err(|e| StorageApiError::AcquireLockError(lock_file_path.display().to_string(), e))?;
+    let block_list = api::read::read_block_list(
+        database_name,
+        db_dir,
+        metrics,
+        cache_setting,
+        cloud_storage_and_setting,
+    )
+    .await?;
+
+    let (since_sec, until_sec) = setting.range.as_secs();
+    let block_metas = block_list
+        .search(since_sec.as_ref(), until_sec.as_ref())
+        .map_err(StorageApiError::from)?
+        .unwrap_or_default();
+
+    let mut writers = PartitionWriters::new(output_dir.join(metrics.as_str()), setting.file_format);
+    let mut schema: Option<SchemaRef> = None;
+    let mut datapoint_num = 0;
+    let mut block_num = 0;
+
+    // the blocks may overlap each other. the groups of the overlapping blocks don't overlap
+    // each other and are in the order of the timestamps.
+    let search_budget = SearchBudget::unlimited();
+    let memory_usage = search_budget.memory_usage();
+    for group in block_list::overlapping_block_groups(block_metas) {
+        let mut dataframes_of_blocks = Vec::with_capacity(group.len());
+        for block_meta in group.iter().map(|idx| &block_metas[*idx]) {
+            let mut block = api::read::read_block(
+                database_name,
+                db_dir,
+                metrics,
+                None,
+                &block_meta.block_timestamp,
+                cache_setting,
+                cloud_storage_and_setting,
+            )
+            .await?;
+            if !setting.range.contains_whole(
+                &block_meta.block_timestamp.since_sec.as_timestamp_nano(),
+                &(block_meta.block_timestamp.until_sec + 1).as_timestamp_nano(),
+            ) {
+                block.retain_matches(&setting.range).await?;
+            }
+            if !block.is_empty() {
+                dataframes_of_blocks.push((block, block_meta));
+            }
+        }
+        let merged_block_num = dataframes_of_blocks.len();
+        let mut block = match api::read::merge_blocks_in_write_order(
+            dataframes_of_blocks,
+            &search_budget,
+            &memory_usage,
+            duplicate_policy,
+        )? {
+            Some(block) => block,
+            None => continue,
+        };
+
+        block.set_column_names(Some(setting.column_names(block.column_len())));
+        let record_batch = block.as_arrow_record_batchs(false, None).await?;
+        match schema.as_ref() {
+            None => schema = Some(record_batch.schema()),
+            Some(schema) if *schema != record_batch.schema() => {
+                return Err(ExportError::UnmatchedSchema(
+                    format!("{schema:?}"),
+                    format!("{:?}", record_batch.schema()),
+                ))
+            }
+            Some(_) => {}
+        }
+
+        let timestamps = &block.timestamp_nanos;
+
+        // the partitions before the head of this group will never be written again.
+        let (head_partition, _) = setting.partition.partition_of(&timestamps[0]);
+        writers.close_before(&head_partition)?;
+
+        let mut start = 0;
+        while start < timestamps.len() {
+            let (partition, partition_until) = setting.partition.partition_of(&timestamps[start]);
+            let end = match partition_until {
+                Some(partition_until) => {
+                    timestamps.partition_point(|timestamp| *timestamp < partition_until)
+                }
+                None => timestamps.len(),
+            };
+            writers.write(partition, &record_batch.slice(start, end - start))?;
+            start = end;
+        }
+
+        datapoint_num += block.len();
+        block_num += merged_block_num;
+    }
+
+    let files = writers.close_all()?;
+    Ok(ExportSummary {
+        files,
+        datapoint_num,
+        block_num,
+    })
+}
+
+enum PartitionWriter {
+    Parquet(ArrowWriter<File>),
+    Csv(csv::Writer<File>),
+}
+
+impl PartitionWriter {
+    fn create(path: &Path, file_format: ExportFileFormat, schema: SchemaRef) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+
+        let writer = match file_format {
+            ExportFileFormat::Parquet | ExportFileFormat::ParquetSnappy => Self::Parquet(
+                ParquetOutput {
+                    file,
+                    snappy_compress: file_format == ExportFileFormat::ParquetSnappy,
+                }
+                .writer(schema)?,
+            ),
+            ExportFileFormat::Csv => Self::Csv(csv::Writer::new(file)),
+        };
+        Ok(writer)
+    }
+
+    fn write(&mut self, record_batch: &RecordBatch) -> Result<()> {
+        match self {
+            Self::Parquet(writer) => writer.write(record_batch)?,
+            Self::Csv(writer) => writer.write(record_batch)?,
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<()> {
+        match self {
+            Self::Parquet(writer) => {
+                writer.close()?;
+            }
+            // the buffered rows are flushed on drop
+            Self::Csv(writer) => drop(writer),
+        }
+        Ok(())
+    }
+}
+
+/// the writers of the partitions being written, ordered by the partition names.
+struct PartitionWriters {
+    dir: PathBuf,
+    file_format: ExportFileFormat,
+    writers: BTreeMap<String, PartitionWriter>,
+    files: Vec<PathBuf>,
+}
+
+impl PartitionWriters {
+    fn new(dir: PathBuf, file_format: ExportFileFormat) -> Self {
+        Self {
+            dir,
+            file_format,
+            writers: BTreeMap::new(),
+            files: vec![],
+        }
+    }
+
+    fn write(&mut self, partition: String, record_batch: &RecordBatch) -> Result<()> {
+        let writer = match self.writers.entry(partition) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path =
+                    self.dir
+                        .join(format!("{}.{}", entry.key(), self.file_format.extension()));
+                let writer =
+                    PartitionWriter::create(&path, self.file_format, record_batch.schema())?;
+                self.files.push(path);
+                entry.insert(writer)
+            }
+        };
+        writer.write(record_batch)
+    }
+
+    fn close_before(&mut self, partition: &str) -> Result<()> {
+        let remainings = self.writers.split_off(partition);
+        for (_, writer) in std::mem::replace(&mut self.writers, remainings) {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn close_all(mut self) -> Result<Vec<PathBuf>> {
+        for (_, writer) in std::mem::take(&mut self.writers) {
+            writer.close()?;
+        }
+        Ok(self.files)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::DataPoint;
+    use crate::tsdb::field::FieldValue;
+    use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+    use std::io::Read;
+    use uuid::Uuid;
+
+    const DAY_IN_NANO: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    #[test]
+    fn partition_of_test() {
+        let (partition, until) =
+            ExportPartition::Day.partition_of(&TimestampNano::new(DAY_IN_NANO + 1));
+        assert_eq!(partition, "1970-01-02");
+        assert_eq!(until, Some(TimestampNano::new(DAY_IN_NANO * 2)));
+
+        let (partition, until) = ExportPartition::None.partition_of(&TimestampNano::new(1));
+        assert_eq!(partition, "all");
+        assert_eq!(until, None);
+    }
+
+    #[test]
+    fn parse_export_datetime_test() {
+        assert_eq!(
+            parse_export_datetime("1970-01-02").unwrap(),
+            TimestampNano::new(DAY_IN_NANO)
+        );
+        assert_eq!(
+            parse_export_datetime("1970-01-02 00:00:01").unwrap(),
+            TimestampNano::new(DAY_IN_NANO + 1_000_000_000)
+        );
+        assert_eq!(
+            parse_export_datetime("1970-01-02T09:00:00+09:00").unwrap(),
+            TimestampNano::new(DAY_IN_NANO)
+        );
+        assert!(parse_export_datetime("yesterday").is_err());
+    }
+
+    #[tokio::test]
+    async fn export_metrics_test() {
+        let temp_dir = tempdir::TempDir::new("export_metrics_test").unwrap();
+        let db_dir = temp_dir.path().join("db");
+        let output_dir = temp_dir.path().join("out");
+        let metrics = Metrics::new("trades").unwrap();
+
+        let datapoint = |timestamp: u64, value: f64| {
+            DataPoint::new(
+                TimestampNano::new(timestamp),
+                vec![FieldValue::Float64(value), FieldValue::Bool(value > 1.0)],
+            )
+        };
+        // the second block spans two days
+        let blocks = vec![
+            vec![datapoint(10, 1.0), datapoint(20, 2.0)],
+            vec![
+                datapoint(DAY_IN_NANO - 10, 3.0),
+                datapoint(DAY_IN_NANO + 10, 4.0),
+                datapoint(DAY_IN_NANO * 2 + 10, 5.0),
+            ],
+        ];
+        let writer_id = Uuid::new_v4();
+        for block in blocks.iter() {
+            api::write::write_datas(&db_dir, &writer_id, &metrics, block, None)
+                .await
+                .unwrap();
+        }
+
+        let setting = ExportSetting::new(ExportFileFormat::Parquet, ExportPartition::Day)
+            .with_range(DatapointsRange::new(
+                Some(TimestampNano::new(20)),
+                Some(TimestampNano::new(DAY_IN_NANO * 2)),
+            ))
+            .with_field_names(Some(vec!["price".to_string(), "is_up".to_string()]));
+        let summary = export_metrics(
+            "db",
+            &db_dir,
+            &metrics,
+            &output_dir,
+            &setting,
+            &CacheSetting::none(),
+            None,
+            &DuplicatePolicy::KeepAll,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.datapoint_num, 3);
+        assert_eq!(summary.block_num, 2);
+        assert_eq!(
+            summary.files,
+            vec![
+                output_dir.join("trades/1970-01-01.parquet"),
+                output_dir.join("trades/1970-01-02.parquet"),
+            ]
+        );
+
+        let read_rows = |path: &Path| {
+            let mut reader = ParquetFileArrowReader::try_new(File::open(path).unwrap()).unwrap();
+            let field_names: Vec<String> = reader
+                .get_schema()
+                .unwrap()
+                .fields()
+                .iter()
+                .map(|field| field.name().to_string())
+                .collect();
+            let row_num: usize = reader
+                .get_record_reader(1024)
+                .unwrap()
+                .map(|record_batch| record_batch.unwrap().num_rows())
+                .sum();
+            (field_names, row_num)
+        };
+        assert_eq!(
+            read_rows(&summary.files[0]),
+            (
+                vec!["ts".to_string(), "price".to_string(), "is_up".to_string()],
+                2
+            )
+        );
+        assert_eq!(read_rows(&summary.files[1]).1, 1);
+
+        let setting = ExportSetting::new(ExportFileFormat::Csv, ExportPartition::None);
+        let summary = export_metrics(
+            "db",
+            &db_dir,
+            &metrics,
+            &output_dir,
+            &setting,
+            &CacheSetting::none(),
+            None,
+            &DuplicatePolicy::KeepAll,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.datapoint_num, 5);
+        assert_eq!(summary.files, vec![output_dir.join("trades/all.csv")]);
+
+        let mut csv = String::new();
+        File::open(&summary.files[0])
+            .unwrap()
+            .read_to_string(&mut csv)
+            .unwrap();
+        assert_eq!(csv.lines().next(), Some("ts,0,1"));
+        assert_eq!(csv.lines().count(), 6);
+    }
+
+    #[tokio::test]
+    async fn export_overlapping_blocks_test() {
+        let temp_dir = tempdir::TempDir::new("export_overlapping_blocks_test").unwrap();
+        let db_dir = temp_dir.path().join("db");
+        let output_dir = temp_dir.path().join("out");
+        let metrics = Metrics::new("trades").unwrap();
+
+        let datapoint = |sec: u64, value: f64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(value)],
+            )
+        };
+        // the later block overlaps the first one and has the same timestamp
+        let blocks = vec![
+            vec![datapoint(10, 1.0), datapoint(30, 3.0)],
+            vec![datapoint(20, 2.0), datapoint(30, 4.0)],
+            vec![datapoint(40, 5.0)],
+        ];
+        let writer_id = Uuid::new_v4();
+        for block in blocks.iter() {
+            api::write::write_datas(&db_dir, &writer_id, &metrics, block, None)
+                .await
+                .unwrap();
+        }
+
+        let export = |duplicate_policy: DuplicatePolicy| {
+            let (db_dir, output_dir, metrics) =
+                (db_dir.clone(), output_dir.clone(), metrics.clone());
+            async move {
+                let setting = ExportSetting::new(ExportFileFormat::Csv, ExportPartition::None)
+                    .with_field_names(Some(vec!["price".to_string()]));
+                let summary = export_metrics(
+                    "db",
+                    &db_dir,
+                    &metrics,
+                    &output_dir,
+                    &setting,
+                    &CacheSetting::none(),
+                    None,
+                    &duplicate_policy,
+                )
+                .await
+                .unwrap();
+                assert_eq!(summary.block_num, 3);
+
+                let mut csv = String::new();
+                File::open(&summary.files[0])
+                    .unwrap()
+                    .read_to_string(&mut csv)
+                    .unwrap();
+                csv.lines()
+                    .skip(1)
+                    .map(|line| line.split(',').nth(1).unwrap().parse::<f64>().unwrap())
+                    .collect::<Vec<f64>>()
+            }
+        };
+
+        assert_eq!(
+            export(DuplicatePolicy::KeepAll).await,
+            vec![1.0, 2.0, 3.0, 4.0, 5.0]
+        );
+        assert_eq!(
+            export(DuplicatePolicy::KeepLast).await,
+            vec![1.0, 2.0, 4.0, 5.0]
+        );
+    }
+}
@@ -0,0 +1,617 @@
+use crate::tsdb::cloudstorage::CloudStorage;
+use crate::tsdb::data_types::{ArrowDatapointsError, RecordBatchDatapointsConverter};
+use crate::tsdb::datapoint::DataPoint;
+use crate::tsdb::field::FieldType;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::storage::api::{self, CloudStorageSetting, StorageApiError};
+use arrow::array::{Array, ArrayRef, Int64Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::csv;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::errors::ParquetError;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const DEFAULT_IMPORT_BLOCK_SIZE: usize = 100_000;
+const READ_BATCH_SIZE: usize = 8192;
+const CSV_SCHEMA_INFER_RECORDS: usize = 1000;
+
+pub type Result<T> = std::result::Result<T, ImportError>;
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("arrow error: {0}")]
+    ArrowError(#[from] ArrowError),
+
+    #[error("parquet error: {0}")]
+    ParquetError(#[from] ParquetError),
+
+    #[error("{0}")]
+    ArrowDatapointsError(#[from] ArrowDatapointsError),
+
+    #[error("storage api error: {0}")]
+    StorageApiError(#[from] StorageApiError),
+
+    #[error("unknown file format: {0}")]
+    UnknownFileFormat(String),
+
+    #[error("timestamp column not found: {0}")]
+    TimestampColumnNotFound(String),
+
+    #[error("invalid timestamp at row {0}: {1}")]
+    InvalidTimestamp(usize, String),
+
+    #[error("unsupported type of the field column. column:{0}, type:{1}")]
+    UnsupportedFieldType(String, String),
+
+    #[error("no datapoints found in the file")]
+    NoDatapoints,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFileFormat {
+    Csv,
+    Parquet,
+}
+
+impl ImportFileFormat {
+    /// guess the format by the extension of the file.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => ext.parse(),
+            None => Err(ImportError::UnknownFileFormat(path.display().to_string())),
+        }
+    }
+}
+
+impl FromStr for ImportFileFormat {
+    type Err = ImportError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            unknown => Err(ImportError::UnknownFileFormat(unknown.to_string())),
+        }
+    }
+}
+
+/// how the values of the timestamp column are interpreted.
+/// `ns`,`us`,`ms`,`s` for the numbers since the unix epoch, `rfc3339`, or any strftime format such as `%Y-%m-%d %H:%M:%S`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportTimestampFormat {
+    Nanos,
+    Micros,
+    Millis,
+    Secs,
+    Rfc3339,
+    Strftime(String),
+}
+
+impl FromStr for ImportTimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "" => Err("empty timestamp format".to_string()),
+            "ns" => Ok(Self::Nanos),
+            "us" => Ok(Self::Micros),
+            "ms" => Ok(Self::Millis),
+            "s" => Ok(Self::Secs),
+            "rfc3339" => Ok(Self::Rfc3339),
+            strftime => Ok(Self::Strftime(strftime.to_string())),
+        }
+    }
+}
+
+impl ImportTimestampFormat {
+    fn nanos_per_unit(&self) -> Option<i64> {
+        match self {
+            Self::Nanos => Some(1),
+            Self::Micros => Some(1_000),
+            Self::Millis => Some(1_000_000),
+            Self::Secs => Some(1_000_000_000),
+            Self::Rfc3339 | Self::Strftime(_) => None,
+        }
+    }
+
+    fn parse_str(&self, s: &str) -> std::result::Result<i64, String> {
+        match self {
+            Self::Rfc3339 => DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.timestamp_nanos())
+                .map_err(|e| format!("{e}")),
+            Self::Strftime(format) => NaiveDateTime::parse_from_str(s, format)
+                .map(|dt| dt.timestamp_nanos())
+                .or_else(|_| DateTime::parse_from_str(s, format).map(|dt| dt.timestamp_nanos()))
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(s, format)
+                        .map(|date| date.and_hms(0, 0, 0).timestamp_nanos())
+                })
+                .map_err(|e| format!("{e}")),
+            unit => {
+                let value = s.parse::<i64>().map_err(|e| format!("{e}"))?;
+                value
+                    .checked_mul(unit.nanos_per_unit().unwrap_or(1))
+                    .ok_or_else(|| format!("timestamp overflow: {value}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportSetting {
+    pub file_format: ImportFileFormat,
+    pub timestamp_column: String,
+    /// guessed by the type of the column if None.
+    pub timestamp_format: Option<ImportTimestampFormat>,
+    /// max number of the datapoints in a block.
+    pub block_size: usize,
+    pub csv_delimiter: u8,
+}
+
+impl ImportSetting {
+    pub fn new(file_format: ImportFileFormat, timestamp_column: &str) -> Self {
+        Self {
+            file_format,
+            timestamp_column: timestamp_column.to_string(),
+            timestamp_format: None,
+            block_size: DEFAULT_IMPORT_BLOCK_SIZE,
+            csv_delimiter: b',',
+        }
+    }
+
+    pub fn with_timestamp_format(
+        mut self,
+        timestamp_format: Option<ImportTimestampFormat>,
+    ) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn with_csv_delimiter(mut self, csv_delimiter: u8) -> Self {
+        self.csv_delimiter = csv_delimiter;
+        self
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImportSummary {
+    pub field_names: Vec<String>,
+    pub field_types: Vec<FieldType>,
+    pub datapoint_num: usize,
+    pub block_num: usize,
+}
+
+/// Reads the whole file, sorts the rows by the timestamp, then writes them as blocks.
+/// The numeric columns are imported as float64 and the boolean columns as bool.
+pub async fn import_file<P: AsRef<Path>>(
+    db_dir: P,
+    metrics: &Metrics,
+    file_path: &Path,
+    setting: &ImportSetting,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<ImportSummary> {
+    let (field_names, field_types, mut datapoints) = read_datapoints(file_path, setting)?;
+    if datapoints.is_empty() {
+        return Err(ImportError::NoDatapoints);
+    }
+    datapoints.sort_by_key(|datapoint| datapoint.timestamp_nano);
+
+    let writer_id = Uuid::new_v4();
+    let blocks = split_into_blocks(&datapoints, setting.block_size);
+    for block in blocks.iter() {
+        api::write::write_datas(
+            db_dir.as_ref(),
+            &writer_id,
+            metrics,
+            block,
+            cloud_storage_and_setting,
+        )
+        .await?;
+    }
+
+    Ok(ImportSummary {
+        field_names,
+        field_types,
+        datapoint_num: datapoints.len(),
+        block_num: blocks.len(),
+    })
+}
+
+/// returns the field names, field types and the unsorted datapoints.
+pub fn read_datapoints(
+    file_path: &Path,
+    setting: &ImportSetting,
+) -> Result<(Vec<String>, Vec<FieldType>, Vec<DataPoint>)> {
+    let record_batches = match setting.file_format {
+        ImportFileFormat::Csv => read_csv(file_path, setting)?,
+        ImportFileFormat::Parquet => read_parquet(file_path)?,
+    };
+
+    let mut converter: Option<RecordBatchDatapointsConverter> = None;
+    let mut datapoints = Vec::<DataPoint>::new();
+    for record_batch in record_batches {
+        let record_batch = normalize_record_batch(record_batch, setting)?;
+        if converter.is_none() {
+            converter = Some(RecordBatchDatapointsConverter::new(
+                &record_batch.schema(),
+                &setting.timestamp_column,
+            )?);
+        }
+        if let Some(converter) = converter.as_ref() {
+            datapoints.append(&mut converter.to_datapoints(&record_batch)?);
+        }
+    }
+
+    match converter {
+        None => Err(ImportError::NoDatapoints),
+        Some(converter) => Ok((
+            converter.field_names(),
+            converter.field_types().to_vec(),
+            datapoints,
+        )),
+    }
+}
+
+fn read_csv(file_path: &Path, setting: &ImportSetting) -> Result<Vec<RecordBatch>> {
+    let mut file = File::open(file_path)?;
+    let (inferred_schema, _) = csv::reader::infer_file_schema(
+        &mut file,
+        setting.csv_delimiter,
+        Some(CSV_SCHEMA_INFER_RECORDS),
+        true,
+    )?;
+    file.seek(SeekFrom::Start(0))?;
+
+    // the timestamp column is read as string and parsed with the timestamp format
+    let fields = inferred_schema
+        .fields()
+        .iter()
+        .map(|field| {
+            if field.name() == &setting.timestamp_column {
+                Field::new(field.name(), DataType::Utf8, true)
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+
+    let reader = csv::ReaderBuilder::new()
+        .has_header(true)
+        .with_delimiter(setting.csv_delimiter)
+        .with_schema(Arc::new(Schema::new(fields)))
+        .with_batch_size(READ_BATCH_SIZE)
+        .build(file)?;
+    let record_batches = reader.collect::<std::result::Result<Vec<RecordBatch>, ArrowError>>()?;
+    Ok(record_batches)
+}
+
+fn read_parquet(file_path: &Path) -> Result<Vec<RecordBatch>> {
+    let file = File::open(file_path)?;
+    let mut reader = ParquetFileArrowReader::try_new(file)?;
+    let record_batches = reader
+        .get_record_reader(READ_BATCH_SIZE)?
+        .collect::<std::result::Result<Vec<RecordBatch>, ArrowError>>()?;
+    Ok(record_batches)
+}
+
+/// converts the timestamp column into the nano seconds and the numeric fields into float64.
+fn normalize_record_batch(
+    record_batch: RecordBatch,
+    setting: &ImportSetting,
+) -> Result<RecordBatch> {
+    let schema = record_batch.schema();
+    let timestamp_column_idx = schema
+        .index_of(&setting.timestamp_column)
+        .map_err(|_| ImportError::TimestampColumnNotFound(setting.timestamp_column.clone()))?;
+
+    let mut fields = Vec::<Field>::with_capacity(schema.fields().len());
+    let mut columns = Vec::<ArrayRef>::with_capacity(schema.fields().len());
+    for (idx, (field, column)) in schema
+        .fields()
+        .iter()
+        .zip(record_batch.columns().iter())
+        .enumerate()
+    {
+        if idx == timestamp_column_idx {
+            let timestamps = timestamp_nanos(column, setting.timestamp_format.as_ref())?;
+            fields.push(Field::new(field.name(), DataType::UInt64, false));
+            columns.push(Arc::new(UInt64Array::from(timestamps)));
+            continue;
+        }
+
+        match field.data_type() {
+            DataType::Float64 | DataType::Boolean => {
+                fields.push(field.clone());
+                columns.push(column.clone());
+            }
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float32 => {
+                fields.push(Field::new(
+                    field.name(),
+                    DataType::Float64,
+                    field.is_nullable(),
+                ));
+                columns.push(cast(column, &DataType::Float64)?);
+            }
+            unsupported => {
+                return Err(ImportError::UnsupportedFieldType(
+                    field.name().to_string(),
+                    format!("{unsupported:?}"),
+                ))
+            }
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+fn timestamp_nanos(
+    column: &ArrayRef,
+    timestamp_format: Option<&ImportTimestampFormat>,
+) -> Result<Vec<u64>> {
+    let nanos: Vec<i64> = match (column.data_type(), timestamp_format) {
+        (DataType::Utf8, format) => {
+            let array = column.as_any().downcast_ref::<StringArray>().unwrap();
+            let mut nanos = Vec::<i64>::with_capacity(array.len());
+            for row_idx in 0..array.len() {
+                if array.is_null(row_idx) {
+                    return Err(ImportError::InvalidTimestamp(row_idx, "null".to_string()));
+                }
+                let value = array.value(row_idx);
+                let parsed = match format {
+                    Some(format) => format.parse_str(value),
+                    // the number in nano seconds or the rfc3339
+                    None => ImportTimestampFormat::Nanos
+                        .parse_str(value)
+                        .or_else(|_| ImportTimestampFormat::Rfc3339.parse_str(value)),
+                };
+                nanos.push(parsed.map_err(|e| {
+                    ImportError::InvalidTimestamp(row_idx, format!("{value}, {e}"))
+                })?);
+            }
+            nanos
+        }
+
+        (data_type, format) => {
+            let nanos_per_unit = match (data_type, format) {
+                (_, Some(format)) => format.nanos_per_unit().ok_or_else(|| {
+                    ImportError::InvalidTimestamp(
+                        0,
+                        format!("{format:?} is not available for the column of {data_type:?}"),
+                    )
+                })?,
+                (DataType::Timestamp(TimeUnit::Second, _), None) => 1_000_000_000,
+                (DataType::Timestamp(TimeUnit::Millisecond, _), None) => 1_000_000,
+                (DataType::Timestamp(TimeUnit::Microsecond, _), None) => 1_000,
+                (DataType::Date64, None) => 1_000_000,
+                (DataType::Date32, None) => 24 * 60 * 60 * 1_000_000_000,
+                (_, None) => 1,
+            };
+
+            let values = cast(column, &DataType::Int64)?;
+            let array = values.as_any().downcast_ref::<Int64Array>().unwrap();
+            let mut nanos = Vec::<i64>::with_capacity(array.len());
+            for row_idx in 0..array.len() {
+                if array.is_null(row_idx) {
+                    return Err(ImportError::InvalidTimestamp(row_idx, "null".to_string()));
+                }
+                let value = array.value(row_idx);
+                let nano = value.checked_mul(nanos_per_unit).ok_or_else(|| {
+                    ImportError::InvalidTimestamp(row_idx, format!("timestamp overflow: {value}"))
+                })?;
+                nanos.push(nano);
+            }
+            nanos
+        }
+    };
+
+    nanos
+        .into_iter()
+        .enumerate()
+        .map(|(row_idx, nano)| {
+            if nano < 0 {
+                Err(ImportError::InvalidTimestamp(
+                    row_idx,
+                    format!("negative timestamp {nano}"),
+                ))
+            } else {
+                Ok(nano as u64)
+            }
+        })
+        .collect()
+}
+
+/// splits the sorted datapoints into the blocks that have at most `block_size` datapoints.
+/// the datapoints in the same second are never split since the blocks are indexed by seconds.
+pub fn split_into_blocks(datapoints: &[DataPoint], block_size: usize) -> Vec<&[DataPoint]> {
+    let mut blocks = Vec::<&[DataPoint]>::new();
+    let mut block_start = 0;
+    for idx in 1..datapoints.len() {
+        let is_new_second = datapoints[idx - 1].timestamp_nano.in_seconds()
+            != datapoints[idx].timestamp_nano.in_seconds();
+        if idx - block_start >= block_size && is_new_second {
+            blocks.push(&datapoints[block_start..idx]);
+            block_start = idx;
+        }
+    }
+    if block_start < datapoints.len() {
+        blocks.push(&datapoints[block_start..]);
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::DatapointsSearchCondition;
+    use crate::tsdb::{DBConfig, Engine, FieldValue, TimestampNano};
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn datapoint(timestamp: u64) -> DataPoint {
+        DataPoint::new(
+            TimestampNano::new(timestamp),
+            vec![FieldValue::Float64(1f64)],
+        )
+    }
+
+    #[test]
+    fn split_into_blocks_test() {
+        let datapoints = vec![
+            datapoint(1_000000000),
+            datapoint(1_500000000),
+            datapoint(2_000000000),
+            datapoint(3_000000000),
+            datapoint(3_100000000),
+            datapoint(3_200000000),
+            datapoint(4_000000000),
+        ];
+
+        let blocks = split_into_blocks(&datapoints, 2);
+        assert_eq!(
+            blocks,
+            vec![
+                &datapoints[0..2],
+                // the datapoints in the 3rd second are kept in a block
+                &datapoints[2..6],
+                &datapoints[6..7],
+            ]
+        );
+
+        let blocks = split_into_blocks(&datapoints, 100);
+        assert_eq!(blocks, vec![&datapoints[..]]);
+        assert!(split_into_blocks(&[], 100).is_empty());
+    }
+
+    #[test]
+    fn parse_timestamp_format_test() {
+        assert_eq!(
+            ImportTimestampFormat::Millis.parse_str("1640995200000"),
+            Ok(1640995200_000000000)
+        );
+        assert_eq!(
+            ImportTimestampFormat::Rfc3339.parse_str("2022-01-01T09:00:00+09:00"),
+            Ok(1640995200_000000000)
+        );
+        assert_eq!(
+            "%Y-%m-%d %H:%M:%S"
+                .parse::<ImportTimestampFormat>()
+                .unwrap()
+                .parse_str("2022-01-01 00:00:01"),
+            Ok(1640995201_000000000)
+        );
+        assert_eq!(
+            ImportTimestampFormat::Strftime("%Y/%m/%d".to_string()).parse_str("2022/01/01"),
+            Ok(1640995200_000000000)
+        );
+        assert!(ImportTimestampFormat::Secs.parse_str("a").is_err());
+    }
+
+    #[tokio::test]
+    async fn import_csv_test() {
+        let temp_dir = TempDir::new("import_csv_test").unwrap();
+        let csv_path = temp_dir.path().join("trades.csv");
+        {
+            let mut csv_file = File::create(&csv_path).unwrap();
+            writeln!(csv_file, "price,timestamp,is_buy,amount").unwrap();
+            writeln!(csv_file, "1.5,2022-01-01 00:00:02,true,3").unwrap();
+            writeln!(csv_file, "2.5,2022-01-01 00:00:00,false,4").unwrap();
+            writeln!(csv_file, "3.5,2022-01-01 00:00:01,true,5").unwrap();
+        }
+
+        let db_dir = temp_dir.path().join("db");
+        let metrics = Metrics::new("trades").unwrap();
+        let setting =
+            ImportSetting::new(ImportFileFormat::from_path(&csv_path).unwrap(), "timestamp")
+                .with_timestamp_format(Some(ImportTimestampFormat::Strftime(
+                    "%Y-%m-%d %H:%M:%S".to_string(),
+                )))
+                .with_block_size(2);
+
+        let summary = import_file(&db_dir, &metrics, &csv_path, &setting, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                field_names: vec![
+                    "price".to_string(),
+                    "is_buy".to_string(),
+                    "amount".to_string()
+                ],
+                field_types: vec![FieldType::Float64, FieldType::Bool, FieldType::Float64],
+                datapoint_num: 3,
+                block_num: 2,
+            }
+        );
+
+        let searched = Engine::search(
+            "test_db",
+            &db_dir,
+            &metrics,
+            None,
+            &DatapointsSearchCondition::all(),
+            &DBConfig::builder_with_no_cache().build(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            searched.into_datapoints().unwrap(),
+            vec![
+                DataPoint::new(
+                    TimestampNano::new(1640995200_000000000),
+                    vec![
+                        FieldValue::Float64(2.5),
+                        FieldValue::Bool(false),
+                        FieldValue::Float64(4f64)
+                    ]
+                ),
+                DataPoint::new(
+                    TimestampNano::new(1640995201_000000000),
+                    vec![
+                        FieldValue::Float64(3.5),
+                        FieldValue::Bool(true),
+                        FieldValue::Float64(5f64)
+                    ]
+                ),
+                DataPoint::new(
+                    TimestampNano::new(1640995202_000000000),
+                    vec![
+                        FieldValue::Float64(1.5),
+                        FieldValue::Bool(true),
+                        FieldValue::Float64(3f64)
+                    ]
+                ),
+            ]
+        );
+    }
+}
@@ -1,15 +1,26 @@
 pub mod context;
+pub mod export;
+pub mod import;
 use crate::tsdb::cloudstorage::CloudStorage;
-use crate::tsdb::data_types::TimeSeriesDataFrame;
+use crate::tsdb::data_types::{DuplicatePolicy, TimeSeriesDataFrame};
 use crate::tsdb::field::FieldType;
 use crate::tsdb::metrics::Metrics;
-use crate::tsdb::storage::wal::{SingleFileWal, WalError, WalWriter};
+use crate::tsdb::storage::wal::{SegmentedWal, WalError, WalWriter};
 use crate::tsdb::store::writable_store::DatapointDefaultSorter;
-use crate::tsdb::{datapoint::DatapointsSearchCondition, storage::*, store::*};
+use crate::tsdb::timestamp_nano::TimestampNano;
+use crate::tsdb::{
+    datapoint::{DataPoint, DatapointsRange, DatapointsSearchCondition},
+    storage::*,
+    store::*,
+};
 use crate::tsdb::{storage::api as storage_api, store};
 pub use context::*;
+pub use export::*;
+pub use import::*;
 use std::path::Path;
+use std::time::Duration;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum EngineError {
@@ -24,6 +35,15 @@ pub enum EngineError {
 
     #[error("wal error {0}")]
     WalError(#[from] WalError),
+
+    #[error("import error {0}")]
+    ImportError(#[from] ImportError),
+
+    #[error("export error {0}")]
+    ExportError(#[from] ExportError),
+
+    #[error("no datapoints to write")]
+    NoDatapoints,
 }
 
 pub type Result<T> = std::result::Result<T, EngineError>;
@@ -32,6 +52,8 @@ pub struct DBConfig {
     pub cache_setting: api::CacheSetting,
     pub cloud_storage: Option<CloudStorage>,
     pub cloud_setting: api::CloudStorageSetting,
+    pub search_budget: api::SearchBudget,
+    pub duplicate_policy: DuplicatePolicy,
 }
 
 impl DBConfig {
@@ -55,6 +77,8 @@ impl DBConfig {
             cache_setting,
             cloud_storage: None,
             cloud_setting: CloudStorageSetting::default(),
+            search_budget: api::SearchBudget::unlimited(),
+            duplicate_policy: DuplicatePolicy::default(),
         }
     }
 
@@ -75,6 +99,8 @@ impl Default for DBConfig {
 
             cloud_storage: None,
             cloud_setting: api::CloudStorageSetting::default(),
+            search_budget: api::SearchBudget::unlimited(),
+            duplicate_policy: DuplicatePolicy::default(),
         }
     }
 }
@@ -83,6 +109,8 @@ pub struct SearchSettingsBuilder {
     cache_setting: api::CacheSetting,
     cloud_storage: Option<CloudStorage>,
     cloud_setting: api::CloudStorageSetting,
+    search_budget: api::SearchBudget,
+    duplicate_policy: DuplicatePolicy,
 }
 
 impl SearchSettingsBuilder {
@@ -99,11 +127,23 @@ impl SearchSettingsBuilder {
         self
     }
 
+    pub fn search_budget(mut self, search_budget: api::SearchBudget) -> SearchSettingsBuilder {
+        self.search_budget = search_budget;
+        self
+    }
+
+    pub fn duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> SearchSettingsBuilder {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
     pub fn build(self) -> DBConfig {
         DBConfig {
             cache_setting: self.cache_setting,
             cloud_storage: self.cloud_storage,
             cloud_setting: self.cloud_setting,
+            search_budget: self.search_budget,
+            duplicate_policy: self.duplicate_policy,
         }
     }
 }
@@ -130,6 +170,146 @@ impl Engine {
         Ok(())
     }
 
+    pub async fn import<P: AsRef<Path>>(
+        db_dir: P,
+        metrics: &Metrics,
+        file_path: &Path,
+        setting: &ImportSetting,
+        cloud_storage: Option<&CloudStorage>,
+    ) -> Result<ImportSummary> {
+        let cloud_setting = api::CloudStorageSetting::default();
+        let summary = import::import_file(
+            db_dir,
+            metrics,
+            file_path,
+            setting,
+            cloud_storage.map(|cloud_storage| (cloud_storage, &cloud_setting)),
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    pub async fn export<P: AsRef<Path>>(
+        database_name: &str,
+        db_dir: P,
+        metrics: &Metrics,
+        output_dir: &Path,
+        setting: &ExportSetting,
+        config: &DBConfig,
+    ) -> Result<ExportSummary> {
+        let summary = export::export_metrics(
+            database_name,
+            db_dir,
+            metrics,
+            output_dir,
+            setting,
+            &config.cache_setting,
+            config.cloud_storage_and_setting(),
+            &config.duplicate_policy,
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    pub async fn compact<P: AsRef<Path>>(
+        db_dir: P,
+        metrics: &Metrics,
+        setting: &storage_api::CompactionSetting,
+        cloud_storage: Option<&CloudStorage>,
+    ) -> Result<storage_api::CompactionSummary> {
+        let cloud_setting = api::CloudStorageSetting::default();
+        let summary = storage_api::compact_blocks(
+            db_dir,
+            &Uuid::new_v4(),
+            metrics,
+            setting,
+            cloud_storage.map(|cloud_storage| (cloud_storage, &cloud_setting)),
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    /// drops the blocks older than the retention.
+    pub async fn expire<P: AsRef<Path>>(
+        db_dir: P,
+        metrics: &Metrics,
+        retention: Duration,
+        cloud_storage: Option<&CloudStorage>,
+    ) -> Result<storage_api::RetentionSummary> {
+        let cloud_setting = api::CloudStorageSetting::default();
+        let summary = storage_api::expire_blocks(
+            db_dir,
+            &Uuid::new_v4(),
+            metrics,
+            retention,
+            TimestampNano::now(),
+            cloud_storage.map(|cloud_storage| (cloud_storage, &cloud_setting)),
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    /// uploads the blocks and the block lists that failed to be uploaded again.
+    /// retried with the backoff until all of them succeed or the attempts run out.
+    pub async fn retry_uploads<P: AsRef<Path>>(
+        db_dir: P,
+        cloud_storage: &CloudStorage,
+        backoff: &storage_api::UploadRetryBackoff,
+        max_attempts: u32,
+    ) -> Result<storage_api::UploadRetrySummary> {
+        let summary = storage_api::retry_failed_uploads_with_backoff(
+            db_dir,
+            &Uuid::new_v4(),
+            cloud_storage,
+            backoff,
+            max_attempts,
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    /// deletes the persisted datapoints in the range.
+    pub async fn delete<P: AsRef<Path>>(
+        db_dir: P,
+        metrics: &Metrics,
+        range: &DatapointsRange,
+        config: &DBConfig,
+    ) -> Result<storage_api::DeletionSummary> {
+        let summary = storage_api::delete_datapoints(
+            db_dir,
+            &Uuid::new_v4(),
+            metrics,
+            range,
+            config.cloud_storage_and_setting(),
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    /// writes the datapoints as a new block even if some blocks already exist in the time range.
+    /// returns the blocks that the new block overlaps.
+    pub async fn write_late_datas<P: AsRef<Path>>(
+        db_dir: P,
+        metrics: &Metrics,
+        mut datapoints: Vec<DataPoint>,
+        config: &DBConfig,
+    ) -> Result<storage_api::write::LateDatasWriteReport> {
+        if datapoints.is_empty() {
+            return Err(EngineError::NoDatapoints);
+        }
+        datapoints.sort_by_key(|datapoint| datapoint.timestamp_nano);
+
+        let report = storage_api::write::write_late_datas(
+            db_dir,
+            &Uuid::new_v4(),
+            metrics,
+            &datapoints,
+            config.cloud_storage_and_setting(),
+        )
+        .await?;
+        Ok(report)
+    }
+
     pub async fn block_list_data<P: AsRef<Path>>(
         database_name: &str,
         db_dir: P,
@@ -152,8 +332,8 @@ impl Engine {
         db_dir: P,
         metrics: Metrics,
         field_types: Vec<FieldType>,
-    ) -> Result<WritableStoreBuilder<DatapointDefaultSorter, SingleFileWal>> {
-        let wal = SingleFileWal::open_or_create(db_dir.as_ref(), &metrics)?;
+    ) -> Result<WritableStoreBuilder<DatapointDefaultSorter, SegmentedWal>> {
+        let wal = SegmentedWal::open_or_create(db_dir.as_ref(), &metrics)?;
         Ok(WritableStore::builder(metrics, field_types, wal))
     }
 
@@ -173,7 +353,7 @@ impl Engine {
         condition: &DatapointsSearchCondition,
         db_config: &DBConfig,
     ) -> Result<Option<TimeSeriesDataFrame>> {
-        let dataframe = api::read::search_dataframe(
+        let dataframe = api::read::search_dataframe_with_budget(
             database_name,
             db_dir,
             metrics,
@@ -181,6 +361,8 @@ impl Engine {
             condition,
             &db_config.cache_setting,
             db_config.cloud_storage_and_setting(),
+            &db_config.search_budget,
+            &db_config.duplicate_policy,
         )
         .await?;
         Ok(dataframe)
@@ -0,0 +1,188 @@
+use super::insert_metrics::opened_insert_store;
+use super::ExecuteError;
+use crate::tsdb::data_types::{DataFrame, DataSeries, SeriesValues};
+use crate::tsdb::datapoint::DatapointsRange;
+use crate::tsdb::engine::{Engine, EngineError};
+use crate::tsdb::{DBConfig, Metrics};
+
+pub async fn execute_delete_metrics(
+    db_dir: &str,
+    db_config: &DBConfig,
+    metrics: &Metrics,
+    range: &DatapointsRange,
+) -> Result<DataFrame, ExecuteError> {
+    // the datapoints that have not been persisted yet are deleted as well, after the persisted ones are deleted
+    // so that nothing is deleted from the store if the deletion fails.
+    // the persistence of the store is kept locked so that no datapoint in the range is persisted meanwhile.
+    // neither the readers nor the writers of the store are blocked by it.
+    let store = opened_insert_store(db_dir, metrics).await;
+    let persistence = match store.as_ref() {
+        Some(store) => Some(store.lock_persistence().await),
+        None => None,
+    };
+
+    let summary = Engine::delete(db_dir, metrics, range, db_config).await?;
+
+    if let (Some(store), Some(persistence)) = (store.as_ref(), persistence.as_ref()) {
+        store
+            .purge_locked(persistence, range.clone())
+            .await
+            .map_err(EngineError::from)?;
+    }
+
+    Ok(DataFrame::new(
+        vec![
+            DataSeries::new(SeriesValues::UInt64(vec![
+                summary.deleted_timestamp_num as u64,
+            ])),
+            DataSeries::new(SeriesValues::UInt64(vec![
+                summary.dropped_blocks.len() as u64
+            ])),
+            DataSeries::new(SeriesValues::UInt64(vec![
+                summary.rewritten_blocks.len() as u64
+            ])),
+        ],
+        Some(vec![
+            "deleted".to_string(),
+            "dropped_blocks".to_string(),
+            "rewritten_blocks".to_string(),
+        ]),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::DatapointsSearchCondition;
+    use crate::tsdb::field::FieldType;
+    use crate::tsdb::query::executor::insert_metrics::execute_insert_metrics;
+    use crate::tsdb::store::PersistCondition;
+    use crate::tsdb::{DataPoint, FieldValue, TimestampNano};
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn insert_then_delete_test() {
+        let temp_db_dir = TempDir::new("insert_then_delete_test").unwrap();
+        let db_dir = temp_db_dir.path().display().to_string();
+        let db_config = DBConfig::builder_with_no_cache().build();
+        let metrics = Metrics::new("test_metrics").unwrap();
+
+        let datapoints = (0..3)
+            .map(|sec| {
+                DataPoint::new(
+                    TimestampNano::new((1640962800 + sec) * 1_000_000_000),
+                    vec![FieldValue::Float64(sec as f64)],
+                )
+            })
+            .collect();
+        execute_insert_metrics(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &[FieldType::Float64],
+            datapoints,
+            None,
+        )
+        .await
+        .unwrap();
+        opened_insert_store(&db_dir, &metrics)
+            .await
+            .unwrap()
+            .persist(PersistCondition::new(DatapointsRange::all(), true))
+            .await
+            .unwrap();
+
+        let range = DatapointsRange::new(
+            Some(TimestampNano::new(1640962801_000000000)),
+            Some(TimestampNano::new(1640962802_000000000)),
+        );
+        let deleted = execute_delete_metrics(&db_dir, &db_config, &metrics, &range)
+            .await
+            .unwrap();
+        assert_eq!(
+            deleted.data_serieses,
+            vec![
+                DataSeries::new(SeriesValues::UInt64(vec![1])),
+                DataSeries::new(SeriesValues::UInt64(vec![0])),
+                DataSeries::new(SeriesValues::UInt64(vec![1])),
+            ]
+        );
+
+        let searched = Engine::search(
+            "test_db",
+            &db_dir,
+            &metrics,
+            None,
+            &DatapointsSearchCondition::all(),
+            &db_config,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            searched.timestamp_nanos,
+            vec![
+                TimestampNano::new(1640962800_000000000),
+                TimestampNano::new(1640962802_000000000)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_then_reopen_test() {
+        let temp_db_dir = TempDir::new("delete_then_reopen_test").unwrap();
+        let db_dir = temp_db_dir.path().display().to_string();
+        let db_config = DBConfig::builder_with_no_cache().build();
+        let metrics = Metrics::new("test_metrics").unwrap();
+
+        // the datapoints are left in the store and the wal, not persisted yet
+        let datapoints = (0..3)
+            .map(|sec| {
+                DataPoint::new(
+                    TimestampNano::new((1640962800 + sec) * 1_000_000_000),
+                    vec![FieldValue::Float64(sec as f64)],
+                )
+            })
+            .collect();
+        execute_insert_metrics(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &[FieldType::Float64],
+            datapoints,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let range = DatapointsRange::new(
+            Some(TimestampNano::new(1640962801_000000000)),
+            Some(TimestampNano::new(1640962802_000000000)),
+        );
+        execute_delete_metrics(&db_dir, &db_config, &metrics, &range)
+            .await
+            .unwrap();
+
+        // the deleted datapoint is not replayed from the wal
+        let reopened = Engine::writable_store_builder(&db_dir, metrics, vec![FieldType::Float64])
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mut reopened = reopened.lock().await;
+        let timestamps: Vec<TimestampNano> = reopened
+            .datapoints()
+            .await
+            .unwrap()
+            .iter()
+            .map(|datapoint| datapoint.timestamp_nano)
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![
+                TimestampNano::new(1640962800_000000000),
+                TimestampNano::new(1640962802_000000000)
+            ]
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use super::ExecuteError;
+use crate::tsdb::data_types::{DataFrame, DataSeries, SeriesValues};
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
+use crate::tsdb::engine::{Engine, EngineError};
+use crate::tsdb::field::{join_field_types, FieldType};
+use crate::tsdb::storage::wal::SegmentedWal;
+use crate::tsdb::store::{
+    DatapointDefaultSorter, PeriodicallyPeristenceShutdown, Persistence, PersistenceTrigger,
+    ShardedWritableStore,
+};
+use crate::tsdb::{DBConfig, DBContext, DataPoint, Metrics};
+use chrono::Duration;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as SyncMutex};
+use tokio::sync::{Mutex, OnceCell as AsyncOnceCell};
+
+/// the queries, the line protocol server and the DoPut handler push to the store concurrently.
+pub(crate) type InsertStore = Arc<ShardedWritableStore<DatapointDefaultSorter, SegmentedWal>>;
+
+/// the store opened by the insert queries. it is persisted by its persistence task as long as the process runs.
+struct InsertStoreEntry {
+    store: InsertStore,
+    field_types: Vec<FieldType>,
+    /// the field keys of the line protocol. they are recorded on the first write of the line protocol.
+    field_keys: SyncMutex<Option<Vec<String>>>,
+    duplicate_policy: DuplicatePolicy,
+    _persistence: PeriodicallyPeristenceShutdown,
+}
+
+type InsertStoreCell = Arc<AsyncOnceCell<InsertStoreEntry>>;
+
+/// the stores are shared among the queries so that a wal file is written by only one store in the process.
+/// the map is locked only to get the cell of the store, and the store is opened in the cell out of the lock.
+static INSERT_STORES: OnceCell<Mutex<HashMap<(PathBuf, Metrics), InsertStoreCell>>> =
+    OnceCell::new();
+
+/// the datapoints inserted are persisted when any of these fires.
+fn insert_persistence_trigger() -> PersistenceTrigger {
+    PersistenceTrigger::default()
+        .with_interval(Duration::seconds(10))
+        .with_max_datapoints(100_000)
+}
+
+fn insert_store_shard_num() -> usize {
+    std::thread::available_parallelism()
+        .map(|parallelism| parallelism.get())
+        .unwrap_or(1)
+}
+
+/// local db dir and the config of the database that the datapoints are written to.
+pub(crate) fn insert_db_dir_and_config(
+    ctx: &DBContext,
+    database_name: Option<&str>,
+) -> Result<(String, DBConfig), ExecuteError> {
+    let database = match ctx.get_database(database_name) {
+        Ok(Some(database)) => database,
+        Ok(None) => {
+            return Err(ExecuteError::NoDatabaseFound(
+                "no database definitions".to_string(),
+            ))
+        }
+        Err(e) => return Err(ExecuteError::NoDatabaseFound(format!("{e}"))),
+    };
+    let db_dir = database
+        .as_local_db_dir(&ctx.data_dir)
+        .display()
+        .to_string();
+    let db_config = DBConfig {
+        cloud_storage: database.cloud_storage.clone(),
+        ..DBConfig::default()
+    };
+    Ok((db_dir, db_config))
+}
+
+/// returns the store of the metrics if it has been opened by the insert queries.
+pub(crate) async fn opened_insert_store(db_dir: &str, metrics: &Metrics) -> Option<InsertStore> {
+    let cell = INSERT_STORES
+        .get()?
+        .lock()
+        .await
+        .get(&(PathBuf::from(db_dir), metrics.clone()))
+        .cloned()?;
+    cell.get().map(|entry| entry.store.clone())
+}
+
+/// returns the store of the metrics that is shared in the process.
+/// the field types must be the same as the ones of the store that is already opened.
+/// the store is opened with the duplicate policy of the db config, and `duplicate_policy` must be
+/// the same as the one of the store already opened if given.
+/// `field_keys` must be the same as the ones given first if given as well.
+pub(crate) async fn insert_store(
+    db_dir: &str,
+    db_config: &DBConfig,
+    metrics: &Metrics,
+    field_types: &[FieldType],
+    field_keys: Option<&[String]>,
+    duplicate_policy: Option<DuplicatePolicy>,
+) -> Result<InsertStore, ExecuteError> {
+    // the block files can hold only these types
+    if let Some(unsupported) = field_types
+        .iter()
+        .find(|field_type| !matches!(field_type, FieldType::Float64 | FieldType::Bool))
+    {
+        return Err(ExecuteError::UnsupportedInsertFieldType(
+            unsupported.to_string(),
+        ));
+    }
+
+    let cell = INSERT_STORES
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .await
+        .entry((PathBuf::from(db_dir), metrics.clone()))
+        .or_default()
+        .clone();
+    let entry = cell
+        .get_or_try_init(|| open_insert_store(db_dir, db_config, metrics, field_types))
+        .await?;
+    if entry.field_types != field_types {
+        return Err(ExecuteError::InsertFieldTypesMismatched(
+            join_field_types(&entry.field_types),
+            join_field_types(field_types),
+        ));
+    }
+    if let Some(duplicate_policy) = duplicate_policy {
+        if entry.duplicate_policy != duplicate_policy {
+            return Err(ExecuteError::InsertDuplicatePolicyMismatched(
+                entry.duplicate_policy.to_string(),
+                duplicate_policy.to_string(),
+            ));
+        }
+    }
+    if let Some(field_keys) = field_keys {
+        let mut entry_field_keys = entry.field_keys.lock().unwrap();
+        match entry_field_keys.as_deref() {
+            Some(entry_field_keys) if entry_field_keys != field_keys => {
+                return Err(ExecuteError::InsertFieldKeysMismatched(
+                    entry_field_keys.join(","),
+                    field_keys.join(","),
+                ));
+            }
+            Some(_) => {}
+            None => *entry_field_keys = Some(field_keys.to_vec()),
+        }
+    }
+    Ok(entry.store.clone())
+}
+
+/// opens the store replaying the wal, and starts the persistence task of it.
+async fn open_insert_store(
+    db_dir: &str,
+    db_config: &DBConfig,
+    metrics: &Metrics,
+    field_types: &[FieldType],
+) -> Result<InsertStoreEntry, ExecuteError> {
+    let persistence = Persistence::Storage(
+        PathBuf::from(db_dir),
+        db_config
+            .cloud_storage
+            .clone()
+            .map(|cloud_storage| (cloud_storage, db_config.cloud_setting.clone())),
+    );
+    let store = Engine::writable_store_builder(db_dir, metrics.clone(), field_types.to_vec())?
+        .persistence(persistence)
+        .duplicate_policy(db_config.duplicate_policy)
+        .build_sharded(insert_store_shard_num())
+        .await
+        .map_err(EngineError::from)?;
+    let persistence = ShardedWritableStore::start_triggered_persist(
+        store.clone(),
+        insert_persistence_trigger(),
+        true,
+    );
+    Ok(InsertStoreEntry {
+        store,
+        field_types: field_types.to_vec(),
+        field_keys: SyncMutex::new(None),
+        duplicate_policy: db_config.duplicate_policy,
+        _persistence: persistence,
+    })
+}
+
+/// the datapoints are written into the wal of the shared store, then persisted by the persistence task of it.
+/// so they can be searched after the next persistence.
+/// `duplicate_policy` is the one given by the query, see `insert_store`.
+pub async fn execute_insert_metrics(
+    db_dir: &str,
+    db_config: &DBConfig,
+    metrics: &Metrics,
+    field_types: &[FieldType],
+    datapoints: Vec<DataPoint>,
+    duplicate_policy: Option<DuplicatePolicy>,
+) -> Result<DataFrame, ExecuteError> {
+    let store = insert_store(
+        db_dir,
+        db_config,
+        metrics,
+        field_types,
+        None,
+        duplicate_policy,
+    )
+    .await?;
+
+    let inserted_num = datapoints.len();
+    store
+        .push_multi(datapoints)
+        .await
+        .map_err(EngineError::from)?;
+
+    Ok(DataFrame::new(
+        vec![DataSeries::new(SeriesValues::UInt64(vec![
+            inserted_num as u64,
+        ]))],
+        Some(vec!["inserted".to_string()]),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::{DatapointsRange, DatapointsSearchCondition};
+    use crate::tsdb::store::PersistCondition;
+    use crate::tsdb::{FieldValue, TimestampNano};
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn insert_then_search_test() {
+        let temp_db_dir = TempDir::new("insert_then_search_test").unwrap();
+        let db_dir = temp_db_dir.path().display().to_string();
+        let db_config = DBConfig::builder_with_no_cache().build();
+        let metrics = Metrics::new("test_metrics").unwrap();
+        let field_types = vec![FieldType::Float64, FieldType::Bool];
+
+        let datapoints = vec![
+            DataPoint::new(
+                TimestampNano::new(1640962801_000000000),
+                vec![FieldValue::Float64(2f64), FieldValue::Bool(false)],
+            ),
+            DataPoint::new(
+                TimestampNano::new(1640962800_000000000),
+                vec![FieldValue::Float64(1.5), FieldValue::Bool(true)],
+            ),
+        ];
+
+        let inserted = execute_insert_metrics(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &field_types,
+            datapoints,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            inserted.data_serieses,
+            vec![DataSeries::new(SeriesValues::UInt64(vec![2]))]
+        );
+
+        opened_insert_store(&db_dir, &metrics)
+            .await
+            .unwrap()
+            .persist(PersistCondition::new(DatapointsRange::all(), true))
+            .await
+            .unwrap();
+
+        let searched = Engine::search(
+            "test_db",
+            &db_dir,
+            &metrics,
+            None,
+            &DatapointsSearchCondition::all(),
+            &db_config,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            searched.timestamp_nanos,
+            vec![
+                TimestampNano::new(1640962800_000000000),
+                TimestampNano::new(1640962801_000000000)
+            ]
+        );
+
+        // the field types must be the same as the first insertion
+        let result = execute_insert_metrics(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &[FieldType::Float64],
+            vec![DataPoint::new(
+                TimestampNano::new(1640962802_000000000),
+                vec![FieldValue::Float64(3f64)],
+            )],
+            None,
+        )
+        .await;
+        assert!(result.is_err());
+
+        // so must be the duplicate policy if given
+        let result = execute_insert_metrics(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &field_types,
+            vec![DataPoint::new(
+                TimestampNano::new(1640962802_000000000),
+                vec![FieldValue::Float64(3f64), FieldValue::Bool(true)],
+            )],
+            Some(DuplicatePolicy::KeepLast),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ExecuteError::InsertDuplicatePolicyMismatched(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn insert_store_opened_once_test() {
+        let temp_db_dir = TempDir::new("insert_store_opened_once_test").unwrap();
+        let db_dir = temp_db_dir.path().display().to_string();
+        let db_config = DBConfig::builder_with_no_cache().build();
+        let metrics = Metrics::new("test_metrics").unwrap();
+        let field_types = vec![FieldType::Float64];
+
+        assert!(opened_insert_store(&db_dir, &metrics).await.is_none());
+        let (store_1, store_2) = tokio::join!(
+            insert_store(&db_dir, &db_config, &metrics, &field_types, None, None),
+            insert_store(&db_dir, &db_config, &metrics, &field_types, None, None),
+        );
+        let (store_1, store_2) = (store_1.unwrap(), store_2.unwrap());
+        assert!(Arc::ptr_eq(&store_1, &store_2));
+        assert!(Arc::ptr_eq(
+            &store_1,
+            &opened_insert_store(&db_dir, &metrics).await.unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn insert_store_field_keys_test() {
+        let temp_db_dir = TempDir::new("insert_store_field_keys_test").unwrap();
+        let db_dir = temp_db_dir.path().display().to_string();
+        let db_config = DBConfig::builder_with_no_cache().build();
+        let metrics = Metrics::new("cpu").unwrap();
+        let field_types = vec![FieldType::Float64];
+        let temp = vec!["temp".to_string()];
+        let load = vec!["load".to_string()];
+
+        // the store opened by the insert query has no field keys until the line protocol is written
+        insert_store(&db_dir, &db_config, &metrics, &field_types, None, None)
+            .await
+            .unwrap();
+        insert_store(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &field_types,
+            Some(&temp),
+            None,
+        )
+        .await
+        .unwrap();
+        insert_store(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &field_types,
+            Some(&temp),
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            insert_store(
+                &db_dir,
+                &db_config,
+                &metrics,
+                &field_types,
+                Some(&load),
+                None
+            )
+            .await,
+            Err(ExecuteError::InsertFieldKeysMismatched(_, _))
+        ));
+    }
+}
@@ -4,12 +4,15 @@ use async_stream::stream;
 use serde_json;
 use tonic::{Request, Response, Status};
 
-use crate::execute_query;
+use crate::{execute_query_with_timeout, ExecuteError};
+use std::time::Duration;
 
 use arrow_flight::{utils::flight_data_from_arrow_batch, FlightData, SchemaAsIpc, Ticket};
 
 use arrow::ipc::writer::IpcWriteOptions;
 
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
 //pub type DoGetStream =
 //    Pin<Box<dyn Stream<Item = Result<FlightData, Status>> + Send + Sync + 'static>>;
 //
@@ -17,11 +20,23 @@ pub async fn handle(
     ctx: &DBContext,
     request: Request<Ticket>,
 ) -> Result<Response<DoGetStream>, Status> {
+    // the deadline of the client.
+    // if the client cancels the request, this future will be dropped and the query will be aborted.
+    let client_timeout = request
+        .metadata()
+        .get(GRPC_TIMEOUT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_grpc_timeout);
+
     let ticket = request.into_inner();
 
     let query = String::from_utf8(ticket.ticket).unwrap();
-    let result = execute_query(ctx, &query).await;
+    let result = execute_query_with_timeout(ctx, &query, client_timeout).await;
     match result {
+        Err(e) if e.is_deadline_exceeded() => Err(Status::deadline_exceeded(format!("{e}"))),
+        Err(e @ ExecuteError::MemoryBudgetExceeded { .. }) => {
+            Err(Status::resource_exhausted(format!("{e}")))
+        }
         Err(e) => Err(Status::invalid_argument(format!("invalid argument :{e}"))),
 
         Ok(result) => {
@@ -51,3 +66,36 @@ pub async fn handle(
         }
     }
 }
+
+/// parse the value of `grpc-timeout` header. e.g. "30S", "100m"
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.is_empty() {
+        return None;
+    }
+    let (num, unit) = value.split_at(value.len() - 1);
+    let num = num.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(num * 60 * 60)),
+        "M" => Some(Duration::from_secs(num * 60)),
+        "S" => Some(Duration::from_secs(num)),
+        "m" => Some(Duration::from_millis(num)),
+        "u" => Some(Duration::from_micros(num)),
+        "n" => Some(Duration::from_nanos(num)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_grpc_timeout_test() {
+        assert_eq!(parse_grpc_timeout("30S"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("10x"), None);
+        assert_eq!(parse_grpc_timeout("S"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+    }
+}
@@ -0,0 +1,127 @@
+use super::DoPutStream;
+use crate::tsdb::data_types::RecordBatchDatapointsConverter;
+use crate::tsdb::engine::DBContext;
+use crate::tsdb::query::executor::insert_metrics::{insert_db_dir_and_config, insert_store};
+use crate::tsdb::Metrics;
+use crate::ExecuteError;
+use async_stream::try_stream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::{Request, Response, Status, Streaming};
+
+use arrow::datatypes::Schema;
+use arrow_flight::{utils::flight_data_to_arrow_batch, FlightData, PutResult};
+
+/// the column that will be the timestamp of the datapoints. the other columns are the fields.
+pub const DO_PUT_TIMESTAMP_COLUMN: &str = "ts";
+
+/// `app_metadata` of the `PutResult` that acknowledges each record batch.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DoPutAck {
+    pub batch_idx: usize,
+    pub inserted: usize,
+}
+
+/// The first flight data must have the descriptor whose path is `[metrics]` or `[database, metrics]`
+/// and the schema of the record batches. The following ones are the record batches.
+/// Each record batch is written into the wal of the metrics and acknowledged by a `PutResult`,
+/// then the datapoints are persisted by the persistence task of the shared store, not waiting for the end of the stream.
+pub async fn handle(
+    ctx: &DBContext,
+    request: Request<Streaming<FlightData>>,
+) -> Result<Response<DoPutStream>, Status> {
+    let mut flight_data_stream = request.into_inner();
+    let schema_data = flight_data_stream
+        .message()
+        .await?
+        .ok_or_else(|| Status::invalid_argument("no flight data"))?;
+
+    let descriptor = schema_data
+        .flight_descriptor
+        .as_ref()
+        .ok_or_else(|| Status::invalid_argument("flight descriptor is required"))?;
+    let (database_name, metrics) = database_and_metrics_from_path(&descriptor.path)?;
+    let metrics = Metrics::new(metrics)
+        .map_err(|e| Status::invalid_argument(format!("invalid metrics :{e}")))?;
+
+    let (db_dir, db_config) = insert_db_dir_and_config(ctx, database_name).map_err(to_status)?;
+
+    let schema = Schema::try_from(&schema_data)
+        .map_err(|e| Status::invalid_argument(format!("invalid schema :{e}")))?;
+    let converter = RecordBatchDatapointsConverter::new(&schema, DO_PUT_TIMESTAMP_COLUMN)
+        .map_err(|e| Status::invalid_argument(format!("{e}")))?;
+    let schema = Arc::new(schema);
+
+    let store = insert_store(
+        &db_dir,
+        &db_config,
+        &metrics,
+        converter.field_types(),
+        None,
+        None,
+    )
+    .await
+    .map_err(to_status)?;
+
+    Ok(Response::new(Box::pin(try_stream! {
+        let mut batch_idx = 0;
+        while let Some(flight_data) = flight_data_stream.message().await? {
+            let record_batch =
+                flight_data_to_arrow_batch(&flight_data, schema.clone(), &HashMap::new())
+                    .map_err(|e| Status::invalid_argument(format!("invalid record batch :{e}")))?;
+            let datapoints = converter
+                .to_datapoints(&record_batch)
+                .map_err(|e| Status::invalid_argument(format!("{e}")))?;
+
+            let inserted = datapoints.len();
+            store
+                .push_multi(datapoints)
+                .await
+                .map_err(|e| Status::internal(format!("failed to write datapoints :{e}")))?;
+
+            let app_metadata = serde_json::to_vec(&DoPutAck { batch_idx, inserted })
+                .map_err(|e| Status::internal(format!("{e}")))?;
+            yield PutResult { app_metadata };
+            batch_idx += 1;
+        }
+    })))
+}
+
+fn database_and_metrics_from_path(path: &[String]) -> Result<(Option<&str>, &str), Status> {
+    match path {
+        [metrics] => Ok((None, metrics.as_str())),
+        [database, metrics] => Ok((Some(database.as_str()), metrics.as_str())),
+        _ => Err(Status::invalid_argument(format!(
+            "the path of the descriptor must be [metrics] or [database, metrics]: {path:?}"
+        ))),
+    }
+}
+
+fn to_status(e: ExecuteError) -> Status {
+    match e {
+        e @ (ExecuteError::InsertFieldTypesMismatched(_, _)
+        | ExecuteError::UnsupportedInsertFieldType(_)) => Status::invalid_argument(format!("{e}")),
+        e @ ExecuteError::NoDatabaseFound(_) => Status::not_found(format!("{e}")),
+        e => Status::internal(format!("{e}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn database_and_metrics_from_path_test() {
+        assert_eq!(
+            database_and_metrics_from_path(&["trades".to_string()]).unwrap(),
+            (None, "trades")
+        );
+        assert_eq!(
+            database_and_metrics_from_path(&["exchange".to_string(), "trades".to_string()])
+                .unwrap(),
+            (Some("exchange"), "trades")
+        );
+        assert!(database_and_metrics_from_path(&[]).is_err());
+    }
+}
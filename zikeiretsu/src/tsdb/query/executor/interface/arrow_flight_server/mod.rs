@@ -1,4 +1,7 @@
 mod do_get_handler;
+mod do_put_handler;
+
+pub use do_put_handler::{DoPutAck, DO_PUT_TIMESTAMP_COLUMN};
 
 use crate::tsdb::engine::DBContext;
 use arrow_flight::{
@@ -15,12 +18,13 @@ use tonic::{Request, Response, Status, Streaming};
 #[derive(Clone)]
 pub struct FlightZikeiretsuService(pub DBContext);
 pub type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+pub type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
 #[tonic::async_trait]
 impl FlightService for FlightZikeiretsuService {
     type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
     type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
     type DoGetStream = DoGetStream;
-    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoPutStream = DoPutStream;
     type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
     type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
     type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
@@ -62,9 +66,9 @@ impl FlightService for FlightZikeiretsuService {
 
     async fn do_put(
         &self,
-        _request: Request<Streaming<FlightData>>,
+        request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        Err(Status::unimplemented("Not yet implemented"))
+        do_put_handler::handle(&self.0, request).await
     }
 
     async fn do_action(
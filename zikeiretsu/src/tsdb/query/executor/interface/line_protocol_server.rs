@@ -0,0 +1,313 @@
+use crate::tsdb::engine::DBContext;
+use crate::tsdb::field::join_field_types;
+use crate::tsdb::query::executor::insert_metrics::{insert_db_dir_and_config, insert_store};
+use crate::tsdb::store::{parse_lines, LineProtocolError, StoreError};
+use crate::tsdb::{DataPoint, FieldType, Metrics, TimestampNano};
+use crate::ExecuteError;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// flush the lines written via tcp at least every this number of lines.
+const TCP_LINES_PER_WRITE: usize = 5000;
+
+/// upper limit of the body of the http request by default.
+const DEFAULT_MAX_BODY_BYTES: usize = 32 * 1024 * 1024;
+
+const PAYLOAD_TOO_LARGE: &str = "413 Payload Too Large";
+
+/// Serves the InfluxDB line protocol on tcp.
+/// The connection that starts with `POST /write` or `POST /api/v2/write` is handled as http,
+/// and the others are regarded as the raw lines.
+/// The http request whose body is larger than `max_body_bytes` is rejected with `413 Payload Too Large`.
+pub async fn line_protocol_server(
+    db_context: DBContext,
+    host: Option<&str>,
+    port: Option<usize>,
+    max_body_bytes: Option<usize>,
+) -> LineProtocolServeResult<()> {
+    let addr = format!("{}:{}", host.unwrap_or("0.0.0.0"), port.unwrap_or(8089));
+    let max_body_bytes = max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+    let listener = TcpListener::bind(&addr).await?;
+    let ctx = Arc::new(db_context);
+
+    println!("zikeiretsu line protocol server listening at [{}]", addr);
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(&ctx, socket, max_body_bytes).await {
+                log::error!("line protocol connection error. peer:{peer}, {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    ctx: &DBContext,
+    socket: TcpStream,
+    max_body_bytes: usize,
+) -> LineProtocolServeResult<()> {
+    let mut reader = BufReader::new(socket);
+    let mut first_line = String::new();
+    if reader.read_line(&mut first_line).await? == 0 {
+        return Ok(());
+    }
+
+    if first_line.starts_with("POST ") {
+        handle_http(ctx, reader, first_line, max_body_bytes).await
+    } else {
+        handle_tcp(ctx, reader, first_line).await
+    }
+}
+
+/// the lines are written to the default database.
+async fn handle_tcp(
+    ctx: &DBContext,
+    mut reader: BufReader<TcpStream>,
+    first_line: String,
+) -> LineProtocolServeResult<()> {
+    let mut lines = first_line;
+    let mut line_num = 1;
+    loop {
+        // write the lines when no more lines have arrived yet
+        if line_num >= TCP_LINES_PER_WRITE || reader.buffer().is_empty() {
+            if let Err(e) = write_lines(ctx, None, &lines).await {
+                log::error!("failed to write lines via tcp. {e}");
+            }
+            lines.clear();
+            line_num = 0;
+        }
+
+        if reader.read_line(&mut lines).await? == 0 {
+            break;
+        }
+        line_num += 1;
+    }
+
+    if !lines.is_empty() {
+        write_lines(ctx, None, &lines).await?;
+    }
+    Ok(())
+}
+
+async fn handle_http(
+    ctx: &DBContext,
+    mut reader: BufReader<TcpStream>,
+    first_line: String,
+    max_body_bytes: usize,
+) -> LineProtocolServeResult<()> {
+    let mut request_line = first_line;
+    loop {
+        let request = read_http_request(&mut reader, &request_line, max_body_bytes).await?;
+        let (status, body) = match request {
+            Err((status, message)) => (status, Some(message)),
+            Ok(request) => match write_lines(ctx, request.database.as_deref(), &request.body).await
+            {
+                Ok(_) => ("204 No Content", None),
+                Err(e) => (e.http_status(), Some(format!("{e}"))),
+            },
+        };
+
+        // the body too large is left unread, so the following requests can't be read on the connection
+        let close = status == PAYLOAD_TOO_LARGE;
+        let connection = if close { "Connection: close\r\n" } else { "" };
+        let response = match body {
+            None => format!("HTTP/1.1 {status}\r\n{connection}Content-Length: 0\r\n\r\n"),
+            Some(message) => {
+                let body = serde_json::json!({ "error": message }).to_string();
+                format!(
+                    "HTTP/1.1 {status}\r\n{connection}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+        };
+        reader.get_mut().write_all(response.as_bytes()).await?;
+        if close {
+            break;
+        }
+
+        request_line = String::new();
+        if reader.read_line(&mut request_line).await? == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct HttpWriteRequest {
+    database: Option<String>,
+    body: String,
+}
+
+type HttpErrorResponse = (&'static str, String);
+
+/// reads the headers and the body following the request line.
+/// the body larger than `max_body_bytes` is not read.
+async fn read_http_request(
+    reader: &mut BufReader<TcpStream>,
+    request_line: &str,
+    max_body_bytes: usize,
+) -> LineProtocolServeResult<std::result::Result<HttpWriteRequest, HttpErrorResponse>> {
+    let mut content_length: Option<usize> = None;
+    let mut unsupported_header: Option<HttpErrorResponse> = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let (name, value) = match header.split_once(':') {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim().to_lowercase()),
+            None => continue,
+        };
+        match (name.as_str(), value.as_str()) {
+            ("content-length", value) => content_length = value.parse::<usize>().ok(),
+            ("transfer-encoding", "chunked") => {
+                unsupported_header = Some((
+                    "411 Length Required",
+                    "chunked transfer encoding is not supported".to_string(),
+                ))
+            }
+            ("content-encoding", value) if value != "identity" => {
+                unsupported_header = Some((
+                    "415 Unsupported Media Type",
+                    format!("content encoding is not supported: {value}"),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let content_length = content_length.unwrap_or(0);
+    if content_length > max_body_bytes {
+        return Ok(Err((
+            PAYLOAD_TOO_LARGE,
+            format!("the body must be at most {max_body_bytes} bytes: {content_length}"),
+        )));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+
+    if let Some(unsupported_header) = unsupported_header {
+        return Ok(Err(unsupported_header));
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/write" && path != "/api/v2/write" {
+        return Ok(Err(("404 Not Found", format!("unknown path: {path}"))));
+    }
+
+    let mut database: Option<String> = None;
+    for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+        match key {
+            "db" | "bucket" => database = Some(value.to_string()),
+            "precision" if value != "ns" => {
+                return Ok(Err((
+                    "400 Bad Request",
+                    format!("only the nano seconds precision is supported: {value}"),
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    match String::from_utf8(body) {
+        Ok(body) => Ok(Ok(HttpWriteRequest { database, body })),
+        Err(_) => Ok(Err(("400 Bad Request", "body is not utf8".to_string()))),
+    }
+}
+
+/// writes the lines into the shared stores of each metrics. they are persisted by the persistence tasks of the stores.
+async fn write_lines(
+    ctx: &DBContext,
+    database_name: Option<&str>,
+    lines: &str,
+) -> LineProtocolServeResult<usize> {
+    let points = parse_lines(lines, TimestampNano::now())?;
+    if points.is_empty() {
+        return Ok(0);
+    }
+    let (db_dir, db_config) = insert_db_dir_and_config(ctx, database_name)?;
+
+    // group by the metrics keeping the order
+    let mut datapoints_of_metrics =
+        Vec::<(Metrics, Vec<String>, Vec<FieldType>, Vec<DataPoint>)>::new();
+    for point in points {
+        let field_types = point.field_types();
+        match datapoints_of_metrics
+            .iter_mut()
+            .find(|(metrics, _, _, _)| *metrics == point.metrics)
+        {
+            Some((_, metrics_field_keys, metrics_field_types, datapoints)) => {
+                if *metrics_field_keys != point.field_keys {
+                    return Err(LineProtocolError::UnmatchedFieldKeys(
+                        metrics_field_keys.join(","),
+                        point.field_keys.join(","),
+                    )
+                    .into());
+                }
+                if *metrics_field_types != field_types {
+                    return Err(LineProtocolError::UnmatchedFields(
+                        join_field_types(metrics_field_types),
+                        join_field_types(&field_types),
+                    )
+                    .into());
+                }
+                datapoints.push(point.datapoint)
+            }
+            None => datapoints_of_metrics.push((
+                point.metrics,
+                point.field_keys,
+                field_types,
+                vec![point.datapoint],
+            )),
+        }
+    }
+
+    let mut written = 0;
+    for (metrics, field_keys, field_types, datapoints) in datapoints_of_metrics {
+        let store = insert_store(
+            &db_dir,
+            &db_config,
+            &metrics,
+            &field_types,
+            Some(&field_keys),
+            None,
+        )
+        .await?;
+        written += datapoints.len();
+        store.push_multi(datapoints).await?;
+    }
+    Ok(written)
+}
+
+pub type LineProtocolServeResult<T> = std::result::Result<T, LineProtocolServeError>;
+#[derive(Error, Debug)]
+pub enum LineProtocolServeError {
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("line protocol error: {0}")]
+    LineProtocolError(#[from] LineProtocolError),
+
+    #[error("execute error: {0}")]
+    ExecuteError(#[from] ExecuteError),
+
+    #[error("store error: {0}")]
+    StoreError(#[from] StoreError),
+}
+
+impl LineProtocolServeError {
+    fn http_status(&self) -> &'static str {
+        match self {
+            Self::LineProtocolError(_)
+            | Self::ExecuteError(ExecuteError::InsertFieldTypesMismatched(_, _))
+            | Self::ExecuteError(ExecuteError::InsertFieldKeysMismatched(_, _))
+            | Self::ExecuteError(ExecuteError::UnsupportedInsertFieldType(_)) => "400 Bad Request",
+            Self::ExecuteError(ExecuteError::NoDatabaseFound(_)) => "404 Not Found",
+            _ => "500 Internal Server Error",
+        }
+    }
+}
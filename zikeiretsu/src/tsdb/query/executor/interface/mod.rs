@@ -1,5 +1,6 @@
 mod arrow_flight_client;
 mod arrow_flight_server;
+mod line_protocol_server;
 use super::{execute_query, output::*, ExecuteError};
 use crate::tsdb::engine::DBContext;
 use arrow::record_batch::*;
@@ -8,6 +9,7 @@ use thiserror::Error;
 
 pub use arrow_flight_client::*;
 pub use arrow_flight_server::*;
+pub use line_protocol_server::*;
 
 pub type Result<T> = std::result::Result<T, ExecutorInterfaceError>;
 
@@ -1,21 +1,30 @@
+pub mod delete_metrics;
 pub mod describe_metrics;
+pub mod insert_metrics;
 pub mod interface;
 pub mod metrics_list;
 pub mod output;
+mod result_cache;
 pub mod search_metrics;
 
 use crate::tsdb::data_types::{ArrowConvatibleDataFrame, ArrowConvatibleDataFrameError};
 use crate::tsdb::engine::EngineError;
-use crate::tsdb::lexer::{interpret, DatabaseName, InterpretedQuery, LexerError, OutputError};
+use crate::tsdb::lexer::{
+    interpret, DatabaseName, DeleteCondition, InsertCondition, InterpretedQuery, LexerError,
+    OutputError,
+};
 use crate::tsdb::query::parser::{parse_query, ParserError};
 use crate::tsdb::query::QuerySetting;
+use crate::tsdb::storage::api::{SearchBudget, StorageApiError};
 use crate::tsdb::{DBConfig, DBContext};
 use arrow::error::ArrowError;
 use arrow::record_batch::*;
 pub use interface::*;
 use std::io::Error as IoError;
 use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
+use tokio::time;
 
 pub use crate::describe_metrics::MetricsDescribe;
 pub use crate::tsdb::Metrics;
@@ -32,13 +41,64 @@ pub struct ExecutedData {
 }
 
 pub async fn execute_query(ctx: &DBContext, query: &str) -> Result<ExecutedData> {
+    execute_query_with_timeout(ctx, query, None).await
+}
+
+/// `timeout` is the upper limit of the execution time given by the caller. e.g. the deadline of the client request.
+/// The shorter one of it and the timeout of the query (or the default of the context) will be applied.
+/// The timeout and the search budget are applied only to the read queries, since cancelling INSERT or DELETE
+/// in the middle could leave the datapoints partially modified.
+pub async fn execute_query_with_timeout(
+    ctx: &DBContext,
+    query: &str,
+    timeout: Option<Duration>,
+) -> Result<ExecutedData> {
     let parsed_query = parse_query(query)?;
     let interpreted_query = interpret(parsed_query)?;
     log::debug!("interpreted_query :{interpreted_query:?}");
+
+    if interpreted_query.is_write() {
+        return execute_interpreted_query(ctx, interpreted_query, SearchBudget::unlimited()).await;
+    }
+
+    let query_timeout = interpreted_query
+        .query_setting()
+        .timeout
+        .or(ctx.default_query_timeout);
+    let timeout = match (query_timeout, timeout) {
+        (Some(query_timeout), Some(timeout)) => Some(query_timeout.min(timeout)),
+        (query_timeout, timeout) => query_timeout.or(timeout),
+    };
+
+    let search_budget =
+        SearchBudget::with_timeout(timeout).memory_limit(ctx.default_query_memory_limit_bytes);
+    match timeout {
+        None => execute_interpreted_query(ctx, interpreted_query, search_budget).await,
+        Some(timeout) => {
+            // dropping the future of the query on timeout releases the data that have been read.
+            match time::timeout(
+                timeout,
+                execute_interpreted_query(ctx, interpreted_query, search_budget),
+            )
+            .await
+            {
+                Ok(Err(e)) if e.is_deadline_exceeded() => Err(ExecuteError::QueryTimeout(timeout)),
+                Ok(result) => result,
+                Err(_) => Err(ExecuteError::QueryTimeout(timeout)),
+            }
+        }
+    }
+}
+
+async fn execute_interpreted_query(
+    ctx: &DBContext,
+    interpreted_query: InterpretedQuery,
+    search_budget: SearchBudget,
+) -> Result<ExecutedData> {
     match interpreted_query {
         InterpretedQuery::ListMetrics(database_name, output_condition, query_setting) => {
             let (db_config, _database_name, db_dir) =
-                to_db_config_and_db_dir(database_name, ctx, query_setting)?;
+                to_db_config_and_db_dir(database_name, ctx, query_setting, search_budget)?;
             let db_dir = db_dir.display().to_string();
             let metrics = metrics_list::execute_metrics_list(Some(&db_dir), &db_config).await?;
 
@@ -50,7 +110,7 @@ pub async fn execute_query(ctx: &DBContext, query: &str) -> Result<ExecutedData>
 
         InterpretedQuery::DescribeMetrics(database_name, describe_condition, query_setting) => {
             let (db_config, database_name, db_dir) =
-                to_db_config_and_db_dir(database_name, ctx, query_setting)?;
+                to_db_config_and_db_dir(database_name, ctx, query_setting, search_budget)?;
             let db_dir = db_dir.display().to_string();
             let df = describe_metrics::execute_describe_metrics(
                 &database_name,
@@ -69,7 +129,7 @@ pub async fn execute_query(ctx: &DBContext, query: &str) -> Result<ExecutedData>
 
         InterpretedQuery::DescribeBlockList(database_name, describe_condition, query_setting) => {
             let (db_config, database_name, db_dir) =
-                to_db_config_and_db_dir(database_name, ctx, query_setting)?;
+                to_db_config_and_db_dir(database_name, ctx, query_setting, search_budget)?;
             let db_dir = db_dir.display().to_string();
             let df = describe_metrics::execute_describe_metrics(
                 &database_name,
@@ -86,11 +146,94 @@ pub async fn execute_query(ctx: &DBContext, query: &str) -> Result<ExecutedData>
             })
         }
 
+        InterpretedQuery::InsertInto(database_name, insert_condition, query_setting) => {
+            let duplicate_policy = query_setting.duplicate_policy;
+            let (db_config, _database_name, db_dir) =
+                to_db_config_and_db_dir(database_name, ctx, query_setting, search_budget)?;
+            let db_dir = db_dir.display().to_string();
+            let InsertCondition {
+                metrics,
+                field_types,
+                datapoints,
+                output_condition,
+            } = insert_condition;
+            let df = insert_metrics::execute_insert_metrics(
+                &db_dir,
+                &db_config,
+                &metrics,
+                &field_types,
+                datapoints,
+                duplicate_policy,
+            )
+            .await?;
+
+            Ok(ExecutedData {
+                records: Some(df.as_arrow_record_batchs(false, None).await?),
+                output_condition,
+            })
+        }
+
+        InterpretedQuery::DeleteFrom(database_name, delete_condition, query_setting) => {
+            let (db_config, _database_name, db_dir) =
+                to_db_config_and_db_dir(database_name, ctx, query_setting, search_budget)?;
+            let db_dir = db_dir.display().to_string();
+            let DeleteCondition {
+                metrics,
+                datapoints_range,
+                output_condition,
+            } = delete_condition;
+            let df = delete_metrics::execute_delete_metrics(
+                &db_dir,
+                &db_config,
+                &metrics,
+                &datapoints_range,
+            )
+            .await?;
+
+            Ok(ExecutedData {
+                records: Some(df.as_arrow_record_batchs(false, None).await?),
+                output_condition,
+            })
+        }
+
         InterpretedQuery::SearchMetrics(database_name, query_condition, query_setting) => {
-            let (db_config, database_name, db_dir) =
-                to_db_config_and_db_dir(database_name, ctx, query_setting)?;
+            let (mut db_config, database_name, db_dir) =
+                to_db_config_and_db_dir(database_name, ctx, query_setting, search_budget)?;
             let db_dir = db_dir.display().to_string();
 
+            let use_result_cache =
+                db_config.cache_setting.read_cache || db_config.cache_setting.write_cache;
+            let result_cache_key = if use_result_cache {
+                result_cache::QueryResultCacheKey::from_current_block_list(
+                    &database_name,
+                    &db_dir,
+                    &db_config,
+                    &query_condition,
+                )
+                .await
+            } else {
+                None
+            };
+            if result_cache_key.is_some() {
+                // the block list has just been read (and downloaded if forced) for the cache key.
+                // the search reads the same file rather than downloading it again.
+                db_config.cloud_setting.force_update_block_list = false;
+            }
+
+            if db_config.cache_setting.read_cache {
+                if let Some(result_cache_key) = result_cache_key.as_ref() {
+                    let s_cache = result_cache::shared_result_cache();
+                    let mut cache = s_cache.write().await;
+                    if let Some(records) = cache.get(result_cache_key) {
+                        log::debug!("query result cache hit");
+                        return Ok(ExecutedData {
+                            records: records.clone(),
+                            output_condition: query_condition.output_condition,
+                        });
+                    }
+                }
+            }
+
             let query_result_df = search_metrics::execute_search_metrics(
                 &database_name,
                 &db_dir,
@@ -99,22 +242,29 @@ pub async fn execute_query(ctx: &DBContext, query: &str) -> Result<ExecutedData>
             )
             .await?;
 
-            match query_result_df {
-                None => Ok(ExecutedData {
-                    records: None,
-                    output_condition: query_condition.output_condition,
-                }),
-                Some(df) => Ok(ExecutedData {
-                    records: Some(
-                        df.as_arrow_record_batchs(
-                            query_condition.format_datetime,
-                            Some(query_condition.timezone),
-                        )
-                        .await?,
-                    ),
-                    output_condition: query_condition.output_condition,
-                }),
+            let records = match query_result_df {
+                None => None,
+                Some(df) => Some(
+                    df.as_arrow_record_batchs(
+                        query_condition.format_datetime,
+                        Some(query_condition.timezone),
+                    )
+                    .await?,
+                ),
+            };
+
+            if db_config.cache_setting.write_cache {
+                if let Some(result_cache_key) = result_cache_key {
+                    let s_cache = result_cache::shared_result_cache();
+                    let mut cache = s_cache.write().await;
+                    cache.write(result_cache_key, records.clone());
+                }
             }
+
+            Ok(ExecutedData {
+                records,
+                output_condition: query_condition.output_condition,
+            })
         }
     }
 }
@@ -123,6 +273,7 @@ fn to_db_config_and_db_dir(
     database_name: Option<DatabaseName>,
     ctx: &DBContext,
     query_setting: QuerySetting,
+    search_budget: SearchBudget,
 ) -> Result<(DBConfig, String, PathBuf)> {
     let database = match ctx.get_database(database_name.as_ref().map(|name| name.as_str())) {
         Ok(database) => match database {
@@ -142,6 +293,8 @@ fn to_db_config_and_db_dir(
             cache_setting: query_setting.cache_setting,
             cloud_storage: database.cloud_storage.clone(),
             cloud_setting: query_setting.cloud_setting,
+            search_budget,
+            duplicate_policy: query_setting.duplicate_policy.unwrap_or_default(),
         },
         database.name(),
         db_dir,
@@ -189,4 +342,58 @@ pub enum ExecuteError {
 
     #[error("parquet error: {0}")]
     ParquetError(#[from] ParquetError),
+
+    #[error("field types of the inserted values mismatched. expected: {0}, actual: {1}")]
+    InsertFieldTypesMismatched(String, String),
+
+    #[error("unsupported field type to be written: {0}")]
+    UnsupportedInsertFieldType(String),
+
+    #[error("duplicate policy of the insertion mismatched. expected: {0}, actual: {1}")]
+    InsertDuplicatePolicyMismatched(String, String),
+
+    #[error("field keys of the insertion mismatched. expected: {0}, actual: {1}")]
+    InsertFieldKeysMismatched(String, String),
+
+    #[error("query timed out. timeout: {0:?}")]
+    QueryTimeout(Duration),
+
+    #[error("query exceeded the memory budget. limit: {limit_bytes} bytes, used: {used_bytes} bytes, rows: {rows}, blocks: {blocks}")]
+    MemoryBudgetExceeded {
+        limit_bytes: usize,
+        used_bytes: usize,
+        rows: usize,
+        blocks: usize,
+    },
+}
+
+impl ExecuteError {
+    /// extract the errors that should be reported to the user as they are from the engine error.
+    pub(crate) fn from_engine_error(e: EngineError) -> Self {
+        match e {
+            EngineError::StorageApiError(StorageApiError::MemoryBudgetExceeded {
+                limit_bytes,
+                used_bytes,
+                rows,
+                blocks,
+            }) => ExecuteError::MemoryBudgetExceeded {
+                limit_bytes,
+                used_bytes,
+                rows,
+                blocks,
+            },
+            e => ExecuteError::EngineError(e),
+        }
+    }
+
+    /// true if the query has been aborted by its timeout.
+    pub fn is_deadline_exceeded(&self) -> bool {
+        matches!(
+            self,
+            ExecuteError::QueryTimeout(_)
+                | ExecuteError::EngineError(EngineError::StorageApiError(
+                    StorageApiError::DeadlineExceeded
+                ))
+        )
+    }
 }
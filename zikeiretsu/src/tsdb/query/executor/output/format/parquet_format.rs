@@ -1,7 +1,9 @@
 use crate::tsdb::query::executor::Result as ExecuteResult;
+use arrow::datatypes::SchemaRef;
 use arrow::record_batch::RecordBatch;
 use parquet::arrow::arrow_writer::ArrowWriter;
 use parquet::basic::Compression;
+use parquet::errors::Result as ParquetResult;
 use parquet::file::properties::WriterProperties;
 use std::fs::File;
 
@@ -12,6 +14,16 @@ pub struct ParquetOutput {
 
 impl ParquetOutput {
     pub fn output(self, record: RecordBatch) -> ExecuteResult<()> {
+        let mut writer = self.writer(record.schema())?;
+
+        writer.write(&record)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    /// the writer to write multiple record batches of the schema into the file one by one.
+    /// `close()` must be called at the end.
+    pub fn writer(self, schema: SchemaRef) -> ParquetResult<ArrowWriter<File>> {
         let props = if self.snappy_compress {
             WriterProperties::builder()
                 .set_compression(Compression::SNAPPY)
@@ -20,10 +32,6 @@ impl ParquetOutput {
             WriterProperties::builder().build()
         };
 
-        let mut writer = ArrowWriter::try_new(self.file, record.schema(), Some(props)).unwrap();
-
-        writer.write(&record)?;
-        writer.close()?;
-        Ok(())
+        ArrowWriter::try_new(self.file, schema, Some(props))
     }
 }
@@ -0,0 +1,286 @@
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
+use crate::tsdb::engine::Engine;
+use crate::tsdb::query::lexer::InterpretedQueryCondition;
+use crate::tsdb::{api::CacheSetting, DBConfig, Metrics, TimestampNano};
+use arrow::record_batch::RecordBatch;
+use lru::LruCache;
+use once_cell::sync::OnceCell;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+static RESULT_CACHE: OnceCell<Arc<RwLock<QueryResultCache>>> = OnceCell::new();
+
+pub(crate) fn shared_result_cache() -> Arc<RwLock<QueryResultCache>> {
+    let result_cache_size = std::env::var("ZDB_RESULT_CACHE_SIZE")
+        .unwrap_or_else(|_| "100".to_string())
+        .parse::<usize>()
+        .unwrap_or(100);
+    let cached = RESULT_CACHE
+        .get_or_init(|| Arc::new(RwLock::new(QueryResultCache::new(result_cache_size))));
+    cached.clone()
+}
+
+/// The cached result is valid as long as the query, the duplicate policy and the block lists of all metrics involved in it are unchanged.
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+pub(crate) struct QueryResultCacheKey {
+    normalized_query: String,
+    block_list_versions: Vec<(Metrics, TimestampNano)>,
+}
+
+impl QueryResultCacheKey {
+    pub fn new(
+        database_name: &str,
+        condition: &InterpretedQueryCondition,
+        duplicate_policy: DuplicatePolicy,
+        block_list_versions: Vec<(Metrics, TimestampNano)>,
+    ) -> Self {
+        Self {
+            normalized_query: normalize_query(database_name, condition, duplicate_policy),
+            block_list_versions,
+        }
+    }
+
+    /// returns None if the block list of the metrics could not be read. e.g. the metrics has no persisted data yet.
+    /// the block list is downloaded if `force_update_block_list` is set. the search is supposed to reuse the downloaded one.
+    pub async fn from_current_block_list<P: AsRef<Path>>(
+        database_name: &str,
+        db_dir: P,
+        db_config: &DBConfig,
+        condition: &InterpretedQueryCondition,
+    ) -> Option<Self> {
+        // block list must be read from the file (or the cloud storage) not from the cache
+        // to detect the updates by other process
+        let block_list_config = DBConfig {
+            cache_setting: CacheSetting::none(),
+            cloud_storage: db_config.cloud_storage.clone(),
+            cloud_setting: db_config.cloud_setting.clone(),
+            search_budget: db_config.search_budget.clone(),
+            duplicate_policy: db_config.duplicate_policy,
+        };
+
+        match Engine::block_list_data(
+            database_name,
+            db_dir,
+            &condition.metrics,
+            &block_list_config,
+        )
+        .await
+        {
+            Ok(block_list) => Some(Self::new(
+                database_name,
+                condition,
+                db_config.duplicate_policy,
+                vec![(block_list.metrics, block_list.updated_timestamp_sec)],
+            )),
+            Err(e) => {
+                log::debug!("skip query result cache. failed to read block list: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// the duplicate policy is a part of the query since it changes the datapoints searched.
+fn normalize_query(
+    database_name: &str,
+    condition: &InterpretedQueryCondition,
+    duplicate_policy: DuplicatePolicy,
+) -> String {
+    // relative datetimes (e.g. today()) are already resolved to absolute timestamps at here.
+    let range = &condition.datetime_search_condition.datapoints_range;
+    format!(
+        "db={database_name};metrics={metrics};fields={fields:?};names={names:?};since={since:?};until={until:?};limit={limit:?};sample={sample:?};rank={rank:?};duplicate_policy={duplicate_policy};format_datetime={format_datetime};tz={tz}{offset}",
+        metrics = condition.metrics,
+        fields = condition.field_selectors,
+        names = condition.field_names,
+        since = range.inner_since_inclusive.map(|ts| ts.as_inner()),
+        until = range.inner_until_exclusive.map(|ts| ts.as_inner()),
+        limit = condition.datetime_search_condition.limit,
+        sample = condition.datetime_search_condition.sample,
+        rank = condition.datetime_search_condition.rank,
+        format_datetime = condition.format_datetime,
+        tz = condition.timezone.tz.name(),
+        offset = condition.timezone.offset,
+    )
+}
+
+pub(crate) struct QueryResultCache {
+    results: LruCache<QueryResultCacheKey, Option<RecordBatch>>,
+}
+
+impl QueryResultCache {
+    pub fn new(cache_size: usize) -> Self {
+        Self {
+            results: LruCache::new(cache_size),
+        }
+    }
+
+    pub fn get(&mut self, key: &QueryResultCacheKey) -> Option<&Option<RecordBatch>> {
+        self.results.get(key)
+    }
+
+    pub fn write(&mut self, key: QueryResultCacheKey, records: Option<RecordBatch>) {
+        self.results.put(key, records);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::{DatapointsRange, DatapointsSearchCondition};
+    use crate::tsdb::query::lexer::OutputCondition;
+    use crate::tsdb::query::parser::clause::OutputFormat;
+    use crate::DEFAULT_TIMEZONE_AND_OFFSET;
+
+    fn query_condition(since: u64) -> InterpretedQueryCondition {
+        InterpretedQueryCondition {
+            metrics: Metrics::new("test_metrics").unwrap(),
+            field_selectors: None,
+            field_names: None,
+            datetime_search_condition: DatapointsSearchCondition {
+                datapoints_range: DatapointsRange::new(Some(TimestampNano::new(since)), None),
+                limit: None,
+                sample: None,
+                rank: None,
+            },
+            output_condition: OutputCondition {
+                output_format: OutputFormat::Table,
+                output_to_memory: true,
+                output_file_path: None,
+            },
+            format_datetime: true,
+            timezone: &*DEFAULT_TIMEZONE_AND_OFFSET,
+        }
+    }
+
+    #[test]
+    fn result_cache_key_test() {
+        let metrics = Metrics::new("test_metrics").unwrap();
+        let key_1 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(10),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics.clone(), TimestampNano::new(100))],
+        );
+
+        let key_2 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(10),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics.clone(), TimestampNano::new(100))],
+        );
+        assert_eq!(key_1, key_2);
+
+        // block list updated
+        let key_3 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(10),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics.clone(), TimestampNano::new(101))],
+        );
+        assert_ne!(key_1, key_3);
+
+        // different query
+        let key_4 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(11),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics.clone(), TimestampNano::new(100))],
+        );
+        assert_ne!(key_1, key_4);
+
+        // different database
+        let key_5 = QueryResultCacheKey::new(
+            "db2",
+            &query_condition(10),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics.clone(), TimestampNano::new(100))],
+        );
+        assert_ne!(key_1, key_5);
+
+        // different duplicate policy
+        let key_6 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(10),
+            DuplicatePolicy::KeepLast,
+            vec![(metrics, TimestampNano::new(100))],
+        );
+        assert_ne!(key_1, key_6);
+    }
+
+    #[tokio::test]
+    async fn result_cache_duplicate_policy_test() {
+        use crate::tsdb::query::executor::execute_query;
+        use crate::tsdb::storage::api as storage_api;
+        use crate::tsdb::{DBContext, DataPoint, Database, FieldValue};
+
+        let temp_data_dir = tempdir::TempDir::new("result_cache_duplicate_policy_test").unwrap();
+        let database = Database::new("db".to_string(), None);
+        let db_dir = database.as_local_db_dir(temp_data_dir.path());
+        let ctx = DBContext::new(
+            temp_data_dir.path().to_path_buf(),
+            Some("db".to_string()),
+            vec![database],
+        );
+
+        let metrics = Metrics::new("result_cache_duplicate_policy_metrics").unwrap();
+        let timestamp = TimestampNano::new(1640962800_000000000);
+        storage_api::write::write_datas(
+            &db_dir,
+            &uuid::Uuid::new_v4(),
+            &metrics,
+            &[
+                DataPoint::new(timestamp, vec![FieldValue::Float64(1.0)]),
+                DataPoint::new(timestamp, vec![FieldValue::Float64(2.0)]),
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+
+        let searched_row_num = |duplicate_policy: &'static str| {
+            let ctx = &ctx;
+            let query = format!(
+                "with duplicate_policy = {duplicate_policy} select * from {metrics} where ts in ('2021-12-30', +3 days)"
+            );
+            async move {
+                execute_query(ctx, &query)
+                    .await
+                    .unwrap()
+                    .records
+                    .map(|records| records.num_rows())
+            }
+        };
+
+        // the result of the one is not returned for the other from the cache
+        assert_eq!(searched_row_num("keep_all").await, Some(2));
+        assert_eq!(searched_row_num("keep_last").await, Some(1));
+        assert_eq!(searched_row_num("keep_all").await, Some(2));
+    }
+
+    #[test]
+    fn result_cache_lru_test() {
+        let metrics = Metrics::new("test_metrics").unwrap();
+        let mut cache = QueryResultCache::new(1);
+        let key_1 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(10),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics.clone(), TimestampNano::new(100))],
+        );
+        let key_2 = QueryResultCacheKey::new(
+            "db",
+            &query_condition(10),
+            DuplicatePolicy::KeepAll,
+            vec![(metrics, TimestampNano::new(101))],
+        );
+
+        cache.write(key_1.clone(), None);
+        assert_eq!(cache.get(&key_1), Some(&None));
+
+        cache.write(key_2.clone(), None);
+        assert_eq!(cache.get(&key_1), None);
+        assert_eq!(cache.get(&key_2), Some(&None));
+    }
+}
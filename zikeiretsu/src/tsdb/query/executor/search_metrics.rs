@@ -19,7 +19,8 @@ pub async fn execute_search_metrics(
         &condition.datetime_search_condition,
         db_config,
     )
-    .await?;
+    .await
+    .map_err(ExecuteError::from_engine_error)?;
     match dataframe {
         None => Ok(None),
         Some(mut dataframe) => {
@@ -0,0 +1,121 @@
+use super::{LexerError, Result as LexerResult};
+use crate::tsdb::field::{join_field_types, FieldType, FieldValue};
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::query::parser::clause::{InsertClause, InsertValue};
+use crate::tsdb::DataPoint;
+use chrono::FixedOffset;
+
+/// all the rows must have the same field types.
+pub(crate) fn interpret_insert(
+    offset: &FixedOffset,
+    insert_clause: &InsertClause<'_>,
+) -> LexerResult<(Metrics, Vec<FieldType>, Vec<DataPoint>)> {
+    let metrics =
+        Metrics::new(insert_clause.metrics.to_string()).map_err(LexerError::InvalidMetrics)?;
+
+    let mut field_types: Option<Vec<FieldType>> = None;
+    let mut datapoints = Vec::<DataPoint>::with_capacity(insert_clause.rows.len());
+    for (row_idx, row) in insert_clause.rows.iter().enumerate() {
+        let field_values: Vec<FieldValue> = row
+            .values
+            .iter()
+            .map(|value| match value {
+                InsertValue::Float64(v) => FieldValue::Float64(*v),
+                InsertValue::Bool(v) => FieldValue::Bool(*v),
+            })
+            .collect();
+
+        let row_field_types: Vec<FieldType> = field_values
+            .iter()
+            .map(|field_value| field_value.as_type())
+            .collect();
+        match field_types.as_ref() {
+            None => field_types = Some(row_field_types),
+            Some(field_types) => {
+                if field_types != &row_field_types {
+                    return Err(LexerError::InvalidInsertValues(format!(
+                        "the field types of the row {row_idx} differ from the first row. expected: {expected}, actual: {actual}",
+                        row_idx = row_idx + 1,
+                        expected = join_field_types(field_types),
+                        actual = join_field_types(&row_field_types),
+                    )));
+                }
+            }
+        }
+
+        datapoints.push(DataPoint::new(
+            row.datetime.to_timestamp_nano(offset),
+            field_values,
+        ));
+    }
+
+    match field_types {
+        None => Err(LexerError::InvalidInsertValues("no rows".to_string())),
+        Some(field_types) => Ok((metrics, field_types, datapoints)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::query::parser::clause::InsertRow;
+    use crate::tsdb::query::parser::parts::DatetimeFilterValue;
+    use crate::tsdb::TimestampNano;
+    use chrono::{TimeZone, Utc};
+
+    fn insert_row(sec: u32, values: Vec<InsertValue>) -> InsertRow {
+        InsertRow {
+            datetime: DatetimeFilterValue::DateString(Utc.ymd(2022, 1, 1).and_hms(0, 0, sec), None),
+            values,
+        }
+    }
+
+    #[test]
+    fn lex_insert_1() {
+        let insert_clause = InsertClause {
+            metrics: "trades",
+            rows: vec![
+                insert_row(0, vec![InsertValue::Float64(1.5), InsertValue::Bool(true)]),
+                insert_row(
+                    1,
+                    vec![InsertValue::Float64(2f64), InsertValue::Bool(false)],
+                ),
+            ],
+        };
+
+        let (metrics, field_types, datapoints) =
+            interpret_insert(&FixedOffset::east(9 * 3600), &insert_clause).unwrap();
+
+        assert_eq!(metrics, Metrics::new("trades").unwrap());
+        assert_eq!(field_types, vec![FieldType::Float64, FieldType::Bool]);
+        assert_eq!(
+            datapoints,
+            vec![
+                DataPoint::new(
+                    TimestampNano::new(1640962800_000000000),
+                    vec![FieldValue::Float64(1.5), FieldValue::Bool(true)]
+                ),
+                DataPoint::new(
+                    TimestampNano::new(1640962801_000000000),
+                    vec![FieldValue::Float64(2f64), FieldValue::Bool(false)]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_insert_err_1() {
+        let insert_clause = InsertClause {
+            metrics: "trades",
+            rows: vec![
+                insert_row(0, vec![InsertValue::Float64(1.5), InsertValue::Bool(true)]),
+                insert_row(
+                    1,
+                    vec![InsertValue::Bool(false), InsertValue::Float64(2f64)],
+                ),
+            ],
+        };
+
+        assert!(interpret_insert(&FixedOffset::east(0), &insert_clause).is_err());
+    }
+}
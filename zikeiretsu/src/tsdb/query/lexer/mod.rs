@@ -1,20 +1,27 @@
 mod from;
+mod insert;
 mod select;
 mod r#where;
 mod with;
 
-use crate::tsdb::datapoint::DatapointsSearchCondition;
+use crate::tsdb::datapoint::{
+    DatapointsRange, DatapointsSearchCondition, SearchDatapointsRank, SearchDatapointsSample,
+};
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
+use crate::tsdb::field::FieldType;
 use crate::tsdb::metrics::Metrics;
 pub use crate::tsdb::query::parser::clause::{OutputFormat, WhereClause, WithClause};
+use crate::tsdb::query::parser::clause::{SampleClause, SelectRank};
 use crate::tsdb::query::parser::*;
-use crate::tsdb::TimeZoneAndOffset;
 use crate::tsdb::{CacheSetting, CloudStorageSetting};
+use crate::tsdb::{DataPoint, TimeZoneAndOffset};
 use either::Either;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::Error as IoError;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug, Serialize, Deserialize)]
@@ -42,6 +49,18 @@ pub enum LexerError {
 
     #[error("invalid metrics:{0}")]
     InvalidMetrics(String),
+
+    #[error("sample clause not supported :{0}")]
+    SampleIsNotSupported(String),
+
+    #[error("top or bottom is not available with :{0}")]
+    RankIsNotSupported(String),
+
+    #[error("invalid insert values :{0}")]
+    InvalidInsertValues(String),
+
+    #[error("delete condition not supported :{0}")]
+    DeleteConditionIsNotSupported(String),
 }
 
 pub type Result<T> = std::result::Result<T, LexerError>;
@@ -82,12 +101,35 @@ pub enum InterpretedQuery {
         InterpretedQueryCondition,
         QuerySetting,
     ),
+    InsertInto(Option<DatabaseName>, InsertCondition, QuerySetting),
+    DeleteFrom(Option<DatabaseName>, DeleteCondition, QuerySetting),
+}
+
+impl InterpretedQuery {
+    pub fn query_setting(&self) -> &QuerySetting {
+        match self {
+            Self::ListMetrics(_, _, query_setting) => query_setting,
+            Self::DescribeMetrics(_, _, query_setting) => query_setting,
+            Self::DescribeBlockList(_, _, query_setting) => query_setting,
+            Self::SearchMetrics(_, _, query_setting) => query_setting,
+            Self::InsertInto(_, _, query_setting) => query_setting,
+            Self::DeleteFrom(_, _, query_setting) => query_setting,
+        }
+    }
+
+    /// whether the query modifies the datapoints.
+    pub fn is_write(&self) -> bool {
+        matches!(self, Self::InsertInto(..) | Self::DeleteFrom(..))
+    }
 }
 
 #[derive(Debug)]
 pub struct QuerySetting {
     pub cache_setting: CacheSetting,
     pub cloud_setting: CloudStorageSetting,
+    pub timeout: Option<Duration>,
+    /// the default one if None.
+    pub duplicate_policy: Option<DuplicatePolicy>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -103,6 +145,21 @@ pub struct DescribeMetrics {
     pub metrics_filter: Option<Metrics>,
 }
 
+#[derive(Debug)]
+pub struct InsertCondition {
+    pub metrics: Metrics,
+    pub field_types: Vec<FieldType>,
+    pub datapoints: Vec<DataPoint>,
+    pub output_condition: OutputCondition,
+}
+
+#[derive(Debug)]
+pub struct DeleteCondition {
+    pub metrics: Metrics,
+    pub datapoints_range: DatapointsRange,
+    pub output_condition: OutputCondition,
+}
+
 #[derive(Debug)]
 pub struct DescribeBlockList {
     pub output_condition: OutputCondition,
@@ -197,6 +254,13 @@ macro_rules! prepend_ts_column_to_head {
 
 pub(crate) fn interpret(parsed_query: ParsedQuery<'_>) -> Result<InterpretedQuery> {
     log::debug!("interpriting parsed query :{parsed_query:?}");
+    if parsed_query.insert.is_some() {
+        return interpret_insert(parsed_query);
+    }
+    if parsed_query.delete.is_some() {
+        return interpret_delete(parsed_query);
+    }
+
     let metrics = match from::parse_from(parsed_query.from.as_ref())? {
         Either::Right(buildin_metrics) => {
             return interpret_buildin_metrics(parsed_query, buildin_metrics)
@@ -209,6 +273,8 @@ pub(crate) fn interpret(parsed_query: ParsedQuery<'_>) -> Result<InterpretedQuer
     let query_setting = QuerySetting {
         cache_setting: with.cache_setting,
         cloud_setting: with.cloud_setting,
+        timeout: with.timeout,
+        duplicate_policy: with.duplicate_policy,
     };
 
     // select columns
@@ -227,13 +293,39 @@ pub(crate) fn interpret(parsed_query: ParsedQuery<'_>) -> Result<InterpretedQuer
             .map(|mut field_names| prepend_ts_column_to_head!(field_names)),
     };
 
-    let datetime_search_condition = match parsed_query.r#where.as_ref() {
+    let mut datetime_search_condition = match parsed_query.r#where.as_ref() {
         None => return Err(LexerError::EmptyFilterCondition),
         Some(filter) => {
             r#where::interpret_datatime_search_condition(&with.timezone.offset, filter)?
         }
     };
 
+    datetime_search_condition.sample = parsed_query.sample.map(|sample| match sample {
+        SampleClause::Percent(percent) => SearchDatapointsSample::Percent(percent),
+        SampleClause::Every(n) => SearchDatapointsSample::Every(n),
+    });
+
+    // the ranked column is the only one selected.
+    if let Some(rank) = parsed_query
+        .select
+        .as_ref()
+        .and_then(|select| select.rank.as_ref())
+    {
+        if let Some(limit) = datetime_search_condition.limit.as_ref() {
+            return Err(LexerError::RankIsNotSupported(format!("{limit:?}")));
+        }
+        datetime_search_condition.rank = Some(match rank {
+            SelectRank::Top(n) => SearchDatapointsRank::Top {
+                field_idx: 0,
+                n: *n,
+            },
+            SelectRank::Bottom(n) => SearchDatapointsRank::Bottom {
+                field_idx: 0,
+                n: *n,
+            },
+        });
+    }
+
     invalid_if_metrics_filter_exists(parsed_query.r#where.as_ref())?;
 
     let output_condition = OutputCondition {
@@ -262,15 +354,109 @@ pub(crate) fn interpret(parsed_query: ParsedQuery<'_>) -> Result<InterpretedQuer
     ))
 }
 
+pub(crate) fn interpret_insert(parsed_query: ParsedQuery<'_>) -> Result<InterpretedQuery> {
+    let with = with::interpret_with(parsed_query.with)?;
+
+    let query_setting = QuerySetting {
+        cache_setting: with.cache_setting,
+        cloud_setting: with.cloud_setting,
+        timeout: with.timeout,
+        duplicate_policy: with.duplicate_policy,
+    };
+
+    let insert_clause = match parsed_query.insert.as_ref() {
+        None => return Err(LexerError::InvalidInsertValues("no values".to_string())),
+        Some(insert_clause) => insert_clause,
+    };
+    let (metrics, field_types, datapoints) =
+        insert::interpret_insert(&with.timezone.offset, insert_clause)?;
+
+    let database_name = with
+        .database
+        .map(|database_name| DatabaseName(database_name.to_string()));
+
+    Ok(InterpretedQuery::InsertInto(
+        database_name,
+        InsertCondition {
+            metrics,
+            field_types,
+            datapoints,
+            output_condition: OutputCondition {
+                output_format: with.output_format,
+                output_to_memory: with.output_to_memory,
+                output_file_path: with.output_file_path,
+            },
+        },
+        query_setting,
+    ))
+}
+
+pub(crate) fn interpret_delete(parsed_query: ParsedQuery<'_>) -> Result<InterpretedQuery> {
+    let with = with::interpret_with(parsed_query.with)?;
+
+    let query_setting = QuerySetting {
+        cache_setting: with.cache_setting,
+        cloud_setting: with.cloud_setting,
+        timeout: with.timeout,
+        duplicate_policy: with.duplicate_policy,
+    };
+
+    let metrics = match parsed_query.delete.as_ref() {
+        None => return Err(LexerError::NoFrom),
+        Some(delete_clause) => {
+            Metrics::new(delete_clause.metrics.to_string()).map_err(LexerError::InvalidMetrics)?
+        }
+    };
+
+    // deleting all the datapoints by mistake must be avoided
+    let where_clause = match parsed_query.r#where.as_ref() {
+        Some(where_clause) if where_clause.datetime_filter.is_some() => where_clause,
+        _ => return Err(LexerError::EmptyFilterCondition),
+    };
+    invalid_if_metrics_filter_exists(Some(where_clause))?;
+    let datetime_search_condition =
+        r#where::interpret_datatime_search_condition(&with.timezone.offset, where_clause)?;
+    if let Some(limit) = datetime_search_condition.limit.as_ref() {
+        return Err(LexerError::DeleteConditionIsNotSupported(format!(
+            "{limit:?}"
+        )));
+    }
+
+    let database_name = with
+        .database
+        .map(|database_name| DatabaseName(database_name.to_string()));
+
+    Ok(InterpretedQuery::DeleteFrom(
+        database_name,
+        DeleteCondition {
+            metrics,
+            datapoints_range: datetime_search_condition.datapoints_range,
+            output_condition: OutputCondition {
+                output_format: with.output_format,
+                output_to_memory: with.output_to_memory,
+                output_file_path: with.output_file_path,
+            },
+        },
+        query_setting,
+    ))
+}
+
 pub(crate) fn interpret_buildin_metrics(
     parsed_query: ParsedQuery<'_>,
     buildin_metrics: from::BuildinMetrics,
 ) -> Result<InterpretedQuery> {
+    if let Some(sample) = parsed_query.sample.as_ref() {
+        return Err(LexerError::SampleIsNotSupported(format!(
+            "{sample:?} on buildin metrics"
+        )));
+    }
     let with = with::interpret_with(parsed_query.with)?;
 
     let query_setting = QuerySetting {
         cache_setting: with.cache_setting,
         cloud_setting: with.cloud_setting,
+        timeout: with.timeout,
+        duplicate_policy: with.duplicate_policy,
     };
     let database_name = with
         .database
@@ -415,4 +601,29 @@ mod test {
                 .is_err());
         }
     }
+
+    #[test]
+    fn test_interpret_delete() {
+        let parsed_query = parse_query(
+            "delete from trades where ts in ('2022-01-01 00:00:00', '2022-01-01 01:00:00')",
+        )
+        .unwrap();
+        match interpret(parsed_query).unwrap() {
+            InterpretedQuery::DeleteFrom(None, delete_condition, _) => {
+                assert_eq!(delete_condition.metrics, Metrics::new("trades").unwrap());
+                assert_eq!(
+                    delete_condition.datapoints_range,
+                    DatapointsRange::new(
+                        Some(crate::tsdb::TimestampNano::new(1640995200_000000000)),
+                        Some(crate::tsdb::TimestampNano::new(1640998800_000000000)),
+                    )
+                );
+            }
+            other => panic!("unexpected query {other:?}"),
+        }
+
+        // a metrics filter is not a time range
+        let parsed_query = parse_query("delete from trades where metrics = trades").unwrap();
+        assert!(interpret(parsed_query).is_err());
+    }
 }
@@ -81,6 +81,7 @@ mod test {
                 Column::ColumnName(ColumnName("c1")),
                 Column::ColumnName(ColumnName("c3")),
             ],
+            rank: None,
         };
 
         let result = interpret_field_selector(Some(&column_map), Some(&select)).unwrap();
@@ -96,6 +97,7 @@ mod test {
 
         let select = SelectClause {
             select_columns: vec![Column::Asterick],
+            rank: None,
         };
 
         let result = interpret_field_selector(Some(&column_map), Some(&select)).unwrap();
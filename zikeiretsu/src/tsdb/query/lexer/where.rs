@@ -27,11 +27,15 @@ fn datetime_filter_to_condition<'q>(
                 Some(to.to_timestamp_nano(offset)),
             ),
             limit: None,
+            sample: None,
+            rank: None,
         }),
 
         DatetimeFilter::Gte(_, from, limit) => Ok(DatapointsSearchCondition {
             datapoints_range: DatapointsRange::new(Some(from.to_timestamp_nano(offset)), None),
             limit: limit.map(SearchDatapointsLimit::Head),
+            sample: None,
+            rank: None,
         }),
         DatetimeFilter::Gt(_, from, limit) => Ok(DatapointsSearchCondition {
             datapoints_range: DatapointsRange::new(
@@ -39,6 +43,8 @@ fn datetime_filter_to_condition<'q>(
                 None,
             ),
             limit: limit.map(SearchDatapointsLimit::Head),
+            sample: None,
+            rank: None,
         }),
         DatetimeFilter::Lte(_, to, limit) => Ok(DatapointsSearchCondition {
             datapoints_range: DatapointsRange::new(
@@ -46,10 +52,14 @@ fn datetime_filter_to_condition<'q>(
                 Some(to.to_timestamp_nano(offset) + Duration::nanoseconds(1)),
             ),
             limit: limit.map(SearchDatapointsLimit::Tail),
+            sample: None,
+            rank: None,
         }),
         DatetimeFilter::Lt(_, to, limit) => Ok(DatapointsSearchCondition {
             datapoints_range: DatapointsRange::new(None, Some(to.to_timestamp_nano(offset))),
             limit: limit.map(SearchDatapointsLimit::Tail),
+            sample: None,
+            rank: None,
         }),
         DatetimeFilter::Equal(_, datetime_value) => {
             let from_dt_nano = datetime_value.to_timestamp_nano(offset);
@@ -71,6 +81,8 @@ fn datetime_filter_to_condition<'q>(
                 ),
 
                 limit: None,
+                sample: None,
+                rank: None,
             })
         }
     }
@@ -1,4 +1,5 @@
 use super::{LexerError, Result as LexerResult};
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
 use crate::tsdb::query::parser::clause::{OutputFormat, WithClause};
 use crate::tsdb::query::parser::*;
 use crate::tsdb::{CacheSetting, CloudStorageSetting};
@@ -6,6 +7,7 @@ use crate::tsdb::{CacheSetting, CloudStorageSetting};
 use crate::{TimeZoneAndOffset, DEFAULT_TIMEZONE_AND_OFFSET};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub(crate) struct With<'q> {
     pub timezone: &'static TimeZoneAndOffset,
@@ -18,6 +20,8 @@ pub(crate) struct With<'q> {
     pub output_file_path: Option<PathBuf>,
     pub cache_setting: CacheSetting,
     pub cloud_setting: CloudStorageSetting,
+    pub timeout: Option<Duration>,
+    pub duplicate_policy: Option<DuplicatePolicy>,
 }
 
 impl<'q> Default for With<'q> {
@@ -35,6 +39,8 @@ impl<'q> Default for With<'q> {
             output_file_path: None,
             cache_setting: CacheSetting::default(),
             cloud_setting: CloudStorageSetting::default(),
+            timeout: None,
+            duplicate_policy: None,
         }
     }
 }
@@ -103,6 +109,12 @@ pub(crate) fn interpret_with(with_clause: Option<WithClause<'_>>) -> LexerResult
                 .force_update_block_list(true)
                 .build();
         }
+
+        // timeout
+        with.timeout = with_clause.def_timeout;
+
+        // duplicate policy
+        with.duplicate_policy = with_clause.def_duplicate_policy;
     }
     Ok(with)
 }
@@ -129,6 +141,8 @@ mod test {
             def_output_file_path: None,
             def_use_cache: true,
             def_force_sync_cloud: true,
+            def_timeout: None,
+            def_duplicate_policy: None,
         };
 
         let result = interpret_with(Some(with_clause)).unwrap();
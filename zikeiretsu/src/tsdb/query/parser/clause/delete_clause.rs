@@ -0,0 +1,49 @@
+use pest::iterators::Pair;
+
+use crate::tsdb::query::parser::*;
+
+#[derive(Debug, PartialEq)]
+pub struct DeleteClause<'q> {
+    pub metrics: &'q str,
+}
+
+pub fn parse(pair: Pair<'_, Rule>) -> Result<DeleteClause<'_>> {
+    #[cfg(debug_assertions)]
+    if pair.as_rule() != Rule::DELETE_CLAUSE {
+        return Err(ParserError::UnexpectedPair(
+            format!("{:?}", Rule::DELETE_CLAUSE),
+            format!("{:?}", pair.as_rule()),
+        ));
+    }
+
+    let mut metrics: Option<&str> = None;
+    for each in pair.into_inner() {
+        if each.as_rule() == Rule::METRICS_NAME {
+            metrics = Some(each.as_str())
+        }
+    }
+
+    match metrics {
+        None => Err(ParserError::EmptyTableName),
+        Some(metrics) => Ok(DeleteClause { metrics }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_delete_1() {
+        let query = r"delete from trades";
+        let mut pairs = QueryGrammer::parse(Rule::DELETE_CLAUSE, query).unwrap();
+        let result = parse(pairs.next().unwrap()).unwrap();
+
+        assert_eq!(result, DeleteClause { metrics: "trades" });
+    }
+
+    #[test]
+    fn test_parse_delete_2() {
+        assert!(QueryGrammer::parse(Rule::DELETE_CLAUSE, r"delete trades").is_err());
+    }
+}
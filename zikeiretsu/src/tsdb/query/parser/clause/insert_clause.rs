@@ -0,0 +1,134 @@
+use pest::iterators::Pair;
+
+use crate::tsdb::query::parser::parts::{boolean, parse_datetime, DatetimeFilterValue};
+use crate::tsdb::query::parser::*;
+
+#[derive(Debug, PartialEq)]
+pub struct InsertClause<'q> {
+    pub metrics: &'q str,
+    pub rows: Vec<InsertRow>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InsertRow {
+    pub datetime: DatetimeFilterValue,
+    pub values: Vec<InsertValue>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum InsertValue {
+    Float64(f64),
+    Bool(bool),
+}
+
+pub fn parse(pair: Pair<'_, Rule>) -> Result<InsertClause<'_>> {
+    #[cfg(debug_assertions)]
+    if pair.as_rule() != Rule::INSERT_CLAUSE {
+        return Err(ParserError::UnexpectedPair(
+            format!("{:?}", Rule::INSERT_CLAUSE),
+            format!("{:?}", pair.as_rule()),
+        ));
+    }
+
+    let mut metrics: Option<&str> = None;
+    let mut rows = Vec::<InsertRow>::new();
+    for each in pair.into_inner() {
+        match each.as_rule() {
+            Rule::METRICS_NAME => metrics = Some(each.as_str()),
+            Rule::INSERT_ROW => rows.push(parse_insert_row(each)?),
+            _ => { /* do nothing */ }
+        }
+    }
+
+    match metrics {
+        None => Err(ParserError::EmptyTableName),
+        Some(metrics) => Ok(InsertClause { metrics, rows }),
+    }
+}
+
+fn parse_insert_row(pair: Pair<'_, Rule>) -> Result<InsertRow> {
+    let mut datetime: Option<DatetimeFilterValue> = None;
+    let mut values = Vec::<InsertValue>::new();
+    for each in pair.into_inner() {
+        match each.as_rule() {
+            Rule::DATETIME => datetime = Some(parse_datetime(each)?),
+            Rule::INSERT_VALUE => {
+                for each_value in each.into_inner() {
+                    match each_value.as_rule() {
+                        Rule::BOOLEAN_VALUE => {
+                            values.push(InsertValue::Bool(boolean::parse_bool(each_value)?))
+                        }
+                        Rule::FLOAT_VALUE => {
+                            let value = each_value.as_str().parse::<f64>().map_err(|_| {
+                                ParserError::InvalidInsertValue(each_value.as_str().to_string())
+                            })?;
+                            values.push(InsertValue::Float64(value))
+                        }
+                        _ => { /* do nothing */ }
+                    }
+                }
+            }
+            _ => { /* do nothing */ }
+        }
+    }
+
+    match datetime {
+        None => Err(ParserError::InvalidGrammer(
+            "insert row has no timestamp".to_string(),
+        )),
+        Some(datetime) => Ok(InsertRow { datetime, values }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_parse_insert_1() {
+        let query = r"insert into trades values ('2022-01-01 00:00:00', 1.5, true), ('2022-01-01 00:00:01', -2, FALSE)";
+        let mut pairs = QueryGrammer::parse(Rule::INSERT_CLAUSE, query).unwrap();
+        let result = parse(pairs.next().unwrap()).unwrap();
+
+        assert_eq!(
+            result,
+            InsertClause {
+                metrics: "trades",
+                rows: vec![
+                    InsertRow {
+                        datetime: DatetimeFilterValue::DateString(
+                            Utc.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                            None
+                        ),
+                        values: vec![InsertValue::Float64(1.5), InsertValue::Bool(true)],
+                    },
+                    InsertRow {
+                        datetime: DatetimeFilterValue::DateString(
+                            Utc.ymd(2022, 1, 1).and_hms(0, 0, 1),
+                            None
+                        ),
+                        values: vec![InsertValue::Float64(-2f64), InsertValue::Bool(false)],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_insert_2() {
+        // a timestamp only row
+        assert!(QueryGrammer::parse(
+            Rule::INSERT_CLAUSE,
+            r"insert into trades values ('2022-01-01 00:00:00')"
+        )
+        .is_err());
+
+        // string values are not supported
+        assert!(QueryGrammer::parse(
+            Rule::INSERT_CLAUSE,
+            r"insert into trades values ('2022-01-01 00:00:00', 'a')"
+        )
+        .is_err());
+    }
+}
@@ -1,9 +1,15 @@
+pub mod delete_clause;
 pub mod from_clause;
+pub mod insert_clause;
+pub mod sample_clause;
 pub mod select_clause;
 pub mod where_clause;
 pub mod with_clause;
 
+pub use delete_clause::*;
 pub use from_clause::*;
+pub use insert_clause::*;
+pub use sample_clause::*;
 pub use select_clause::*;
 pub use where_clause::*;
 pub use with_clause::*;
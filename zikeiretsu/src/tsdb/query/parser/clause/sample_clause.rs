@@ -0,0 +1,89 @@
+use pest::iterators::Pair;
+
+use crate::tsdb::query::parser::*;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SampleClause {
+    Percent(f64),
+    Every(u64),
+}
+
+pub fn parse(pair: Pair<'_, Rule>) -> Result<SampleClause> {
+    #[cfg(debug_assertions)]
+    if pair.as_rule() != Rule::SAMPLE_CLAUSE {
+        return Err(ParserError::UnexpectedPair(
+            format!("{:?}", Rule::SAMPLE_CLAUSE),
+            format!("{:?}", pair.as_rule()),
+        ));
+    }
+
+    for each in pair.into_inner() {
+        match each.as_rule() {
+            Rule::SAMPLE_PERCENT => {
+                let percent_str = each.as_str().trim_end_matches('%').trim();
+                let percent = percent_str
+                    .parse::<f64>()
+                    .map_err(|_| ParserError::InvalidSample(each.as_str().to_string()))?;
+                if percent <= 0f64 || percent > 100f64 {
+                    return Err(ParserError::InvalidSample(each.as_str().to_string()));
+                }
+                return Ok(SampleClause::Percent(percent));
+            }
+
+            Rule::SAMPLE_EVERY => {
+                for each_in_every in each.clone().into_inner() {
+                    if each_in_every.as_rule() == Rule::ASCII_DIGITS {
+                        let n = each_in_every.as_str().parse::<u64>()?;
+                        if n == 0 {
+                            return Err(ParserError::InvalidSample(each.as_str().to_string()));
+                        }
+                        return Ok(SampleClause::Every(n));
+                    }
+                }
+                return Err(ParserError::InvalidSample(each.as_str().to_string()));
+            }
+            _ => { /* do nothing */ }
+        }
+    }
+
+    Err(ParserError::InvalidGrammer(
+        "sample clause has no sample rate".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_sample(sample_str: &str) -> Result<SampleClause> {
+        let pair = QueryGrammer::parse(Rule::SAMPLE_CLAUSE, sample_str)
+            .unwrap()
+            .next()
+            .unwrap();
+        parse(pair)
+    }
+
+    #[test]
+    fn test_parse_sample_1() {
+        assert_eq!(
+            parse_sample("sample 1%").unwrap(),
+            SampleClause::Percent(1f64)
+        );
+        assert_eq!(
+            parse_sample("SAMPLE 0.5 %").unwrap(),
+            SampleClause::Percent(0.5f64)
+        );
+        assert_eq!(
+            parse_sample("sample every 100").unwrap(),
+            SampleClause::Every(100)
+        );
+    }
+
+    #[test]
+    fn test_parse_sample_2() {
+        assert!(parse_sample("sample 0%").is_err());
+        assert!(parse_sample("sample 101%").is_err());
+        assert!(parse_sample("sample every 0").is_err());
+        assert!(QueryGrammer::parse(Rule::SAMPLE_CLAUSE, "sample 10").is_err());
+    }
+}
@@ -5,6 +5,14 @@ use crate::tsdb::query::parser::*;
 #[derive(Debug, PartialEq)]
 pub struct SelectClause<'q> {
     pub select_columns: Vec<Column<'q>>,
+    /// `top(column, n)` or `bottom(column, n)`. the column is in the select_columns.
+    pub rank: Option<SelectRank>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SelectRank {
+    Top(usize),
+    Bottom(usize),
 }
 
 pub fn parse(pair: Pair<'_, Rule>) -> Result<SelectClause<'_>> {
@@ -17,6 +25,7 @@ pub fn parse(pair: Pair<'_, Rule>) -> Result<SelectClause<'_>> {
     }
 
     let mut select_columns: Option<Vec<Column<'_>>> = None;
+    let mut rank: Option<SelectRank> = None;
     for each in pair.into_inner() {
         match each.as_rule() {
             Rule::COLUMNS => {
@@ -24,6 +33,11 @@ pub fn parse(pair: Pair<'_, Rule>) -> Result<SelectClause<'_>> {
                 select_columns = Some(columns)
             }
             Rule::KW_ASTERISK => select_columns = Some(vec![Column::Asterick]),
+            Rule::RANK_FN => {
+                let (column, select_rank) = parse_rank_fn(each)?;
+                select_columns = Some(vec![column]);
+                rank = Some(select_rank);
+            }
             _ => {}
         }
     }
@@ -34,7 +48,33 @@ pub fn parse(pair: Pair<'_, Rule>) -> Result<SelectClause<'_>> {
             Err(ParserError::EmptyColumns("select clause".to_string()))
         }
         None => Err(ParserError::EmptyColumns("select clause".to_string())),
-        Some(select_columns) => Ok(SelectClause { select_columns }),
+        Some(select_columns) => Ok(SelectClause {
+            select_columns,
+            rank,
+        }),
+    }
+}
+
+fn parse_rank_fn(pair: Pair<'_, Rule>) -> Result<(Column<'_>, SelectRank)> {
+    let mut is_top: Option<bool> = None;
+    let mut column: Option<Column<'_>> = None;
+    let mut n: Option<usize> = None;
+    for each in pair.into_inner() {
+        match each.as_rule() {
+            Rule::KW_TOP => is_top = Some(true),
+            Rule::KW_BOTTOM => is_top = Some(false),
+            Rule::COLUMN_NAME => column = Some(Column::ColumnName(ColumnName(each.as_str()))),
+            Rule::ASCII_DIGITS => n = Some(each.as_str().parse::<usize>()?),
+            _ => {}
+        }
+    }
+
+    match (is_top, column, n) {
+        (Some(true), Some(column), Some(n)) => Ok((column, SelectRank::Top(n))),
+        (Some(false), Some(column), Some(n)) => Ok((column, SelectRank::Bottom(n))),
+        _ => Err(ParserError::InvalidGrammer(
+            "top or bottom needs a column and the number of the datapoints".to_string(),
+        )),
     }
 }
 
@@ -58,9 +98,56 @@ mod test {
                 select_columns: vec![
                     Column::ColumnName(ColumnName("ts")),
                     Column::ColumnName(ColumnName("some"))
-                ]
+                ],
+                rank: None,
             },
             parsed.unwrap()
         )
     }
+
+    #[test]
+    fn parse_select_rank_1() {
+        let pairs = QueryGrammer::parse(Rule::SELECT_CLAUSE, "select top(price, 10)");
+
+        assert!(pairs.is_ok());
+        let parsed = parse(pairs.unwrap().next().unwrap());
+        assert_eq!(
+            SelectClause {
+                select_columns: vec![Column::ColumnName(ColumnName("price"))],
+                rank: Some(SelectRank::Top(10)),
+            },
+            parsed.unwrap()
+        );
+
+        let pairs = QueryGrammer::parse(Rule::SELECT_CLAUSE, "SELECT bottom( price ,3 )");
+
+        assert!(pairs.is_ok());
+        let parsed = parse(pairs.unwrap().next().unwrap());
+        assert_eq!(
+            SelectClause {
+                select_columns: vec![Column::ColumnName(ColumnName("price"))],
+                rank: Some(SelectRank::Bottom(3)),
+            },
+            parsed.unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_select_rank_2() {
+        // columns which name starts with the keyword
+        let pairs = QueryGrammer::parse(Rule::SELECT_CLAUSE, "select top_price, bottom");
+
+        assert!(pairs.is_ok());
+        let parsed = parse(pairs.unwrap().next().unwrap());
+        assert_eq!(
+            SelectClause {
+                select_columns: vec![
+                    Column::ColumnName(ColumnName("top_price")),
+                    Column::ColumnName(ColumnName("bottom"))
+                ],
+                rank: None,
+            },
+            parsed.unwrap()
+        );
+    }
 }
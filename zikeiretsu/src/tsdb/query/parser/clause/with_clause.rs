@@ -1,9 +1,12 @@
 use super::super::boolean::parse_bool;
+use super::super::duration_parser::parse_duration_delta;
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
 use crate::tsdb::query::parser::*;
 use crate::tsdb::TimeZoneAndOffset;
 use pest::iterators::Pair;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OutputFormat {
@@ -24,6 +27,8 @@ pub struct WithClause<'q> {
     pub def_use_cache: bool,
     pub def_format_datetime: bool,
     pub def_force_sync_cloud: bool,
+    pub def_timeout: Option<Duration>,
+    pub def_duplicate_policy: Option<DuplicatePolicy>,
 }
 
 pub fn parse(pair: Pair<'_, Rule>) -> Result<WithClause<'_>> {
@@ -45,6 +50,8 @@ pub fn parse(pair: Pair<'_, Rule>) -> Result<WithClause<'_>> {
         def_format_datetime: true,
         def_use_cache: true,
         def_force_sync_cloud: false,
+        def_timeout: None,
+        def_duplicate_policy: None,
     };
     for each in pair.into_inner() {
         if each.as_rule() == Rule::WITH_CLAUSE_DEFINES {
@@ -133,6 +140,35 @@ pub fn parse(pair: Pair<'_, Rule>) -> Result<WithClause<'_>> {
                         }
                     }
 
+                    Rule::DEFINE_TIMEOUT => {
+                        for each_inner in each_define.into_inner() {
+                            if each_inner.as_rule() == Rule::DURATION_DELTA {
+                                let timeout_str = each_inner.as_str();
+                                let timeout_micro_sec = *parse_duration_delta(each_inner)?;
+                                if timeout_micro_sec <= 0 {
+                                    return Err(ParserError::InvalidGrammer(format!(
+                                        "timeout must be positive: {timeout_str}"
+                                    )));
+                                }
+                                with_clause.def_timeout =
+                                    Some(Duration::from_micros(timeout_micro_sec as u64));
+                            }
+                        }
+                    }
+
+                    Rule::DEFINE_DUPLICATE_POLICY => {
+                        for each_inner in each_define.into_inner() {
+                            if each_inner.as_rule() == Rule::DUPLICATE_POLICY {
+                                let duplicate_policy = each_inner
+                                    .as_str()
+                                    .to_lowercase()
+                                    .parse::<DuplicatePolicy>()
+                                    .map_err(ParserError::InvalidGrammer)?;
+                                with_clause.def_duplicate_policy = Some(duplicate_policy);
+                            }
+                        }
+                    }
+
                     _ => {
                         return Err(ParserError::InvalidGrammer(format!(
                             "invalid defines in with clause:{}",
@@ -281,4 +317,33 @@ mod test {
         assert_eq!(result.def_output, Some(OutputFormat::Parquet));
         assert_eq!(result.def_output_file_path, None,);
     }
+
+    #[test]
+    fn test_parse_with_10() {
+        let query = r"with timeout = 30 seconds          ";
+        let mut pairs = QueryGrammer::parse(Rule::WITH_CLAUSE, query).unwrap();
+        let result = parse(pairs.next().unwrap()).unwrap();
+        assert_eq!(result.def_columns, None);
+        assert_eq!(result.def_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_parse_with_11() {
+        let query = r"with timeout = -30 seconds          ";
+        let mut pairs = QueryGrammer::parse(Rule::WITH_CLAUSE, query).unwrap();
+        let result = parse(pairs.next().unwrap());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_with_12() {
+        let query = r"with duplicate_policy = KEEP_LAST, timeout = 30 seconds          ";
+        let mut pairs = QueryGrammer::parse(Rule::WITH_CLAUSE, query).unwrap();
+        let result = parse(pairs.next().unwrap()).unwrap();
+        assert_eq!(result.def_duplicate_policy, Some(DuplicatePolicy::KeepLast));
+        assert_eq!(result.def_timeout, Some(Duration::from_secs(30)));
+
+        let query = r"with duplicate_policy = keep_nothing          ";
+        assert!(QueryGrammer::parse(Rule::WITH_CLAUSE, query).is_err());
+    }
 }
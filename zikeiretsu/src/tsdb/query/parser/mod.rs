@@ -89,6 +89,12 @@ pub enum ParserError {
 
     #[error("invalid timezone: {0}")]
     InvalidTimeZone(String),
+
+    #[error("invalid sample: {0}. e.g. `sample 10%` or `sample every 100`")]
+    InvalidSample(String),
+
+    #[error("invalid insert value: {0}")]
+    InvalidInsertValue(String),
 }
 
 pub type Result<T> = std::result::Result<T, ParserError>;
@@ -98,7 +104,10 @@ pub struct ParsedQuery<'q> {
     pub with: Option<WithClause<'q>>,
     pub select: Option<SelectClause<'q>>,
     pub from: Option<FromClause<'q>>,
+    pub sample: Option<SampleClause>,
     pub r#where: Option<WhereClause<'q>>,
+    pub insert: Option<InsertClause<'q>>,
+    pub delete: Option<DeleteClause<'q>>,
 }
 
 impl<'q> ParsedQuery<'q> {
@@ -107,7 +116,10 @@ impl<'q> ParsedQuery<'q> {
             with: None,
             select: None,
             from: None,
+            sample: None,
             r#where: None,
+            insert: None,
+            delete: None,
         }
     }
 }
@@ -149,6 +161,18 @@ pub fn parse_query<'q>(query_str: &'q str) -> Result<ParsedQuery<'q>> {
                 let from_clause = from_clause::parse(each_pair)?;
                 parsed_query.from = Some(from_clause);
             }
+            Rule::INSERT_CLAUSE => {
+                let insert_clause = insert_clause::parse(each_pair)?;
+                parsed_query.insert = Some(insert_clause);
+            }
+            Rule::DELETE_CLAUSE => {
+                let delete_clause = delete_clause::parse(each_pair)?;
+                parsed_query.delete = Some(delete_clause);
+            }
+            Rule::SAMPLE_CLAUSE => {
+                let sample_clause = sample_clause::parse(each_pair)?;
+                parsed_query.sample = Some(sample_clause);
+            }
             Rule::WHERE_CLAUSE => {
                 let where_clause = where_clause::parse(each_pair)?;
                 parsed_query.r#where = Some(where_clause);
@@ -479,4 +503,57 @@ where ts in ('2012-12-13 9:00:00', '2012-12-13 9:00:00')
 
         assert!(parsed_query.is_ok());
     }
+
+    #[test]
+    fn parse_query_11() {
+        let query = r#"with
+  	    cols = [_, volume, price]
+     select top(price, 10)
+     from trades
+     where ts in (yesterday(), today())
+ "#;
+
+        let parsed_query = parse_query(query);
+
+        assert!(parsed_query.is_ok());
+        let parsed_query = parsed_query.unwrap();
+        assert_eq!(
+            parsed_query.select.unwrap().rank,
+            Some(clause::SelectRank::Top(10))
+        );
+    }
+
+    #[test]
+    fn parse_query_12() {
+        let query = r#"with
+  	    tz = Asia/Tokyo
+     insert into trades values ('2022-01-01 00:00:00', 1.5, true), ('2022-01-01 00:00:01', 2, false);
+ "#;
+
+        let parsed_query = parse_query(query);
+
+        assert!(parsed_query.is_ok());
+        let parsed_query = parsed_query.unwrap();
+        assert!(parsed_query.select.is_none());
+        assert_eq!(parsed_query.insert.unwrap().rows.len(), 2);
+    }
+
+    #[test]
+    fn parse_query_13() {
+        let query = r#"with
+  	    tz = Asia/Tokyo
+     delete from trades where ts in ('2022-01-01 00:00:00', '2022-01-01 01:00:00');
+ "#;
+
+        let parsed_query = parse_query(query);
+
+        assert!(parsed_query.is_ok());
+        let parsed_query = parsed_query.unwrap();
+        assert!(parsed_query.select.is_none());
+        assert_eq!(parsed_query.delete.unwrap().metrics, "trades");
+        assert!(parsed_query.r#where.is_some());
+
+        // the where clause is required
+        assert!(parse_query("delete from trades").is_err());
+    }
 }
@@ -0,0 +1,472 @@
+use super::super::persisted_error;
+use super::write::{
+    create_cloud_lock_file, create_local_lock_file, remove_local_block, replace_block_list_file,
+    write_error_files,
+};
+use super::{
+    block, block_list, block_timestamp_to_block_file_path, cloud_setting::*, CacheSetting, Result,
+    StorageApiError,
+};
+use crate::tsdb::cloudstorage::*;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::timestamp_nano::TimestampNano;
+use crate::tsdb::TimeSeriesDataFrame;
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+pub const DEFAULT_COMPACTION_MAX_TIMESTAMP_NUM: usize = 100_000;
+pub const DEFAULT_COMPACTION_MAX_TIME_SPAN_SEC: u64 = 60 * 60 * 24;
+
+/// upper limits of a block made by the compaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionSetting {
+    pub max_timestamp_num: usize,
+    pub max_time_span_sec: Option<u64>,
+}
+
+impl Default for CompactionSetting {
+    fn default() -> Self {
+        Self {
+            max_timestamp_num: DEFAULT_COMPACTION_MAX_TIMESTAMP_NUM,
+            max_time_span_sec: Some(DEFAULT_COMPACTION_MAX_TIME_SPAN_SEC),
+        }
+    }
+}
+
+impl CompactionSetting {
+    pub fn new(max_timestamp_num: usize) -> Self {
+        Self {
+            max_timestamp_num,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_time_span_sec(mut self, max_time_span_sec: Option<u64>) -> Self {
+        self.max_time_span_sec = max_time_span_sec;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionSummary {
+    pub block_num_before: usize,
+    pub block_num_after: usize,
+    /// the blocks newly written by merging the others.
+    pub compacted_blocks: Vec<block_list::BlockTimestamp>,
+}
+
+struct CompactedBlock {
+    block_meta: block_list::BlockMetaInfo,
+    block_file_dir: PathBuf,
+    block_file_path: PathBuf,
+    /// indices of the merged blocks in the block list.
+    merged: Range<usize>,
+}
+
+/// groups of the adjacent blocks in the block list to be merged. each group has two blocks at least.
+fn plan_compaction(
+    block_meta_infos: &[block_list::BlockMetaInfo],
+    setting: &CompactionSetting,
+) -> Vec<Range<usize>> {
+    let mut groups = Vec::<Range<usize>>::new();
+    let mut push_group = |group: Range<usize>, block_timestamp: &block_list::BlockTimestamp| {
+        // the block of the same timestamp would be overwritten before the block list is replaced,
+        // even if it is in the group, since the merged block is written to the path of the timestamp.
+        let collides = block_meta_infos
+            .iter()
+            .any(|block_meta| block_meta.block_timestamp == *block_timestamp);
+        if group.len() > 1 && !collides {
+            groups.push(group)
+        }
+    };
+
+    let mut group_start = 0;
+    let mut merged_block_timestamp: Option<block_list::BlockTimestamp> = None;
+    let mut merged_timestamp_num = 0;
+    for (idx, block_meta) in block_meta_infos.iter().enumerate() {
+        if let Some(mut merged) = merged_block_timestamp {
+            merged.merge(&block_meta.block_timestamp);
+            let within_span = match setting.max_time_span_sec {
+                Some(max_time_span_sec) => merged.until_sec - merged.since_sec <= max_time_span_sec,
+                None => true,
+            };
+            if within_span
+                && merged_timestamp_num + block_meta.timestamp_num <= setting.max_timestamp_num
+            {
+                merged_block_timestamp = Some(merged);
+                merged_timestamp_num += block_meta.timestamp_num;
+                continue;
+            }
+
+            push_group(group_start..idx, merged_block_timestamp.as_ref().unwrap());
+        }
+        group_start = idx;
+        merged_block_timestamp = Some(block_meta.block_timestamp);
+        merged_timestamp_num = block_meta.timestamp_num;
+    }
+    if let Some(merged) = merged_block_timestamp {
+        push_group(group_start..block_meta_infos.len(), &merged);
+    }
+    groups
+}
+
+/// merges the adjacent blocks in the block list into larger ones up to the limits of the setting,
+/// then replaces the block list at once.
+/// the blocks on the cloud storage are replaced as well under the cloud lock file if the datas are uploaded after write.
+pub async fn compact_blocks<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    setting: &CompactionSetting,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<CompactionSummary> {
+    let cloud_infos = match cloud_storage_and_setting {
+        Some((cloud_storage, cloud_setting)) if cloud_setting.upload_data_after_write => {
+            let cloud_lock_file_path =
+                create_cloud_lock_file(writer_id, metrics, cloud_storage).await?;
+            Some((cloud_lock_file_path, cloud_storage, cloud_setting))
+        }
+        _ => None,
+    };
+
+    let result = compact_blocks_under_lock(
+        db_dir.as_ref(),
+        writer_id,
+        metrics,
+        setting,
+        cloud_storage_and_setting,
+        cloud_infos
+            .as_ref()
+            .map(|(_, cloud_storage, cloud_setting)| (*cloud_storage, *cloud_setting)),
+    )
+    .await;
+
+    if let Some((cloud_lock_file_path, _, _)) = cloud_infos {
+        cloud_lock_file_path.remove().await?;
+    }
+
+    result
+}
+
+async fn compact_blocks_under_lock(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    setting: &CompactionSetting,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+    upload_to: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<CompactionSummary> {
+    let _lockfile = create_local_lock_file(db_dir, writer_id, metrics)?;
+
+    let cache_setting = CacheSetting::none();
+    let mut block_list = super::read::read_block_list(
+        "", // database name is unnecessary if no cache wil be used
+        db_dir,
+        metrics,
+        &cache_setting,
+        cloud_storage_and_setting,
+    )
+    .await?;
+
+    let block_num_before = block_list.block_num();
+    let groups = plan_compaction(&block_list.block_meta_infos, setting);
+    if groups.is_empty() {
+        return Ok(CompactionSummary {
+            block_num_before,
+            block_num_after: block_num_before,
+            compacted_blocks: vec![],
+        });
+    }
+
+    // write the merged blocks first. the block list still refers to the old blocks.
+    let mut compacted_blocks = Vec::<CompactedBlock>::new();
+    for group in groups.into_iter() {
+        let mut merged_block_timestamp = block_list.block_meta_infos[group.start].block_timestamp;
+        let mut merged_dataframe = TimeSeriesDataFrame::empty();
+        // merge in the write order to keep the order of the rows of the same timestamp
+        let mut block_metas = block_list.block_meta_infos[group.clone()].to_vec();
+        block_metas.sort_by_key(|block_meta| block_meta.write_seq);
+        let write_seq = block_metas.last().map(|block_meta| block_meta.write_seq);
+        for block_meta in block_metas.iter() {
+            merged_block_timestamp.merge(&block_meta.block_timestamp);
+            let mut block = super::read::read_block(
+                "",
+                db_dir,
+                metrics,
+                None,
+                &block_meta.block_timestamp,
+                &cache_setting,
+                cloud_storage_and_setting,
+            )
+            .await?;
+            merged_dataframe.merge_sorted(&mut block)?;
+        }
+        let datapoints = merged_dataframe.into_datapoints()?;
+        let timestamp_num = datapoints
+            .iter()
+            .map(|datapoint| datapoint.timestamp_nano)
+            .collect::<HashSet<_>>()
+            .len();
+
+        let (block_file_dir, block_file_path) =
+            block_timestamp_to_block_file_path(db_dir, metrics, &merged_block_timestamp);
+        fs::create_dir_all(block_file_dir.as_path())
+            .map_err(StorageApiError::CreateBlockFileError)?;
+        let tmp_block_file_path = block_file_path.with_extension("tmp");
+        block::write_to_block_file(&tmp_block_file_path, &datapoints)?;
+        fs::rename(&tmp_block_file_path, &block_file_path)
+            .map_err(StorageApiError::CreateBlockFileError)?;
+
+        compacted_blocks.push(CompactedBlock {
+            block_meta: block_list::BlockMetaInfo::new(merged_block_timestamp, timestamp_num)
+                .with_write_seq(write_seq.unwrap_or_default()),
+            block_file_dir,
+            block_file_path,
+            merged: group,
+        });
+    }
+
+    // replace the block list at once. the merged block keeps the position of the last block
+    // of the group since the block list is sorted by until_sec.
+    let old_block_meta_infos = std::mem::take(&mut block_list.block_meta_infos);
+    {
+        let mut compacted = compacted_blocks.iter().peekable();
+        let mut idx = 0;
+        while idx < old_block_meta_infos.len() {
+            match compacted.peek() {
+                Some(compacted_block) if compacted_block.merged.start == idx => {
+                    block_list.block_meta_infos.push(compacted_block.block_meta);
+                    idx = compacted_block.merged.end;
+                    compacted.next();
+                }
+                _ => {
+                    block_list.block_meta_infos.push(old_block_meta_infos[idx]);
+                    idx += 1;
+                }
+            }
+        }
+    }
+    block_list.update_updated_at(TimestampNano::now());
+    let block_num_after = block_list.block_num();
+
+    let block_list_file_path = replace_block_list_file(db_dir, metrics, block_list).await?;
+
+    // remove the merged blocks. none of them has the path of the compacted blocks.
+    let mut removed_block_timestamps = Vec::<block_list::BlockTimestamp>::new();
+    for compacted_block in compacted_blocks.iter() {
+        for block_meta in old_block_meta_infos[compacted_block.merged.clone()].iter() {
+            let block_timestamp = block_meta.block_timestamp;
+            remove_local_block(db_dir, metrics, &block_timestamp).await?;
+            removed_block_timestamps.push(block_timestamp);
+        }
+    }
+
+    if let Some((cloud_storage, cloud_setting)) = upload_to {
+        let upload_result = replace_blocks_on_cloud(
+            &block_list_file_path,
+            &compacted_blocks,
+            &removed_block_timestamps,
+            metrics,
+            cloud_storage,
+        )
+        .await;
+
+        match upload_result {
+            Ok(_) => {
+                if cloud_setting.remove_local_file_after_upload {
+                    for compacted_block in compacted_blocks.iter() {
+                        fs::remove_dir_all(compacted_block.block_file_dir.as_path())
+                            .map_err(StorageApiError::RemoveBlockDirError)?;
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("failed to replace the compacted blocks on the cloud :{e:?}");
+                // the merged blocks are recorded as well so that their removal is retried.
+                let block_timestamps: Vec<block_list::BlockTimestamp> = compacted_blocks
+                    .iter()
+                    .map(|compacted_block| compacted_block.block_meta.block_timestamp)
+                    .chain(removed_block_timestamps.iter().copied())
+                    .collect();
+                write_error_files(
+                    db_dir,
+                    metrics,
+                    persisted_error::PersistedErrorType::FailedToUploadBlockOrBLockList,
+                    &block_timestamps,
+                    Some(format!(
+                        "failed to replace the compacted blocks. error:{e:?}"
+                    )),
+                )
+                .await?;
+            }
+        }
+    }
+
+    Ok(CompactionSummary {
+        block_num_before,
+        block_num_after,
+        compacted_blocks: compacted_blocks
+            .into_iter()
+            .map(|compacted_block| compacted_block.block_meta.block_timestamp)
+            .collect(),
+    })
+}
+
+/// upload the new blocks and the block list, then remove the merged blocks.
+async fn replace_blocks_on_cloud(
+    block_list_file_path: &Path,
+    compacted_blocks: &[CompactedBlock],
+    removed_block_timestamps: &[block_list::BlockTimestamp],
+    metrics: &Metrics,
+    cloud_storage: &CloudStorage,
+) -> Result<()> {
+    for compacted_block in compacted_blocks.iter() {
+        let cloud_block_file_path = CloudBlockFilePath::new(
+            metrics,
+            &compacted_block.block_meta.block_timestamp,
+            cloud_storage,
+        );
+        cloud_block_file_path
+            .upload(&compacted_block.block_file_path)
+            .await?;
+    }
+
+    let cloud_block_list_file_path = CloudBlockListFilePath::new(metrics, cloud_storage);
+    cloud_block_list_file_path
+        .upload(block_list_file_path)
+        .await?;
+
+    for block_timestamp in removed_block_timestamps.iter() {
+        let cloud_block_file_path =
+            CloudBlockFilePath::new(metrics, block_timestamp, cloud_storage);
+        cloud_block_file_path.remove().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::storage::api::write;
+    use crate::tsdb::*;
+
+    macro_rules! blmeta {
+        ($since:expr,$until:expr,$num:expr) => {
+            block_list::BlockMetaInfo::new(
+                block_list::BlockTimestamp::new(
+                    TimestampSec::new($since),
+                    TimestampSec::new($until),
+                ),
+                $num,
+            )
+        };
+    }
+
+    #[test]
+    fn plan_compaction_test() {
+        let block_metas = vec![
+            blmeta!(10, 20, 2),
+            blmeta!(20, 30, 2),
+            blmeta!(30, 40, 2),
+            blmeta!(40, 50, 5),
+            blmeta!(50, 60, 1),
+            blmeta!(100, 200, 1),
+        ];
+
+        let setting = CompactionSetting::new(6).with_max_time_span_sec(Some(50));
+        assert_eq!(plan_compaction(&block_metas, &setting), vec![0..3, 3..5]);
+
+        let setting = CompactionSetting::new(100).with_max_time_span_sec(None);
+        assert_eq!(plan_compaction(&block_metas, &setting), vec![0..6]);
+
+        let setting = CompactionSetting::new(1);
+        assert!(plan_compaction(&block_metas, &setting).is_empty());
+
+        // the merged block would collide with the block out of the group
+        let block_metas = vec![blmeta!(10, 15, 1), blmeta!(15, 20, 1), blmeta!(10, 20, 5)];
+        let setting = CompactionSetting::new(2);
+        assert!(plan_compaction(&block_metas, &setting).is_empty());
+
+        // the merged block would collide with the block in the group
+        let block_metas = vec![blmeta!(12, 15, 1), blmeta!(10, 20, 5)];
+        let setting = CompactionSetting::new(100).with_max_time_span_sec(None);
+        assert!(plan_compaction(&block_metas, &setting).is_empty());
+        let block_metas = vec![blmeta!(10, 15, 1), blmeta!(15, 20, 5), blmeta!(10, 20, 1)];
+        assert!(plan_compaction(&block_metas, &setting).is_empty());
+    }
+
+    #[tokio::test]
+    async fn compact_blocks_test() {
+        let temp_dir = tempdir::TempDir::new("compact_blocks_test").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("compaction").unwrap();
+        let writer_id = Uuid::new_v4();
+
+        let datapoint = |sec: u64, value: f64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(value)],
+            )
+        };
+        for block in [
+            vec![datapoint(10, 1.0), datapoint(11, 2.0)],
+            vec![datapoint(12, 3.0)],
+            vec![datapoint(13, 4.0), datapoint(14, 5.0)],
+            vec![datapoint(100, 6.0)],
+        ] {
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+        let search_all = || async {
+            super::super::read::search_dataframe(
+                "db",
+                db_dir,
+                &metrics,
+                None,
+                &DatapointsSearchCondition::all(),
+                &CacheSetting::none(),
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        };
+        let before = search_all().await;
+
+        let setting = CompactionSetting::new(100).with_max_time_span_sec(Some(10));
+        let summary = compact_blocks(db_dir, &writer_id, &metrics, &setting, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            CompactionSummary {
+                block_num_before: 4,
+                block_num_after: 2,
+                compacted_blocks: vec![block_list::BlockTimestamp::new(
+                    TimestampSec::new(10),
+                    TimestampSec::new(15)
+                )],
+            }
+        );
+
+        assert_eq!(search_all().await, before);
+        let (old_block_dir, _) = block_timestamp_to_block_file_path(
+            db_dir,
+            &metrics,
+            &block_list::BlockTimestamp::new(TimestampSec::new(12), TimestampSec::new(13)),
+        );
+        assert!(!old_block_dir.exists());
+
+        // nothing to be compacted
+        let summary = compact_blocks(db_dir, &writer_id, &metrics, &setting, None)
+            .await
+            .unwrap();
+        assert_eq!(summary.block_num_before, 2);
+        assert_eq!(summary.block_num_after, 2);
+        assert!(summary.compacted_blocks.is_empty());
+    }
+}
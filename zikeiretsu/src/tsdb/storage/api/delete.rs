@@ -0,0 +1,434 @@
+use super::super::persisted_error;
+use super::write::{
+    create_cloud_lock_file, create_local_lock_file, remove_local_block, replace_block_list_file,
+    write_error_files,
+};
+use super::{
+    block, block_list, block_timestamp_to_block_file_path, cloud_setting::*, CacheSetting, Result,
+    StorageApiError,
+};
+use crate::tsdb::cloudstorage::*;
+use crate::tsdb::datapoint::{DataPoint, DatapointsRange};
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::timestamp_nano::TimestampNano;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeletionSummary {
+    /// the blocks whose all datas are in the range.
+    pub dropped_blocks: Vec<block_list::BlockTimestamp>,
+    /// the blocks that some datas remain in.
+    pub rewritten_blocks: Vec<block_list::BlockTimestamp>,
+    pub deleted_timestamp_num: usize,
+    pub block_num_after: usize,
+}
+
+impl DeletionSummary {
+    fn nothing_deleted(block_num_after: usize) -> Self {
+        Self {
+            dropped_blocks: vec![],
+            rewritten_blocks: vec![],
+            deleted_timestamp_num: 0,
+            block_num_after,
+        }
+    }
+}
+
+struct RewrittenBlock {
+    block_meta: block_list::BlockMetaInfo,
+    block_file_path: PathBuf,
+}
+
+/// deletes the datapoints in the range from the persisted blocks.
+/// the blocks whose all datas are in the range are dropped, and the others that overlap the range are rewritten.
+/// the blocks on the cloud storage are replaced as well under the cloud lock file if the datas are uploaded after write.
+pub async fn delete_datapoints<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    range: &DatapointsRange,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<DeletionSummary> {
+    let cloud_infos = match cloud_storage_and_setting {
+        Some((cloud_storage, cloud_setting)) if cloud_setting.upload_data_after_write => {
+            let cloud_lock_file_path =
+                create_cloud_lock_file(writer_id, metrics, cloud_storage).await?;
+            Some((cloud_lock_file_path, cloud_storage))
+        }
+        _ => None,
+    };
+
+    let result = delete_datapoints_under_lock(
+        db_dir.as_ref(),
+        writer_id,
+        metrics,
+        range,
+        cloud_storage_and_setting,
+        cloud_infos
+            .as_ref()
+            .map(|(_, cloud_storage)| *cloud_storage),
+    )
+    .await;
+
+    if let Some((cloud_lock_file_path, _)) = cloud_infos {
+        cloud_lock_file_path.remove().await?;
+    }
+
+    result
+}
+
+async fn delete_datapoints_under_lock(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    range: &DatapointsRange,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+    upload_to: Option<&CloudStorage>,
+) -> Result<DeletionSummary> {
+    let _lockfile = create_local_lock_file(db_dir, writer_id, metrics)?;
+
+    let cache_setting = CacheSetting::none();
+    let block_list = super::read::read_block_list(
+        "", // database name is unnecessary if no cache wil be used
+        db_dir,
+        metrics,
+        &cache_setting,
+        cloud_storage_and_setting,
+    )
+    .await;
+    let mut block_list = match block_list {
+        Ok(block_list) => block_list,
+        Err(StorageApiError::NoBlockListFile(_)) => return Ok(DeletionSummary::nothing_deleted(0)),
+        Err(e) => return Err(e),
+    };
+
+    let mut dropped_blocks = Vec::<block_list::BlockTimestamp>::new();
+    let mut rewritten_blocks = Vec::<RewrittenBlock>::new();
+    let mut deleted_timestamp_num = 0;
+    let mut block_metas = Vec::<block_list::BlockMetaInfo>::new();
+    for block_meta in block_list.block_meta_infos.iter() {
+        let block_timestamp = block_meta.block_timestamp;
+        // the timestamps in the block are in [since_sec, until_sec)
+        let block_since = block_timestamp.since_sec.as_timestamp_nano();
+        let block_until = block_timestamp.until_sec.as_timestamp_nano();
+        let (range_since, range_until) = range.as_ref();
+        let out_of_range = matches!(range_until, Some(until) if *until <= block_since)
+            || matches!(range_since, Some(since) if block_until <= *since);
+        if out_of_range {
+            block_metas.push(*block_meta);
+            continue;
+        }
+
+        if range.contains_whole(
+            &block_since,
+            &TimestampNano::new(block_until.as_inner() - 1),
+        ) {
+            deleted_timestamp_num += block_meta.timestamp_num;
+            dropped_blocks.push(block_timestamp);
+            continue;
+        }
+
+        let block = super::read::read_block(
+            "",
+            db_dir,
+            metrics,
+            None,
+            &block_timestamp,
+            &cache_setting,
+            cloud_storage_and_setting,
+        )
+        .await?;
+        let datapoints: Vec<DataPoint> = block
+            .into_datapoints()?
+            .into_iter()
+            .filter(|datapoint| !range.contains(&datapoint.timestamp_nano))
+            .collect();
+        let timestamp_num = datapoints
+            .iter()
+            .map(|datapoint| datapoint.timestamp_nano)
+            .collect::<HashSet<_>>()
+            .len();
+        if timestamp_num == block_meta.timestamp_num {
+            block_metas.push(*block_meta);
+            continue;
+        }
+
+        deleted_timestamp_num += block_meta.timestamp_num - timestamp_num;
+        if datapoints.is_empty() {
+            dropped_blocks.push(block_timestamp);
+            continue;
+        }
+
+        // the rewritten block keeps its block timestamp so that the order of the block list never changes
+        let (block_file_dir, block_file_path) =
+            block_timestamp_to_block_file_path(db_dir, metrics, &block_timestamp);
+        fs::create_dir_all(block_file_dir.as_path())
+            .map_err(StorageApiError::CreateBlockFileError)?;
+        let tmp_block_file_path = block_file_path.with_extension("tmp");
+        block::write_to_block_file(&tmp_block_file_path, &datapoints)?;
+        fs::rename(&tmp_block_file_path, &block_file_path)
+            .map_err(StorageApiError::CreateBlockFileError)?;
+        super::read::remove_block_cache(metrics, &block_timestamp).await;
+
+        let block_meta = block_list::BlockMetaInfo::new(block_timestamp, timestamp_num)
+            .with_write_seq(block_meta.write_seq);
+        block_metas.push(block_meta);
+        rewritten_blocks.push(RewrittenBlock {
+            block_meta,
+            block_file_path,
+        });
+    }
+
+    let block_num_after = block_metas.len();
+    if dropped_blocks.is_empty() && rewritten_blocks.is_empty() {
+        return Ok(DeletionSummary::nothing_deleted(block_num_after));
+    }
+
+    // replace the block list at once. the dropped blocks are unreachable after that.
+    block_list.block_meta_infos = block_metas;
+    block_list.update_updated_at(TimestampNano::now());
+    let block_list_file_path = replace_block_list_file(db_dir, metrics, block_list).await?;
+    for block_timestamp in dropped_blocks.iter() {
+        remove_local_block(db_dir, metrics, block_timestamp).await?;
+    }
+
+    if let Some(cloud_storage) = upload_to {
+        let replace_result = replace_blocks_on_cloud(
+            &block_list_file_path,
+            block_num_after,
+            &rewritten_blocks,
+            &dropped_blocks,
+            metrics,
+            cloud_storage,
+        )
+        .await;
+
+        if let Err(e) = replace_result {
+            log::error!("failed to replace the blocks deleted on the cloud :{e:?}");
+            write_replace_error_files(
+                db_dir,
+                metrics,
+                &rewritten_blocks,
+                &dropped_blocks,
+                format!("failed to delete the datapoints in {range}. error:{e:?}"),
+            )
+            .await?;
+        }
+    }
+
+    Ok(DeletionSummary {
+        dropped_blocks,
+        rewritten_blocks: rewritten_blocks
+            .into_iter()
+            .map(|rewritten_block| rewritten_block.block_meta.block_timestamp)
+            .collect(),
+        deleted_timestamp_num,
+        block_num_after,
+    })
+}
+
+/// records all the rewritten and the dropped blocks so that the replacement on the cloud is retried.
+async fn write_replace_error_files(
+    db_dir: &Path,
+    metrics: &Metrics,
+    rewritten_blocks: &[RewrittenBlock],
+    dropped_blocks: &[block_list::BlockTimestamp],
+    detail: String,
+) -> Result<()> {
+    let block_timestamps: Vec<block_list::BlockTimestamp> = rewritten_blocks
+        .iter()
+        .map(|rewritten_block| rewritten_block.block_meta.block_timestamp)
+        .chain(dropped_blocks.iter().copied())
+        .collect();
+    write_error_files(
+        db_dir,
+        metrics,
+        persisted_error::PersistedErrorType::FailedToUploadBlockOrBLockList,
+        &block_timestamps,
+        Some(detail),
+    )
+    .await
+}
+
+/// upload the rewritten blocks and the block list, then remove the dropped blocks.
+async fn replace_blocks_on_cloud(
+    block_list_file_path: &Path,
+    block_num_after: usize,
+    rewritten_blocks: &[RewrittenBlock],
+    dropped_blocks: &[block_list::BlockTimestamp],
+    metrics: &Metrics,
+    cloud_storage: &CloudStorage,
+) -> Result<()> {
+    for rewritten_block in rewritten_blocks.iter() {
+        CloudBlockFilePath::new(
+            metrics,
+            &rewritten_block.block_meta.block_timestamp,
+            cloud_storage,
+        )
+        .upload(&rewritten_block.block_file_path)
+        .await?;
+    }
+
+    let cloud_block_list_file_path = CloudBlockListFilePath::new(metrics, cloud_storage);
+    if block_num_after == 0 {
+        cloud_block_list_file_path.remove().await?;
+    } else {
+        cloud_block_list_file_path
+            .upload(block_list_file_path)
+            .await?;
+    }
+
+    for block_timestamp in dropped_blocks.iter() {
+        CloudBlockFilePath::new(metrics, block_timestamp, cloud_storage)
+            .remove()
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::storage::api::write;
+    use crate::tsdb::*;
+
+    #[tokio::test]
+    async fn delete_datapoints_test() {
+        let temp_dir = tempdir::TempDir::new("delete_datapoints_test").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("deletion").unwrap();
+        let writer_id = Uuid::new_v4();
+
+        let datapoint = |sec: u64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(sec as f64)],
+            )
+        };
+        for block in [
+            vec![datapoint(10), datapoint(15), datapoint(19)],
+            vec![datapoint(20), datapoint(29)],
+            vec![datapoint(30), datapoint(35), datapoint(39)],
+            vec![datapoint(100)],
+        ] {
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        let sec = |sec: u64| TimestampNano::new(sec * 1_000_000_000);
+        let block_timestamp = |since: u64, until: u64| {
+            block_list::BlockTimestamp::new(TimestampSec::new(since), TimestampSec::new(until))
+        };
+
+        // the second block is dropped, the first and the third ones are rewritten
+        let range = DatapointsRange::new(Some(sec(15)), Some(sec(31)));
+        let summary = delete_datapoints(db_dir, &writer_id, &metrics, &range, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            DeletionSummary {
+                dropped_blocks: vec![block_timestamp(20, 30)],
+                rewritten_blocks: vec![block_timestamp(10, 20), block_timestamp(30, 40)],
+                deleted_timestamp_num: 5,
+                block_num_after: 3,
+            }
+        );
+
+        let search_all = || async {
+            super::super::read::search_dataframe(
+                "db",
+                db_dir,
+                &metrics,
+                None,
+                &DatapointsSearchCondition::all(),
+                &CacheSetting::none(),
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        };
+        assert_eq!(
+            search_all().await.timestamp_nanos,
+            vec![sec(10), sec(35), sec(39), sec(100)]
+        );
+
+        // nothing in the range
+        let range = DatapointsRange::new(Some(sec(50)), Some(sec(60)));
+        let summary = delete_datapoints(db_dir, &writer_id, &metrics, &range, None)
+            .await
+            .unwrap();
+        assert_eq!(summary, DeletionSummary::nothing_deleted(3));
+
+        // all the datapoints are deleted
+        let summary = delete_datapoints(
+            db_dir,
+            &writer_id,
+            &metrics,
+            &DatapointsRange::since(sec(0)),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.deleted_timestamp_num, 4);
+        assert_eq!(summary.block_num_after, 0);
+        let (block_dir, _) =
+            block_timestamp_to_block_file_path(db_dir, &metrics, &block_timestamp(100, 101));
+        assert!(!block_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn write_replace_error_files_test() {
+        let temp_dir = tempdir::TempDir::new("write_replace_error_files_test").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("deletion").unwrap();
+        let block_timestamp = |since: u64, until: u64| {
+            block_list::BlockTimestamp::new(TimestampSec::new(since), TimestampSec::new(until))
+        };
+
+        // the cloud step failed after two blocks were rewritten and two were dropped
+        let rewritten_blocks: Vec<RewrittenBlock> =
+            [block_timestamp(10, 20), block_timestamp(30, 40)]
+                .into_iter()
+                .map(|block_timestamp| {
+                    let (_, block_file_path) =
+                        block_timestamp_to_block_file_path(db_dir, &metrics, &block_timestamp);
+                    RewrittenBlock {
+                        block_meta: block_list::BlockMetaInfo::new(block_timestamp, 1),
+                        block_file_path,
+                    }
+                })
+                .collect();
+        let dropped_blocks = vec![block_timestamp(20, 30), block_timestamp(40, 50)];
+        write_replace_error_files(
+            db_dir,
+            &metrics,
+            &rewritten_blocks,
+            &dropped_blocks,
+            "error:timeout".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let pending_uploads = super::super::list_pending_uploads(db_dir).unwrap();
+        assert_eq!(pending_uploads.len(), 4);
+        let pending_block_timestamps: HashSet<block_list::BlockTimestamp> = pending_uploads
+            .into_iter()
+            .filter_map(|pending_upload| pending_upload.block_timestamp)
+            .collect();
+        assert_eq!(
+            pending_block_timestamps,
+            HashSet::from([
+                block_timestamp(10, 20),
+                block_timestamp(20, 30),
+                block_timestamp(30, 40),
+                block_timestamp(40, 50),
+            ])
+        );
+    }
+}
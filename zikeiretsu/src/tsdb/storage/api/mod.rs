@@ -1,6 +1,12 @@
 pub mod cloud_setting;
+pub mod compaction;
+pub mod delete;
 pub mod read;
 pub mod repair;
+pub mod retention;
+mod sampling;
+pub mod search_budget;
+pub mod upload_retry;
 pub mod write;
 
 use crate::tsdb::cloudstorage::*;
@@ -9,6 +15,11 @@ use crate::tsdb::storage::{block, block_list, persisted_error};
 use crate::tsdb::timestamp_nano::TimestampNano;
 use crate::tsdb::DataframeError;
 pub use cloud_setting::*;
+pub use compaction::*;
+pub use delete::*;
+pub use retention::*;
+pub use search_budget::*;
+pub use upload_retry::*;
 
 use std::path::{Path, PathBuf};
 use thiserror::Error;
@@ -109,6 +120,20 @@ pub enum StorageApiError {
 
     #[error("data frame error. {0}")]
     DataframeError(#[from] DataframeError),
+
+    #[error("task join error. {0}")]
+    JoinError(#[from] tokio::task::JoinError),
+
+    #[error("deadline of the search exceeded")]
+    DeadlineExceeded,
+
+    #[error("memory budget of the search exceeded. limit: {limit_bytes} bytes, used: {used_bytes} bytes, rows: {rows}, blocks: {blocks}")]
+    MemoryBudgetExceeded {
+        limit_bytes: usize,
+        used_bytes: usize,
+        rows: usize,
+        blocks: usize,
+    },
 }
 
 pub(crate) fn lockfile_path(db_dir: &Path, metrics: &Metrics) -> PathBuf {
@@ -123,8 +148,13 @@ pub(crate) fn block_list_dir_path(db_dir: &Path) -> PathBuf {
     db_dir.join("block_list")
 }
 
+pub(crate) fn persisted_error_dir_path(db_dir: &Path) -> PathBuf {
+    db_dir.join("error")
+}
+
+/// the file name is the error time in nano seconds so that the files can be sorted by it.
 pub(crate) fn persisted_error_file_path(db_dir: &Path, timestamp_nano: &TimestampNano) -> PathBuf {
-    db_dir.join(format!("error/{timestamp_nano}.list"))
+    persisted_error_dir_path(db_dir).join(format!("{}.list", timestamp_nano.as_inner()))
 }
 
 pub(crate) fn block_timestamp_to_block_file_path(
@@ -1,13 +1,18 @@
 use super::{
     block_list_dir_path, block_list_file_path, block_timestamp_to_block_file_path,
-    cloud_setting::*, lockfile_path, CacheSetting, Result, StorageApiError,
+    cloud_setting::*, lockfile_path, sampling::BlockSampler, search_budget::MemoryUsage,
+    CacheSetting, Result, SearchBudget, StorageApiError,
 };
 use crate::tsdb::{
     cloudstorage::*,
     storage::{block, block_list, cache},
 };
-use crate::tsdb::{datapoint::*, metrics::Metrics, time_series_dataframe::*};
+use crate::tsdb::{
+    datapoint::*, datapoints_rank::*, duplicate_policy::DuplicatePolicy, metrics::Metrics,
+    time_series_dataframe::*,
+};
 use futures::future::join_all;
+use futures::stream::{self, StreamExt};
 use lockfile::Lockfile;
 use log;
 use once_cell::sync::{Lazy, OnceCell};
@@ -17,6 +22,9 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use walkdir::WalkDir;
 
+/// number of the blocks read concurrently while ranking the datapoints
+const RANK_SEARCH_CONCURRENCY: usize = 8;
+
 static CACHE: OnceCell<Arc<RwLock<cache::Cache>>> = OnceCell::new();
 static LOCAL_BLOCK_LIST_FILE_PATTERN: Lazy<Regex> =
     Lazy::new(|| Regex::new(block_list::BLOCK_LIST_FILE_NAME_PATTERN).unwrap());
@@ -116,7 +124,7 @@ fn filter_block_metas_by_limit<'a>(
     block_metas: &'a [block_list::BlockMetaInfo],
     limit: &SearchDatapointsLimit,
 ) -> &'a [block_list::BlockMetaInfo] {
-    //TODO(tacogips) consider about wrapping case
+    //  the blocks must not overlap each other.
     //  time range ==> [TR]
     //
     //  |(TR 1)  | (TR 2)  |  (TR 3)  |
@@ -177,6 +185,32 @@ pub async fn search_dataframe<P: AsRef<Path>>(
     condition: &DatapointsSearchCondition,
     cache_setting: &CacheSetting,
     cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<Option<TimeSeriesDataFrame>> {
+    search_dataframe_with_budget(
+        database_name,
+        db_dir,
+        metrics,
+        field_selectors,
+        condition,
+        cache_setting,
+        cloud_storage_and_setting,
+        &SearchBudget::unlimited(),
+        &DuplicatePolicy::default(),
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn search_dataframe_with_budget<P: AsRef<Path>>(
+    database_name: &str,
+    db_dir: P,
+    metrics: &Metrics,
+    field_selectors: Option<&[usize]>,
+    condition: &DatapointsSearchCondition,
+    cache_setting: &CacheSetting,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+    search_budget: &SearchBudget,
+    duplicate_policy: &DuplicatePolicy,
 ) -> Result<Option<TimeSeriesDataFrame>> {
     log::debug!("search_dataframe. seaching db_dir: {:?}", db_dir.as_ref());
     log::debug!("search_dataframe. field_selectors: {:?}", field_selectors);
@@ -214,64 +248,185 @@ pub async fn search_dataframe<P: AsRef<Path>>(
         None => Ok(None),
         Some(mut block_metas) => {
             if let Some(limit) = condition.limit.as_ref() {
-                block_metas = filter_block_metas_by_limit(block_metas, limit)
+                // the all blocks are needed to find the head or tail datapoints if the blocks overlap.
+                // the timestamp nums of the blocks are counted before sampling, so the blocks are not pruned with sample.
+                if condition.sample.is_none() && !block_list::has_overlapping_blocks(block_metas) {
+                    block_metas = filter_block_metas_by_limit(block_metas, limit)
+                }
             }
 
-            let tasks = block_metas.iter().map(|block_meta| async move {
-                let mut block = read_block(
-                    database_name,
-                    db_dir,
-                    metrics,
-                    field_selectors,
-                    &block_meta.block_timestamp,
-                    cache_setting,
-                    cloud_storage_and_setting,
-                )
-                .await?;
-                // cut out partial datas from the dataframe
-                if !condition.datapoints_range.contains_whole(
+            let contains_whole_block = |block_meta: &block_list::BlockMetaInfo| {
+                condition.datapoints_range.contains_whole(
                     &block_meta.block_timestamp.since_sec.as_timestamp_nano(),
                     &(block_meta.block_timestamp.until_sec + 1).as_timestamp_nano(),
-                ) {
-                    block.retain_matches(&condition.datapoints_range).await?;
+                )
+            };
+
+            // decide the rows to pick before reading blocks to skip the blocks nothing to be picked.
+            let block_metas_and_rows = match condition.sample.as_ref() {
+                None => block_metas
+                    .iter()
+                    .map(|block_meta| (block_meta, None, None))
+                    .collect::<Vec<(
+                        &block_list::BlockMetaInfo,
+                        Option<Vec<usize>>,
+                        Option<TimeSeriesDataFrame>,
+                    )>>(),
+                Some(sample) => {
+                    let mut sampler = BlockSampler::new(sample);
+                    let mut block_metas_and_rows = vec![];
+                    for block_meta in block_metas.iter() {
+                        if contains_whole_block(block_meta) {
+                            let row_indices = sampler.sample_row_indices(block_meta.timestamp_num);
+                            if !row_indices.is_empty() {
+                                block_metas_and_rows.push((block_meta, Some(row_indices), None));
+                            }
+                        } else {
+                            // the rows out of the range must not be counted by the sampler
+                            // so the edge blocks are read and cut out before sampling.
+                            search_budget.check_deadline()?;
+                            let mut block = read_block_rows(
+                                database_name,
+                                db_dir,
+                                metrics,
+                                field_selectors,
+                                &block_meta.block_timestamp,
+                                None,
+                                cache_setting,
+                                cloud_storage_and_setting,
+                            )
+                            .await?;
+                            block.retain_matches(&condition.datapoints_range).await?;
+                            let row_indices = sampler.sample_row_indices(block.len());
+                            if !row_indices.is_empty() {
+                                let sampled_block = block.pick_rows(&row_indices);
+                                block_metas_and_rows.push((
+                                    block_meta,
+                                    Some(row_indices),
+                                    Some(sampled_block),
+                                ));
+                            }
+                        }
+                    }
+                    block_metas_and_rows
+                }
+            };
+
+            // the duplicates over the overlapping blocks must be removed before ranking.
+            let rank_block_groups = condition.rank.as_ref().map(|_| {
+                if *duplicate_policy == DuplicatePolicy::KeepAll {
+                    (0..block_metas_and_rows.len())
+                        .map(|idx| vec![idx])
+                        .collect::<Vec<Vec<usize>>>()
+                } else {
+                    let block_metas = block_metas_and_rows
+                        .iter()
+                        .map(|(block_meta, _, _)| **block_meta)
+                        .collect::<Vec<block_list::BlockMetaInfo>>();
+                    block_list::overlapping_block_groups(&block_metas)
                 }
-
-                Ok((block, &block_meta.block_timestamp))
             });
 
+            let memory_usage = search_budget.memory_usage();
+            let memory_usage = &memory_usage;
+            let tasks = block_metas_and_rows.into_iter().map(
+                |(block_meta, row_indices, sampled_block)| async move {
+                    // give the other tasks (and the timer of the query timeout) a chance to run
+                    // then abort here if the deadline has passed.
+                    tokio::task::yield_now().await;
+                    search_budget.check_deadline()?;
+
+                    let mut block = match sampled_block {
+                        // the edge block has been cut out and sampled already
+                        Some(sampled_block) => sampled_block,
+                        None => {
+                            let mut block = read_block_rows(
+                                database_name,
+                                db_dir,
+                                metrics,
+                                field_selectors,
+                                &block_meta.block_timestamp,
+                                row_indices.as_deref(),
+                                cache_setting,
+                                cloud_storage_and_setting,
+                            )
+                            .await?;
+                            // cut out partial datas from the dataframe
+                            if !contains_whole_block(block_meta) {
+                                block.retain_matches(&condition.datapoints_range).await?;
+                            }
+                            block
+                        }
+                    };
+                    block.dedup(duplicate_policy)?;
+                    memory_usage.add_block(&block)?;
+
+                    Ok((block, block_meta))
+                },
+            );
+
+            if let (Some(rank), Some(rank_block_groups)) =
+                (condition.rank.as_ref(), rank_block_groups)
+            {
+                // rank the datapoints block by block, or by the group of the overlapping blocks,
+                // instead of merging all blocks into one dataframe.
+                let mut tasks = tasks.map(Some).collect::<Vec<_>>();
+                let group_tasks = rank_block_groups
+                    .into_iter()
+                    .map(|group| {
+                        let group_tasks = group
+                            .iter()
+                            .filter_map(|idx| tasks[*idx].take())
+                            .collect::<Vec<_>>();
+                        async move {
+                            let dataframes_of_blocks: Result<Vec<_>> =
+                                join_all(group_tasks).await.into_iter().collect();
+                            merge_blocks_in_write_order(
+                                dataframes_of_blocks?,
+                                search_budget,
+                                memory_usage,
+                                duplicate_policy,
+                            )
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut rank_heap = DatapointsRankHeap::new(rank.clone());
+                let mut blocks =
+                    stream::iter(group_tasks).buffer_unordered(RANK_SEARCH_CONCURRENCY);
+                while let Some(block) = blocks.next().await {
+                    let block = match block? {
+                        Some(block) => block,
+                        None => continue,
+                    };
+                    search_budget.check_deadline()?;
+                    rank_heap.push_dataframe(&block)?;
+
+                    // the block is released at the end of this iteration
+                    memory_usage.replace(block.approximate_size_in_bytes(), 0)?;
+                }
+                return Ok(rank_heap.into_dataframe()?);
+            }
+
             let dataframes_of_blocks = join_all(tasks).await;
             let dataframes_of_blocks: Result<
-                Vec<(TimeSeriesDataFrame, &block_list::BlockTimestamp)>,
+                Vec<(TimeSeriesDataFrame, &block_list::BlockMetaInfo)>,
             > = dataframes_of_blocks.into_iter().collect();
 
-            let mut dataframes_of_blocks = dataframes_of_blocks?;
-            if dataframes_of_blocks.is_empty() {
-                Ok(None)
-            } else {
-                let (mut merged_dataframe, mut prev_block_timestamp) =
-                    dataframes_of_blocks.remove(0);
-
-                for (mut each_dataframes_block, each_block_timestamp) in
-                    dataframes_of_blocks.into_iter()
-                {
-                    if prev_block_timestamp.is_before(each_block_timestamp)
-                        || prev_block_timestamp.is_adjacent_before_of(each_block_timestamp)
-                    {
-                        merged_dataframe.append(&mut each_dataframes_block).unwrap();
-                    } else {
-                        merged_dataframe
-                            .merge(&mut each_dataframes_block)
-                            .await
-                            .unwrap();
+            let merged_dataframe = merge_blocks_in_write_order(
+                dataframes_of_blocks?,
+                search_budget,
+                memory_usage,
+                duplicate_policy,
+            )?;
+            match merged_dataframe {
+                None => Ok(None),
+                Some(mut merged_dataframe) => {
+                    if let Some(limit) = condition.limit.as_ref() {
+                        merged_dataframe.limit(limit);
                     }
-
-                    prev_block_timestamp = each_block_timestamp;
-                }
-
-                if let Some(limit) = condition.limit.as_ref() {
-                    merged_dataframe.limit(limit);
+                    Ok(Some(merged_dataframe))
                 }
-                Ok(Some(merged_dataframe))
             }
         }
     };
@@ -279,6 +434,51 @@ pub async fn search_dataframe<P: AsRef<Path>>(
     result
 }
 
+/// the blocks may overlap each other if late datas were written.
+/// merge them in the write order so that the rows of the same timestamp are ordered as written,
+/// then remove the duplicates over the blocks.
+pub(crate) fn merge_blocks_in_write_order(
+    mut dataframes_of_blocks: Vec<(TimeSeriesDataFrame, &block_list::BlockMetaInfo)>,
+    search_budget: &SearchBudget,
+    memory_usage: &MemoryUsage,
+    duplicate_policy: &DuplicatePolicy,
+) -> Result<Option<TimeSeriesDataFrame>> {
+    if dataframes_of_blocks.is_empty() {
+        return Ok(None);
+    }
+
+    dataframes_of_blocks.sort_by_key(|(_, block_meta)| block_meta.write_seq);
+    let (mut merged_dataframe, merged_block_meta) = dataframes_of_blocks.remove(0);
+    let mut merged_block_timestamp = merged_block_meta.block_timestamp;
+    let mut merged_dataframe_bytes = merged_dataframe.approximate_size_in_bytes();
+
+    for (mut each_dataframes_block, each_block_meta) in dataframes_of_blocks.into_iter() {
+        let each_block_timestamp = &each_block_meta.block_timestamp;
+        search_budget.check_deadline()?;
+        let each_block_bytes = each_dataframes_block.approximate_size_in_bytes();
+        if merged_block_timestamp.is_before(each_block_timestamp)
+            || merged_block_timestamp.is_adjacent_before_of(each_block_timestamp)
+        {
+            merged_dataframe.append(&mut each_dataframes_block)?;
+        } else {
+            merged_dataframe.merge_sorted(&mut each_dataframes_block)?;
+        }
+
+        // the block is released at the end of this iteration
+        let merged_bytes = merged_dataframe.approximate_size_in_bytes();
+        memory_usage.replace(merged_dataframe_bytes + each_block_bytes, merged_bytes)?;
+        merged_dataframe_bytes = merged_bytes;
+
+        merged_block_timestamp.merge(each_block_timestamp);
+    }
+
+    // remove the duplicates over the blocks
+    merged_dataframe.dedup(duplicate_policy)?;
+    let deduped_bytes = merged_dataframe.approximate_size_in_bytes();
+    memory_usage.replace(merged_dataframe_bytes, deduped_bytes)?;
+    Ok(Some(merged_dataframe))
+}
+
 pub async fn read_block(
     database_name: &str,
     root_dir: &Path,
@@ -287,6 +487,32 @@ pub async fn read_block(
     block_timestamp: &block_list::BlockTimestamp,
     cache_setting: &CacheSetting,
     cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<TimeSeriesDataFrame> {
+    read_block_rows(
+        database_name,
+        root_dir,
+        metrics,
+        field_selectors,
+        block_timestamp,
+        None,
+        cache_setting,
+        cloud_storage_and_setting,
+    )
+    .await
+}
+
+/// read only the rows at `row_indices` if passed.
+/// the partial dataframe won't be stored in the block cache.
+#[allow(clippy::too_many_arguments)]
+async fn read_block_rows(
+    database_name: &str,
+    root_dir: &Path,
+    metrics: &Metrics,
+    field_selectors: Option<&[usize]>,
+    block_timestamp: &block_list::BlockTimestamp,
+    row_indices: Option<&[usize]>,
+    cache_setting: &CacheSetting,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
 ) -> Result<TimeSeriesDataFrame> {
     log::debug!("reading block file metrics:{metrics} ,timestamps:{block_timestamp}");
     let (_, block_file_path) =
@@ -327,6 +553,21 @@ pub async fn read_block(
         None
     };
 
+    if let Some(row_indices) = row_indices {
+        let read_df = match cached_df {
+            Some(cached_df) => {
+                log::debug!("block cache hit {},{}", metrics, block_timestamp);
+                cached_df.pick_rows(row_indices)
+            }
+            None => block::read_from_block_file_with_rows(
+                &block_file_path,
+                field_selectors,
+                row_indices,
+            )?,
+        };
+        return Ok(read_df);
+    }
+
     let read_df = match cached_df {
         Some(cached_df) => {
             log::debug!("block cache hit {},{}", metrics, block_timestamp);
@@ -352,6 +593,23 @@ pub async fn read_block(
     Ok(read_df)
 }
 
+/// the block cache must be removed when the block file is rewritten.
+pub(crate) async fn remove_block_cache(
+    metrics: &Metrics,
+    block_timestamp: &block_list::BlockTimestamp,
+) {
+    let s_cache = shared_cache();
+    let mut cache = s_cache.write().await;
+    cache.block_cache.remove(metrics, block_timestamp).await;
+}
+
+/// the block list cache must be removed when some blocks are removed from the block list.
+pub(crate) async fn remove_block_list_cache(metrics: &Metrics) {
+    let s_cache = shared_cache();
+    let mut cache = s_cache.write().await;
+    cache.block_list_cache.remove(metrics).await;
+}
+
 fn read_from_block_file(
     block_file_path: &PathBuf,
     field_selectors: Option<&[usize]>,
@@ -436,6 +694,7 @@ pub(crate) async fn read_block_list(
 mod test {
     use super::*;
     use crate::tsdb::metrics::Metrics;
+    use crate::tsdb::storage::api::write;
     use crate::tsdb::*;
 
     #[test]
@@ -510,4 +769,343 @@ mod test {
         let result = filter_block_metas_by_limit(&block_metas, &SearchDatapointsLimit::Tail(4));
         assert_eq!(result, block_metas!({21,30,4},{31,40,3}));
     }
+
+    #[tokio::test]
+    async fn test_search_sample_with_limit() {
+        let temp_dir = tempdir::TempDir::new("test_search_sample_with_limit").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("sampled").unwrap();
+        let writer_id = uuid::Uuid::new_v4();
+
+        for secs in [1..5u64, 5..9u64] {
+            let block = secs
+                .map(|sec| {
+                    DataPoint::new(
+                        TimestampNano::new(sec * 1_000_000_000),
+                        vec![FieldValue::Float64(sec as f64)],
+                    )
+                })
+                .collect::<Vec<DataPoint>>();
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        // the first block has enough datapoints for the limit only before sampling
+        let condition = DatapointsSearchCondition {
+            limit: Some(SearchDatapointsLimit::Head(3)),
+            sample: Some(SearchDatapointsSample::Every(2)),
+            ..DatapointsSearchCondition::all()
+        };
+        let df = search_dataframe(
+            "db",
+            db_dir,
+            &metrics,
+            None,
+            &condition,
+            &CacheSetting::none(),
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(df.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_sample_edge_block() {
+        let temp_dir = tempdir::TempDir::new("test_search_sample_edge_block").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("sampled").unwrap();
+        let writer_id = uuid::Uuid::new_v4();
+
+        for secs in [1..5u64, 5..9u64] {
+            let block = secs
+                .map(|sec| {
+                    DataPoint::new(
+                        TimestampNano::new(sec * 1_000_000_000),
+                        vec![FieldValue::Float64(sec as f64)],
+                    )
+                })
+                .collect::<Vec<DataPoint>>();
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        // only the rows of 3 and 4 secs in the first block are in the range
+        let condition = DatapointsSearchCondition {
+            datapoints_range: DatapointsRange::new(
+                Some(TimestampNano::new(3 * 1_000_000_000)),
+                None,
+            ),
+            sample: Some(SearchDatapointsSample::Every(3)),
+            ..DatapointsSearchCondition::all()
+        };
+        let df = search_dataframe(
+            "db",
+            db_dir,
+            &metrics,
+            None,
+            &condition,
+            &CacheSetting::none(),
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            df.timestamp_nanos,
+            vec![
+                TimestampNano::new(3 * 1_000_000_000),
+                TimestampNano::new(6 * 1_000_000_000)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_overlapping_blocks() {
+        let temp_dir = tempdir::TempDir::new("test_search_overlapping_blocks").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("late_datas").unwrap();
+        let writer_id = uuid::Uuid::new_v4();
+
+        let datapoint = |sec: u64, value: f64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(value)],
+            )
+        };
+        let block_timestamp = |since: u64, until: u64| {
+            block_list::BlockTimestamp::new(TimestampSec::new(since), TimestampSec::new(until))
+        };
+
+        for block in [
+            vec![datapoint(10, 1.0), datapoint(19, 2.0)],
+            vec![datapoint(20, 3.0), datapoint(29, 4.0)],
+        ] {
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        let report = write::write_late_datas(
+            db_dir,
+            &writer_id,
+            &metrics,
+            &[datapoint(15, 5.0), datapoint(25, 6.0)],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.block_timestamp, block_timestamp(15, 26));
+        assert_eq!(
+            report.overlapped_blocks,
+            vec![block_timestamp(10, 20), block_timestamp(20, 30)]
+        );
+        assert!(!report.merged_into_existing_block);
+
+        // exactly the same block timestamp
+        let report = write::write_late_datas(
+            db_dir,
+            &writer_id,
+            &metrics,
+            &[datapoint(10, 7.0), datapoint(19, 8.0)],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.block_timestamp, block_timestamp(10, 20));
+        assert_eq!(report.overlapped_blocks, vec![block_timestamp(15, 26)]);
+        assert!(report.merged_into_existing_block);
+
+        let search = |limit: Option<SearchDatapointsLimit>| {
+            let condition = DatapointsSearchCondition {
+                limit,
+                ..DatapointsSearchCondition::all()
+            };
+            let metrics = metrics.clone();
+            async move {
+                search_dataframe(
+                    "db",
+                    db_dir,
+                    &metrics,
+                    None,
+                    &condition,
+                    &CacheSetting::none(),
+                    None,
+                )
+                .await
+                .unwrap()
+                .unwrap()
+            }
+        };
+        let expected = |datapoints: Vec<(u64, f64)>| {
+            let (secs, values): (Vec<u64>, Vec<f64>) = datapoints.into_iter().unzip();
+            TimeSeriesDataFrame::new(
+                secs.into_iter()
+                    .map(|sec| TimestampNano::new(sec * 1_000_000_000))
+                    .collect(),
+                vec![DataSeries::new(SeriesValues::Float64(values))],
+                None,
+            )
+        };
+
+        assert_eq!(
+            search(None).await,
+            expected(vec![
+                (10, 1.0),
+                (10, 7.0),
+                (15, 5.0),
+                (19, 2.0),
+                (19, 8.0),
+                (20, 3.0),
+                (25, 6.0),
+                (29, 4.0),
+            ])
+        );
+        assert_eq!(
+            search(Some(SearchDatapointsLimit::Head(1))).await,
+            expected(vec![(10, 1.0), (10, 7.0)])
+        );
+        assert_eq!(
+            search(Some(SearchDatapointsLimit::Tail(1))).await,
+            expected(vec![(29, 4.0)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_overlapping_blocks_in_write_order() {
+        let temp_dir =
+            tempdir::TempDir::new("test_search_overlapping_blocks_in_write_order").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("late_datas").unwrap();
+        let writer_id = uuid::Uuid::new_v4();
+
+        let datapoint = |sec: u64, value: f64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(value)],
+            )
+        };
+
+        // the later block comes first in the block list since its until_sec is smaller
+        for block in [
+            vec![datapoint(10, 1.0), datapoint(20, 2.0), datapoint(30, 3.0)],
+            vec![datapoint(15, 4.0), datapoint(20, 5.0)],
+        ] {
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        let search = |duplicate_policy: DuplicatePolicy| {
+            let metrics = metrics.clone();
+            async move {
+                search_dataframe_with_budget(
+                    "db",
+                    db_dir,
+                    &metrics,
+                    None,
+                    &DatapointsSearchCondition::all(),
+                    &CacheSetting::none(),
+                    None,
+                    &SearchBudget::unlimited(),
+                    &duplicate_policy,
+                )
+                .await
+                .unwrap()
+                .unwrap()
+            }
+        };
+        let values = |df: TimeSeriesDataFrame| match &df.columns[0].values {
+            SeriesValues::Float64(values) => values.clone(),
+            values => panic!("unexpected values {values:?}"),
+        };
+
+        assert_eq!(
+            values(search(DuplicatePolicy::KeepAll).await),
+            vec![1.0, 4.0, 2.0, 5.0, 3.0]
+        );
+        assert_eq!(
+            values(search(DuplicatePolicy::KeepFirst).await),
+            vec![1.0, 4.0, 2.0, 3.0]
+        );
+        assert_eq!(
+            values(search(DuplicatePolicy::KeepLast).await),
+            vec![1.0, 4.0, 5.0, 3.0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rank_overlapping_blocks_with_duplicate_policy() {
+        let temp_dir =
+            tempdir::TempDir::new("test_rank_overlapping_blocks_with_duplicate_policy").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("late_datas").unwrap();
+        let writer_id = uuid::Uuid::new_v4();
+
+        let datapoint = |sec: u64, value: f64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(value)],
+            )
+        };
+
+        for block in [
+            vec![datapoint(10, 1.0), datapoint(20, 2.0), datapoint(30, 3.0)],
+            vec![datapoint(15, 4.0), datapoint(20, 5.0)],
+            vec![datapoint(40, 6.0)],
+        ] {
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        let rank = |duplicate_policy: DuplicatePolicy| {
+            let metrics = metrics.clone();
+            async move {
+                let condition = DatapointsSearchCondition {
+                    rank: Some(SearchDatapointsRank::Top {
+                        field_idx: 0,
+                        n: 10,
+                    }),
+                    ..DatapointsSearchCondition::all()
+                };
+                let df = search_dataframe_with_budget(
+                    "db",
+                    db_dir,
+                    &metrics,
+                    None,
+                    &condition,
+                    &CacheSetting::none(),
+                    None,
+                    &SearchBudget::unlimited(),
+                    &duplicate_policy,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+                let mut values = match &df.columns[0].values {
+                    SeriesValues::Float64(values) => values.clone(),
+                    values => panic!("unexpected values {values:?}"),
+                };
+                values.sort_by(|l, r| l.partial_cmp(r).unwrap());
+                values
+            }
+        };
+
+        assert_eq!(
+            rank(DuplicatePolicy::KeepAll).await,
+            vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]
+        );
+        assert_eq!(
+            rank(DuplicatePolicy::KeepFirst).await,
+            vec![1.0, 2.0, 3.0, 4.0, 6.0]
+        );
+        assert_eq!(
+            rank(DuplicatePolicy::KeepLast).await,
+            vec![1.0, 3.0, 4.0, 5.0, 6.0]
+        );
+    }
 }
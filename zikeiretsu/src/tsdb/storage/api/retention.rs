@@ -0,0 +1,296 @@
+use super::super::persisted_error;
+use super::write::{
+    create_cloud_lock_file, create_local_lock_file, remove_local_block, replace_block_list_file,
+    write_error_files,
+};
+use super::{block_list, cloud_setting::*, CacheSetting, Result, StorageApiError};
+use crate::tsdb::cloudstorage::*;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::timestamp_nano::TimestampNano;
+use crate::tsdb::timestamp_sec::TimestampSec;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// how long the datas are kept. the metrics that have no own retention follow the default one.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RetentionPolicy {
+    pub default_retention: Option<Duration>,
+    pub metrics_retentions: HashMap<Metrics, Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn new(default_retention: Option<Duration>) -> Self {
+        Self {
+            default_retention,
+            metrics_retentions: HashMap::new(),
+        }
+    }
+
+    pub fn with_metrics_retention(mut self, metrics: Metrics, retention: Duration) -> Self {
+        self.metrics_retentions.insert(metrics, retention);
+        self
+    }
+
+    pub fn retention_of(&self, metrics: &Metrics) -> Option<Duration> {
+        self.metrics_retentions
+            .get(metrics)
+            .copied()
+            .or(self.default_retention)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default_retention.is_none() && self.metrics_retentions.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionSummary {
+    /// the datas before this are expired.
+    pub expired_before: TimestampSec,
+    pub expired_blocks: Vec<block_list::BlockTimestamp>,
+    pub block_num_after: usize,
+}
+
+/// drops the blocks whose all datas are older than the retention from the block list,
+/// then removes the block files on local and the cloud storage.
+/// the blocks that contain any datas in the retention are kept as a whole.
+pub async fn expire_blocks<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    retention: Duration,
+    now: TimestampNano,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<RetentionSummary> {
+    let expired_before = TimestampSec::new(now.in_seconds().saturating_sub(retention.as_secs()));
+
+    let cloud_infos = match cloud_storage_and_setting {
+        Some((cloud_storage, cloud_setting)) if cloud_setting.upload_data_after_write => {
+            let cloud_lock_file_path =
+                create_cloud_lock_file(writer_id, metrics, cloud_storage).await?;
+            Some((cloud_lock_file_path, cloud_storage))
+        }
+        _ => None,
+    };
+
+    let result = expire_blocks_under_lock(
+        db_dir.as_ref(),
+        writer_id,
+        metrics,
+        expired_before,
+        cloud_storage_and_setting,
+        cloud_infos
+            .as_ref()
+            .map(|(_, cloud_storage)| *cloud_storage),
+    )
+    .await;
+
+    if let Some((cloud_lock_file_path, _)) = cloud_infos {
+        cloud_lock_file_path.remove().await?;
+    }
+
+    result
+}
+
+async fn expire_blocks_under_lock(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    expired_before: TimestampSec,
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+    upload_to: Option<&CloudStorage>,
+) -> Result<RetentionSummary> {
+    let _lockfile = create_local_lock_file(db_dir, writer_id, metrics)?;
+
+    let block_list = super::read::read_block_list(
+        "", // database name is unnecessary if no cache wil be used
+        db_dir,
+        metrics,
+        &CacheSetting::none(),
+        cloud_storage_and_setting,
+    )
+    .await;
+    let mut block_list = match block_list {
+        Ok(block_list) => block_list,
+        Err(StorageApiError::NoBlockListFile(_)) => {
+            return Ok(RetentionSummary {
+                expired_before,
+                expired_blocks: vec![],
+                block_num_after: 0,
+            })
+        }
+        Err(e) => return Err(e),
+    };
+
+    // until_sec is exclusive
+    let (expired_block_metas, block_metas): (Vec<_>, Vec<_>) = block_list
+        .block_meta_infos
+        .into_iter()
+        .partition(|block_meta| block_meta.block_timestamp.until_sec <= expired_before);
+    block_list.block_meta_infos = block_metas;
+    let expired_blocks: Vec<block_list::BlockTimestamp> = expired_block_metas
+        .into_iter()
+        .map(|block_meta| block_meta.block_timestamp)
+        .collect();
+    let block_num_after = block_list.block_num();
+
+    if expired_blocks.is_empty() {
+        return Ok(RetentionSummary {
+            expired_before,
+            expired_blocks,
+            block_num_after,
+        });
+    }
+
+    // update the block list first. the expired blocks are unreachable after that.
+    block_list.update_updated_at(TimestampNano::now());
+    let block_list_file_path = replace_block_list_file(db_dir, metrics, block_list).await?;
+    for block_timestamp in expired_blocks.iter() {
+        remove_local_block(db_dir, metrics, block_timestamp).await?;
+    }
+
+    if let Some(cloud_storage) = upload_to {
+        let remove_result = async {
+            let cloud_block_list_file_path = CloudBlockListFilePath::new(metrics, cloud_storage);
+            if block_num_after == 0 {
+                cloud_block_list_file_path.remove().await?;
+            } else {
+                cloud_block_list_file_path
+                    .upload(&block_list_file_path)
+                    .await?;
+            }
+
+            for block_timestamp in expired_blocks.iter() {
+                CloudBlockFilePath::new(metrics, block_timestamp, cloud_storage)
+                    .remove()
+                    .await?;
+            }
+            Ok::<(), StorageApiError>(())
+        }
+        .await;
+
+        if let Err(e) = remove_result {
+            log::error!("failed to remove the expired blocks on the cloud :{e:?}");
+            write_error_files(
+                db_dir,
+                metrics,
+                persisted_error::PersistedErrorType::FailedToUploadBlockOrBLockList,
+                &expired_blocks,
+                Some(format!("failed to remove the expired blocks. error:{e:?}")),
+            )
+            .await?;
+        }
+    }
+
+    Ok(RetentionSummary {
+        expired_before,
+        expired_blocks,
+        block_num_after,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::storage::api::write;
+    use crate::tsdb::*;
+
+    #[test]
+    fn retention_of_test() {
+        let trades = Metrics::new("trades").unwrap();
+        let orders = Metrics::new("orders").unwrap();
+
+        let policy = RetentionPolicy::new(Some(Duration::from_secs(90)))
+            .with_metrics_retention(trades.clone(), Duration::from_secs(30));
+        assert_eq!(policy.retention_of(&trades), Some(Duration::from_secs(30)));
+        assert_eq!(policy.retention_of(&orders), Some(Duration::from_secs(90)));
+
+        let policy = RetentionPolicy::default();
+        assert!(policy.is_empty());
+        assert_eq!(policy.retention_of(&orders), None);
+    }
+
+    #[tokio::test]
+    async fn expire_blocks_test() {
+        let temp_dir = tempdir::TempDir::new("expire_blocks_test").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("retention").unwrap();
+        let writer_id = Uuid::new_v4();
+
+        let datapoint = |sec: u64| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(sec as f64)],
+            )
+        };
+        for block in [
+            vec![datapoint(10), datapoint(19)],
+            vec![datapoint(20), datapoint(29)],
+            vec![datapoint(30), datapoint(39)],
+        ] {
+            write::write_datas(db_dir, &writer_id, &metrics, &block, None)
+                .await
+                .unwrap();
+        }
+
+        // the datas before 30 sec are expired. the second block ends at 29 sec.
+        let now = TimestampNano::new(40 * 1_000_000_000);
+        let summary = expire_blocks(
+            db_dir,
+            &writer_id,
+            &metrics,
+            Duration::from_secs(10),
+            now,
+            None,
+        )
+        .await
+        .unwrap();
+        let block_timestamp = |since: u64, until: u64| {
+            block_list::BlockTimestamp::new(TimestampSec::new(since), TimestampSec::new(until))
+        };
+        assert_eq!(
+            summary,
+            RetentionSummary {
+                expired_before: TimestampSec::new(30),
+                expired_blocks: vec![block_timestamp(10, 20), block_timestamp(20, 30)],
+                block_num_after: 1,
+            }
+        );
+
+        let df = super::super::read::search_dataframe(
+            "db",
+            db_dir,
+            &metrics,
+            None,
+            &DatapointsSearchCondition::all(),
+            &CacheSetting::none(),
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            df.timestamp_nanos,
+            vec![
+                TimestampNano::new(30 * 1_000_000_000),
+                TimestampNano::new(39 * 1_000_000_000)
+            ]
+        );
+
+        // all the blocks are expired
+        let summary = expire_blocks(
+            db_dir,
+            &writer_id,
+            &metrics,
+            Duration::from_secs(0),
+            now,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.expired_blocks, vec![block_timestamp(30, 40)]);
+        assert_eq!(summary.block_num_after, 0);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::tsdb::datapoint::SearchDatapointsSample;
+use crate::tsdb::TimestampNano;
+
+/// Decides which rows to pick from each block before reading it.
+/// The blocks must be passed in order of the timestamps.
+pub(crate) struct BlockSampler {
+    sample: SearchDatapointsSample,
+    rows_passed: u64,
+    rng: XorShift,
+}
+
+impl BlockSampler {
+    pub fn new(sample: &SearchDatapointsSample) -> Self {
+        Self {
+            sample: sample.clone(),
+            rows_passed: 0,
+            rng: XorShift::new(TimestampNano::now().as_inner()),
+        }
+    }
+
+    /// returns the sorted indices of the rows to pick from the block that has `row_num` rows.
+    /// the block doesn't need to be read if it's empty.
+    /// `row_num` must be the number of the rows in the search range to keep the phase of `Every`.
+    pub fn sample_row_indices(&mut self, row_num: usize) -> Vec<usize> {
+        let row_indices = match self.sample {
+            SearchDatapointsSample::Every(n) => {
+                let n = n.max(1);
+                let first = ((n - self.rows_passed % n) % n) as usize;
+                (first..row_num).step_by(n as usize).collect()
+            }
+
+            SearchDatapointsSample::Percent(percent) => {
+                let rate = percent / 100f64;
+                if rate >= 1f64 {
+                    (0..row_num).collect()
+                } else if rate <= 0f64 {
+                    vec![]
+                } else {
+                    // skip the rows by geometric distribution that is equivalent to
+                    // pick each row with the probability of the rate.
+                    let log_not_picked = (1f64 - rate).ln();
+                    let mut row_indices = vec![];
+                    let mut idx = self.next_gap(log_not_picked);
+                    while idx < row_num {
+                        row_indices.push(idx);
+                        idx = idx.saturating_add(1 + self.next_gap(log_not_picked));
+                    }
+                    row_indices
+                }
+            }
+        };
+        self.rows_passed += row_num as u64;
+        row_indices
+    }
+
+    fn next_gap(&mut self, log_not_picked: f64) -> usize {
+        let gap = (self.rng.next_f64().ln() / log_not_picked).floor();
+        if gap >= usize::MAX as f64 {
+            usize::MAX
+        } else {
+            gap as usize
+        }
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // state must not be zero
+        Self(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// (0, 1]
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 1f64) / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sample_every_test() {
+        let mut sampler = BlockSampler::new(&SearchDatapointsSample::Every(3));
+        assert_eq!(sampler.sample_row_indices(7), vec![0, 3, 6]);
+        assert_eq!(sampler.sample_row_indices(1), Vec::<usize>::new());
+        assert_eq!(sampler.sample_row_indices(4), vec![1]);
+        assert_eq!(sampler.sample_row_indices(2), vec![0]);
+    }
+
+    #[test]
+    fn sample_percent_test() {
+        let mut sampler = BlockSampler::new(&SearchDatapointsSample::Percent(100f64));
+        assert_eq!(sampler.sample_row_indices(3), vec![0, 1, 2]);
+
+        let mut sampler = BlockSampler::new(&SearchDatapointsSample::Percent(0f64));
+        assert!(sampler.sample_row_indices(100).is_empty());
+
+        let mut sampler = BlockSampler::new(&SearchDatapointsSample::Percent(10f64));
+        let row_indices = sampler.sample_row_indices(100_000);
+        assert!(row_indices.len() > 8_000 && row_indices.len() < 12_000);
+        assert!(row_indices.windows(2).all(|w| w[0] < w[1]));
+    }
+}
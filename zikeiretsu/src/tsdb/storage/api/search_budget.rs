@@ -0,0 +1,164 @@
+use super::{Result, StorageApiError};
+use crate::tsdb::TimeSeriesDataFrame;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Limits of the resources that a single search can consume.
+/// The limits are checked between block reads so that the search can be aborted cooperatively.
+#[derive(Debug, Clone, Default)]
+pub struct SearchBudget {
+    deadline: Option<Instant>,
+    memory_limit_bytes: Option<usize>,
+}
+
+impl SearchBudget {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timeout(timeout: Option<Duration>) -> Self {
+        Self {
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            memory_limit_bytes: None,
+        }
+    }
+
+    pub fn memory_limit(mut self, memory_limit_bytes: Option<usize>) -> Self {
+        self.memory_limit_bytes = memory_limit_bytes;
+        self
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn memory_limit_bytes(&self) -> Option<usize> {
+        self.memory_limit_bytes
+    }
+
+    pub(crate) fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => Err(StorageApiError::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            limit_bytes: self.memory_limit_bytes,
+            bytes: AtomicUsize::new(0),
+            rows: AtomicUsize::new(0),
+            blocks: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Tracks the approximate memory that the dataframes materialized in a search hold.
+pub(crate) struct MemoryUsage {
+    limit_bytes: Option<usize>,
+    bytes: AtomicUsize,
+    rows: AtomicUsize,
+    blocks: AtomicUsize,
+}
+
+impl MemoryUsage {
+    /// account a dataframe read from a block
+    pub fn add_block(&self, block: &TimeSeriesDataFrame) -> Result<()> {
+        let bytes = self
+            .bytes
+            .fetch_add(block.approximate_size_in_bytes(), Ordering::SeqCst)
+            + block.approximate_size_in_bytes();
+        self.rows.fetch_add(block.len(), Ordering::SeqCst);
+        self.blocks.fetch_add(1, Ordering::SeqCst);
+        self.check(bytes)
+    }
+
+    /// account the change of the memory on merging dataframes.
+    pub fn replace(&self, released_bytes: usize, allocated_bytes: usize) -> Result<()> {
+        let bytes = if allocated_bytes >= released_bytes {
+            let grown = allocated_bytes - released_bytes;
+            self.bytes.fetch_add(grown, Ordering::SeqCst) + grown
+        } else {
+            // the released size is approximate, so it may exceed the accounted one
+            let shrunk = released_bytes - allocated_bytes;
+            let previous = self
+                .bytes
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |bytes| {
+                    Some(bytes.saturating_sub(shrunk))
+                })
+                .unwrap_or_else(|bytes| bytes);
+            previous.saturating_sub(shrunk)
+        };
+        self.check(bytes)
+    }
+
+    fn check(&self, bytes: usize) -> Result<()> {
+        match self.limit_bytes {
+            Some(limit_bytes) if bytes > limit_bytes => {
+                Err(StorageApiError::MemoryBudgetExceeded {
+                    limit_bytes,
+                    used_bytes: bytes,
+                    rows: self.rows.load(Ordering::SeqCst),
+                    blocks: self.blocks.load(Ordering::SeqCst),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::*;
+
+    #[test]
+    fn search_budget_deadline_test() {
+        assert!(SearchBudget::unlimited().check_deadline().is_ok());
+        assert!(SearchBudget::with_timeout(None).check_deadline().is_ok());
+        assert!(SearchBudget::with_timeout(Some(Duration::from_secs(60)))
+            .check_deadline()
+            .is_ok());
+        assert!(SearchBudget::with_timeout(Some(Duration::from_secs(0)))
+            .check_deadline()
+            .is_err());
+    }
+
+    #[test]
+    fn search_budget_memory_test() {
+        let block = TimeSeriesDataFrame::new(
+            vec![TimestampNano::new(1), TimestampNano::new(2)],
+            vec![DataSeries::new(SeriesValues::Float64(vec![1f64, 2f64]))],
+            None,
+        );
+        let block_size = block.approximate_size_in_bytes();
+        assert!(block_size >= 32);
+
+        let usage = SearchBudget::unlimited().memory_usage();
+        assert!(usage.add_block(&block).is_ok());
+        assert!(usage.add_block(&block).is_ok());
+
+        let usage = SearchBudget::unlimited()
+            .memory_limit(Some(block_size * 2))
+            .memory_usage();
+        assert!(usage.add_block(&block).is_ok());
+        assert!(usage.add_block(&block).is_ok());
+        assert!(usage.replace(block_size, 0).is_ok());
+        assert!(usage.add_block(&block).is_ok());
+
+        let result = usage.add_block(&block);
+        match result {
+            Err(StorageApiError::MemoryBudgetExceeded { rows, blocks, .. }) => {
+                assert_eq!(rows, 8);
+                assert_eq!(blocks, 4);
+            }
+            _ => panic!("memory budget must be exceeded"),
+        }
+
+        // releasing more than accounted does not underflow
+        let usage = SearchBudget::unlimited().memory_usage();
+        assert!(usage.add_block(&block).is_ok());
+        assert!(usage.replace(block_size * 2, 0).is_ok());
+        assert!(usage.add_block(&block).is_ok());
+    }
+}
@@ -0,0 +1,391 @@
+use super::super::persisted_error;
+use super::write::{create_cloud_lock_file, create_local_lock_file};
+use super::{
+    block_list, block_list_file_path, block_timestamp_to_block_file_path, persisted_error_dir_path,
+    CacheSetting, Result, StorageApiError,
+};
+use crate::tsdb::cloudstorage::*;
+use crate::tsdb::metrics::Metrics;
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::{task, time};
+use uuid::Uuid;
+
+/// how often the worker looks for the error files while no upload is pending.
+pub const UPLOAD_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// the wait between the retries of the uploads. it's doubled on every failure up to the max.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadRetryBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for UploadRetryBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl UploadRetryBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max }
+    }
+
+    /// the wait after the failures in a row.
+    pub fn wait(&self, failures: u32) -> Duration {
+        let exp = failures.saturating_sub(1).min(31);
+        self.initial.saturating_mul(1 << exp).min(self.max)
+    }
+}
+
+/// the upload recorded in the error file that is not done yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingUpload {
+    pub error_file_path: PathBuf,
+    pub metrics: Option<Metrics>,
+    pub block_timestamp: Option<block_list::BlockTimestamp>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UploadRetrySummary {
+    pub uploaded: Vec<(Metrics, block_list::BlockTimestamp)>,
+    pub pending: Vec<PendingUpload>,
+}
+
+impl UploadRetrySummary {
+    pub fn is_completed(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// the uploads failed and recorded in the error files under the db dir.
+pub fn list_pending_uploads<P: AsRef<Path>>(db_dir: P) -> Result<Vec<PendingUpload>> {
+    let mut pending_uploads = Vec::<PendingUpload>::new();
+    for error_file_path in
+        persisted_error::list_persisted_error_files(persisted_error_dir_path(db_dir.as_ref()))?
+    {
+        let pending_upload = match persisted_error::read_persisted_error(&error_file_path) {
+            Ok(persisted_error) => PendingUpload {
+                error_file_path,
+                metrics: persisted_error.metrics().cloned(),
+                block_timestamp: persisted_error.block_timestamp().copied(),
+                reason: persisted_error.detail().unwrap_or_default().to_string(),
+            },
+            Err(e) => PendingUpload {
+                error_file_path,
+                metrics: None,
+                block_timestamp: None,
+                reason: format!("unreadable error file: {e}"),
+            },
+        };
+        pending_uploads.push(pending_upload);
+    }
+    Ok(pending_uploads)
+}
+
+/// `list_pending_uploads` of the metrics.
+pub fn list_pending_uploads_of_metrics<P: AsRef<Path>>(
+    db_dir: P,
+    metrics: &Metrics,
+) -> Result<Vec<PendingUpload>> {
+    let mut pending_uploads = list_pending_uploads(db_dir)?;
+    pending_uploads.retain(|pending_upload| pending_upload.metrics.as_ref() == Some(metrics));
+    Ok(pending_uploads)
+}
+
+/// uploads the blocks and the block lists recorded in the error files again, and removes the error files of the succeeded ones.
+/// the blocks already removed from the block list on local are removed from the cloud storage as well.
+pub async fn retry_failed_uploads<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    cloud_storage: &CloudStorage,
+) -> Result<UploadRetrySummary> {
+    let db_dir = db_dir.as_ref();
+    retry_pending_uploads(
+        db_dir,
+        writer_id,
+        list_pending_uploads(db_dir)?,
+        cloud_storage,
+    )
+    .await
+}
+
+/// `retry_failed_uploads` of the metrics. the error files of the other metrics are left as they are.
+pub async fn retry_failed_uploads_of_metrics<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    cloud_storage: &CloudStorage,
+) -> Result<UploadRetrySummary> {
+    let db_dir = db_dir.as_ref();
+    let pending_uploads = list_pending_uploads_of_metrics(db_dir, metrics)?;
+    retry_pending_uploads(db_dir, writer_id, pending_uploads, cloud_storage).await
+}
+
+async fn retry_pending_uploads(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    pending_uploads: Vec<PendingUpload>,
+    cloud_storage: &CloudStorage,
+) -> Result<UploadRetrySummary> {
+    let mut summary = UploadRetrySummary::default();
+    for mut pending_upload in pending_uploads {
+        let (metrics, block_timestamp) =
+            match (&pending_upload.metrics, &pending_upload.block_timestamp) {
+                (Some(metrics), Some(block_timestamp)) => (metrics.clone(), *block_timestamp),
+                _ => {
+                    summary.pending.push(pending_upload);
+                    continue;
+                }
+            };
+
+        match retry_upload(db_dir, writer_id, &metrics, &block_timestamp, cloud_storage).await {
+            Ok(()) => {
+                fs::remove_file(&pending_upload.error_file_path)
+                    .map_err(persisted_error::PersistedErrorIOError::from)?;
+                log::info!("uploaded the block {block_timestamp} of {metrics} again");
+                summary.uploaded.push((metrics, block_timestamp));
+            }
+            Err(e) => {
+                log::warn!("failed to upload the block {block_timestamp} of {metrics} again: {e}");
+                pending_upload.reason = format!("{e}");
+                summary.pending.push(pending_upload);
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// retries the uploads until all of them succeed or the attempts run out, waiting with the backoff between them.
+/// the pending uploads of the last attempt are returned.
+pub async fn retry_failed_uploads_with_backoff<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    cloud_storage: &CloudStorage,
+    backoff: &UploadRetryBackoff,
+    max_attempts: u32,
+) -> Result<UploadRetrySummary> {
+    let db_dir = db_dir.as_ref();
+    let mut uploaded = Vec::<(Metrics, block_list::BlockTimestamp)>::new();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let mut summary = retry_failed_uploads(db_dir, writer_id, cloud_storage).await?;
+        uploaded.append(&mut summary.uploaded);
+        if summary.is_completed() || attempt >= max_attempts {
+            return Ok(UploadRetrySummary {
+                uploaded,
+                pending: summary.pending,
+            });
+        }
+
+        let wait = backoff.wait(attempt);
+        log::info!(
+            "{} uploads are pending. retrying in {wait:?}",
+            summary.pending.len()
+        );
+        time::sleep(wait).await;
+    }
+}
+
+/// retries the uploads recorded in the error files in a task.
+/// the error files are looked for at the interval, and retried with the backoff while any of them fails.
+pub fn start_upload_retry_worker(
+    db_dir: PathBuf,
+    cloud_storage: CloudStorage,
+    interval: Duration,
+    backoff: UploadRetryBackoff,
+) -> UploadRetryWorkerShutdown {
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<DateTime<Utc>>(1);
+    let join_handle = task::spawn(async move {
+        let writer_id = Uuid::new_v4();
+        let mut failures = 0;
+        loop {
+            let wait = if failures == 0 {
+                interval
+            } else {
+                backoff.wait(failures)
+            };
+            let waiting_shutdown = time::timeout(wait, shutdown_rx.recv()).await;
+            if waiting_shutdown.is_ok() {
+                log::info!("breaking the upload retry loop");
+                break;
+            }
+
+            match retry_failed_uploads(&db_dir, &writer_id, &cloud_storage).await {
+                Ok(summary) if summary.is_completed() => failures = 0,
+                Ok(summary) => {
+                    failures += 1;
+                    log::warn!(
+                        "{} uploads are still pending after {failures} retries",
+                        summary.pending.len()
+                    );
+                }
+                Err(e) => {
+                    failures += 1;
+                    log::error!("failed to retry the uploads: {e}");
+                }
+            }
+        }
+    });
+
+    UploadRetryWorkerShutdown {
+        shutdown_tx,
+        join_handle,
+    }
+}
+
+/// the worker stops when this is dropped as well.
+pub struct UploadRetryWorkerShutdown {
+    shutdown_tx: mpsc::Sender<DateTime<Utc>>,
+    join_handle: task::JoinHandle<()>,
+}
+
+impl UploadRetryWorkerShutdown {
+    pub async fn shutdown_and_wait(self) -> Result<()> {
+        // the worker has already stopped if the receiver is closed
+        let _ = self.shutdown_tx.send(Utc::now()).await;
+        self.join_handle.await?;
+        Ok(())
+    }
+}
+
+async fn retry_upload(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    block_timestamp: &block_list::BlockTimestamp,
+    cloud_storage: &CloudStorage,
+) -> Result<()> {
+    let cloud_lock_file_path = create_cloud_lock_file(writer_id, metrics, cloud_storage).await?;
+    let result =
+        retry_upload_under_lock(db_dir, writer_id, metrics, block_timestamp, cloud_storage).await;
+    cloud_lock_file_path.remove().await?;
+    result
+}
+
+async fn retry_upload_under_lock(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    block_timestamp: &block_list::BlockTimestamp,
+    cloud_storage: &CloudStorage,
+) -> Result<()> {
+    let _lockfile = create_local_lock_file(db_dir, writer_id, metrics)?;
+
+    let block_list = match super::read::read_block_list(
+        "", // database name is unnecessary if no cache wil be used
+        db_dir,
+        metrics,
+        &CacheSetting::none(),
+        None,
+    )
+    .await
+    {
+        Ok(block_list) => Some(block_list),
+        Err(StorageApiError::NoBlockListFile(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let in_block_list = block_list.iter().any(|block_list| {
+        block_list
+            .block_meta_infos
+            .iter()
+            .any(|block_meta| block_meta.block_timestamp == *block_timestamp)
+    });
+    let cloud_block_file_path = CloudBlockFilePath::new(metrics, block_timestamp, cloud_storage);
+    if in_block_list {
+        let (_, block_file_path) =
+            block_timestamp_to_block_file_path(db_dir, metrics, block_timestamp);
+        if !block_file_path.exists() {
+            return Err(StorageApiError::NoBlockFile(
+                block_file_path.display().to_string(),
+            ));
+        }
+        cloud_block_file_path.upload(&block_file_path).await?;
+    } else {
+        // compacted, expired or deleted after the error
+        cloud_block_file_path.remove().await?;
+    }
+
+    let cloud_block_list_file_path = CloudBlockListFilePath::new(metrics, cloud_storage);
+    match block_list {
+        Some(block_list) if block_list.block_num() > 0 => {
+            cloud_block_list_file_path
+                .upload(&block_list_file_path(db_dir, metrics))
+                .await?
+        }
+        _ => cloud_block_list_file_path.remove().await?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::storage::api::write;
+    use crate::tsdb::storage::persisted_error::PersistedErrorType;
+    use crate::tsdb::{TimestampNano, TimestampSec};
+
+    #[test]
+    fn upload_retry_backoff_test() {
+        let backoff = UploadRetryBackoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(backoff.wait(1), Duration::from_secs(1));
+        assert_eq!(backoff.wait(2), Duration::from_secs(2));
+        assert_eq!(backoff.wait(4), Duration::from_secs(8));
+        assert_eq!(backoff.wait(5), Duration::from_secs(10));
+        assert_eq!(backoff.wait(100), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn list_pending_uploads_test() {
+        let temp_dir = tempdir::TempDir::new("list_pending_uploads_test").unwrap();
+        let db_dir = temp_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+        assert!(list_pending_uploads(db_dir).unwrap().is_empty());
+
+        let block_timestamp =
+            block_list::BlockTimestamp::new(TimestampSec::new(1), TimestampSec::new(2));
+        write::write_error_file(
+            db_dir,
+            TimestampNano::new(10),
+            &metrics,
+            PersistedErrorType::FailedToUploadBlockOrBLockList,
+            block_timestamp,
+            Some("error:timeout".to_string()),
+        )
+        .await
+        .unwrap();
+        fs::write(persisted_error_dir_path(db_dir).join("20.list"), b"broken").unwrap();
+
+        let pending_uploads = list_pending_uploads(db_dir).unwrap();
+        assert_eq!(pending_uploads.len(), 2);
+        assert_eq!(
+            pending_uploads[0],
+            PendingUpload {
+                error_file_path: persisted_error_dir_path(db_dir).join("10.list"),
+                metrics: Some(metrics),
+                block_timestamp: Some(block_timestamp),
+                reason: "error:timeout".to_string(),
+            }
+        );
+        assert_eq!(pending_uploads[1].metrics, None);
+
+        let other_metrics = Metrics::new("other").unwrap();
+        assert_eq!(
+            list_pending_uploads_of_metrics(db_dir, &metrics).unwrap(),
+            pending_uploads[..1].to_vec()
+        );
+        assert!(list_pending_uploads_of_metrics(db_dir, &other_metrics)
+            .unwrap()
+            .is_empty());
+    }
+}
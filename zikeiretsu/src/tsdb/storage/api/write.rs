@@ -18,6 +18,23 @@ use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+/// the result of writing the datas that may be older than the blocks already persisted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LateDatasWriteReport {
+    pub block_timestamp: block_list::BlockTimestamp,
+    /// the blocks already persisted that the written block overlaps.
+    /// the block of exactly the same timestamp is not included since the datas are merged into it.
+    pub overlapped_blocks: Vec<block_list::BlockTimestamp>,
+    /// whether the datas are merged into the block of exactly the same timestamp.
+    pub merged_into_existing_block: bool,
+}
+
+impl LateDatasWriteReport {
+    pub fn is_overlapped(&self) -> bool {
+        self.merged_into_existing_block || !self.overlapped_blocks.is_empty()
+    }
+}
+
 pub async fn write_datas<P: AsRef<Path>>(
     db_dir: P,
     writer_id: &Uuid,
@@ -25,21 +42,43 @@ pub async fn write_datas<P: AsRef<Path>>(
     data_points: &[DataPoint],
     cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
 ) -> Result<()> {
+    let report = write_late_datas(
+        db_dir,
+        writer_id,
+        metrics,
+        data_points,
+        cloud_storage_and_setting,
+    )
+    .await?;
+
+    if report.is_overlapped() {
+        log::warn!(
+            "the written block {block_timestamp} of {metrics} overlaps the persisted blocks {overlapped_blocks:?}",
+            block_timestamp = report.block_timestamp,
+            overlapped_blocks = report.overlapped_blocks,
+        );
+    }
+    Ok(())
+}
+
+/// writes the datas even if there are blocks already persisted in the time range of them.
+/// the new block will overlap them, or will be merged into the block of exactly the same timestamp.
+/// the overlapping blocks are merge-sorted in the write order on reading.
+pub async fn write_late_datas<P: AsRef<Path>>(
+    db_dir: P,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+    data_points: &[DataPoint],
+    cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
+) -> Result<LateDatasWriteReport> {
     debug_assert!(!data_points.is_empty());
     debug_assert!(DataPoint::check_datapoints_is_sorted(data_points).is_ok());
 
     let cloud_infos = if let Some((cloud_storage, cloud_setting)) = cloud_storage_and_setting {
         if cloud_setting.upload_data_after_write {
-            let cloud_lock_file_path = CloudLockfilePath::new(metrics, cloud_storage);
-            if cloud_lock_file_path.exists().await? {
-                return Err(StorageApiError::CreateLockfileError(format!(
-                    "cloud lock file already exists at {lock_file_url} ",
-                    lock_file_url = cloud_lock_file_path.as_url()
-                )));
-            } else {
-                cloud_lock_file_path.create(writer_id).await?;
-                Some((cloud_lock_file_path, cloud_storage, cloud_setting))
-            }
+            let cloud_lock_file_path =
+                create_cloud_lock_file(writer_id, metrics, cloud_storage).await?;
+            Some((cloud_lock_file_path, cloud_storage, cloud_setting))
         } else {
             None
         }
@@ -57,6 +96,8 @@ pub async fn write_datas<P: AsRef<Path>>(
                 block_file_dir,
                 block_file_path,
                 block_timestamp,
+                overlapped_blocks,
+                merged_into_existing_block,
             } = match write_datas_to_local(
                 db_dir,
                 writer_id,
@@ -107,7 +148,11 @@ pub async fn write_datas<P: AsRef<Path>>(
                     }
                 }
             }
-            Ok(())
+            Ok(LateDatasWriteReport {
+                block_timestamp,
+                overlapped_blocks,
+                merged_into_existing_block,
+            })
         };
         write().await
     };
@@ -120,6 +165,85 @@ pub async fn write_datas<P: AsRef<Path>>(
     result
 }
 
+/// fails if the lock file already exists on the cloud storage.
+pub(crate) async fn create_cloud_lock_file<'a>(
+    writer_id: &Uuid,
+    metrics: &'a Metrics,
+    cloud_storage: &'a CloudStorage,
+) -> Result<CloudLockfilePath<'a>> {
+    let cloud_lock_file_path = CloudLockfilePath::new(metrics, cloud_storage);
+    if cloud_lock_file_path.exists().await? {
+        return Err(StorageApiError::CreateLockfileError(format!(
+            "cloud lock file already exists at {lock_file_url} ",
+            lock_file_url = cloud_lock_file_path.as_url()
+        )));
+    }
+    cloud_lock_file_path.create(writer_id).await?;
+    Ok(cloud_lock_file_path)
+}
+
+/// the lock file is removed when the returned one is dropped.
+pub(crate) fn create_local_lock_file(
+    db_dir: &Path,
+    writer_id: &Uuid,
+    metrics: &Metrics,
+) -> Result<Lockfile> {
+    let lock_file_path = lockfile_path(db_dir, metrics);
+    //TODO(tacogips) modulize lockfile to make writer id process DRY.see 'repair' module
+    let mut lockfile = Lockfile::create(&lock_file_path)
+        .map_err(|e| StorageApiError::AcquireLockError(lock_file_path.display().to_string(), e))?;
+    lockfile
+        .write_all(writer_id.to_string().as_bytes())
+        .map_err(|e| {
+            StorageApiError::CreateLockfileError(format!(
+                "could not write writer id to lock file {:?}, error:{}, path:{:?}",
+                writer_id, e, lock_file_path
+            ))
+        })?;
+    Ok(lockfile)
+}
+
+/// replaces the block list file at once not to leave the half written one.
+/// the block list file is removed if the block list has no blocks.
+pub(crate) async fn replace_block_list_file(
+    db_dir: &Path,
+    metrics: &Metrics,
+    block_list: block_list::BlockList,
+) -> Result<PathBuf> {
+    let block_list_file_path = block_list_file_path(db_dir, metrics);
+    if block_list.block_num() == 0 {
+        if block_list_file_path.exists() {
+            fs::remove_file(&block_list_file_path).map_err(StorageApiError::RemoveBlockDirError)?;
+        }
+    } else {
+        let tmp_block_list_file_path = block_list_file_path.with_extension("tmp");
+        if tmp_block_list_file_path.exists() {
+            fs::remove_file(&tmp_block_list_file_path)
+                .map_err(StorageApiError::RemoveBlockDirError)?;
+        }
+        block_list::write_to_block_listfile(&tmp_block_list_file_path, block_list)?;
+        fs::rename(&tmp_block_list_file_path, &block_list_file_path)
+            .map_err(StorageApiError::CreateBlockFileError)?;
+    }
+    super::read::remove_block_list_cache(metrics).await;
+    Ok(block_list_file_path)
+}
+
+/// removes the block file and its directory on local if exists.
+pub(crate) async fn remove_local_block(
+    db_dir: &Path,
+    metrics: &Metrics,
+    block_timestamp: &block_list::BlockTimestamp,
+) -> Result<()> {
+    super::read::remove_block_cache(metrics, block_timestamp).await;
+    let (block_file_dir, _) = block_timestamp_to_block_file_path(db_dir, metrics, block_timestamp);
+    match fs::remove_dir_all(block_file_dir.as_path()) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(StorageApiError::RemoveBlockDirError(e)),
+    }
+}
+
 pub async fn remove_cloud_lock_file_if_same_writer(
     writer_id: &Uuid,
     metrics: &Metrics,
@@ -171,6 +295,8 @@ struct WrittenBlockInfo {
     block_file_dir: PathBuf,
     block_file_path: PathBuf,
     block_timestamp: block_list::BlockTimestamp,
+    overlapped_blocks: Vec<block_list::BlockTimestamp>,
+    merged_into_existing_block: bool,
 }
 
 fn calc_unique_timestamp_num(data_points: &[DataPoint]) -> usize {
@@ -188,57 +314,94 @@ async fn write_datas_to_local(
     data_points: &[DataPoint],
     cloud_storage_and_setting: Option<(&CloudStorage, &CloudStorageSetting)>,
 ) -> Result<WrittenBlockInfo> {
-    let lock_file_path = lockfile_path(db_dir, metrics);
-    //TODO(tacogips) modulize lockfile to make writer id process DRY.see 'repair' module
-    let mut lockfile = Lockfile::create(&lock_file_path)
-        .map_err(|e| StorageApiError::AcquireLockError(lock_file_path.display().to_string(), e))?;
-    lockfile
-        .write_all(writer_id.to_string().as_bytes())
-        .map_err(|e| {
-            StorageApiError::CreateLockfileError(format!(
-                "could not write writer id to lock file {:?}, error:{}, path:{:?}",
-                writer_id, e, lock_file_path
-            ))
-        })?;
+    let _lockfile = create_local_lock_file(db_dir, writer_id, metrics)?;
 
     let head = data_points.get(0).unwrap();
     let tail = data_points.get(data_points.len() - 1).unwrap();
-    let timestamp_num = calc_unique_timestamp_num(data_points);
 
     let block_timestamp = block_list::BlockTimestamp {
         since_sec: head.timestamp_nano.as_timestamp_sec(),
         until_sec: tail.timestamp_nano.as_timestamp_sec() + 1,
     };
-    let block_meta = block_list::BlockMetaInfo {
-        block_timestamp,
-        timestamp_num,
-    };
 
     let cache_setting = super::CacheSetting {
         read_cache: false,
         write_cache: false,
     };
 
-    // write block list file first
-    let block_list_file_path = {
-        let block_list = super::read::read_block_list(
-            "", // buggy: database name is unnecessary if no cache wil be used
-            db_dir,
-            metrics,
-            &cache_setting,
-            cloud_storage_and_setting,
-        )
-        .await;
+    let block_list = super::read::read_block_list(
+        "", // buggy: database name is unnecessary if no cache wil be used
+        db_dir,
+        metrics,
+        &cache_setting,
+        cloud_storage_and_setting,
+    )
+    .await;
+
+    let mut block_list = match block_list {
+        Ok(block_list) => block_list,
+        Err(StorageApiError::NoBlockListFile(_)) => {
+            block_list::BlockList::new(metrics.clone(), TimestampNano::now(), vec![])
+        }
+        Err(e) => return Err(e),
+    };
+
+    let overlapped_blocks: Vec<block_list::BlockTimestamp> = block_list
+        .block_meta_infos
+        .iter()
+        .map(|block_meta| block_meta.block_timestamp)
+        .filter(|each| *each != block_timestamp && each.is_overlapped_with(&block_timestamp))
+        .collect();
+    let existing_block_meta_idx = block_list
+        .block_meta_infos
+        .iter()
+        .position(|block_meta| block_meta.block_timestamp == block_timestamp);
+
+    let (block_file_dir, block_file_path) =
+        block_timestamp_to_block_file_path(db_dir, metrics, &block_timestamp);
 
-        let mut block_list = match block_list {
-            Ok(block_list) => block_list,
-            Err(StorageApiError::NoBlockListFile(_)) => {
-                block_list::BlockList::new(metrics.clone(), TimestampNano::now(), vec![])
+    // the block of exactly the same timestamp has the same path. merge the datas into it.
+    let merged_data_points = match existing_block_meta_idx {
+        Some(_) => {
+            let existing_block = super::read::read_block(
+                "",
+                db_dir,
+                metrics,
+                None,
+                &block_timestamp,
+                &cache_setting,
+                cloud_storage_and_setting,
+            )
+            .await?;
+            let mut merged_data_points = existing_block.into_datapoints()?;
+            merged_data_points.extend_from_slice(data_points);
+            // stable sort. the existing datas come first in the same timestamps
+            merged_data_points.sort_by_key(|datapoint| datapoint.timestamp_nano);
+            Some(merged_data_points)
+        }
+        None => {
+            if block_file_path.exists() {
+                return Err(StorageApiError::UnsupportedStorageStatus(format!(
+                    "block file already exists at {block_file_path} but it's not in the block list. repair the block list first",
+                    block_file_path = block_file_path.display()
+                )));
             }
-            Err(e) => return Err(e),
-        };
+            None
+        }
+    };
+    let data_points = merged_data_points.as_deref().unwrap_or(data_points);
+
+    // the merged block is regarded as the latest one as well since it has the latest datas.
+    let block_meta =
+        block_list::BlockMetaInfo::new(block_timestamp, calc_unique_timestamp_num(data_points))
+            .with_write_seq(block_list.next_write_seq());
 
-        block_list.add_blockmeta(block_meta)?;
+    // write block list file first
+    let block_list_file_path = {
+        match existing_block_meta_idx {
+            Some(idx) => block_list.block_meta_infos[idx] = block_meta,
+            None => block_list.add_blockmeta(block_meta)?,
+        }
         block_list.update_updated_at(TimestampNano::now());
 
         let block_list_file_path = block_list_file_path(db_dir, metrics);
@@ -247,29 +410,29 @@ async fn write_datas_to_local(
     };
 
     // write block file
-    let (block_file_dir, block_file_path) = {
-        let (block_file_dir, block_file_path) =
-            block_timestamp_to_block_file_path(db_dir, metrics, &block_meta.block_timestamp);
-        if block_file_path.exists() {
-            return Err(StorageApiError::UnsupportedStorageStatus(format!(
-                "block file already exists at {block_file_path}. merging block files is not supported yet...",
-                block_file_path =block_file_path.display()
-            )));
-        }
-
-        create_dir_all(block_file_dir.as_path()).map_err(StorageApiError::CreateBlockFileError)?;
+    create_dir_all(block_file_dir.as_path()).map_err(StorageApiError::CreateBlockFileError)?;
+    if existing_block_meta_idx.is_some() {
+        // replace the existing block file at once not to leave the half written one
+        let tmp_block_file_path = block_file_path.with_extension("tmp");
+        block::write_to_block_file(&tmp_block_file_path, data_points)?;
+        fs::rename(&tmp_block_file_path, &block_file_path)
+            .map_err(StorageApiError::CreateBlockFileError)?;
+        super::read::remove_block_cache(metrics, &block_timestamp).await;
+    } else {
         block::write_to_block_file(&block_file_path, data_points)?;
-        (block_file_dir, block_file_path)
-    };
+    }
+
     Ok(WrittenBlockInfo {
         block_list_file_path,
         block_file_dir,
         block_file_path,
         block_timestamp,
+        overlapped_blocks,
+        merged_into_existing_block: existing_block_meta_idx.is_some(),
     })
 }
 
-async fn upload_to_cloud(
+pub(crate) async fn upload_to_cloud(
     block_list_file_path: &Path,
     block_file_path: &Path,
     metrics: &Metrics,
@@ -292,7 +455,7 @@ async fn upload_to_cloud(
     Ok(())
 }
 
-async fn write_error_file(
+pub(crate) async fn write_error_file(
     db_dir: &Path,
     error_time: TimestampNano,
     metrics: &Metrics,
@@ -312,3 +475,27 @@ async fn write_error_file(
     persisted_error::write_persisted_error(error_file_path, err)?;
     Ok(())
 }
+
+/// writes the error file of each block so that all of them are retried.
+/// the error files are named after the error time, so it is shifted by a nano second for each block.
+pub(crate) async fn write_error_files(
+    db_dir: &Path,
+    metrics: &Metrics,
+    error_type: persisted_error::PersistedErrorType,
+    block_timestamps: &[block_list::BlockTimestamp],
+    detail: Option<String>,
+) -> Result<()> {
+    let error_time = TimestampNano::now();
+    for (idx, block_timestamp) in block_timestamps.iter().enumerate() {
+        write_error_file(
+            db_dir,
+            TimestampNano::new(error_time.as_inner() + idx as u64),
+            metrics,
+            error_type.clone(),
+            *block_timestamp,
+            detail.clone(),
+        )
+        .await?;
+    }
+    Ok(())
+}
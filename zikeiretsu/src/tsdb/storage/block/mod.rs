@@ -182,6 +182,26 @@ pub fn read_from_block_file<P: AsRef<Path>>(
     read::read_from_block_with_specific_fields(&block_data, field_selectors)
 }
 
+/// read only the rows at `row_indices` (must be sorted) from the block file.
+pub fn read_from_block_file_with_rows<P: AsRef<Path>>(
+    path: P,
+    field_selectors: Option<&[usize]>,
+    row_indices: &[usize],
+) -> Result<TimeSeriesDataFrame> {
+    let block_file =
+        File::open(path.as_ref()).map_err(|e| BlockError::file_error(e, path.as_ref()))?;
+    let block_data = unsafe {
+        MmapOptions::new()
+            .map(&block_file)
+            .map_err(|e| BlockError::file_error(e, path))?
+    };
+    read::read_from_block_with_specific_fields_and_rows(
+        &block_data,
+        field_selectors,
+        Some(row_indices),
+    )
+}
+
 pub fn write_to_block_file<P: AsRef<Path>>(path: P, datapoints: &[DataPoint]) -> Result<()> {
     let mut block_file = if path.as_ref().exists() {
         OpenOptions::new()
@@ -308,6 +328,34 @@ mod test {
         assert_eq!(read_data.into_datapoints().unwrap(), datapoints);
     }
 
+    #[test]
+    fn test_block_with_rows_1() {
+        let datapoints = float_data_points!(
+            {1629745451_715062000, vec![100f64,12f64]},
+            {1629745451_715063000, vec![200f64,36f64]},
+            {1629745451_715064000, vec![200f64,36f64]},
+            {1629745451_715065000, vec![300f64,36f64]},
+            {1629745451_715066000, vec![300f64,36f64]}
+        );
+
+        let mut data = Vec::<u8>::new();
+        let result = write::write_to_block(&mut data, &datapoints);
+        assert!(result.is_ok());
+
+        let read_data =
+            read::read_from_block_with_specific_fields_and_rows(&data, None, Some(&[0, 3, 10]));
+
+        assert!(read_data.is_ok());
+        let read_data = read_data.unwrap();
+        assert_eq!(read_data.len(), 2);
+
+        let expected = float_data_points!(
+            {1629745451_715062000, vec![100f64,12f64]},
+            {1629745451_715065000, vec![300f64,36f64]}
+        );
+        assert_eq!(read_data.into_datapoints().unwrap(), expected);
+    }
+
     #[test]
     fn test_block_2() {
         let datapoints = float_data_points!(
@@ -1,5 +1,6 @@
 use super::compress::bools;
 use super::{field_type_convert, BlockError, Result, TimestampDeltas};
+use crate::tsdb::util::pick_values;
 use crate::tsdb::*;
 use bits_ope::*;
 use std::collections::HashMap;
@@ -7,6 +8,15 @@ use std::collections::HashMap;
 pub(crate) fn read_from_block_with_specific_fields(
     block_data: &[u8],
     field_selectors: Option<&[usize]>,
+) -> Result<TimeSeriesDataFrame> {
+    read_from_block_with_specific_fields_and_rows(block_data, field_selectors, None)
+}
+
+/// `row_indices` must be sorted. only the rows at the indices are materialized if passed.
+pub(crate) fn read_from_block_with_specific_fields_and_rows(
+    block_data: &[u8],
+    field_selectors: Option<&[usize]>,
+    row_indices: Option<&[usize]>,
 ) -> Result<TimeSeriesDataFrame> {
     // 1. number  of data
     let (number_of_data, mut block_idx): (u64, usize) =
@@ -135,6 +145,14 @@ pub(crate) fn read_from_block_with_specific_fields(
         }
     };
 
+    let (timestamps, number_of_rows) = match row_indices {
+        None => (timestamps, number_of_datapoints),
+        Some(row_indices) => {
+            let timestamps = pick_values(&timestamps, row_indices);
+            let number_of_rows = timestamps.len();
+            (timestamps, number_of_rows)
+        }
+    };
     let data_field_size = if field_selectors_map.is_empty() {
         number_of_field as usize
     } else {
@@ -142,7 +160,7 @@ pub(crate) fn read_from_block_with_specific_fields(
     };
     let mut block_field_values = Vec::<SeriesValues>::with_capacity(data_field_size);
     for _ in 0..data_field_size {
-        block_field_values.push(SeriesValues::Vacant(number_of_datapoints));
+        block_field_values.push(SeriesValues::Vacant(number_of_rows));
     }
 
     let is_field_to_select = |idx: usize| {
@@ -167,7 +185,7 @@ pub(crate) fn read_from_block_with_specific_fields(
                 if let Some(data_series_idx) = is_field_to_select(field_idx) {
                     let _ = std::mem::replace(
                         &mut block_field_values[data_series_idx],
-                        SeriesValues::Float64(float_values),
+                        SeriesValues::Float64(pick_rows(float_values, row_indices)),
                     );
                 }
             }
@@ -184,7 +202,7 @@ pub(crate) fn read_from_block_with_specific_fields(
                 if let Some(data_series_idx) = is_field_to_select(field_idx) {
                     let _ = std::mem::replace(
                         &mut block_field_values[data_series_idx],
-                        SeriesValues::Bool(bool_values),
+                        SeriesValues::Bool(pick_rows(bool_values, row_indices)),
                     );
                 }
             }
@@ -206,3 +224,10 @@ pub(crate) fn read_from_block_with_specific_fields(
     );
     Ok(dataframe)
 }
+
+fn pick_rows<T: Clone>(values: Vec<T>, row_indices: Option<&[usize]>) -> Vec<T> {
+    match row_indices {
+        None => values,
+        Some(row_indices) => pick_values(&values, row_indices),
+    }
+}
@@ -34,6 +34,10 @@ impl BlockTimestamp {
         other.until_sec <= self.since_sec
     }
 
+    pub fn is_overlapped_with(&self, other: &Self) -> bool {
+        self.since_sec < other.until_sec && other.since_sec < self.until_sec
+    }
+
     #[allow(dead_code)]
     fn is_valid(&self) -> bool {
         self.since_sec <= self.until_sec
@@ -8,6 +8,7 @@
 ///  (5) timestamp second head (untile)(v byte)
 ///  (6) timestamp second (until)(v byte)
 ///  (7) timestamp nums in each blocks (v byte)
+///  (8) write sequences of each blocks (v byte). the files written before it was added don't have it.
 ///
 mod block_timestamp;
 
@@ -110,21 +111,32 @@ impl From<Vec<TimestampSec>> for TimestampSecDeltas {
 pub struct BlockMetaInfo {
     pub block_timestamp: BlockTimestamp,
     pub timestamp_num: usize,
+    /// the order in which the blocks were written.
+    /// the rows of the same timestamp in the overlapping blocks are ordered by it.
+    pub write_seq: u64,
 }
 impl BlockMetaInfo {
     pub(crate) fn new(block_timestamp: BlockTimestamp, timestamp_num: usize) -> Self {
         Self {
             block_timestamp,
             timestamp_num,
+            write_seq: 0,
         }
     }
 
+    pub(crate) fn with_write_seq(mut self, write_seq: u64) -> Self {
+        self.write_seq = write_seq;
+        self
+    }
+
     pub fn from_splited_timestamps(
         since_secs: Vec<TimestampSec>,
         until_secs: Vec<TimestampSec>,
         timestmap_nums: Vec<u64>,
+        write_seqs: Vec<u64>,
     ) -> Vec<BlockMetaInfo> {
         debug_assert_eq!(since_secs.len(), until_secs.len());
+        debug_assert_eq!(since_secs.len(), write_seqs.len());
         let timestamp_pairs: Vec<(TimestampSec, TimestampSec)> =
             since_secs.into_iter().zip(until_secs.into_iter()).collect();
         let block_timestsamps = BlockTimestamp::from_timestamp_pairs(timestamp_pairs);
@@ -132,8 +144,10 @@ impl BlockMetaInfo {
         block_timestsamps
             .into_iter()
             .zip(timestmap_nums.into_iter())
-            .map(|(block_timestamp, timestamp_num)| {
+            .zip(write_seqs.into_iter())
+            .map(|((block_timestamp, timestamp_num), write_seq)| {
                 BlockMetaInfo::new(block_timestamp, timestamp_num as usize)
+                    .with_write_seq(write_seq)
             })
             .collect()
     }
@@ -192,6 +206,15 @@ impl BlockList {
             _ => panic!("range of block list has bug"),
         }
     }
+    /// the write sequence of the block to be written next.
+    pub fn next_write_seq(&self) -> u64 {
+        self.block_meta_infos
+            .iter()
+            .map(|block_meta| block_meta.write_seq + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
     pub fn update_updated_at(&mut self, dt: TimestampNano) {
         self.updated_timestamp_sec = dt;
     }
@@ -237,18 +260,23 @@ impl BlockList {
         }
     }
 
-    fn split_block_list_timestamps(&self) -> (Vec<TimestampSec>, Vec<TimestampSec>, Vec<usize>) {
+    #[allow(clippy::type_complexity)]
+    fn split_block_list_timestamps(
+        &self,
+    ) -> (Vec<TimestampSec>, Vec<TimestampSec>, Vec<usize>, Vec<u64>) {
         let mut sinces = Vec::<TimestampSec>::new();
         let mut untils = Vec::<TimestampSec>::new();
         let mut timesamp_nums = Vec::<usize>::new();
+        let mut write_seqs = Vec::<u64>::new();
 
         for each in self.block_meta_infos.iter() {
             sinces.push(each.block_timestamp.since_sec);
             untils.push(each.block_timestamp.until_sec);
             timesamp_nums.push(each.timestamp_num);
+            write_seqs.push(each.write_seq);
         }
 
-        (sinces, untils, timesamp_nums)
+        (sinces, untils, timesamp_nums, write_seqs)
     }
 
     pub fn search(
@@ -276,16 +304,13 @@ impl BlockList {
                 match lower_idx {
                     None => Ok(None),
                     Some(lower_idx) => {
-                        let upper_idx = binary_search_by(
-                            block_meta_infos,
-                            |block_meta| block_meta.block_timestamp.since_sec.cmp(until),
-                            BinaryRangeSearchType::AtMostInclusive,
-                        );
+                        let upper_idx =
+                            last_block_idx_since_at_most(&block_meta_infos[lower_idx..], until);
 
                         match upper_idx {
-                            Some(upper_idx) => {
-                                Ok(Some(&block_meta_infos[lower_idx..upper_idx + 1]))
-                            }
+                            Some(upper_idx) => Ok(Some(
+                                &block_meta_infos[lower_idx..lower_idx + upper_idx + 1],
+                            )),
                             None => Ok(None),
                         }
                     }
@@ -306,11 +331,7 @@ impl BlockList {
             }
 
             (None, Some(until)) => {
-                let upper_idx = binary_search_by(
-                    block_meta_infos,
-                    |block_meta| block_meta.block_timestamp.since_sec.cmp(until),
-                    BinaryRangeSearchType::AtMostInclusive,
-                );
+                let upper_idx = last_block_idx_since_at_most(block_meta_infos, until);
 
                 match upper_idx {
                     Some(upper_idx) => Ok(Some(&block_meta_infos[..upper_idx + 1])),
@@ -328,6 +349,55 @@ impl BlockList {
     }
 }
 
+/// the blocks are sorted by until_sec so the since_secs are not sorted if some blocks overlap.
+/// so the upper bound is searched linearly from the tail.
+fn last_block_idx_since_at_most(
+    block_meta_infos: &[BlockMetaInfo],
+    until: &TimestampSec,
+) -> Option<usize> {
+    block_meta_infos
+        .iter()
+        .rposition(|block_meta| block_meta.block_timestamp.since_sec <= *until)
+}
+
+/// whether any of the blocks overlaps the others. the blocks must be sorted by until_sec.
+pub fn has_overlapping_blocks(block_meta_infos: &[BlockMetaInfo]) -> bool {
+    let mut max_until: Option<&TimestampSec> = None;
+    for each in block_meta_infos.iter() {
+        if let Some(max_until) = max_until {
+            if each.block_timestamp.since_sec < *max_until {
+                return true;
+            }
+        }
+        max_until = Some(&each.block_timestamp.until_sec);
+    }
+    false
+}
+
+/// groups of the indices of the blocks that overlap each other directly or through the other blocks.
+/// the groups are in the order of since_sec.
+pub fn overlapping_block_groups(block_meta_infos: &[BlockMetaInfo]) -> Vec<Vec<usize>> {
+    let mut indices: Vec<usize> = (0..block_meta_infos.len()).collect();
+    indices.sort_by_key(|idx| block_meta_infos[*idx].block_timestamp.since_sec);
+
+    let mut groups = Vec::<Vec<usize>>::new();
+    let mut max_until: Option<TimestampSec> = None;
+    for idx in indices {
+        let block_timestamp = &block_meta_infos[idx].block_timestamp;
+        match (groups.last_mut(), max_until) {
+            (Some(group), Some(until)) if block_timestamp.since_sec < until => {
+                group.push(idx);
+                max_until = Some(until.max(block_timestamp.until_sec));
+            }
+            _ => {
+                groups.push(vec![idx]);
+                max_until = Some(block_timestamp.until_sec);
+            }
+        }
+    }
+    groups
+}
+
 pub(crate) fn write_to_blocklist<W>(mut block_list_file: W, block_list: BlockList) -> Result<()>
 where
     W: Write,
@@ -349,7 +419,7 @@ where
     //  (2) number of block timestamps (n bytes)
     base_128_variants::compress_u64(block_timestamp_size as u64, &mut block_list_file)?;
 
-    let (sinces, untils, timestamp_nums) = block_list.split_block_list_timestamps();
+    let (sinces, untils, timestamp_nums, write_seqs) = block_list.split_block_list_timestamps();
 
     //  (3) timestamp second head (since)(v byte)
     //  (4) timestamp second deltas(since)(v byte)
@@ -367,6 +437,9 @@ where
         &mut block_list_file,
     )?;
 
+    // (8) write sequences of each block
+    simple8b_rle::compress(&write_seqs, &mut block_list_file)?;
+
     Ok(())
 }
 
@@ -459,17 +532,33 @@ pub(crate) fn read_from_blocklist(metrics: &Metrics, block_data: &[u8]) -> Resul
 
     // (7) timestamps nums in each block
     let mut timestamp_nums = Vec::<u64>::new();
-    let _block_idx = simple8b_rle::decompress(
+    let consumed_idx = simple8b_rle::decompress(
         &block_data[block_idx..],
         &mut timestamp_nums,
         Some(number_of_block_timstamps as usize),
     )?;
+    let block_idx = block_idx + consumed_idx;
+
+    // (8) write sequences of each block
+    // the blocks of the older files are regarded as written in the order of the block list.
+    let write_seqs = if block_idx < block_data.len() {
+        let mut write_seqs = Vec::<u64>::new();
+        simple8b_rle::decompress(
+            &block_data[block_idx..],
+            &mut write_seqs,
+            Some(number_of_block_timstamps as usize),
+        )?;
+        write_seqs
+    } else {
+        (0..number_of_block_timstamps).collect()
+    };
 
     //TODO(tacogips) rename from_splited_timestamps
     let block_meta_infos = BlockMetaInfo::from_splited_timestamps(
         since_timedeltas.as_timestamp_secs(),
         until_timedeltas.as_timestamp_secs(),
         timestamp_nums,
+        write_seqs,
     );
 
     let block_list = BlockList {
@@ -543,6 +632,46 @@ mod test {
         assert_eq!(result, block_list);
     }
 
+    #[test]
+    fn test_wr_block_list_write_seqs() {
+        let metrics = Metrics::new("dummy").unwrap();
+        let block_list = BlockList::new(
+            metrics.clone(),
+            TimestampNano::new(1629745452_715062000),
+            vec![
+                BlockMetaInfo::new(
+                    BlockTimestamp::new(TimestampSec::new(12), TimestampSec::new(21)),
+                    2,
+                )
+                .with_write_seq(1),
+                BlockMetaInfo::new(
+                    BlockTimestamp::new(TimestampSec::new(10), TimestampSec::new(31)),
+                    3,
+                )
+                .with_write_seq(0),
+            ],
+        );
+        assert_eq!(block_list.next_write_seq(), 2);
+
+        let mut dest = Vec::<u8>::new();
+        write_to_blocklist(&mut dest, block_list.clone()).unwrap();
+        assert_eq!(read_from_blocklist(&metrics, &dest).unwrap(), block_list);
+
+        // the block list written without the write sequences
+        let mut write_seqs = Vec::<u8>::new();
+        simple8b_rle::compress(&[1u64, 0], &mut write_seqs).unwrap();
+        dest.truncate(dest.len() - write_seqs.len());
+        let result = read_from_blocklist(&metrics, &dest).unwrap();
+        assert_eq!(
+            result
+                .block_meta_infos
+                .iter()
+                .map(|block_meta| block_meta.write_seq)
+                .collect::<Vec<u64>>(),
+            vec![0, 1]
+        );
+    }
+
     macro_rules! blts {
         ($since:expr,$until:expr) => {
             BlockTimestamp::new(TimestampSec::new($since), TimestampSec::new($until))
@@ -580,6 +709,22 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_overlapping_block_groups() {
+        let block_meta_infos = block_metas!({10,20},{21,30},{15,25},{40,50});
+        assert_eq!(
+            overlapping_block_groups(&block_meta_infos),
+            vec![vec![0, 2, 1], vec![3]]
+        );
+
+        let block_meta_infos = block_metas!({10,20},{20,30});
+        assert_eq!(
+            overlapping_block_groups(&block_meta_infos),
+            vec![vec![0], vec![1]]
+        );
+        assert!(overlapping_block_groups(&[]).is_empty());
+    }
+
     #[test]
     fn test_block_timestamps_insert_1() {
         let mut block_timestamps = block_timestamps!({10,20},{10,20}, {21,30});
@@ -1042,4 +1187,31 @@ mod test {
             assert_eq!(blocklist.block_meta_infos, expected);
         }
     }
+
+    #[test]
+    fn test_block_list_overlapping_blocks() {
+        let updated_timestamp = TimestampNano::new(1629745452_715062000);
+        let init_metas = block_metas!({10,20},{20,30},{30,40});
+
+        let metrics = Metrics::new("dummy").unwrap();
+        let mut blocklist = BlockList::new(metrics, updated_timestamp, init_metas);
+        assert!(!has_overlapping_blocks(&blocklist.block_meta_infos));
+
+        // late datas
+        blocklist.add_blockmeta(blmeta!(12, 15)).unwrap();
+        assert_eq!(
+            blocklist.block_meta_infos,
+            block_metas!({12,15},{10,20},{20,30},{30,40})
+        );
+        assert!(has_overlapping_blocks(&blocklist.block_meta_infos));
+
+        let result = blocklist.search(Some(&ts!(13)), Some(&ts!(14))).unwrap();
+        assert_eq!(result.unwrap(), block_metas!({12,15},{10,20}));
+
+        let result = blocklist.search(None, Some(&ts!(11))).unwrap();
+        assert_eq!(result.unwrap(), block_metas!({12,15},{10,20}));
+
+        let result = blocklist.search(Some(&ts!(25)), Some(&ts!(35))).unwrap();
+        assert_eq!(result.unwrap(), block_metas!({20,30},{30,40}));
+    }
 }
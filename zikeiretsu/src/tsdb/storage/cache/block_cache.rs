@@ -48,4 +48,17 @@ impl BlockCache {
         };
         self.block_dfs.put(key, block);
     }
+
+    /// removes the cached block of all the databases.
+    pub async fn remove(&mut self, metrics: &Metrics, block_timestamp: &BlockTimestamp) {
+        let keys: Vec<BlockCacheKey> = self
+            .block_dfs
+            .iter()
+            .filter(|(key, _)| key.metrics == *metrics && key.block_timestamp == *block_timestamp)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys.iter() {
+            self.block_dfs.pop(key);
+        }
+    }
 }
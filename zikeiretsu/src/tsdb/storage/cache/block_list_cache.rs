@@ -33,4 +33,9 @@ impl BlockListCache {
         };
         self.block_lists.insert(key, block_list);
     }
+
+    /// removes the cached block list of all the databases.
+    pub async fn remove(&mut self, metrics: &Metrics) {
+        self.block_lists.retain(|key, _| key.metrics != *metrics);
+    }
 }
@@ -3,9 +3,9 @@ use crate::tsdb::metrics::Metrics;
 use crate::tsdb::timestamp_nano::*;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
 use std::io::{Error as IOError, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
@@ -19,13 +19,13 @@ pub enum PersistedErrorIOError {
     IOError(#[from] IOError),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum PersistedErrorType {
     FailedToUploadBlockOrBLockList,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PersistedError {
     time: TimestampNano,
     metrics: Option<Metrics>,
@@ -50,13 +50,40 @@ impl PersistedError {
             detail,
         }
     }
+
+    pub fn time(&self) -> &TimestampNano {
+        &self.time
+    }
+
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    pub fn error_type(&self) -> &PersistedErrorType {
+        &self.error_type
+    }
+
+    pub fn block_timestamp(&self) -> Option<&BlockTimestamp> {
+        self.block_timestamp.as_ref()
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
 }
 
 pub(crate) fn write_persisted_error<P: AsRef<Path>>(
     path: P,
     persisted_error: PersistedError,
 ) -> Result<()> {
-    let mut dest_file = OpenOptions::new().read(true).create(true).open(path)?;
+    if let Some(error_dir) = path.as_ref().parent() {
+        fs::create_dir_all(error_dir)?;
+    }
+    let mut dest_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
 
     let serialized_error = serde_json::to_string(&persisted_error)?;
     dest_file.write_all(serialized_error.as_bytes())?;
@@ -64,3 +91,74 @@ pub(crate) fn write_persisted_error<P: AsRef<Path>>(
     dest_file.flush()?;
     Ok(())
 }
+
+pub(crate) fn read_persisted_error<P: AsRef<Path>>(path: P) -> Result<PersistedError> {
+    let serialized_error = fs::read(path)?;
+    Ok(serde_json::from_slice(&serialized_error)?)
+}
+
+/// the paths of the persisted error files in the error directory in the order of the error time.
+pub(crate) fn list_persisted_error_files<P: AsRef<Path>>(error_dir: P) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(error_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut error_file_paths = Vec::<PathBuf>::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("list") {
+            error_file_paths.push(path);
+        }
+    }
+    // the file names are the error times in nano seconds
+    error_file_paths.sort_by_key(|path| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+    });
+    Ok(error_file_paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::TimestampSec;
+
+    #[test]
+    fn test_write_then_read_persisted_errors() {
+        let temp_dir = tempdir::TempDir::new("persisted_error_test").unwrap();
+        let error_dir = temp_dir.path().join("error");
+
+        let persisted_error = |time: u64| {
+            PersistedError::new(
+                TimestampNano::new(time),
+                Some(Metrics::new("test").unwrap()),
+                PersistedErrorType::FailedToUploadBlockOrBLockList,
+                Some(BlockTimestamp::new(
+                    TimestampSec::new(1),
+                    TimestampSec::new(2),
+                )),
+                Some("detail".to_string()),
+            )
+        };
+        for time in [20, 3] {
+            write_persisted_error(
+                error_dir.join(format!("{time}.list")),
+                persisted_error(time),
+            )
+            .unwrap();
+        }
+
+        let error_file_paths = list_persisted_error_files(&error_dir).unwrap();
+        assert_eq!(
+            error_file_paths,
+            vec![error_dir.join("3.list"), error_dir.join("20.list")]
+        );
+        assert_eq!(
+            read_persisted_error(&error_file_paths[0]).unwrap(),
+            persisted_error(3)
+        );
+    }
+}
@@ -1,12 +1,17 @@
+mod record;
+mod segmented_wal;
 mod single_file_wal;
+mod sync_policy;
 
 use crate::tsdb::datapoint::DataPoint;
 use crate::tsdb::metrics::Metrics;
 use async_trait::async_trait;
 use bincode::Error as BincodeError;
+pub use segmented_wal::{SegmentedWal, DEFAULT_WAL_SEGMENT_SIZE};
 pub use single_file_wal::SingleFileWal;
 use std::io::Error as IOError;
 use std::path::Path;
+pub use sync_policy::WalSyncPolicy;
 use thiserror::Error;
 pub type Result<T> = std::result::Result<T, WalError>;
 
@@ -19,13 +24,28 @@ pub enum WalError {
 
     #[error("wal file open error: {0}")]
     WalFileOpenError(String),
+
+    #[error("wal record too large: {0}")]
+    RecordTooLarge(String),
 }
 
+/// the position in the wal that the records before it are sealed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalCheckpoint(pub(crate) u64);
+
 #[async_trait]
 pub trait WalWriter: Sync + Send {
     async fn write(&mut self, datapoint: &[DataPoint]) -> Result<()>;
     async fn load(&self) -> Result<Vec<DataPoint>>;
     fn clean(&mut self) -> Result<()>;
+    /// seals the records written so far. the following records are written after the returned checkpoint.
+    fn seal(&mut self) -> Result<WalCheckpoint>;
+    /// removes the records before the checkpoint, that are already persisted in the blocks.
+    fn checkpoint(&mut self, checkpoint: WalCheckpoint) -> Result<()>;
+    /// syncs the written records to the disk regardless of the sync policy.
+    fn sync(&mut self) -> Result<()>;
+    /// syncs the records left unsynced by the sync policy if they are due. called by `start_wal_sync`.
+    fn sync_due(&mut self) -> Result<()>;
     fn open_or_create(data_dir_path: &Path, metrics: &Metrics) -> Result<Self>
     where
         Self: Sized;
@@ -44,6 +64,18 @@ impl WalWriter for EmptyWal {
     fn clean(&mut self) -> Result<()> {
         Ok(())
     }
+    fn seal(&mut self) -> Result<WalCheckpoint> {
+        Ok(WalCheckpoint(0))
+    }
+    fn checkpoint(&mut self, _checkpoint: WalCheckpoint) -> Result<()> {
+        Ok(())
+    }
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_due(&mut self) -> Result<()> {
+        Ok(())
+    }
     fn open_or_create(_data_dir_path: &Path, _metrics: &Metrics) -> Result<Self> {
         Ok(EmptyWal)
     }
@@ -0,0 +1,344 @@
+use super::{Result, WalError};
+use crate::tsdb::datapoint::DataPoint;
+use crate::tsdb::field::{FieldType, FieldValue};
+use crate::tsdb::{TimestampNano, TimestampSec};
+use std::io::Write;
+
+/// the records start with a marker. the records written by the former versions start with
+/// the length of the datapoint in 64bit big endian, so their first byte is always zero.
+const DATAPOINT_RECORD_MARKER: u8 = 0xA5;
+const BATCH_RECORD_MARKER: u8 = 0xA6;
+
+/// marker(1) + length of the payload(4) + crc32 of the payload(4)
+const RECORD_HEADER_SIZE: usize = 9;
+const LEGACY_RECORD_HEADER_SIZE: usize = 8;
+
+fn write_record<W>(mut w: W, marker: u8, payload: &[u8]) -> Result<()>
+where
+    W: Write,
+{
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    header[0] = marker;
+    let payload_len = u32::try_from(payload.len())
+        .map_err(|_| WalError::RecordTooLarge(format!("payload of {} bytes", payload.len())))?;
+    header[1..5].copy_from_slice(&payload_len.to_be_bytes());
+    header[5..9].copy_from_slice(&crc32fast::hash(payload).to_be_bytes());
+    w.write_all(&header)?;
+    w.write_all(payload)?;
+    Ok(())
+}
+
+/// a record of a bincode serialized datapoint. only the former versions write it.
+#[cfg(test)]
+pub(super) fn write_datapoint_record<W>(w: W, datapoint: &DataPoint) -> Result<()>
+where
+    W: Write,
+{
+    let serialized = bincode::serialize(&datapoint)?;
+    write_record(w, DATAPOINT_RECORD_MARKER, &serialized)
+}
+
+/// writes the datapoints as batch records. a batch record is made for each run of the datapoints of the same field types.
+/// the payload is
+/// [datapoint num(4)][field num(2)][field types(1 each)][timestamps(8 each)][field values of the datapoints]
+pub(super) fn write_batch_records<W>(mut w: W, datapoints: &[DataPoint]) -> Result<()>
+where
+    W: Write,
+{
+    let mut payload = Vec::<u8>::new();
+    let mut batch_start = 0;
+    while batch_start < datapoints.len() {
+        let field_types: Vec<FieldType> = datapoints[batch_start]
+            .field_values
+            .iter()
+            .map(|field_value| field_value.as_type())
+            .collect();
+        let batch_len = datapoints[batch_start..]
+            .iter()
+            .take_while(|datapoint| same_field_types(&field_types, &datapoint.field_values))
+            .count();
+        let batch = &datapoints[batch_start..batch_start + batch_len];
+
+        let datapoint_num = u32::try_from(batch.len()).map_err(|_| {
+            WalError::RecordTooLarge(format!("batch of {} datapoints", batch.len()))
+        })?;
+        let field_num = u16::try_from(field_types.len()).map_err(|_| {
+            WalError::RecordTooLarge(format!("datapoint of {} fields", field_types.len()))
+        })?;
+
+        payload.clear();
+        payload.extend_from_slice(&datapoint_num.to_be_bytes());
+        payload.extend_from_slice(&field_num.to_be_bytes());
+        for field_type in field_types.iter() {
+            payload.push(field_type_tag(field_type));
+        }
+        for datapoint in batch {
+            payload.extend_from_slice(&datapoint.timestamp_nano.as_inner().to_be_bytes());
+        }
+        for datapoint in batch {
+            for field_value in datapoint.field_values.iter() {
+                write_field_value(&mut payload, field_value)?;
+            }
+        }
+        write_record(&mut w, BATCH_RECORD_MARKER, &payload)?;
+
+        batch_start += batch_len;
+    }
+    Ok(())
+}
+
+fn same_field_types(field_types: &[FieldType], field_values: &[FieldValue]) -> bool {
+    field_types.len() == field_values.len()
+        && field_types
+            .iter()
+            .zip(field_values.iter())
+            .all(|(field_type, field_value)| *field_type == field_value.as_type())
+}
+
+fn field_type_tag(field_type: &FieldType) -> u8 {
+    match field_type {
+        FieldType::Float64 => 0,
+        FieldType::UInt64 => 1,
+        FieldType::Bool => 2,
+        FieldType::TimestampNano => 3,
+        FieldType::TimestampSec => 4,
+        FieldType::String => 5,
+        FieldType::Vacant => 6,
+    }
+}
+
+fn field_type_from_tag(tag: u8) -> Option<FieldType> {
+    match tag {
+        0 => Some(FieldType::Float64),
+        1 => Some(FieldType::UInt64),
+        2 => Some(FieldType::Bool),
+        3 => Some(FieldType::TimestampNano),
+        4 => Some(FieldType::TimestampSec),
+        5 => Some(FieldType::String),
+        6 => Some(FieldType::Vacant),
+        _ => None,
+    }
+}
+
+fn write_field_value(payload: &mut Vec<u8>, field_value: &FieldValue) -> Result<()> {
+    match field_value {
+        FieldValue::Float64(v) => payload.extend_from_slice(&v.to_be_bytes()),
+        FieldValue::UInt64(v) => payload.extend_from_slice(&v.to_be_bytes()),
+        FieldValue::Bool(v) => payload.push(*v as u8),
+        FieldValue::TimestampNano(v) => payload.extend_from_slice(&v.as_inner().to_be_bytes()),
+        FieldValue::TimestampSec(v) => payload.extend_from_slice(&(**v).to_be_bytes()),
+        FieldValue::String(v) => {
+            let len = u32::try_from(v.len()).map_err(|_| {
+                WalError::RecordTooLarge(format!("string field of {} bytes", v.len()))
+            })?;
+            payload.extend_from_slice(&len.to_be_bytes());
+            payload.extend_from_slice(v.as_bytes());
+        }
+        FieldValue::Vacant => {}
+    }
+    Ok(())
+}
+
+/// reads the payload of a batch record from the head.
+struct PayloadReader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let taken = self
+            .payload
+            .get(self.position..self.position.checked_add(len)?)?;
+        self.position += len;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)?.try_into().ok().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)?.try_into().ok().map(u32::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)?.try_into().ok().map(u64::from_be_bytes)
+    }
+
+    fn field_value(&mut self, field_type: &FieldType) -> Option<FieldValue> {
+        let field_value = match field_type {
+            FieldType::Float64 => FieldValue::Float64(f64::from_bits(self.u64()?)),
+            FieldType::UInt64 => FieldValue::UInt64(self.u64()?),
+            FieldType::Bool => FieldValue::Bool(self.u8()? != 0),
+            FieldType::TimestampNano => FieldValue::TimestampNano(TimestampNano::new(self.u64()?)),
+            FieldType::TimestampSec => FieldValue::TimestampSec(TimestampSec::new(self.u64()?)),
+            FieldType::String => {
+                let len = self.u32()? as usize;
+                FieldValue::String(String::from_utf8(self.take(len)?.to_vec()).ok()?)
+            }
+            FieldType::Vacant => FieldValue::Vacant,
+        };
+        Some(field_value)
+    }
+}
+
+fn read_batch_payload(payload: &[u8]) -> Option<Vec<DataPoint>> {
+    let mut reader = PayloadReader {
+        payload,
+        position: 0,
+    };
+    let datapoint_num = reader.u32()? as usize;
+    let field_num = reader.u16()? as usize;
+    let field_types = (0..field_num)
+        .map(|_| reader.u8().and_then(field_type_from_tag))
+        .collect::<Option<Vec<FieldType>>>()?;
+
+    // every timestamp takes 8 bytes
+    if payload.len() < datapoint_num.checked_mul(8)? {
+        return None;
+    }
+    let timestamps = (0..datapoint_num)
+        .map(|_| reader.u64().map(TimestampNano::new))
+        .collect::<Option<Vec<TimestampNano>>>()?;
+
+    let mut datapoints = Vec::<DataPoint>::with_capacity(datapoint_num);
+    for timestamp in timestamps {
+        let field_values = field_types
+            .iter()
+            .map(|field_type| reader.field_value(field_type))
+            .collect::<Option<Vec<FieldValue>>>()?;
+        datapoints.push(DataPoint::new(timestamp, field_values));
+    }
+
+    if reader.position != payload.len() {
+        return None;
+    }
+    Some(datapoints)
+}
+
+/// the datapoints in the wal and the length of the valid records.
+/// reading stops at the first corrupt or partial record, which is left by a crash in the middle of writing.
+pub(super) fn read_records(datas: &[u8]) -> (Vec<DataPoint>, usize) {
+    let mut result = Vec::<DataPoint>::new();
+    let mut current_index: usize = 0;
+    while current_index < datas.len() {
+        match read_record(&datas[current_index..]) {
+            Some((mut datapoints, record_size)) => {
+                result.append(&mut datapoints);
+                current_index += record_size;
+            }
+            None => break,
+        }
+    }
+
+    (result, current_index)
+}
+
+/// returns the datapoints and the size of the record. None if the record is corrupt or partial.
+fn read_record(datas: &[u8]) -> Option<(Vec<DataPoint>, usize)> {
+    let marker = datas[0];
+    if marker == DATAPOINT_RECORD_MARKER || marker == BATCH_RECORD_MARKER {
+        let header = datas.get(0..RECORD_HEADER_SIZE)?;
+        let data_length = u32::from_be_bytes(header[1..5].try_into().ok()?) as usize;
+        let crc = u32::from_be_bytes(header[5..9].try_into().ok()?);
+        let payload = datas.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + data_length)?;
+        if crc32fast::hash(payload) != crc {
+            return None;
+        }
+
+        let datapoints = if marker == BATCH_RECORD_MARKER {
+            read_batch_payload(payload)?
+        } else {
+            vec![bincode::deserialize::<DataPoint>(payload).ok()?]
+        };
+        Some((datapoints, RECORD_HEADER_SIZE + data_length))
+    } else {
+        // written by the former versions with no checksum
+        let header = datas.get(0..LEGACY_RECORD_HEADER_SIZE)?;
+        let data_length = usize::try_from(u64::from_be_bytes(header.try_into().ok()?)).ok()?;
+        let record_size = LEGACY_RECORD_HEADER_SIZE.checked_add(data_length)?;
+        let payload = datas.get(LEGACY_RECORD_HEADER_SIZE..record_size)?;
+        let datapoint: DataPoint = bincode::deserialize(payload).ok()?;
+        Some((vec![datapoint], record_size))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batch_records() {
+        let datapoints = vec![
+            DataPoint::new(
+                TimestampNano::new(1),
+                vec![FieldValue::Float64(1.5), FieldValue::Bool(true)],
+            ),
+            DataPoint::new(
+                TimestampNano::new(2),
+                vec![FieldValue::Float64(-2.0), FieldValue::Bool(false)],
+            ),
+            // the field types differ from the above
+            DataPoint::new(
+                TimestampNano::new(3),
+                vec![
+                    FieldValue::UInt64(3),
+                    FieldValue::String("a".to_string()),
+                    FieldValue::TimestampNano(TimestampNano::new(4)),
+                    FieldValue::TimestampSec(TimestampSec::new(5)),
+                    FieldValue::Vacant,
+                ],
+            ),
+        ];
+
+        let mut records = Vec::<u8>::new();
+        write_batch_records(&mut records, &datapoints).unwrap();
+        assert_eq!(read_records(&records), (datapoints.clone(), records.len()));
+
+        // the batch records are smaller than the bincode serialized ones
+        let mut datapoint_records = Vec::<u8>::new();
+        for datapoint in datapoints[..2].iter() {
+            write_datapoint_record(&mut datapoint_records, datapoint).unwrap();
+        }
+        let mut batch_records = Vec::<u8>::new();
+        write_batch_records(&mut batch_records, &datapoints[..2]).unwrap();
+        assert!(batch_records.len() < datapoint_records.len());
+
+        // the records of the different formats can be mixed
+        datapoint_records.append(&mut batch_records);
+        let (loaded, _) = read_records(&datapoint_records);
+        assert_eq!(
+            loaded,
+            vec![
+                datapoints[0].clone(),
+                datapoints[1].clone(),
+                datapoints[0].clone(),
+                datapoints[1].clone()
+            ]
+        );
+
+        // a torn batch record is discarded as a whole
+        let (loaded, valid_length) = read_records(&records[..records.len() - 1]);
+        assert_eq!(loaded, datapoints[..2].to_vec());
+        assert!(valid_length < records.len());
+    }
+
+    #[test]
+    fn test_batch_records_too_large() {
+        let datapoint = DataPoint::new(
+            TimestampNano::new(1),
+            vec![FieldValue::Vacant; u16::MAX as usize + 1],
+        );
+        let mut records = Vec::<u8>::new();
+        assert!(matches!(
+            write_batch_records(&mut records, &[datapoint]),
+            Err(WalError::RecordTooLarge(_))
+        ));
+        assert!(records.is_empty());
+    }
+}
@@ -0,0 +1,311 @@
+use super::record::write_batch_records;
+use super::single_file_wal::{load_wal_file, WAL_FILE_NAME};
+use super::sync_policy::{WalSyncPolicy, WalSyncer};
+use super::{Result, WalCheckpoint, WalError, WalWriter};
+use crate::tsdb::datapoint::DataPoint;
+use crate::tsdb::metrics::Metrics;
+use async_trait::async_trait;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_WAL_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+const SEGMENT_FILE_EXTENSION: &str = "seg";
+const CHECKPOINT_FILE_NAME: &str = "checkpoint";
+
+/// the wal split into the segment files. a new segment is started when the active one exceeds the size.
+/// the segments up to the checkpoint have been persisted in the blocks, and are removed.
+pub struct SegmentedWal {
+    wal_dir: PathBuf,
+    max_segment_size: u64,
+    active_segment: File,
+    active_segment_seq: u64,
+    active_segment_size: u64,
+    syncer: WalSyncer,
+}
+
+impl SegmentedWal {
+    pub fn with_max_segment_size(mut self, max_segment_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self
+    }
+
+    pub fn with_sync_policy(mut self, sync_policy: WalSyncPolicy) -> Self {
+        self.syncer = WalSyncer::new(sync_policy);
+        self
+    }
+
+    /// the sealed segment is synced before switching to the new one.
+    fn roll_over(&mut self) -> Result<()> {
+        self.syncer.sync_pending(&self.active_segment)?;
+        let seq = self.active_segment_seq + 1;
+        self.active_segment = open_segment(&segment_file_path(&self.wal_dir, seq), true)?;
+        self.active_segment_seq = seq;
+        self.active_segment_size = 0;
+        Ok(())
+    }
+}
+
+fn segment_file_path(wal_dir: &Path, seq: u64) -> PathBuf {
+    wal_dir.join(format!("{seq:020}.{SEGMENT_FILE_EXTENSION}"))
+}
+
+fn open_segment(path: &Path, create: bool) -> Result<File> {
+    let segment = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(create)
+        .open(path)?;
+    Ok(segment)
+}
+
+/// sequence numbers of the segment files in ascending order.
+fn list_segment_seqs(wal_dir: &Path) -> Result<Vec<u64>> {
+    let mut seqs = Vec::<u64>::new();
+    for entry in fs::read_dir(wal_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_FILE_EXTENSION) {
+            continue;
+        }
+        if let Some(seq) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        {
+            seqs.push(seq);
+        }
+    }
+    seqs.sort_unstable();
+    Ok(seqs)
+}
+
+/// the checkpoint file consists of the sequence number of the last persisted segment(8) and its crc32(4).
+fn read_checkpoint(wal_dir: &Path) -> Result<Option<u64>> {
+    let checkpoint_file_path = wal_dir.join(CHECKPOINT_FILE_NAME);
+    let bytes = match fs::read(&checkpoint_file_path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    if bytes.len() != 12 || crc32fast::hash(&bytes[..8]).to_be_bytes() != bytes[8..12] {
+        log::warn!("ignored the corrupt wal checkpoint file {checkpoint_file_path:?}");
+        return Ok(None);
+    }
+    let mut seq: [u8; 8] = Default::default();
+    seq.copy_from_slice(&bytes[..8]);
+    Ok(Some(u64::from_be_bytes(seq)))
+}
+
+fn write_checkpoint(wal_dir: &Path, seq: u64) -> Result<()> {
+    let mut bytes = seq.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&crc32fast::hash(&bytes).to_be_bytes());
+
+    let checkpoint_file_path = wal_dir.join(CHECKPOINT_FILE_NAME);
+    let tmp_checkpoint_file_path = checkpoint_file_path.with_extension("tmp");
+    let mut tmp_checkpoint_file = File::create(&tmp_checkpoint_file_path)?;
+    tmp_checkpoint_file.write_all(&bytes)?;
+    tmp_checkpoint_file.sync_all()?;
+    fs::rename(&tmp_checkpoint_file_path, &checkpoint_file_path)?;
+    Ok(())
+}
+
+/// removes the segments up to the checkpoint and the wal file of the former versions.
+fn remove_segments_until(wal_dir: &Path, seq: u64) -> Result<()> {
+    let mut removings: Vec<PathBuf> = list_segment_seqs(wal_dir)?
+        .into_iter()
+        .filter(|each| *each <= seq)
+        .map(|each| segment_file_path(wal_dir, each))
+        .collect();
+    removings.push(wal_dir.join(WAL_FILE_NAME));
+
+    for each in removings {
+        match fs::remove_file(&each) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WalWriter for SegmentedWal {
+    /// the datapoints are written as batch records and synced at once.
+    /// the segment is rolled over before the write if it has exceeded the size, so a segment can exceed it by a write.
+    async fn write(&mut self, datapoints: &[DataPoint]) -> Result<()> {
+        if datapoints.is_empty() {
+            return Ok(());
+        }
+
+        if self.active_segment_size >= self.max_segment_size {
+            self.roll_over()?;
+        }
+
+        let mut records = Vec::<u8>::new();
+        write_batch_records(&mut records, datapoints)?;
+        self.active_segment.write_all(&records)?;
+        self.active_segment_size += records.len() as u64;
+        self.syncer.after_write(&self.active_segment)
+    }
+
+    async fn load(&self) -> Result<Vec<DataPoint>> {
+        let mut datapoints = Vec::<DataPoint>::new();
+
+        // the wal file of the former versions precedes all the segments
+        let legacy_wal_file_path = self.wal_dir.join(WAL_FILE_NAME);
+        if legacy_wal_file_path.exists() {
+            let mut loaded = load_wal_file(&open_segment(&legacy_wal_file_path, false)?)?;
+            datapoints.append(&mut loaded);
+        }
+
+        for seq in list_segment_seqs(&self.wal_dir)? {
+            let mut loaded = load_wal_file(&open_segment(
+                &segment_file_path(&self.wal_dir, seq),
+                false,
+            )?)?;
+            datapoints.append(&mut loaded);
+        }
+        Ok(datapoints)
+    }
+
+    fn clean(&mut self) -> Result<()> {
+        let checkpoint = self.seal()?;
+        self.checkpoint(checkpoint)
+    }
+
+    /// the checkpoint is the sequence number of the last sealed segment.
+    fn seal(&mut self) -> Result<WalCheckpoint> {
+        if self.active_segment_size == 0 {
+            return Ok(WalCheckpoint(self.active_segment_seq - 1));
+        }
+        let sealed_seq = self.active_segment_seq;
+        self.roll_over()?;
+        Ok(WalCheckpoint(sealed_seq))
+    }
+
+    fn checkpoint(&mut self, checkpoint: WalCheckpoint) -> Result<()> {
+        // the active segment is never removed
+        let seq = checkpoint.0.min(self.active_segment_seq - 1);
+        write_checkpoint(&self.wal_dir, seq)?;
+        remove_segments_until(&self.wal_dir, seq)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.syncer.sync(&self.active_segment)
+    }
+
+    fn sync_due(&mut self) -> Result<()> {
+        self.syncer.sync_due(&self.active_segment)
+    }
+
+    fn open_or_create(data_dir_path: &Path, metrics: &Metrics) -> Result<Self> {
+        let mut wal_dir = PathBuf::new();
+        wal_dir.push(data_dir_path);
+        wal_dir.push("wal");
+        wal_dir.push(metrics.as_str());
+        if !wal_dir.exists() {
+            fs::create_dir_all(&wal_dir).map_err(|e| {
+                WalError::WalFileOpenError(format!(
+                    "could not create wal directory {wal_dir:?}: {e}"
+                ))
+            })?;
+        }
+
+        // finish removing the segments if the process had stopped in the middle of it
+        let checkpoint = read_checkpoint(&wal_dir)?;
+        if let Some(checkpoint) = checkpoint {
+            remove_segments_until(&wal_dir, checkpoint)?;
+        }
+
+        // the records are always written to a new segment, not after the torn tail of the last one.
+        let last_seq = list_segment_seqs(&wal_dir)?
+            .last()
+            .copied()
+            .unwrap_or(0)
+            .max(checkpoint.unwrap_or(0));
+        let active_segment_seq = last_seq + 1;
+        let active_segment = open_segment(&segment_file_path(&wal_dir, active_segment_seq), true)?;
+
+        Ok(Self {
+            wal_dir,
+            max_segment_size: DEFAULT_WAL_SEGMENT_SIZE,
+            active_segment,
+            active_segment_seq,
+            active_segment_size: 0,
+            syncer: WalSyncer::new(WalSyncPolicy::default()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::{FieldValue, TimestampNano};
+    use tempdir::TempDir;
+
+    fn datapoints(secs: std::ops::Range<u64>) -> Vec<DataPoint> {
+        secs.map(|sec| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(sec as f64)],
+            )
+        })
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn test_segmented_wal_roll_over_and_checkpoint() {
+        let temp_data_dir = TempDir::new("segmented_wal_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("s").unwrap();
+        let wal_dir = db_dir.join("wal").join(metrics.as_str());
+
+        // every write exceeds the segment size
+        let mut wal = SegmentedWal::open_or_create(db_dir, &metrics)
+            .unwrap()
+            .with_max_segment_size(1);
+        for sec in 0..3 {
+            wal.write(&datapoints(sec..sec + 1)).await.unwrap();
+        }
+        assert_eq!(list_segment_seqs(&wal_dir).unwrap(), vec![1, 2, 3]);
+        assert_eq!(wal.load().await.unwrap(), datapoints(0..3));
+
+        let checkpoint = wal.seal().unwrap();
+        assert_eq!(checkpoint, WalCheckpoint(3));
+
+        // written during the persistence
+        wal.write(&datapoints(3..4)).await.unwrap();
+        wal.checkpoint(checkpoint).unwrap();
+        assert_eq!(list_segment_seqs(&wal_dir).unwrap(), vec![4]);
+        assert_eq!(wal.load().await.unwrap(), datapoints(3..4));
+
+        // reopening starts a new segment after the checkpoint
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        assert_eq!(wal.active_segment_seq, 5);
+        assert_eq!(wal.load().await.unwrap(), datapoints(3..4));
+    }
+
+    #[tokio::test]
+    async fn test_segmented_wal_with_legacy_wal_file() {
+        let temp_data_dir = TempDir::new("segmented_wal_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("s").unwrap();
+
+        let mut legacy_wal = super::super::SingleFileWal::open_or_create(db_dir, &metrics).unwrap();
+        legacy_wal.write(&datapoints(0..2)).await.unwrap();
+
+        let mut wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        wal.write(&datapoints(2..3)).await.unwrap();
+        assert_eq!(wal.load().await.unwrap(), datapoints(0..3));
+
+        wal.clean().unwrap();
+        assert!(wal.load().await.unwrap().is_empty());
+        assert!(!db_dir
+            .join("wal")
+            .join(metrics.as_str())
+            .join(WAL_FILE_NAME)
+            .exists());
+    }
+}
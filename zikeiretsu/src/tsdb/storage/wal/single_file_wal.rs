@@ -1,74 +1,82 @@
-use super::{Result, WalError, WalWriter};
+use super::record::{read_records, write_batch_records};
+use super::sync_policy::{WalSyncPolicy, WalSyncer};
+use super::{Result, WalCheckpoint, WalError, WalWriter};
 use crate::tsdb::datapoint::DataPoint;
 use crate::tsdb::metrics::Metrics;
 use async_trait::async_trait;
 use memmap2::MmapOptions;
-use std::fs::create_dir_all;
+use std::fs::{self, create_dir_all};
 use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-const WAL_FILE_NAME: &str = "wal.dat";
+pub(super) const WAL_FILE_NAME: &str = "wal.dat";
 
 pub struct SingleFileWal {
+    wal_file_path: PathBuf,
     wal_file: File,
+    syncer: WalSyncer,
 }
 
-fn write_datapint_to_wal<W>(mut w: W, datapoint: &DataPoint) -> Result<()>
-where
-    W: Write,
-{
-    let serialized = bincode::serialize(&datapoint)?;
-    let data_size = serialized.len() as u64;
-
-    w.write_all(&data_size.to_be_bytes())?;
-    //TODO(tacodigs) if consequence data writing fails, should we delete the written data length
-    //also?
-    w.write_all(&serialized)?;
-    Ok(())
+impl SingleFileWal {
+    pub fn with_sync_policy(mut self, sync_policy: WalSyncPolicy) -> Self {
+        self.syncer = WalSyncer::new(sync_policy);
+        self
+    }
 }
 
-fn read_datapint_from_wal(datas: &[u8]) -> Result<Vec<DataPoint>> {
-    let mut result = Vec::<DataPoint>::new();
-    let mut current_index: usize = 0;
-    while current_index < datas.len() {
-        //datalength in 64bit
-        let mut data_length: [u8; 8] = Default::default();
-        data_length.copy_from_slice(&datas[current_index..current_index + 8usize]);
-        let data_length: usize = u64::from_be_bytes(data_length) as usize;
-        current_index += 8;
-
-        let raw_datapoint = &datas[current_index..current_index + data_length]; //datalength in 64bit
-        current_index += data_length;
+fn open_wal_file(wal_file_path: &Path) -> Result<File> {
+    // the records must be appended at the tail even after the file is truncated
+    let wal_file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(wal_file_path)?;
+    Ok(wal_file)
+}
 
-        let deserialized: DataPoint = bincode::deserialize(&raw_datapoint)?;
-        result.push(deserialized);
+/// loads the datapoints in the wal file. the corrupt or partial records at the tail are truncated.
+pub(super) fn load_wal_file(wal_file: &File) -> Result<Vec<DataPoint>> {
+    if wal_file.metadata()?.len() == 0 {
+        return Ok(vec![]);
     }
 
-    Ok(result)
+    let wal_data = unsafe {
+        MmapOptions::new()
+            .map(wal_file)
+            .map_err(|e| WalError::WalFileOpenError(format!("{}", e)))?
+    };
+    let (datapoints, valid_length) = read_records(&wal_data);
+    let wal_length = wal_data.len();
+    drop(wal_data);
+
+    // truncate the torn tail so that the following records will be appended to the valid ones
+    if valid_length < wal_length {
+        log::warn!(
+            "discarded {} bytes of the corrupt or partial records at the tail of the wal",
+            wal_length - valid_length
+        );
+        wal_file.set_len(valid_length as u64)?;
+    }
+    Ok(datapoints)
 }
 
 #[async_trait]
 impl WalWriter for SingleFileWal {
+    /// the records of the datapoints are written and synced at once.
     async fn write(&mut self, datapoints: &[DataPoint]) -> Result<()> {
-        for each_datapoint in datapoints {
-            write_datapint_to_wal(&mut self.wal_file, each_datapoint)?
+        if datapoints.is_empty() {
+            return Ok(());
         }
-        Ok(())
+
+        let mut records = Vec::<u8>::new();
+        write_batch_records(&mut records, datapoints)?;
+        self.wal_file.write_all(&records)?;
+        self.syncer.after_write(&self.wal_file)
     }
 
     async fn load(&self) -> Result<Vec<DataPoint>> {
-        if self.wal_file.metadata()?.len() == 0 {
-            return Ok(vec![]);
-        } else {
-            let wal_data = unsafe {
-                MmapOptions::new()
-                    .map(&self.wal_file)
-                    .map_err(|e| WalError::WalFileOpenError(format!("{}", e)))?
-            };
-            let datapoints = read_datapint_from_wal(&wal_data)?;
-            Ok(datapoints)
-        }
+        load_wal_file(&self.wal_file)
     }
 
     fn clean(&mut self) -> Result<()> {
@@ -76,6 +84,39 @@ impl WalWriter for SingleFileWal {
         Ok(())
     }
 
+    /// the checkpoint is the length of the wal file.
+    fn seal(&mut self) -> Result<WalCheckpoint> {
+        Ok(WalCheckpoint(self.wal_file.metadata()?.len()))
+    }
+
+    /// replaces the wal file with the one of the records after the checkpoint.
+    fn checkpoint(&mut self, checkpoint: WalCheckpoint) -> Result<()> {
+        let wal_length = self.wal_file.metadata()?.len();
+        if wal_length <= checkpoint.0 {
+            return self.clean();
+        }
+
+        let mut remainings = Vec::<u8>::new();
+        self.wal_file.seek(SeekFrom::Start(checkpoint.0))?;
+        self.wal_file.read_to_end(&mut remainings)?;
+
+        let tmp_wal_file_path = self.wal_file_path.with_extension("tmp");
+        let mut tmp_wal_file = File::create(&tmp_wal_file_path)?;
+        tmp_wal_file.write_all(&remainings)?;
+        tmp_wal_file.sync_all()?;
+        fs::rename(&tmp_wal_file_path, &self.wal_file_path)?;
+        self.wal_file = open_wal_file(&self.wal_file_path)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.syncer.sync(&self.wal_file)
+    }
+
+    fn sync_due(&mut self) -> Result<()> {
+        self.syncer.sync_due(&self.wal_file)
+    }
+
     fn open_or_create(data_dir_path: &Path, metrics: &Metrics) -> Result<Self> {
         let mut wal_file_path = PathBuf::new();
 
@@ -93,20 +134,12 @@ impl WalWriter for SingleFileWal {
             create_dir_all(wal_dir)?;
         }
 
-        let wal_file = if wal_file_path.exists() {
-            OpenOptions::new()
-                .read(true)
-                .append(true)
-                .create(true)
-                .open(wal_file_path)?
-        } else {
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(wal_file_path)?
-        };
-        Ok(Self { wal_file })
+        let wal_file = open_wal_file(&wal_file_path)?;
+        Ok(Self {
+            wal_file_path,
+            wal_file,
+            syncer: WalSyncer::new(WalSyncPolicy::default()),
+        })
     }
 }
 
@@ -201,4 +234,134 @@ mod test {
 
         assert!(v.is_empty());
     }
+
+    fn wal_file_path(db_dir: &Path, metrics: &Metrics) -> PathBuf {
+        let mut wal_file_path = PathBuf::new();
+        wal_file_path.push(db_dir);
+        wal_file_path.push("wal");
+        wal_file_path.push(metrics.as_str());
+        wal_file_path.push(WAL_FILE_NAME);
+        wal_file_path
+    }
+
+    #[tokio::test]
+    async fn test_load_torn_tail() {
+        let temp_data_dir = TempDir::new("wal_test").expect("Could not create temp dir");
+        let db_dir = temp_data_dir.path();
+        let test_metrics = Metrics::new("s").unwrap();
+        let mut wal = SingleFileWal::open_or_create(db_dir, &test_metrics).unwrap();
+
+        let datapoints = float_data_points!(
+            {1629745451_715062000, vec![200f64, 12f64]},
+            {1629745451_715063000, vec![300f64, 36f64]}
+        );
+        wal.write(&datapoints).await.unwrap();
+        let wal_file_path = wal_file_path(db_dir, &test_metrics);
+        let valid_length = wal_file_path.metadata().unwrap().len();
+
+        // crashed in the middle of writing the third record
+        {
+            let mut record = Vec::<u8>::new();
+            write_batch_records(
+                &mut record,
+                &float_data_points!({1629745451_715064000, vec![400f64, 36f64]}),
+            )
+            .unwrap();
+            let mut wal_file = OpenOptions::new()
+                .append(true)
+                .open(wal_file_path.as_path())
+                .unwrap();
+            wal_file.write_all(&record[..record.len() - 3]).unwrap();
+        }
+
+        let wal = SingleFileWal::open_or_create(db_dir, &test_metrics).unwrap();
+        assert_eq!(wal.load().await.unwrap(), datapoints);
+        assert_eq!(wal_file_path.metadata().unwrap().len(), valid_length);
+    }
+
+    #[tokio::test]
+    async fn test_load_corrupt_record() {
+        let temp_data_dir = TempDir::new("wal_test").expect("Could not create temp dir");
+        let db_dir = temp_data_dir.path();
+        let test_metrics = Metrics::new("s").unwrap();
+        let mut wal = SingleFileWal::open_or_create(db_dir, &test_metrics).unwrap();
+
+        let datapoints = float_data_points!(
+            {1629745451_715062000, vec![200f64, 12f64]},
+            {1629745451_715063000, vec![300f64, 36f64]}
+        );
+        // the datapoints written at once are in a record
+        wal.write(&datapoints[..1]).await.unwrap();
+        wal.write(&datapoints[1..]).await.unwrap();
+
+        // flip the last byte of the second record
+        let wal_file_path = wal_file_path(db_dir, &test_metrics);
+        let mut bytes = std::fs::read(wal_file_path.as_path()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(wal_file_path.as_path(), &bytes).unwrap();
+
+        let mut wal = SingleFileWal::open_or_create(db_dir, &test_metrics).unwrap();
+        assert_eq!(wal.load().await.unwrap(), datapoints[..1].to_vec());
+
+        // the records are appended after the valid ones
+        let appended = float_data_points!({1629745451_715064000, vec![400f64, 36f64]});
+        wal.write(&appended).await.unwrap();
+        assert_eq!(
+            wal.load().await.unwrap(),
+            vec![datapoints[0].clone(), appended[0].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_legacy_records() {
+        let temp_data_dir = TempDir::new("wal_test").expect("Could not create temp dir");
+        let db_dir = temp_data_dir.path();
+        let test_metrics = Metrics::new("s").unwrap();
+        let mut wal = SingleFileWal::open_or_create(db_dir, &test_metrics).unwrap();
+
+        // the records of the former versions are the length in 64bit followed by the datapoint
+        let legacy = float_data_points!({1629745451_715062000, vec![200f64, 12f64]});
+        let serialized = bincode::serialize(&legacy[0]).unwrap();
+        let mut record = (serialized.len() as u64).to_be_bytes().to_vec();
+        record.extend_from_slice(&serialized);
+        std::fs::write(wal_file_path(db_dir, &test_metrics), &record).unwrap();
+
+        let datapoints = float_data_points!({1629745451_715063000, vec![300f64, 36f64]});
+        wal.write(&datapoints).await.unwrap();
+
+        assert_eq!(
+            wal.load().await.unwrap(),
+            vec![legacy[0].clone(), datapoints[0].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_mixed_records() {
+        let temp_data_dir = TempDir::new("wal_test").expect("Could not create temp dir");
+        let db_dir = temp_data_dir.path();
+        let test_metrics = Metrics::new("s").unwrap();
+        let mut wal = SingleFileWal::open_or_create(db_dir, &test_metrics).unwrap();
+
+        // a legacy record followed by a record of a datapoint
+        let former = float_data_points!(
+            {1629745451_715062000, vec![200f64, 12f64]},
+            {1629745451_715063000, vec![300f64, 36f64]}
+        );
+        let serialized = bincode::serialize(&former[0]).unwrap();
+        let mut records = (serialized.len() as u64).to_be_bytes().to_vec();
+        records.extend_from_slice(&serialized);
+        super::super::record::write_datapoint_record(&mut records, &former[1]).unwrap();
+        std::fs::write(wal_file_path(db_dir, &test_metrics), &records).unwrap();
+
+        let datapoints = float_data_points!(
+            {1629745451_715064000, vec![400f64, 36f64]},
+            {1629745451_715065000, vec![500f64, 36f64]}
+        );
+        wal.write(&datapoints).await.unwrap();
+
+        let mut expected = former;
+        expected.extend(datapoints);
+        assert_eq!(wal.load().await.unwrap(), expected);
+    }
 }
@@ -0,0 +1,130 @@
+use super::Result;
+use std::fs::File;
+use std::time::{Duration, Instant};
+
+/// when the written records are synced to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// syncs on every write. a write of multiple datapoints is synced once.
+    Always,
+    /// syncs on every n writes.
+    /// the writes fewer than n are left unsynced until the next write unless `start_wal_sync` is running.
+    EveryN(usize),
+    /// syncs on the first write after the interval has passed since the last sync.
+    /// the last writes are left unsynced until the next write unless `start_wal_sync` is running.
+    Interval(Duration),
+    /// never syncs. it's left to the OS.
+    Never,
+}
+
+impl Default for WalSyncPolicy {
+    fn default() -> Self {
+        Self::Always
+    }
+}
+
+/// tracks the writes not synced yet along with the policy.
+#[derive(Debug)]
+pub(super) struct WalSyncer {
+    policy: WalSyncPolicy,
+    unsynced_writes: usize,
+    last_synced_at: Instant,
+}
+
+impl WalSyncer {
+    pub(super) fn new(policy: WalSyncPolicy) -> Self {
+        Self {
+            policy,
+            unsynced_writes: 0,
+            last_synced_at: Instant::now(),
+        }
+    }
+
+    pub(super) fn after_write(&mut self, wal_file: &File) -> Result<()> {
+        self.unsynced_writes += 1;
+        let sync_now = match self.policy {
+            WalSyncPolicy::Always => true,
+            WalSyncPolicy::EveryN(n) => self.unsynced_writes >= n,
+            WalSyncPolicy::Interval(interval) => self.last_synced_at.elapsed() >= interval,
+            WalSyncPolicy::Never => false,
+        };
+        if sync_now {
+            self.sync(wal_file)?;
+        }
+        Ok(())
+    }
+
+    /// syncs the writes not synced yet unless the policy is `Never`.
+    pub(super) fn sync_pending(&mut self, wal_file: &File) -> Result<()> {
+        if self.unsynced_writes > 0 && self.policy != WalSyncPolicy::Never {
+            self.sync(wal_file)?;
+        }
+        Ok(())
+    }
+
+    /// syncs the writes left unsynced by `after_write` so that they are not kept waiting for the next write.
+    /// with `Interval`, they are synced only after the interval has passed since the last sync.
+    pub(super) fn sync_due(&mut self, wal_file: &File) -> Result<()> {
+        let sync_now = self.unsynced_writes > 0
+            && match self.policy {
+                WalSyncPolicy::Always | WalSyncPolicy::EveryN(_) => true,
+                WalSyncPolicy::Interval(interval) => self.last_synced_at.elapsed() >= interval,
+                WalSyncPolicy::Never => false,
+            };
+        if sync_now {
+            self.sync(wal_file)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn sync(&mut self, wal_file: &File) -> Result<()> {
+        wal_file.sync_data()?;
+        self.unsynced_writes = 0;
+        self.last_synced_at = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wal_syncer() {
+        let wal_file = tempfile::tempfile().unwrap();
+
+        let mut syncer = WalSyncer::new(WalSyncPolicy::EveryN(3));
+        syncer.after_write(&wal_file).unwrap();
+        syncer.after_write(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 2);
+        syncer.after_write(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 0);
+        syncer.after_write(&wal_file).unwrap();
+        syncer.sync_due(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 0);
+
+        let mut syncer = WalSyncer::new(WalSyncPolicy::Never);
+        syncer.after_write(&wal_file).unwrap();
+        syncer.sync_pending(&wal_file).unwrap();
+        syncer.sync_due(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 1);
+
+        let mut syncer = WalSyncer::new(WalSyncPolicy::Interval(Duration::from_secs(3600)));
+        syncer.after_write(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 1);
+        syncer.sync_due(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 1);
+        syncer.sync_pending(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 0);
+
+        let mut syncer = WalSyncer::new(WalSyncPolicy::Always);
+        syncer.after_write(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 0);
+
+        let mut syncer = WalSyncer::new(WalSyncPolicy::Interval(Duration::from_millis(1)));
+        syncer.after_write(&wal_file).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        syncer.sync_due(&wal_file).unwrap();
+        assert_eq!(syncer.unsynced_writes, 0);
+    }
+}
@@ -0,0 +1,345 @@
+//! Parser of the InfluxDB line protocol.
+//!
+//! `measurement[,tag_key=tag_value...] field_key=field_value[,field_key=field_value...] [timestamp]`
+//!
+//! The measurement will be the metrics and the fields will be the field values ordered by the field keys.
+//! The tags are ignored since the datapoints have no tags, and the timestamp is regarded as in nano seconds.
+//!
+//! The integer fields (`1i`, `1u`) are parsed as float64 since the block files can hold only float64 and bool,
+//! so the integers larger than 2^53 lose the precision. The string fields are rejected as the invalid line.
+
+use super::{DatapointSorter, StoreError, WritableStore};
+use crate::tsdb::datapoint::DataPoint;
+use crate::tsdb::field::{join_field_types, FieldType, FieldValue};
+use crate::tsdb::storage::wal::WalWriter;
+use crate::tsdb::Metrics;
+use crate::tsdb::TimestampNano;
+use thiserror::Error;
+
+type Result<T> = std::result::Result<T, LineProtocolError>;
+
+#[derive(Error, Debug)]
+pub enum LineProtocolError {
+    #[error("invalid line at {0}. {1}")]
+    InvalidLine(usize, String),
+
+    #[error("unmatched metrics. expected:{0}, actual:{1}")]
+    UnmatchedMetrics(String, String),
+
+    #[error("unmatched fields. expected:{0}, actual:{1}")]
+    UnmatchedFields(String, String),
+
+    #[error("unmatched field keys. expected:{0}, actual:{1}")]
+    UnmatchedFieldKeys(String, String),
+
+    #[error("store error. {0}")]
+    StoreError(#[from] StoreError),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineProtocolPoint {
+    pub metrics: Metrics,
+    /// sorted by the keys
+    pub field_keys: Vec<String>,
+    pub datapoint: DataPoint,
+}
+
+impl LineProtocolPoint {
+    pub fn field_types(&self) -> Vec<FieldType> {
+        self.datapoint
+            .field_values
+            .iter()
+            .map(|field_value| field_value.as_type())
+            .collect()
+    }
+}
+
+/// parses the newline separated lines. the empty lines and the comment lines starting with '#' are skipped.
+/// `default_timestamp` is used for the lines that have no timestamp.
+pub fn parse_lines(
+    lines: &str,
+    default_timestamp: TimestampNano,
+) -> Result<Vec<LineProtocolPoint>> {
+    let mut points = Vec::<LineProtocolPoint>::new();
+    for (line_idx, line) in lines.lines().enumerate() {
+        let point = parse_line(line, default_timestamp)
+            .map_err(|e| LineProtocolError::InvalidLine(line_idx + 1, e))?;
+        if let Some(point) = point {
+            points.push(point);
+        }
+    }
+    Ok(points)
+}
+
+/// returns None if the line is empty or a comment.
+pub fn parse_line(
+    line: &str,
+    default_timestamp: TimestampNano,
+) -> std::result::Result<Option<LineProtocolPoint>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let sections = split_unescaped(line, ' ', true);
+    let (series_key, fields, timestamp) = match sections.as_slice() {
+        [series_key, fields] => (*series_key, *fields, None),
+        [series_key, fields, timestamp] => (*series_key, *fields, Some(*timestamp)),
+        _ => return Err(format!("invalid number of sections: {line}")),
+    };
+
+    // tags are ignored
+    let measurement = split_unescaped(series_key, ',', false)[0];
+    let measurement = unescape(measurement);
+    if measurement.is_empty() {
+        return Err("empty measurement".to_string());
+    }
+    let metrics = Metrics::new(measurement)?;
+
+    let mut key_and_values = Vec::<(String, FieldValue)>::new();
+    for field in split_unescaped(fields, ',', true) {
+        let (key, value) = match split_unescaped(field, '=', true).as_slice() {
+            [key, value] if !key.is_empty() => (unescape(key), parse_field_value(value)?),
+            _ => return Err(format!("invalid field: {field}")),
+        };
+        if key_and_values.iter().any(|(each_key, _)| *each_key == key) {
+            return Err(format!("duplicated field: {key}"));
+        }
+        key_and_values.push((key, value));
+    }
+    key_and_values.sort_by(|(l, _), (r, _)| l.cmp(r));
+
+    let timestamp_nano = match timestamp {
+        None => default_timestamp,
+        Some(timestamp) => match timestamp.parse::<i64>() {
+            Ok(timestamp) if timestamp >= 0 => TimestampNano::new(timestamp as u64),
+            _ => return Err(format!("invalid timestamp: {timestamp}")),
+        },
+    };
+
+    let (field_keys, field_values): (Vec<String>, Vec<FieldValue>) =
+        key_and_values.into_iter().unzip();
+    Ok(Some(LineProtocolPoint {
+        metrics,
+        field_keys,
+        datapoint: DataPoint::new(timestamp_nano, field_values),
+    }))
+}
+
+fn parse_field_value(value: &str) -> std::result::Result<FieldValue, String> {
+    if value.starts_with('"') {
+        return Err(format!("string field is not supported: {value}"));
+    }
+
+    match value {
+        "t" | "T" | "true" | "True" | "TRUE" => return Ok(FieldValue::Bool(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => return Ok(FieldValue::Bool(false)),
+        _ => {}
+    }
+
+    if let Some(integer) = value.strip_suffix('i') {
+        return integer
+            .parse::<i64>()
+            .map(|integer| FieldValue::Float64(integer as f64))
+            .map_err(|_| format!("invalid integer: {value}"));
+    }
+
+    if let Some(unsigned) = value.strip_suffix('u') {
+        return unsigned
+            .parse::<u64>()
+            .map(|unsigned| FieldValue::Float64(unsigned as f64))
+            .map_err(|_| format!("invalid unsigned integer: {value}"));
+    }
+
+    value
+        .parse::<f64>()
+        .map(FieldValue::Float64)
+        .map_err(|_| format!("invalid field value: {value}"))
+}
+
+/// splits by the delimiter that is not escaped by backslash (nor in the double quotes if `respect_quotes`)
+fn split_unescaped(s: &str, delimiter: char, respect_quotes: bool) -> Vec<&str> {
+    let mut result = Vec::<&str>::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut in_quotes = false;
+    for (idx, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' if respect_quotes => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => {
+                result.push(&s[start..idx]);
+                start = idx + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    result.push(&s[start..]);
+    result
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(',' | ' ' | '=' | '\\')) => result.push(chars.next().unwrap()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// parses the lines and pushes the datapoints into the store.
+/// the all lines must be of the metrics and the field types of the store,
+/// and their field keys must be `field_keys` that are the keys of the fields of the store in sorted order.
+pub async fn push_line_protocol<S, Wal>(
+    store: &mut WritableStore<S, Wal>,
+    field_keys: &[String],
+    lines: &str,
+) -> Result<usize>
+where
+    S: DatapointSorter + 'static,
+    Wal: WalWriter + 'static,
+{
+    let points = parse_lines(lines, TimestampNano::now())?;
+    let mut datapoints = Vec::<DataPoint>::with_capacity(points.len());
+    for point in points {
+        if &point.metrics != store.metrics() {
+            return Err(LineProtocolError::UnmatchedMetrics(
+                store.metrics().to_string(),
+                point.metrics.to_string(),
+            ));
+        }
+        if point.field_keys != field_keys {
+            return Err(LineProtocolError::UnmatchedFieldKeys(
+                field_keys.join(","),
+                point.field_keys.join(","),
+            ));
+        }
+        let field_types = point.field_types();
+        if field_types != store.field_types() {
+            return Err(LineProtocolError::UnmatchedFields(
+                join_field_types(store.field_types()),
+                join_field_types(&field_types),
+            ));
+        }
+        datapoints.push(point.datapoint);
+    }
+
+    let pushed = datapoints.len();
+    store.push_multi(datapoints).await?;
+    Ok(pushed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::storage::wal::EmptyWal;
+
+    #[test]
+    fn parse_line_test() {
+        let default_timestamp = TimestampNano::new(1);
+        let point = parse_line(
+            r#"cpu\ load,host=server\ 01,region=us price=1.5,is_up=t,count=-3i,total=4u 1640962800000000000"#,
+            default_timestamp,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(point.metrics, Metrics::new("cpu load").unwrap());
+        assert_eq!(point.field_keys, vec!["count", "is_up", "price", "total"]);
+        assert_eq!(
+            point.datapoint,
+            DataPoint::new(
+                TimestampNano::new(1640962800000000000),
+                vec![
+                    FieldValue::Float64(-3f64),
+                    FieldValue::Bool(true),
+                    FieldValue::Float64(1.5),
+                    FieldValue::Float64(4f64),
+                ]
+            )
+        );
+
+        let point = parse_line("cpu value=-2", default_timestamp)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            point.datapoint,
+            DataPoint::new(default_timestamp, vec![FieldValue::Float64(-2f64)])
+        );
+
+        assert_eq!(parse_line("  ", default_timestamp).unwrap(), None);
+        assert_eq!(parse_line("# comment", default_timestamp).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_line_err_test() {
+        let default_timestamp = TimestampNano::new(1);
+        assert!(parse_line("cpu", default_timestamp).is_err());
+        assert!(parse_line("cpu value=", default_timestamp).is_err());
+        assert!(parse_line("cpu value=-1u", default_timestamp).is_err());
+        assert!(parse_line(r#"cpu value="a""#, default_timestamp).is_err());
+        assert!(parse_line("cpu value=1,value=2", default_timestamp).is_err());
+        assert!(parse_line(r#"cpu value="a"#, default_timestamp).is_err());
+        assert!(parse_line("cpu value=1 -1", default_timestamp).is_err());
+
+        let result = parse_lines("cpu value=1\ncpu value=a", default_timestamp);
+        assert!(matches!(result, Err(LineProtocolError::InvalidLine(2, _))));
+    }
+
+    #[tokio::test]
+    async fn push_line_protocol_test() {
+        let store = WritableStore::builder(
+            Metrics::new("cpu").unwrap(),
+            vec![FieldType::Bool, FieldType::Float64],
+            EmptyWal,
+        )
+        .build()
+        .await
+        .unwrap();
+        let mut store = store.lock().await;
+        let field_keys = vec!["up".to_string(), "value".to_string()];
+
+        let pushed = push_line_protocol(
+            &mut store,
+            &field_keys,
+            "cpu,host=a value=1.5,up=true 2\ncpu value=2.5,up=false 1\n",
+        )
+        .await
+        .unwrap();
+        assert_eq!(pushed, 2);
+        assert_eq!(
+            store.datapoints().await.unwrap(),
+            &vec![
+                DataPoint::new(
+                    TimestampNano::new(1),
+                    vec![FieldValue::Bool(false), FieldValue::Float64(2.5)]
+                ),
+                DataPoint::new(
+                    TimestampNano::new(2),
+                    vec![FieldValue::Bool(true), FieldValue::Float64(1.5)]
+                ),
+            ]
+        );
+
+        assert!(
+            push_line_protocol(&mut store, &field_keys, "mem value=1,up=t 3")
+                .await
+                .is_err()
+        );
+        assert!(push_line_protocol(&mut store, &field_keys, "cpu value=1 3")
+            .await
+            .is_err());
+        // the same field types but the other field keys
+        assert!(matches!(
+            push_line_protocol(&mut store, &field_keys, "cpu load=1,up=t 3").await,
+            Err(LineProtocolError::UnmatchedFieldKeys(_, _))
+        ));
+    }
+}
@@ -1,10 +1,15 @@
+pub mod line_protocol;
+pub mod store_manager;
 pub mod writable_store;
 
+use crate::tsdb::duplicate_policy::DuplicatedTimestampError;
 use crate::tsdb::storage::api as storage_api;
 use crate::tsdb::storage::wal::WalError;
 use crate::tsdb::util;
 use chrono::{DateTime, Utc};
+pub use line_protocol::*;
 use std::cmp::Ordering;
+pub use store_manager::*;
 use thiserror::Error;
 use tokio::sync::mpsc;
 use tokio::task;
@@ -47,6 +52,21 @@ pub enum StoreError {
 
     #[error("Wal Error. {0}")]
     WalError(#[from] WalError),
+
+    #[error("{0}")]
+    DuplicatedTimestamp(#[from] DuplicatedTimestampError),
+
+    #[error("no field types of the metrics in the schema. {0}")]
+    NoMetricsSchema(String),
+
+    #[error("the datapoints on memory exceed the high-water mark. {0}")]
+    HighWaterMarkExceeded(String),
+
+    #[error("the datapoints on memory of the stores exceed the max buffered bytes. {0}")]
+    MaxBufferedBytesExceeded(String),
+
+    #[error("failed to write the datapoints to the wal with the other writers. {0}")]
+    WalGroupCommitError(String),
 }
 
 type Result<T> = std::result::Result<T, StoreError>;
@@ -283,6 +303,8 @@ mod test {
                     Some(TimestampNano::new(1629745451_715066001)),
                 ),
                 limit: None,
+                sample: None,
+                rank: None,
             };
 
             let cache_setting = api::CacheSetting::none();
@@ -412,6 +434,8 @@ mod test {
             let condition = DatapointsSearchCondition {
                 datapoints_range: DatapointsRange::new(None, None),
                 limit: None,
+                sample: None,
+                rank: None,
             };
 
             let cache_setting = api::CacheSetting::none();
@@ -0,0 +1,541 @@
+use super::writable_store::{
+    estimated_bytes, DatapointDefaultSorter, PersistCondition, Persistence, ShutdownReport,
+    WritableStore,
+};
+use super::{Result, StoreError};
+use crate::tsdb::datapoint::{DataPoint, DatapointsRange};
+use crate::tsdb::duplicate_policy::DuplicatePolicy;
+use crate::tsdb::field::FieldType;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::storage::api as storage_api;
+use crate::tsdb::storage::wal::{SegmentedWal, WalWriter};
+use crate::tsdb::timestamp_nano::TimestampNano;
+use crate::tsdb::CloudStorage;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex, OnceCell};
+use tokio::{task, time};
+use uuid::Uuid;
+
+pub type ManagedStore = Arc<Mutex<WritableStore<DatapointDefaultSorter, SegmentedWal>>>;
+
+/// the field types of the metrics. the metrics not listed follow the default field types if any.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MetricsSchema {
+    pub default_field_types: Option<Vec<FieldType>>,
+    pub metrics_field_types: HashMap<Metrics, Vec<FieldType>>,
+}
+
+impl MetricsSchema {
+    pub fn new(default_field_types: Option<Vec<FieldType>>) -> Self {
+        Self {
+            default_field_types,
+            metrics_field_types: HashMap::new(),
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics, field_types: Vec<FieldType>) -> Self {
+        self.metrics_field_types.insert(metrics, field_types);
+        self
+    }
+
+    pub fn field_types_of(&self, metrics: &Metrics) -> Option<&[FieldType]> {
+        self.metrics_field_types
+            .get(metrics)
+            .or(self.default_field_types.as_ref())
+            .map(|field_types| field_types.as_slice())
+    }
+}
+
+pub struct StoreManagerBuilder {
+    db_dir: PathBuf,
+    schema: MetricsSchema,
+    cloud_storage_and_setting: Option<(CloudStorage, storage_api::CloudStorageSetting)>,
+    duplicate_policy: DuplicatePolicy,
+    max_buffered_bytes: Option<usize>,
+}
+
+impl StoreManagerBuilder {
+    pub fn cloud_storage_and_setting(
+        mut self,
+        cloud_storage: CloudStorage,
+        cloud_setting: storage_api::CloudStorageSetting,
+    ) -> Self {
+        self.cloud_storage_and_setting = Some((cloud_storage, cloud_setting));
+        self
+    }
+
+    pub fn duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// the stores are persisted from the largest one when the datapoints on memory exceed this size.
+    pub fn max_buffered_bytes(mut self, max_buffered_bytes: usize) -> Self {
+        self.max_buffered_bytes = Some(max_buffered_bytes);
+        self
+    }
+
+    pub fn build(self) -> Arc<StoreManager> {
+        Arc::new(StoreManager {
+            db_dir: self.db_dir,
+            schema: self.schema,
+            cloud_storage_and_setting: self.cloud_storage_and_setting,
+            duplicate_policy: self.duplicate_policy,
+            max_buffered_bytes: self.max_buffered_bytes,
+            stores: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+#[derive(Clone)]
+struct ManagedStoreEntry {
+    store: ManagedStore,
+    /// the estimated size of the datapoints on memory
+    buffered_bytes: Arc<AtomicUsize>,
+}
+
+/// the writable stores of the metrics in a database. the store of a metrics is created on the first push.
+pub struct StoreManager {
+    db_dir: PathBuf,
+    schema: MetricsSchema,
+    cloud_storage_and_setting: Option<(CloudStorage, storage_api::CloudStorageSetting)>,
+    duplicate_policy: DuplicatePolicy,
+    max_buffered_bytes: Option<usize>,
+    /// the map is locked only to get the cell of the store, and the store is opened in the cell out of the lock.
+    stores: Mutex<HashMap<Metrics, Arc<OnceCell<ManagedStoreEntry>>>>,
+}
+
+impl StoreManager {
+    pub fn builder<P: Into<PathBuf>>(db_dir: P, schema: MetricsSchema) -> StoreManagerBuilder {
+        StoreManagerBuilder {
+            db_dir: db_dir.into(),
+            schema,
+            cloud_storage_and_setting: None,
+            duplicate_policy: DuplicatePolicy::default(),
+            max_buffered_bytes: None,
+        }
+    }
+
+    /// the datapoints on memory of the metrics. the store is created with the field types in the schema if not opened yet.
+    /// the store itself is not exposed so that the datapoints are pushed only via the manager that tracks the buffered size.
+    pub async fn datapoints(&self, metrics: &Metrics) -> Result<Vec<DataPoint>> {
+        let entry = self.store_entry(metrics).await?;
+        let mut store = entry.store.lock().await;
+        Ok(store.datapoints().await?.clone())
+    }
+
+    async fn store_entry(&self, metrics: &Metrics) -> Result<ManagedStoreEntry> {
+        let field_types = self
+            .schema
+            .field_types_of(metrics)
+            .ok_or_else(|| StoreError::NoMetricsSchema(metrics.to_string()))?
+            .to_vec();
+
+        let cell = self
+            .stores
+            .lock()
+            .await
+            .entry(metrics.clone())
+            .or_default()
+            .clone();
+        let entry = cell
+            .get_or_try_init(|| self.open_store(metrics, field_types))
+            .await?;
+        Ok(entry.clone())
+    }
+
+    /// opens the store replaying the wal.
+    async fn open_store(
+        &self,
+        metrics: &Metrics,
+        field_types: Vec<FieldType>,
+    ) -> Result<ManagedStoreEntry> {
+        let wal = SegmentedWal::open_or_create(&self.db_dir, metrics)?;
+        let store = WritableStore::builder(metrics.clone(), field_types, wal)
+            .persistence(Persistence::Storage(
+                self.db_dir.clone(),
+                self.cloud_storage_and_setting.clone(),
+            ))
+            .duplicate_policy(self.duplicate_policy)
+            .build()
+            .await?;
+
+        // the datapoints replayed from the wal are on memory as well
+        let buffered_bytes = {
+            let mut locked_store = store.lock().await;
+            Arc::new(AtomicUsize::new(estimated_bytes(
+                locked_store.datapoints().await?,
+            )))
+        };
+        Ok(ManagedStoreEntry {
+            store,
+            buffered_bytes,
+        })
+    }
+
+    /// returns error if the datapoints on memory can't get under `max_buffered_bytes` by persisting the stores,
+    /// e.g. the persistence failed or the datapoints are in the future.
+    pub async fn push_multi(&self, metrics: &Metrics, datapoints: Vec<DataPoint>) -> Result<()> {
+        let pushing_bytes = estimated_bytes(&datapoints);
+        if let Some(max_buffered_bytes) = self.max_buffered_bytes {
+            if self.buffered_bytes().await + pushing_bytes > max_buffered_bytes {
+                self.release_buffer(max_buffered_bytes.saturating_sub(pushing_bytes))
+                    .await?;
+                let buffered_bytes = self.buffered_bytes().await;
+                if buffered_bytes + pushing_bytes > max_buffered_bytes {
+                    return Err(StoreError::MaxBufferedBytesExceeded(format!(
+                        "max:{max_buffered_bytes}, buffered:{buffered_bytes}, pushing:{pushing_bytes}"
+                    )));
+                }
+            }
+        }
+
+        let entry = self.store_entry(metrics).await?;
+        WritableStore::push_multi_waiting(&entry.store, datapoints).await?;
+        entry
+            .buffered_bytes
+            .fetch_add(pushing_bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// the estimated size of the datapoints on memory in all the stores.
+    pub async fn buffered_bytes(&self) -> usize {
+        self.entries()
+            .await
+            .iter()
+            .map(|(_, _, buffered_bytes)| buffered_bytes.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub async fn metrics(&self) -> Vec<Metrics> {
+        self.entries()
+            .await
+            .into_iter()
+            .map(|(metrics, _, _)| metrics)
+            .collect()
+    }
+
+    /// the stores opened. the ones being opened are not included.
+    async fn entries(&self) -> Vec<(Metrics, ManagedStore, Arc<AtomicUsize>)> {
+        self.stores
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(metrics, cell)| {
+                cell.get().map(|entry| {
+                    (
+                        metrics.clone(),
+                        entry.store.clone(),
+                        entry.buffered_bytes.clone(),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// persists the stores from the largest one until the buffered size gets down to the target.
+    async fn release_buffer(&self, target_bytes: usize) -> Result<()> {
+        let mut entries = self.entries().await;
+        entries.sort_by_key(|(_, _, buffered_bytes)| {
+            std::cmp::Reverse(buffered_bytes.load(Ordering::Relaxed))
+        });
+
+        for (metrics, store, buffered_bytes) in entries {
+            if self.buffered_bytes().await <= target_bytes {
+                break;
+            }
+            log::debug!("persisting the store of {metrics} to release the buffer");
+            persist_store(&store, &buffered_bytes).await?;
+        }
+        Ok(())
+    }
+
+    /// persists the datapoints of all the stores.
+    pub async fn persist_all(&self) -> Result<()> {
+        for (_, store, buffered_bytes) in self.entries().await {
+            persist_store(&store, &buffered_bytes).await?;
+        }
+        Ok(())
+    }
+
+    /// persists all the stores and removes the lock files of them.
+    /// the errors of the stores are logged and the first one is returned after all the stores are done.
+    pub async fn shutdown(&self) -> Result<()> {
+        let entries = self.entries().await;
+
+        let mut first_error: Option<StoreError> = None;
+        for (metrics, store, buffered_bytes) in entries {
+            if let Err(e) = persist_store(&store, &buffered_bytes).await {
+                log::error!("failed to persist the store of {metrics} on shutdown: {e}");
+                first_error.get_or_insert(e);
+            }
+            if let Err(e) = store.lock().await.scavange_on_shutdown().await {
+                log::error!("scavenge on shutdown failed. metrics:{metrics}: {e}");
+                first_error.get_or_insert(e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// persists all the datapoints including the ones in the future, retries the failed uploads until the deadline,
+    /// then releases the lock files. what could not be done is reported instead of failing.
+    pub async fn graceful_shutdown(&self, upload_deadline: std::time::Duration) -> ShutdownReport {
+        let entries = self.entries().await;
+
+        let mut report = ShutdownReport::default();
+        for (_, store, buffered_bytes) in entries.iter() {
+            let mut store = store.lock().await;
+            store.flush_on_shutdown(&mut report).await;
+            match store.datapoints().await {
+                Ok(datapoints) => {
+                    buffered_bytes.store(estimated_bytes(datapoints), Ordering::Relaxed)
+                }
+                Err(e) => log::error!("failed to read the datapoints on shutdown: {e}"),
+            }
+        }
+
+        report
+            .retry_uploads(
+                &self.db_dir,
+                &Uuid::new_v4(),
+                None,
+                self.cloud_storage_and_setting
+                    .as_ref()
+                    .map(|(cloud_storage, _)| cloud_storage),
+                upload_deadline,
+            )
+            .await;
+
+        for (_, store, _) in entries.iter() {
+            store
+                .lock()
+                .await
+                .release_locks_on_shutdown(&mut report)
+                .await;
+        }
+        report
+    }
+
+    /// persists all the stores in a task at the interval. the stores are persisted and scavenged after the task stops.
+    pub fn start_periodically_persistence(
+        manager: Arc<StoreManager>,
+        interval_duration: Duration,
+    ) -> StoreManagerPersistenceShutdown {
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<DateTime<Utc>>(1);
+        let interval_duration = interval_duration.to_std().unwrap();
+        let task_manager = manager.clone();
+        let join_handle = task::spawn(async move {
+            loop {
+                let waiting_shutdown = time::timeout(interval_duration, shutdown_rx.recv()).await;
+                if waiting_shutdown.is_ok() {
+                    log::info!("breaking the periodicaly persistence loop of the store manager");
+                    break;
+                }
+
+                log::debug!("start periodically persistent of all the stores");
+                if let Err(e) = task_manager.persist_all().await {
+                    log::error!("store persisted error:{e}");
+                }
+            }
+        });
+
+        StoreManagerPersistenceShutdown {
+            manager,
+            shutdown_tx,
+            join_handle,
+        }
+    }
+}
+
+pub struct StoreManagerPersistenceShutdown {
+    manager: Arc<StoreManager>,
+    shutdown_tx: mpsc::Sender<DateTime<Utc>>,
+    join_handle: task::JoinHandle<()>,
+}
+
+impl StoreManagerPersistenceShutdown {
+    /// stops the persistence task, then persists all the stores and removes the lock files of them.
+    pub async fn shutdown_and_wait(self) -> Result<()> {
+        self.shutdown_tx.send(chrono::Utc::now()).await?;
+        self.join_handle.await?;
+        self.manager.shutdown().await
+    }
+
+    /// stops the persistence task, then shuts the store manager down gracefully.
+    pub async fn graceful_shutdown_and_wait(
+        self,
+        upload_deadline: std::time::Duration,
+    ) -> Result<ShutdownReport> {
+        self.shutdown_tx.send(chrono::Utc::now()).await?;
+        self.join_handle.await?;
+        Ok(self.manager.graceful_shutdown(upload_deadline).await)
+    }
+}
+
+async fn persist_store(store: &ManagedStore, buffered_bytes: &AtomicUsize) -> Result<()> {
+    let condition = PersistCondition::new(
+        DatapointsRange::new(None, Some(TimestampNano::now() + Duration::nanoseconds(1))),
+        true,
+    );
+    let mut store = store.lock().await;
+    let result = store.persist(condition).await;
+
+    // the datapoints in the future are left in the store
+    buffered_bytes.store(
+        estimated_bytes(store.datapoints().await?),
+        Ordering::Relaxed,
+    );
+    result?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::DatapointsSearchCondition;
+    use crate::tsdb::field::FieldValue;
+    use crate::tsdb::storage::api::CacheSetting;
+    use tempdir::TempDir;
+
+    fn datapoints(secs: std::ops::Range<u64>) -> Vec<DataPoint> {
+        secs.map(|sec| {
+            DataPoint::new(
+                TimestampNano::new(sec * 1_000_000_000),
+                vec![FieldValue::Float64(sec as f64)],
+            )
+        })
+        .collect()
+    }
+
+    async fn persisted_timestamps(db_dir: &std::path::Path, metrics: &Metrics) -> Vec<u64> {
+        storage_api::read::search_dataframe(
+            "db",
+            db_dir,
+            metrics,
+            None,
+            &DatapointsSearchCondition::all(),
+            &CacheSetting::none(),
+            None,
+        )
+        .await
+        .unwrap()
+        .map(|df| {
+            df.timestamp_nanos
+                .iter()
+                .map(|ts| ts.as_inner() / 1_000_000_000)
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_store_manager() {
+        let temp_dir = TempDir::new("store_manager_test").unwrap();
+        let db_dir = temp_dir.path();
+        let trades = Metrics::new("trades").unwrap();
+        let orders = Metrics::new("orders").unwrap();
+
+        let schema =
+            MetricsSchema::default().with_metrics(trades.clone(), vec![FieldType::Float64]);
+        let manager = StoreManager::builder(db_dir, schema).build();
+
+        manager.push_multi(&trades, datapoints(1..3)).await.unwrap();
+        assert!(manager.push_multi(&orders, datapoints(1..3)).await.is_err());
+        assert_eq!(manager.metrics().await, vec![trades.clone()]);
+        assert_eq!(
+            manager.buffered_bytes().await,
+            estimated_bytes(&datapoints(1..3))
+        );
+
+        manager.shutdown().await.unwrap();
+        assert_eq!(manager.buffered_bytes().await, 0);
+        assert_eq!(persisted_timestamps(db_dir, &trades).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_store_manager_graceful_shutdown() {
+        let temp_dir = TempDir::new("store_manager_test").unwrap();
+        let db_dir = temp_dir.path();
+        let trades = Metrics::new("trades").unwrap();
+
+        let schema = MetricsSchema::new(Some(vec![FieldType::Float64]));
+        let manager = StoreManager::builder(db_dir, schema).build();
+        let persistence =
+            StoreManager::start_periodically_persistence(manager.clone(), Duration::hours(1));
+        manager.push_multi(&trades, datapoints(1..3)).await.unwrap();
+
+        let report = persistence
+            .graceful_shutdown_and_wait(std::time::Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(report.flushed, vec![trades.clone()]);
+        assert!(report.is_clean());
+        assert_eq!(manager.buffered_bytes().await, 0);
+        assert_eq!(persisted_timestamps(db_dir, &trades).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_store_manager_max_buffered_bytes() {
+        let temp_dir = TempDir::new("store_manager_test").unwrap();
+        let db_dir = temp_dir.path();
+        let trades = Metrics::new("trades").unwrap();
+        let orders = Metrics::new("orders").unwrap();
+
+        let schema = MetricsSchema::new(Some(vec![FieldType::Float64]));
+        let manager = StoreManager::builder(db_dir, schema)
+            .max_buffered_bytes(estimated_bytes(&datapoints(0..3)))
+            .build();
+
+        manager.push_multi(&trades, datapoints(1..3)).await.unwrap();
+        manager.push_multi(&orders, datapoints(1..2)).await.unwrap();
+        assert!(persisted_timestamps(db_dir, &trades).await.is_empty());
+
+        // the largest store is persisted to push the datapoints
+        manager.push_multi(&orders, datapoints(2..3)).await.unwrap();
+        assert_eq!(persisted_timestamps(db_dir, &trades).await, vec![1, 2]);
+        assert!(persisted_timestamps(db_dir, &orders).await.is_empty());
+        assert_eq!(
+            manager.buffered_bytes().await,
+            estimated_bytes(&datapoints(1..3))
+        );
+        assert_eq!(manager.datapoints(&orders).await.unwrap(), datapoints(1..3));
+    }
+
+    #[tokio::test]
+    async fn test_store_manager_max_buffered_bytes_exceeded() {
+        let temp_dir = TempDir::new("store_manager_test").unwrap();
+        let db_dir = temp_dir.path();
+        let trades = Metrics::new("trades").unwrap();
+
+        let schema = MetricsSchema::new(Some(vec![FieldType::Float64]));
+        let manager = StoreManager::builder(db_dir, schema)
+            .max_buffered_bytes(estimated_bytes(&datapoints(0..2)))
+            .build();
+
+        // the datapoints in the future are not persisted to release the buffer
+        let future_sec = TimestampNano::now().as_inner() / 1_000_000_000 + 3600;
+        manager
+            .push_multi(&trades, datapoints(future_sec..future_sec + 2))
+            .await
+            .unwrap();
+        let result = manager
+            .push_multi(&trades, datapoints(future_sec + 2..future_sec + 3))
+            .await;
+        assert!(matches!(
+            result,
+            Err(StoreError::MaxBufferedBytesExceeded(_))
+        ));
+        assert_eq!(
+            manager.datapoints(&trades).await.unwrap(),
+            datapoints(future_sec..future_sec + 2)
+        );
+    }
+}
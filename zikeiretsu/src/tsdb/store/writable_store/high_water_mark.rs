@@ -0,0 +1,69 @@
+use crate::tsdb::datapoint::DataPoint;
+use crate::tsdb::field::FieldValue;
+use std::time::Duration;
+
+/// how long the pushing waits at most between the retries of the early persistence, unless the datapoints are released.
+pub const HIGH_WATER_MARK_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighWaterMarkAction {
+    /// retries the early persistence until the datapoints are released or the duration elapses, then fails.
+    /// it waits in `WritableStore::push_multi_waiting` with the store unlocked.
+    /// `WritableStore::push_multi` can't wait holding the lock of the store, so it fails right away as `Reject`.
+    Wait(Duration),
+    /// fails right away if the early persistence could not release the datapoints.
+    Reject,
+}
+
+/// the limits of the datapoints on memory. crossing any of them triggers the early persistence on pushing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighWaterMark {
+    pub max_datapoints: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub action: HighWaterMarkAction,
+}
+
+impl HighWaterMark {
+    pub fn new(action: HighWaterMarkAction) -> Self {
+        Self {
+            max_datapoints: None,
+            max_bytes: None,
+            action,
+        }
+    }
+
+    pub fn with_max_datapoints(mut self, max_datapoints: usize) -> Self {
+        self.max_datapoints = Some(max_datapoints);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn is_exceeded(&self, datapoint_num: usize, bytes: usize) -> bool {
+        matches!(self.max_datapoints, Some(max_datapoints) if datapoint_num > max_datapoints)
+            || matches!(self.max_bytes, Some(max_bytes) if bytes > max_bytes)
+    }
+}
+
+/// the approximate size of the datapoints on memory.
+pub fn estimated_bytes(datapoints: &[DataPoint]) -> usize {
+    datapoints
+        .iter()
+        .map(|datapoint| {
+            let strings_bytes: usize = datapoint
+                .field_values
+                .iter()
+                .map(|field_value| match field_value {
+                    FieldValue::String(s) => s.len(),
+                    _ => 0,
+                })
+                .sum();
+            std::mem::size_of::<DataPoint>()
+                + datapoint.field_values.len() * std::mem::size_of::<FieldValue>()
+                + strings_bytes
+        })
+        .sum()
+}
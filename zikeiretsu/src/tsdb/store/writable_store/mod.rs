@@ -1,4 +1,7 @@
+mod high_water_mark;
 mod persistence;
+mod sharded;
+mod shutdown;
 mod sorter;
 
 use super::*;
@@ -8,14 +11,18 @@ use uuid::Uuid;
 use crate::tsdb::storage::wal::WalWriter;
 use crate::tsdb::util;
 use crate::tsdb::{
-    datapoint::*, datapoints_searcher::*, field::*, metrics::Metrics, storage::api as storage_api,
+    datapoint::*, datapoints_searcher::*, duplicate_policy::*, field::*, metrics::Metrics,
+    storage::api as storage_api, TimestampNano,
 };
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
+pub use high_water_mark::*;
 pub use persistence::*;
+pub use sharded::*;
+pub use shutdown::*;
 pub use sorter::*;
 use std::marker::Send;
 pub use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, Notify};
 
 pub struct WritableStoreBuilder<S: DatapointSorter + 'static, Wal: WalWriter> {
     metrics: Metrics,
@@ -24,6 +31,8 @@ pub struct WritableStoreBuilder<S: DatapointSorter + 'static, Wal: WalWriter> {
     sorter: S,
     wal: Wal,
     persistence: Persistence,
+    duplicate_policy: DuplicatePolicy,
+    high_water_mark: Option<HighWaterMark>,
 }
 
 impl Default for Persistence {
@@ -41,6 +50,8 @@ impl<Wal: WalWriter> WritableStoreBuilder<DatapointDefaultSorter, Wal> {
             sorter: DatapointDefaultSorter,
             persistence: Persistence::default(),
             wal,
+            duplicate_policy: DuplicatePolicy::default(),
+            high_water_mark: None,
         }
     }
 }
@@ -61,6 +72,8 @@ impl<S: DatapointSorter + Send + 'static, Wal: WalWriter> WritableStoreBuilder<S
             convert_dirty_to_sorted_on_read,
             persistence,
             wal,
+            duplicate_policy,
+            high_water_mark,
             ..
         } = self;
 
@@ -71,6 +84,8 @@ impl<S: DatapointSorter + Send + 'static, Wal: WalWriter> WritableStoreBuilder<S
             convert_dirty_to_sorted_on_read,
             persistence,
             wal,
+            duplicate_policy,
+            high_water_mark,
         }
     }
 
@@ -79,8 +94,26 @@ impl<S: DatapointSorter + Send + 'static, Wal: WalWriter> WritableStoreBuilder<S
         self
     }
 
+    /// how the datapoints of the same timestamp are treated. all of them are kept by default.
+    pub fn duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// the limits of the datapoints on memory. no limit by default.
+    pub fn high_water_mark(mut self, high_water_mark: HighWaterMark) -> Self {
+        self.high_water_mark = Some(high_water_mark);
+        self
+    }
+
     pub async fn build(self) -> Result<Arc<Mutex<WritableStore<S, Wal>>>> {
         let datapoints_in_wal = self.wal.load().await?;
+        let buffered_bytes = estimated_bytes(&datapoints_in_wal);
+        let unpersisted_since = if datapoints_in_wal.is_empty() {
+            None
+        } else {
+            Some(Utc::now())
+        };
 
         let store = WritableStore {
             store_id: Uuid::new_v4(),
@@ -92,25 +125,91 @@ impl<S: DatapointSorter + Send + 'static, Wal: WalWriter> WritableStoreBuilder<S
             sorter: self.sorter,
             persistence: self.persistence,
             wal: self.wal,
+            duplicate_policy: self.duplicate_policy,
+            high_water_mark: self.high_water_mark,
+            buffered_bytes,
+            released: Arc::new(Notify::new()),
+            persistence_signal: None,
+            unpersisted_num: datapoints_in_wal.len(),
+            unpersisted_bytes: buffered_bytes,
+            unpersisted_since,
         };
         Ok(Arc::new(Mutex::new(store)))
     }
+
+    /// builds the store that the datapoints can be pushed to concurrently without locking the whole of it.
+    /// the datapoints in the wal are loaded into the first shard.
+    pub async fn build_sharded(
+        self,
+        shard_num: usize,
+    ) -> Result<Arc<ShardedWritableStore<S, Wal>>> {
+        let datapoints_in_wal = self.wal.load().await?;
+        let unpersisted = UnpersistedStatus {
+            datapoint_num: datapoints_in_wal.len(),
+            bytes: estimated_bytes(&datapoints_in_wal),
+            since: if datapoints_in_wal.is_empty() {
+                None
+            } else {
+                Some(Utc::now())
+            },
+        };
+        let mut shards: Vec<std::sync::Mutex<Vec<DataPoint>>> = (0..shard_num.max(1))
+            .map(|_| std::sync::Mutex::new(vec![]))
+            .collect();
+        *shards[0].get_mut().unwrap() = datapoints_in_wal;
+
+        let store = ShardedWritableStore {
+            store_id: Uuid::new_v4(),
+            metrics: self.metrics,
+            field_types: self.field_types,
+            convert_dirty_to_sorted_on_read: self.convert_dirty_to_sorted_on_read,
+            shards,
+            next_shard: std::sync::atomic::AtomicUsize::new(0),
+            shard_datapoint_num: std::sync::atomic::AtomicUsize::new(unpersisted.datapoint_num),
+            shard_bytes: std::sync::atomic::AtomicUsize::new(unpersisted.bytes),
+            snapshot: std::sync::RwLock::new(Arc::new(vec![])),
+            snapshot_bytes: std::sync::atomic::AtomicUsize::new(0),
+            sorter: Mutex::new(self.sorter),
+            persistence_lock: Mutex::new(()),
+            persistence: self.persistence,
+            wal: Mutex::new(self.wal),
+            wal_queue: std::sync::Mutex::new(vec![]),
+            duplicate_policy: self.duplicate_policy,
+            high_water_mark: self.high_water_mark,
+            released: Notify::new(),
+            persistence_signal: std::sync::Mutex::new(None),
+            unpersisted: std::sync::Mutex::new(unpersisted),
+        };
+        Ok(Arc::new(store))
+    }
 }
 
 pub struct WritableStore<S: DatapointSorter + 'static, Wal: WalWriter> {
     store_id: Uuid,
     metrics: Metrics,
-    #[allow(dead_code)]
     field_types: Vec<FieldType>,
 
     convert_dirty_to_sorted_on_read: bool,
 
-    //TODO(tacogips) Consider LEFT-RIGHT pattern instead of locking to increase performance.
+    // see ShardedWritableStore for the concurrent ingestion without locking the whole store.
     dirty_datapoints: Vec<DataPoint>,
     sorted_datapoints: Vec<DataPoint>,
     sorter: S,
     persistence: Persistence,
     wal: Wal,
+    duplicate_policy: DuplicatePolicy,
+    high_water_mark: Option<HighWaterMark>,
+    /// the estimated size of the dirty and the sorted datapoints
+    buffered_bytes: usize,
+    /// notified when the datapoints are removed from the store. see `push_multi_waiting`.
+    released: Arc<Notify>,
+    /// set by the triggered persistence task
+    persistence_signal: Option<PersistenceSignal>,
+
+    unpersisted_num: usize,
+    unpersisted_bytes: usize,
+    /// when the oldest datapoint not persisted yet was pushed
+    unpersisted_since: Option<DateTime<Utc>>,
 }
 
 impl<Wal: WalWriter> WritableStore<DatapointDefaultSorter, Wal> {
@@ -128,39 +227,187 @@ where
     S: DatapointSorter + 'static,
     Wal: WalWriter + 'static,
 {
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn field_types(&self) -> &[FieldType] {
+        &self.field_types
+    }
+
+    /// the number of the dirty and the sorted datapoints.
+    pub fn datapoint_num(&self) -> usize {
+        self.dirty_datapoints.len() + self.sorted_datapoints.len()
+    }
+
+    /// the estimated size of the datapoints on memory.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// the number of the datapoints pushed or loaded from the wal, and not persisted yet.
+    pub fn unpersisted_datapoint_num(&self) -> usize {
+        self.unpersisted_num
+    }
+
+    pub fn unpersisted_bytes(&self) -> usize {
+        self.unpersisted_bytes
+    }
+
+    pub fn unpersisted_since(&self) -> Option<DateTime<Utc>> {
+        self.unpersisted_since
+    }
+
+    fn update_buffered_bytes(&mut self) {
+        self.buffered_bytes =
+            estimated_bytes(&self.dirty_datapoints) + estimated_bytes(&self.sorted_datapoints);
+    }
+
+    /// fails without waiting if the high-water mark can't be kept. see `push_multi_waiting` to wait for the room.
     pub async fn push_multi(&mut self, data_points: Vec<DataPoint>) -> Result<()> {
         #[cfg(feature = "trace-log")]
         log::trace!("push multi data: {data_points:?}");
 
         #[cfg(feature = "validate")]
-        for data_point in data_points.iter() {
-            if !same_field_types(&self.field_types, &data_point.field_values) {
-                let expected = self
-                    .field_types
-                    .iter()
-                    .map(|e| e.to_string())
-                    .collect::<Vec<String>>()
-                    .join(",");
-
-                let data_point_fields = data_point
-                    .field_values
-                    .iter()
-                    .map(|e| e.as_type().to_string())
-                    .collect::<Vec<String>>()
-                    .join(",");
-
-                return Err(StoreError::DataFieldTypesMismatched(
-                    expected,
-                    data_point_fields,
-                ));
-            }
+        validate_field_types(&self.field_types, &data_points)?;
+
+        if self.duplicate_policy == DuplicatePolicy::Error {
+            self.check_duplicated_timestamps(&data_points).await?;
+        }
+
+        let pushing_bytes = estimated_bytes(&data_points);
+        if !self
+            .release_for_high_water_mark(data_points.len(), pushing_bytes)
+            .await
+        {
+            return Err(StoreError::HighWaterMarkExceeded(format!(
+                "metrics:{}, datapoints:{}, bytes:{}, pushing datapoints:{}",
+                self.metrics,
+                self.datapoint_num(),
+                self.buffered_bytes,
+                data_points.len()
+            )));
         }
 
         self.wal.write(&data_points).await?;
 
+        self.buffered_bytes += pushing_bytes;
+        self.unpersisted_num += data_points.len();
+        self.unpersisted_bytes += pushing_bytes;
+        if self.unpersisted_since.is_none() {
+            self.unpersisted_since = Some(Utc::now());
+        }
         for each_data_point in data_points {
             self.dirty_datapoints.push(each_data_point);
         }
+        if let Some(persistence_signal) = self.persistence_signal.as_ref() {
+            persistence_signal.notify_if_fired(self.unpersisted_num, self.unpersisted_bytes);
+        }
+        Ok(())
+    }
+
+    /// pushes the datapoints, waiting for the room under the high-water mark with `HighWaterMarkAction::Wait`.
+    /// the store is unlocked while waiting, so that the persistence task and the readers go on.
+    pub async fn push_multi_waiting(
+        store: &Arc<Mutex<WritableStore<S, Wal>>>,
+        data_points: Vec<DataPoint>,
+    ) -> Result<()> {
+        let pushing_num = data_points.len();
+        let pushing_bytes = estimated_bytes(&data_points);
+        let started_at = std::time::Instant::now();
+        loop {
+            let mut locked_store = store.lock().await;
+            let max_wait = match locked_store.high_water_mark.as_ref() {
+                Some(HighWaterMark {
+                    action: HighWaterMarkAction::Wait(max_wait),
+                    ..
+                }) => *max_wait,
+                _ => return locked_store.push_multi(data_points).await,
+            };
+            let wait = max_wait
+                .checked_sub(started_at.elapsed())
+                .filter(|wait| !wait.is_zero());
+            if wait.is_none()
+                || locked_store
+                    .release_for_high_water_mark(pushing_num, pushing_bytes)
+                    .await
+            {
+                return locked_store.push_multi(data_points).await;
+            }
+
+            // registered before unlocking not to miss the release
+            let released = locked_store.released.clone();
+            let notified = released.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            drop(locked_store);
+
+            let retry_after = wait.unwrap_or_default().min(HIGH_WATER_MARK_RETRY_INTERVAL);
+            let _ = tokio::time::timeout(retry_after, notified).await;
+        }
+    }
+
+    /// persists the datapoints early if pushing the datapoints crosses the high-water mark,
+    /// and returns whether there is room for them.
+    /// the datapoints are accepted anyway if the store is empty, since no room can be made for them.
+    async fn release_for_high_water_mark(
+        &mut self,
+        pushing_num: usize,
+        pushing_bytes: usize,
+    ) -> bool {
+        let high_water_mark = match self.high_water_mark.clone() {
+            Some(high_water_mark) => high_water_mark,
+            None => return true,
+        };
+        let is_exceeded = |store: &Self| {
+            store.datapoint_num() > 0
+                && high_water_mark.is_exceeded(
+                    store.datapoint_num() + pushing_num,
+                    store.buffered_bytes + pushing_bytes,
+                )
+        };
+        if !is_exceeded(self) {
+            return true;
+        }
+
+        if matches!(self.persistence, Persistence::Storage(..)) {
+            log::info!(
+                "persisting early on the high-water mark. metrics:{}, datapoints:{}, bytes:{}",
+                self.metrics,
+                self.datapoint_num(),
+                self.buffered_bytes
+            );
+            let condition = PersistCondition::new(
+                DatapointsRange::new(None, Some(TimestampNano::now() + Duration::nanoseconds(1))),
+                true,
+            );
+            if let Err(e) = self.persist(condition).await {
+                log::warn!("early persistence on the high-water mark failed: {e}");
+            }
+        }
+        !is_exceeded(self)
+    }
+
+    /// returns error if any of the timestamps of the datapoints already exists in the store or in the datapoints.
+    async fn check_duplicated_timestamps(&mut self, data_points: &[DataPoint]) -> Result<()> {
+        self.apply_dirties().await?;
+
+        let mut timestamps: Vec<TimestampNano> = data_points
+            .iter()
+            .map(|data_point| data_point.timestamp_nano)
+            .collect();
+        timestamps.sort();
+        DuplicatePolicy::Error.indices_to_keep(&timestamps)?;
+
+        for timestamp in timestamps.iter() {
+            if self
+                .sorted_datapoints
+                .binary_search_by_key(timestamp, |datapoint| datapoint.timestamp_nano)
+                .is_ok()
+            {
+                return Err(DuplicatedTimestampError(*timestamp).into());
+            }
+        }
         Ok(())
     }
 
@@ -171,39 +418,17 @@ where
             return Ok(());
         }
 
-        let mut sorter = self.sorter.clone();
-        self.dirty_datapoints.sort_by(|l, r| sorter.compare(l, r));
+        merge_dirties(
+            &mut self.sorted_datapoints,
+            &mut self.dirty_datapoints,
+            &mut self.sorter.clone(),
+            self.duplicate_policy,
+        )?;
 
-        if self.sorted_datapoints.is_empty() {
-            self.sorted_datapoints.append(&mut self.dirty_datapoints);
-        } else {
-            while let Some(head) = self.dirty_datapoints.get(0) {
-                let last = self.sorted_datapoints.last().unwrap();
-                match last.timestamp_nano.cmp(&head.timestamp_nano) {
-                    Ordering::Equal | Ordering::Less => {
-                        self.sorted_datapoints.append(&mut self.dirty_datapoints);
-                        break;
-                    }
-                    _ => {
-                        let head = self.dirty_datapoints.remove(0);
-                        match binary_search_by(
-                            &self.sorted_datapoints,
-                            |datapoint| datapoint.timestamp_nano.cmp(&head.timestamp_nano),
-                            BinaryRangeSearchType::AtMostInclusive,
-                        ) {
-                            Some(idx) => {
-                                self.sorted_datapoints.insert(idx, head);
-                            }
-                            None => {
-                                self.sorted_datapoints.insert(0, head);
-                            }
-                        }
-                    }
-                };
-            }
+        // the duplicates may have been removed
+        if self.duplicate_policy != DuplicatePolicy::KeepAll {
+            self.update_buffered_bytes();
         }
-        self.dirty_datapoints.clear();
-
         Ok(())
     }
 
@@ -212,15 +437,29 @@ where
         self.sorted_datapoints.shrink_to_fit();
     }
 
+    /// removes the datapoints in the range, then rewrites the wal with the datapoints left
+    /// so that the removed ones are never replayed.
     pub async fn purge(&mut self, datapoint_search_condition: DatapointsRange) -> Result<()> {
-        let datapoints = self.datapoints_mut().await?;
-        let datapoints_searcher = DatapointSearcher::new(datapoints);
-
-        if let Some((_, indices)) = datapoints_searcher
+        self.apply_dirties().await?;
+        let indices = DatapointSearcher::new(&self.sorted_datapoints)
             .search_with_indices(&datapoint_search_condition)
             .await
-        {
-            remove_range(datapoints, indices)?;
+            .map(|(_, indices)| indices);
+
+        if let Some(indices) = indices {
+            // the records written so far are sealed, and removed after the datapoints left are written again.
+            let checkpoint = self.wal.seal()?;
+            remove_range(&mut self.sorted_datapoints, indices)?;
+            self.update_buffered_bytes();
+            self.released.notify_waiters();
+            self.wal.write(&self.sorted_datapoints).await?;
+            self.wal.checkpoint(checkpoint)?;
+
+            self.unpersisted_num = self.sorted_datapoints.len();
+            self.unpersisted_bytes = self.buffered_bytes;
+            if self.sorted_datapoints.is_empty() {
+                self.unpersisted_since = None;
+            }
         }
 
         Ok(())
@@ -233,11 +472,17 @@ where
             let writer_id = self.store_id;
             let all_datapoints = self.datapoints_mut().await?;
             let datapoints_searcher = DatapointSearcher::new(all_datapoints);
-
-            if let Some((datapoints, indices)) = datapoints_searcher
+            let indices = datapoints_searcher
                 .search_with_indices(&condition.datapoint_search_condition)
                 .await
-            {
+                .map(|(_, indices)| indices);
+
+            if let Some(indices) = indices {
+                // the records written so far are sealed, and removed after the persistence.
+                let checkpoint = self.wal.seal()?;
+
+                let (start, end) = indices;
+                let datapoints = &self.sorted_datapoints[start..=end];
                 storage_api::write::write_datas(
                     db_dir,
                     &writer_id,
@@ -249,13 +494,20 @@ where
                 )
                 .await?;
 
+                // the datapoints not persisted are written to the wal again so that the sealed records can be removed.
+                let mut unpersisted_datapoints = self.sorted_datapoints[..start].to_vec();
+                unpersisted_datapoints.extend_from_slice(&self.sorted_datapoints[end + 1..]);
+                unpersisted_datapoints.extend_from_slice(&self.dirty_datapoints);
+
                 if condition.remove_from_store_after_persisted {
                     log::debug!(
                         "clear writable store after persistence. indices:{indices:?}, datapoint len: {data_len}",
-                        data_len = datapoints.len(),
+                        data_len = end + 1 - start,
                     );
-                    remove_range(all_datapoints, indices)?;
+                    remove_range(&mut self.sorted_datapoints, indices)?;
                     self.shrink_to_fit_vec();
+                    self.update_buffered_bytes();
+                    self.released.notify_waiters();
 
                     log::debug!(
                         "after clear writable store, sorted datapoint len: {sorted_datapoint_len }, dirty datapoint len: {dirty_datapoint_len}",
@@ -264,9 +516,27 @@ where
                     );
                 }
 
-                self.wal.clean()?;
+                self.wal.write(&unpersisted_datapoints).await?;
+                self.wal.checkpoint(checkpoint)?;
+
+                // the datapoints left are regarded as pushed now not to fire the age trigger again right away
+                self.unpersisted_num = unpersisted_datapoints.len();
+                self.unpersisted_bytes = estimated_bytes(&unpersisted_datapoints);
+                self.unpersisted_since = if unpersisted_datapoints.is_empty() {
+                    None
+                } else {
+                    Some(Utc::now())
+                };
                 Ok(Some(()))
             } else {
+                // nothing is persisted. the datapoints are regarded as pushed now as well
+                self.unpersisted_num = self.datapoint_num();
+                self.unpersisted_bytes = self.buffered_bytes;
+                self.unpersisted_since = if self.unpersisted_num == 0 {
+                    None
+                } else {
+                    Some(Utc::now())
+                };
                 Ok(None)
             }
         } else {
@@ -307,8 +577,7 @@ where
         task::spawn(async move {
             while let Some(datapoints) = datapoints_rx.recv().await {
                 log::trace!("datapoints to push multi {:?}", datapoints);
-                let mut locked_store = store.lock().await;
-                if let Err(e) = locked_store.push_multi(datapoints).await {
+                if let Err(e) = Self::push_multi_waiting(&store, datapoints).await {
                     log::error!("error on push multiple datapoints :{:?}", e,);
                 }
             }
@@ -324,6 +593,27 @@ where
         start_periodically_persistence(store, persist_interval_duration, clear_after_persisted)
     }
 
+    /// persists the store when any of the conditions of the trigger fires first.
+    pub fn start_triggered_persist(
+        store: Arc<Mutex<WritableStore<S, Wal>>>,
+        trigger: PersistenceTrigger,
+        clear_after_persisted: bool,
+    ) -> PeriodicallyPeristenceShutdown {
+        start_triggered_persistence(store, trigger, clear_after_persisted)
+    }
+
+    /// syncs the wal on every tick. see `start_wal_sync`.
+    pub fn start_wal_sync(
+        store: Arc<Mutex<WritableStore<S, Wal>>>,
+        tick: Duration,
+    ) -> PeriodicallyPeristenceShutdown {
+        start_wal_sync(store, tick)
+    }
+
+    pub(crate) fn sync_wal_due(&mut self) -> Result<()> {
+        Ok(self.wal.sync_due()?)
+    }
+
     pub async fn scavange_on_shutdown(&self) -> Result<()> {
         if let Persistence::Storage(db_dir, cloud_storage_and_setting) = self.persistence.clone() {
             storage_api::write::remove_local_lock_file_if_same_writer(
@@ -346,6 +636,87 @@ where
     }
 }
 
+#[cfg(feature = "validate")]
+fn validate_field_types(field_types: &[FieldType], data_points: &[DataPoint]) -> Result<()> {
+    for data_point in data_points.iter() {
+        if !same_field_types(field_types, &data_point.field_values) {
+            let expected = field_types
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+
+            let data_point_fields = data_point
+                .field_values
+                .iter()
+                .map(|e| e.as_type().to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+
+            return Err(StoreError::DataFieldTypesMismatched(
+                expected,
+                data_point_fields,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// sorts the dirty datapoints and merges them into the sorted ones. the dirty datapoints are drained.
+fn merge_dirties<S: DatapointSorter>(
+    sorted_datapoints: &mut Vec<DataPoint>,
+    dirty_datapoints: &mut Vec<DataPoint>,
+    sorter: &mut S,
+    duplicate_policy: DuplicatePolicy,
+) -> Result<()> {
+    if dirty_datapoints.is_empty() {
+        return Ok(());
+    }
+
+    dirty_datapoints.sort_by(|l, r| sorter.compare(l, r));
+
+    if sorted_datapoints.is_empty() {
+        sorted_datapoints.append(dirty_datapoints);
+    } else {
+        while let Some(head) = dirty_datapoints.get(0) {
+            let last = sorted_datapoints.last().unwrap();
+            match last.timestamp_nano.cmp(&head.timestamp_nano) {
+                Ordering::Equal | Ordering::Less => {
+                    sorted_datapoints.append(dirty_datapoints);
+                    break;
+                }
+                _ => {
+                    let head = dirty_datapoints.remove(0);
+                    match binary_search_by(
+                        sorted_datapoints,
+                        |datapoint| datapoint.timestamp_nano.cmp(&head.timestamp_nano),
+                        BinaryRangeSearchType::AtMostInclusive,
+                    ) {
+                        // after the datapoints of the same timestamp to keep the order of arrival
+                        Some(idx) => {
+                            sorted_datapoints.insert(idx + 1, head);
+                        }
+                        None => {
+                            sorted_datapoints.insert(0, head);
+                        }
+                    }
+                }
+            };
+        }
+    }
+    dirty_datapoints.clear();
+
+    // the duplicates were rejected on pushing if the policy is Error
+    if matches!(
+        duplicate_policy,
+        DuplicatePolicy::KeepFirst | DuplicatePolicy::KeepLast
+    ) {
+        duplicate_policy.dedup_datapoints(sorted_datapoints)?;
+    }
+
+    Ok(())
+}
+
 fn remove_range(datapoints: &mut Vec<DataPoint>, range: (usize, usize)) -> Result<()> {
     util::remove_range(datapoints, range)?;
     Ok(())
@@ -389,4 +760,361 @@ mod test {
 
         assert_eq!(expected, datapoints);
     }
+
+    #[tokio::test]
+    async fn test_duplicate_policy() {
+        use crate::tsdb::storage::wal::EmptyWal;
+
+        let build_store = |duplicate_policy: DuplicatePolicy| async move {
+            WritableStore::builder(
+                Metrics::new("test").unwrap(),
+                vec![FieldType::Float64],
+                EmptyWal,
+            )
+            .duplicate_policy(duplicate_policy)
+            .build()
+            .await
+            .unwrap()
+        };
+
+        for (duplicate_policy, expected) in [
+            (
+                DuplicatePolicy::KeepAll,
+                float_data_points!({1, vec![1]}, {2, vec![2]}, {2, vec![3]}, {2, vec![4]}, {3, vec![5]}),
+            ),
+            (
+                DuplicatePolicy::KeepFirst,
+                float_data_points!({1, vec![1]}, {2, vec![2]}, {3, vec![5]}),
+            ),
+            (
+                DuplicatePolicy::KeepLast,
+                float_data_points!({1, vec![1]}, {2, vec![4]}, {3, vec![5]}),
+            ),
+        ] {
+            let store = build_store(duplicate_policy).await;
+            let mut store = store.lock().await;
+            store
+                .push_multi(float_data_points!({2, vec![2]}, {3, vec![5]}))
+                .await
+                .unwrap();
+            store.apply_dirties().await.unwrap();
+            store
+                .push_multi(float_data_points!({2, vec![3]}, {1, vec![1]}, {2, vec![4]}))
+                .await
+                .unwrap();
+
+            assert_eq!(store.datapoints().await.unwrap(), &expected);
+        }
+
+        let store = build_store(DuplicatePolicy::Error).await;
+        let mut store = store.lock().await;
+        store
+            .push_multi(float_data_points!({2, vec![2]}, {3, vec![5]}))
+            .await
+            .unwrap();
+        assert!(store
+            .push_multi(float_data_points!({1, vec![1]}, {1, vec![3]}))
+            .await
+            .is_err());
+        assert!(store
+            .push_multi(float_data_points!({1, vec![1]}, {2, vec![3]}))
+            .await
+            .is_err());
+        store
+            .push_multi(float_data_points!({1, vec![1]}))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.datapoints().await.unwrap(),
+            &float_data_points!({1, vec![1]}, {2, vec![2]}, {3, vec![5]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_with_torn_wal() {
+        use crate::tsdb::storage::wal::SingleFileWal;
+        use std::io::Write;
+
+        let temp_data_dir = tempdir::TempDir::new("torn_wal_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+
+        let mut wal = SingleFileWal::open_or_create(db_dir, &metrics).unwrap();
+        let datapoints = float_data_points!({1, vec![1]}, {2, vec![2]});
+        wal.write(&datapoints).await.unwrap();
+
+        // a record header with no payload
+        let wal_file_path = db_dir.join("wal").join(metrics.as_str()).join("wal.dat");
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(wal_file_path)
+            .unwrap()
+            .write_all(&[0xA5, 0, 0, 0, 100])
+            .unwrap();
+
+        let wal = SingleFileWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics, vec![FieldType::Float64], wal)
+            .build()
+            .await
+            .unwrap();
+        let mut store = store.lock().await;
+        assert_eq!(store.datapoints().await.unwrap(), &datapoints);
+    }
+
+    #[tokio::test]
+    async fn test_high_water_mark() {
+        use crate::tsdb::storage::wal::EmptyWal;
+
+        // nothing can be released on memory
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .high_water_mark(HighWaterMark::new(HighWaterMarkAction::Reject).with_max_datapoints(2))
+        .build()
+        .await
+        .unwrap();
+        let mut store = store.lock().await;
+        store
+            .push_multi(float_data_points!({1, vec![1]}, {2, vec![2]}))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.push_multi(float_data_points!({3, vec![3]})).await,
+            Err(StoreError::HighWaterMarkExceeded(_))
+        ));
+        assert_eq!(store.datapoint_num(), 2);
+
+        // the early persistence releases the datapoints
+        let temp_data_dir = tempdir::TempDir::new("high_water_mark_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+        .high_water_mark(
+            HighWaterMark::new(HighWaterMarkAction::Wait(std::time::Duration::from_secs(1)))
+                .with_max_bytes(estimated_bytes(
+                    &float_data_points!({1, vec![1]}, {2, vec![2]}),
+                )),
+        )
+        .build()
+        .await
+        .unwrap();
+        let mut store = store.lock().await;
+        store
+            .push_multi(float_data_points!({1, vec![1]}, {2, vec![2]}))
+            .await
+            .unwrap();
+        store
+            .push_multi(float_data_points!({3, vec![3]}))
+            .await
+            .unwrap();
+        assert_eq!(store.datapoint_num(), 1);
+        assert_eq!(
+            store.buffered_bytes(),
+            estimated_bytes(&float_data_points!({3, vec![3]}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_multi_waiting() {
+        use crate::tsdb::storage::wal::EmptyWal;
+
+        let temp_data_dir = tempdir::TempDir::new("high_water_mark_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+        .high_water_mark(
+            HighWaterMark::new(HighWaterMarkAction::Wait(std::time::Duration::from_secs(
+                10,
+            )))
+            .with_max_datapoints(2),
+        )
+        .build()
+        .await
+        .unwrap();
+
+        // the datapoints in the future are not released by the early persistence
+        let future = TimestampNano::now().as_inner() + 3_600_000_000_000;
+        store
+            .lock()
+            .await
+            .push_multi(float_data_points!({future, vec![1]}, {future + 1, vec![2]}))
+            .await
+            .unwrap();
+        // can't wait holding the lock
+        assert!(matches!(
+            store
+                .lock()
+                .await
+                .push_multi(float_data_points!({1, vec![3]}))
+                .await,
+            Err(StoreError::HighWaterMarkExceeded(_))
+        ));
+
+        let waiting = tokio::spawn({
+            let store = store.clone();
+            async move {
+                WritableStore::push_multi_waiting(&store, float_data_points!({1, vec![3]})).await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // the store is not locked while waiting
+        store
+            .lock()
+            .await
+            .persist(PersistCondition::new(DatapointsRange::all(), true))
+            .await
+            .unwrap();
+        waiting.await.unwrap().unwrap();
+        assert_eq!(store.lock().await.datapoint_num(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_triggered_persistence() {
+        use crate::tsdb::storage::wal::EmptyWal;
+
+        let temp_data_dir = tempdir::TempDir::new("triggered_persistence_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+        .build()
+        .await
+        .unwrap();
+
+        // the count is signalled on pushing, not waiting for the check
+        let shutdown = WritableStore::start_triggered_persist(
+            store.clone(),
+            PersistenceTrigger::default()
+                .with_max_datapoints(2)
+                .with_check_interval(Duration::hours(1)),
+            true,
+        );
+
+        store
+            .lock()
+            .await
+            .push_multi(float_data_points!({1, vec![1]}))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(store.lock().await.unpersisted_datapoint_num(), 1);
+
+        store
+            .lock()
+            .await
+            .push_multi(float_data_points!({2, vec![2]}))
+            .await
+            .unwrap();
+        for _ in 0..100 {
+            if store.lock().await.unpersisted_datapoint_num() == 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        {
+            let store = store.lock().await;
+            assert_eq!(store.unpersisted_datapoint_num(), 0);
+            assert_eq!(store.unpersisted_since(), None);
+            assert_eq!(store.datapoint_num(), 0);
+        }
+        shutdown.shutdown_and_wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_persist_nothing_resets_unpersisted() {
+        use crate::tsdb::storage::wal::EmptyWal;
+
+        let temp_data_dir = tempdir::TempDir::new("triggered_persistence_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+        .build()
+        .await
+        .unwrap();
+        let mut store = store.lock().await;
+
+        let future = TimestampNano::now().as_inner() + 3_600_000_000_000;
+        store
+            .push_multi(float_data_points!({future, vec![1]}))
+            .await
+            .unwrap();
+        let pushed_long_ago = Utc::now() - Duration::hours(1);
+        store.unpersisted_since = Some(pushed_long_ago);
+
+        // the datapoint in the future is not persisted, and the age trigger doesn't fire again right away
+        let persisted = store
+            .persist(PersistCondition::new(
+                DatapointsRange::new(None, Some(TimestampNano::now())),
+                true,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(persisted, None);
+        assert_eq!(store.unpersisted_datapoint_num(), 1);
+        assert!(store.unpersisted_since().unwrap() > pushed_long_ago);
+    }
+
+    #[tokio::test]
+    async fn test_persist_removes_persisted_wal_records() {
+        use crate::tsdb::storage::wal::SegmentedWal;
+
+        let temp_data_dir = tempdir::TempDir::new("persist_wal_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics.clone(), vec![FieldType::Float64], wal)
+            .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+            .build()
+            .await
+            .unwrap();
+        {
+            let mut store = store.lock().await;
+            store
+                .push_multi(float_data_points!(
+                    {1_000_000_000, vec![1]},
+                    {2_000_000_000, vec![2]},
+                    {3_000_000_000, vec![3]}
+                ))
+                .await
+                .unwrap();
+            store
+                .persist(PersistCondition::new(
+                    DatapointsRange::until(ts!(3_000_000_000)),
+                    true,
+                ))
+                .await
+                .unwrap();
+        }
+
+        // only the datapoint not persisted is replayed
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics, vec![FieldType::Float64], wal)
+            .build()
+            .await
+            .unwrap();
+        let mut store = store.lock().await;
+        assert_eq!(
+            store.datapoints().await.unwrap(),
+            &float_data_points!({3_000_000_000, vec![3]})
+        );
+    }
 }
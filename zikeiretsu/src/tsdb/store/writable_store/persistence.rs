@@ -5,11 +5,12 @@ use crate::tsdb::storage::wal::WalWriter;
 use crate::tsdb::store::writable_store::Result;
 use crate::tsdb::timestamp_nano::TimestampNano;
 use crate::tsdb::CloudStorage;
+use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
 use log;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, Notify};
 use tokio::{task, time};
 
 #[derive(Clone)]
@@ -38,12 +39,212 @@ pub enum Persistence {
     ),
 }
 
+/// the conditions to persist the store. the store is persisted when any of them fires first.
+/// the size is checked on pushing, and the age is checked at the check interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistenceTrigger {
+    /// since the last persistence
+    pub interval: Option<Duration>,
+    pub max_datapoints: Option<usize>,
+    pub max_bytes: Option<usize>,
+    /// since the oldest datapoint not persisted yet was pushed
+    pub max_age: Option<Duration>,
+    pub check_interval: Duration,
+}
+
+impl Default for PersistenceTrigger {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            max_datapoints: None,
+            max_bytes: None,
+            max_age: None,
+            check_interval: Duration::seconds(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersistenceTriggerReason {
+    Interval,
+    DatapointNum,
+    Bytes,
+    Age,
+}
+
+impl PersistenceTrigger {
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn with_max_datapoints(mut self, max_datapoints: usize) -> Self {
+        self.max_datapoints = Some(max_datapoints);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// the reason to persist the store if any of the conditions fires.
+    pub fn fired(
+        &self,
+        since_last_persistence: Duration,
+        unpersisted_num: usize,
+        unpersisted_bytes: usize,
+        oldest_unpersisted_age: Option<Duration>,
+    ) -> Option<PersistenceTriggerReason> {
+        if let Some(reason) = self.size_fired(unpersisted_num, unpersisted_bytes) {
+            Some(reason)
+        } else if matches!((self.max_age, oldest_unpersisted_age), (Some(max_age), Some(age)) if age >= max_age)
+        {
+            Some(PersistenceTriggerReason::Age)
+        } else if matches!(self.interval, Some(interval) if since_last_persistence >= interval) {
+            Some(PersistenceTriggerReason::Interval)
+        } else {
+            None
+        }
+    }
+
+    /// the reason to persist the store if the count or the size condition fires.
+    pub fn size_fired(
+        &self,
+        unpersisted_num: usize,
+        unpersisted_bytes: usize,
+    ) -> Option<PersistenceTriggerReason> {
+        if matches!(self.max_datapoints, Some(max_datapoints) if unpersisted_num >= max_datapoints.max(1))
+        {
+            Some(PersistenceTriggerReason::DatapointNum)
+        } else if unpersisted_num > 0
+            && matches!(self.max_bytes, Some(max_bytes) if unpersisted_bytes >= max_bytes)
+        {
+            Some(PersistenceTriggerReason::Bytes)
+        } else {
+            None
+        }
+    }
+
+    /// how long to wait until the next check. the count and the size are signalled on pushing instead.
+    fn next_check_wait(&self, since_last_persistence: Duration) -> Duration {
+        let until_interval = self
+            .interval
+            .map(|interval| interval - since_last_persistence);
+        let wait = if self.max_age.is_some() {
+            until_interval.map_or(self.check_interval, |until_interval| {
+                until_interval.min(self.check_interval)
+            })
+        } else {
+            until_interval.unwrap_or(self.check_interval)
+        };
+        wait.max(Duration::milliseconds(1))
+    }
+}
+
+/// the datapoints not persisted yet, that the triggers are checked with.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UnpersistedStatus {
+    pub datapoint_num: usize,
+    pub bytes: usize,
+    /// when the oldest one was pushed
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// wakes the persistence task up when the datapoints pushed cross the count or the size of the trigger,
+/// not to wait for the next check.
+#[derive(Debug, Clone)]
+pub struct PersistenceSignal {
+    trigger: PersistenceTrigger,
+    notify: Arc<Notify>,
+}
+
+impl PersistenceSignal {
+    fn new(trigger: PersistenceTrigger) -> Self {
+        Self {
+            trigger,
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    pub(crate) fn notify_if_fired(&self, unpersisted_num: usize, unpersisted_bytes: usize) {
+        if self
+            .trigger
+            .size_fired(unpersisted_num, unpersisted_bytes)
+            .is_some()
+        {
+            // the permit is kept if the task is persisting, so it checks again right after that
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// the store that the persistence tasks run on.
+#[async_trait]
+pub trait PersistentStore: Send + Sync + 'static {
+    async fn store_metrics(&self) -> Metrics;
+    /// the store signals it on pushing.
+    async fn set_persistence_signal(&self, signal: PersistenceSignal);
+    async fn persist_store(&self, condition: PersistCondition) -> Result<Option<()>>;
+    async fn unpersisted_status(&self) -> UnpersistedStatus;
+    async fn sync_store_wal_due(&self) -> Result<()>;
+    async fn scavange_store(&self) -> Result<()>;
+}
+
+#[async_trait]
+impl<S, Wal> PersistentStore for Mutex<WritableStore<S, Wal>>
+where
+    S: DatapointSorter + 'static,
+    Wal: WalWriter + 'static,
+{
+    async fn store_metrics(&self) -> Metrics {
+        self.lock().await.metrics.clone()
+    }
+
+    async fn set_persistence_signal(&self, signal: PersistenceSignal) {
+        self.lock().await.persistence_signal = Some(signal);
+    }
+
+    async fn persist_store(&self, condition: PersistCondition) -> Result<Option<()>> {
+        self.lock().await.persist(condition).await
+    }
+
+    async fn unpersisted_status(&self) -> UnpersistedStatus {
+        let store = self.lock().await;
+        UnpersistedStatus {
+            datapoint_num: store.unpersisted_datapoint_num(),
+            bytes: store.unpersisted_bytes(),
+            since: store.unpersisted_since(),
+        }
+    }
+
+    async fn sync_store_wal_due(&self) -> Result<()> {
+        self.lock().await.sync_wal_due()
+    }
+
+    async fn scavange_store(&self) -> Result<()> {
+        self.lock().await.scavange_on_shutdown().await
+    }
+}
+
 pub struct PeriodicallyPeristenceShutdown {
     shutdown_tx: mpsc::Sender<DateTime<Utc>>,
     join_handle: task::JoinHandle<()>,
 }
 
 impl PeriodicallyPeristenceShutdown {
+    /// the datapoints until now are persisted and the lock files are removed before the task ends.
+    /// the datapoints in the future are left in the store and the wal. use `WritableStore::graceful_shutdown` to flush them as well.
     pub async fn shutdown_and_wait(self) -> Result<()> {
         self.shutdown_tx.send(chrono::Utc::now()).await?;
         self.join_handle.await?;
@@ -129,3 +330,154 @@ pub fn start_periodically_persistence<S: DatapointSorter + 'static, Wal: WalWrit
         join_handle,
     }
 }
+
+/// persists the store whenever any of the conditions of the trigger fires.
+pub fn start_triggered_persistence<P: PersistentStore>(
+    store: Arc<P>,
+    trigger: PersistenceTrigger,
+    remove_from_store_after_persisted: bool,
+) -> PeriodicallyPeristenceShutdown {
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<DateTime<Utc>>(1);
+    let join_handle = task::spawn(async move {
+        let signal = PersistenceSignal::new(trigger.clone());
+        store.set_persistence_signal(signal.clone()).await;
+        // the datapoints may have been pushed before the signal is set
+        let unpersisted = store.unpersisted_status().await;
+        signal.notify_if_fired(unpersisted.datapoint_num, unpersisted.bytes);
+
+        let mut last_persisted_at = Utc::now();
+        loop {
+            let wait = trigger
+                .next_check_wait(Utc::now() - last_persisted_at)
+                .to_std()
+                .unwrap();
+            let shutdown_requested = tokio::select! {
+                _ = shutdown_rx.recv() => true,
+                _ = signal.notify.notified() => false,
+                _ = time::sleep(wait) => false,
+            };
+
+            let condition = PersistCondition {
+                datapoint_search_condition: DatapointsRange::new(
+                    None,
+                    Some(TimestampNano::now() + Duration::nanoseconds(1)),
+                ),
+                remove_from_store_after_persisted,
+            };
+
+            if shutdown_requested {
+                log::info!("breaking the triggered persistence loop");
+                if let Err(e) = store.persist_store(condition).await {
+                    log::error!("store persisted error:{e}");
+                }
+                if let Err(e) = store.scavange_store().await {
+                    log::error!(" scavenge on shutdown failed: {e}");
+                }
+                break;
+            }
+
+            let now = Utc::now();
+            let unpersisted = store.unpersisted_status().await;
+            let reason = trigger.fired(
+                now - last_persisted_at,
+                unpersisted.datapoint_num,
+                unpersisted.bytes,
+                unpersisted
+                    .since
+                    .map(|unpersisted_since| now - unpersisted_since),
+            );
+            if let Some(reason) = reason {
+                log::debug!(
+                    "start triggered persistence. metrics:{}, reason:{reason:?}",
+                    store.store_metrics().await
+                );
+                if let Err(e) = store.persist_store(condition).await {
+                    log::error!("store persisted error:{e}");
+                }
+                last_persisted_at = Utc::now();
+            }
+        }
+    });
+
+    PeriodicallyPeristenceShutdown {
+        shutdown_tx,
+        join_handle,
+    }
+}
+
+/// syncs the records of the wal left unsynced by the sync policy on every tick,
+/// so that they are not kept unsynced until the next write. the interval should not exceed the one of `WalSyncPolicy::Interval`.
+pub fn start_wal_sync<P: PersistentStore>(
+    store: Arc<P>,
+    tick: Duration,
+) -> PeriodicallyPeristenceShutdown {
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<DateTime<Utc>>(1);
+    let tick = tick.to_std().unwrap();
+    let join_handle = task::spawn(async move {
+        loop {
+            let waiting_shutdown = time::timeout(tick, shutdown_rx.recv()).await;
+            if let Err(e) = store.sync_store_wal_due().await {
+                log::error!(
+                    "failed to sync the wal of {}: {e}",
+                    store.store_metrics().await
+                );
+            }
+            if waiting_shutdown.is_ok() {
+                log::info!("breaking the wal sync loop");
+                break;
+            }
+        }
+    });
+
+    PeriodicallyPeristenceShutdown {
+        shutdown_tx,
+        join_handle,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_persistence_trigger() {
+        let trigger = PersistenceTrigger::default()
+            .with_interval(Duration::seconds(60))
+            .with_max_datapoints(100)
+            .with_max_bytes(1000)
+            .with_max_age(Duration::seconds(10));
+
+        assert_eq!(trigger.fired(Duration::seconds(1), 0, 0, None), None);
+        assert_eq!(
+            trigger.fired(Duration::seconds(1), 100, 10, Some(Duration::seconds(1))),
+            Some(PersistenceTriggerReason::DatapointNum)
+        );
+        assert_eq!(
+            trigger.fired(Duration::seconds(1), 10, 1000, Some(Duration::seconds(1))),
+            Some(PersistenceTriggerReason::Bytes)
+        );
+        assert_eq!(
+            trigger.fired(Duration::seconds(1), 10, 10, Some(Duration::seconds(10))),
+            Some(PersistenceTriggerReason::Age)
+        );
+        assert_eq!(
+            trigger.fired(Duration::seconds(60), 0, 0, None),
+            Some(PersistenceTriggerReason::Interval)
+        );
+
+        assert_eq!(
+            trigger.next_check_wait(Duration::seconds(1)),
+            Duration::seconds(1)
+        );
+        assert_eq!(
+            trigger.next_check_wait(Duration::milliseconds(59_500)),
+            Duration::milliseconds(500)
+        );
+        assert_eq!(
+            PersistenceTrigger::default()
+                .with_interval(Duration::seconds(60))
+                .next_check_wait(Duration::seconds(1)),
+            Duration::seconds(59)
+        );
+    }
+}
@@ -0,0 +1,938 @@
+use super::{
+    estimated_bytes, merge_dirties, start_triggered_persistence, start_wal_sync, storage_api,
+    DatapointSorter, HighWaterMark, HighWaterMarkAction, PeriodicallyPeristenceShutdown,
+    PersistCondition, Persistence, PersistenceSignal, PersistenceTrigger, PersistentStore, Result,
+    StoreError, UnpersistedStatus, HIGH_WATER_MARK_RETRY_INTERVAL,
+};
+use crate::tsdb::datapoint::*;
+use crate::tsdb::datapoints_searcher::*;
+use crate::tsdb::duplicate_policy::{DuplicatePolicy, DuplicatedTimestampError};
+use crate::tsdb::field::*;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::storage::wal::{WalCheckpoint, WalWriter};
+use crate::tsdb::timestamp_nano::TimestampNano;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex as SyncMutex, RwLock};
+use tokio::sync::{oneshot, Mutex, MutexGuard, Notify};
+use uuid::Uuid;
+
+/// the datapoints waiting to be written to the wal, and where the result of the write is sent to.
+pub(super) struct QueuedWrite {
+    datapoints: Vec<DataPoint>,
+    written_tx: oneshot::Sender<std::result::Result<(), String>>,
+}
+
+/// the store for the concurrent ingestion.
+/// the pushed datapoints are appended to one of the shard buffers, and merged into the sorted snapshot on refresh.
+/// the readers hold the snapshot that never changes, so neither the readers nor the merge block the writers.
+/// the snapshot is updated in place unless any reader holds it.
+/// the writes are queued and the writer that gets the wal first writes and syncs all of them at once,
+/// so the concurrent writers share a sync instead of waiting for each other's.
+pub struct ShardedWritableStore<S: DatapointSorter + 'static, Wal: WalWriter> {
+    pub(super) store_id: Uuid,
+    pub(super) metrics: Metrics,
+    pub(super) field_types: Vec<FieldType>,
+    pub(super) convert_dirty_to_sorted_on_read: bool,
+
+    pub(super) shards: Vec<SyncMutex<Vec<DataPoint>>>,
+    pub(super) next_shard: AtomicUsize,
+    /// updated with the shard locked
+    pub(super) shard_datapoint_num: AtomicUsize,
+    pub(super) shard_bytes: AtomicUsize,
+    pub(super) snapshot: RwLock<Arc<Vec<DataPoint>>>,
+    pub(super) snapshot_bytes: AtomicUsize,
+
+    /// held while the snapshot is rebuilt so that the merges are not interleaved
+    pub(super) sorter: Mutex<S>,
+    /// held while the store is persisted or purged so that they are not interleaved.
+    /// the sorter is not held while the datapoints are written to the storage.
+    pub(super) persistence_lock: Mutex<()>,
+    pub(super) persistence: Persistence,
+    pub(super) wal: Mutex<Wal>,
+    pub(super) wal_queue: SyncMutex<Vec<QueuedWrite>>,
+    pub(super) duplicate_policy: DuplicatePolicy,
+    pub(super) high_water_mark: Option<HighWaterMark>,
+    /// notified when the datapoints are removed from the store
+    pub(super) released: Notify,
+    /// set by the triggered persistence task
+    pub(super) persistence_signal: SyncMutex<Option<PersistenceSignal>>,
+    /// updated with the wal locked
+    pub(super) unpersisted: SyncMutex<UnpersistedStatus>,
+}
+
+impl<S, Wal> ShardedWritableStore<S, Wal>
+where
+    S: DatapointSorter + 'static,
+    Wal: WalWriter + 'static,
+{
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn field_types(&self) -> &[FieldType] {
+        &self.field_types
+    }
+
+    pub fn shard_num(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// the number of the datapoints in the snapshot and in the shards.
+    pub fn datapoint_num(&self) -> usize {
+        self.snapshot().len() + self.shard_datapoint_num.load(AtomicOrdering::Relaxed)
+    }
+
+    /// the estimated size of the datapoints on memory.
+    pub fn buffered_bytes(&self) -> usize {
+        self.snapshot_bytes.load(AtomicOrdering::Relaxed)
+            + self.shard_bytes.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn unpersisted_status(&self) -> UnpersistedStatus {
+        self.unpersisted.lock().unwrap().clone()
+    }
+
+    /// with the `Error` duplicate policy, the pushes are serialized with the merges to detect the duplicates.
+    pub async fn push_multi(&self, data_points: Vec<DataPoint>) -> Result<()> {
+        #[cfg(feature = "trace-log")]
+        log::trace!("push multi data: {data_points:?}");
+
+        #[cfg(feature = "validate")]
+        super::validate_field_types(&self.field_types, &data_points)?;
+
+        if let Some(high_water_mark) = self.high_water_mark.as_ref() {
+            self.release_for_high_water_mark(
+                high_water_mark,
+                data_points.len(),
+                estimated_bytes(&data_points),
+            )
+            .await?;
+        }
+
+        let _sorter = if self.duplicate_policy == DuplicatePolicy::Error {
+            let sorter = self.sorter.lock().await;
+            self.check_duplicated_timestamps(&data_points)?;
+            Some(sorter)
+        } else {
+            None
+        };
+
+        let (written_tx, written_rx) = oneshot::channel();
+        self.wal_queue.lock().unwrap().push(QueuedWrite {
+            datapoints: data_points,
+            written_tx,
+        });
+        self.write_queued().await;
+        match written_rx.await {
+            Ok(written) => written.map_err(StoreError::WalGroupCommitError),
+            Err(e) => Err(StoreError::WalGroupCommitError(format!("{e}"))),
+        }
+    }
+
+    /// writes all the queued datapoints to the wal at once. nothing is left if the other writer has written them.
+    /// the datapoints reach the shard before the wal is released,
+    /// so that every datapoint in the sealed wal records is in a shard or in the snapshot.
+    async fn write_queued(&self) {
+        let mut wal = self.wal.lock().await;
+        let queued = std::mem::take(&mut *self.wal_queue.lock().unwrap());
+        if queued.is_empty() {
+            return;
+        }
+
+        let mut datapoints = Vec::<DataPoint>::new();
+        let mut written_txs = Vec::with_capacity(queued.len());
+        for QueuedWrite {
+            datapoints: mut queued_datapoints,
+            written_tx,
+        } in queued
+        {
+            datapoints.append(&mut queued_datapoints);
+            written_txs.push(written_tx);
+        }
+
+        let written = match wal.write(&datapoints).await {
+            Ok(()) => {
+                let pushed_num = datapoints.len();
+                let pushed_bytes = estimated_bytes(&datapoints);
+                let shard_idx =
+                    self.next_shard.fetch_add(1, AtomicOrdering::Relaxed) % self.shards.len();
+                {
+                    let mut shard = self.shards[shard_idx].lock().unwrap();
+                    shard.append(&mut datapoints);
+                    self.shard_datapoint_num
+                        .fetch_add(pushed_num, AtomicOrdering::Relaxed);
+                    self.shard_bytes
+                        .fetch_add(pushed_bytes, AtomicOrdering::Relaxed);
+                }
+
+                let mut unpersisted = self.unpersisted.lock().unwrap();
+                unpersisted.datapoint_num += pushed_num;
+                unpersisted.bytes += pushed_bytes;
+                unpersisted.since.get_or_insert_with(Utc::now);
+                if let Some(persistence_signal) = self.persistence_signal.lock().unwrap().as_ref() {
+                    persistence_signal
+                        .notify_if_fired(unpersisted.datapoint_num, unpersisted.bytes);
+                }
+                Ok(())
+            }
+            Err(e) => Err(format!("{e}")),
+        };
+        for written_tx in written_txs {
+            // the writer may have stopped waiting
+            let _ = written_tx.send(written.clone());
+        }
+    }
+
+    /// persists the datapoints early if pushing the datapoints crosses the high-water mark.
+    /// no lock is held while waiting for the release, so the other writers and the persistence go on meanwhile.
+    /// the datapoints are accepted anyway if the store is empty, since no room can be made for them.
+    async fn release_for_high_water_mark(
+        &self,
+        high_water_mark: &HighWaterMark,
+        pushing_num: usize,
+        pushing_bytes: usize,
+    ) -> Result<()> {
+        let is_exceeded = || {
+            let datapoint_num = self.datapoint_num();
+            datapoint_num > 0
+                && high_water_mark.is_exceeded(
+                    datapoint_num + pushing_num,
+                    self.buffered_bytes() + pushing_bytes,
+                )
+        };
+        if !is_exceeded() {
+            return Ok(());
+        }
+
+        let started_at = std::time::Instant::now();
+        loop {
+            let notified = self.released.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if matches!(self.persistence, Persistence::Storage(..)) {
+                log::info!(
+                    "persisting early on the high-water mark. metrics:{}, datapoints:{}, bytes:{}",
+                    self.metrics,
+                    self.datapoint_num(),
+                    self.buffered_bytes()
+                );
+                let condition = PersistCondition::new(
+                    DatapointsRange::new(
+                        None,
+                        Some(TimestampNano::now() + Duration::nanoseconds(1)),
+                    ),
+                    true,
+                );
+                if let Err(e) = self.persist(condition).await {
+                    log::warn!("early persistence on the high-water mark failed: {e}");
+                }
+            }
+            if !is_exceeded() {
+                return Ok(());
+            }
+
+            let wait = match high_water_mark.action {
+                HighWaterMarkAction::Wait(max_wait) => max_wait.checked_sub(started_at.elapsed()),
+                HighWaterMarkAction::Reject => None,
+            };
+            match wait {
+                Some(wait) if !wait.is_zero() => {
+                    let _ =
+                        tokio::time::timeout(wait.min(HIGH_WATER_MARK_RETRY_INTERVAL), notified)
+                            .await;
+                }
+                _ => {
+                    return Err(StoreError::HighWaterMarkExceeded(format!(
+                        "metrics:{}, datapoints:{}, bytes:{}, pushing datapoints:{pushing_num}",
+                        self.metrics,
+                        self.datapoint_num(),
+                        self.buffered_bytes()
+                    )))
+                }
+            }
+        }
+    }
+
+    /// returns error if any of the timestamps of the datapoints already exists in the store or in the datapoints.
+    /// must be called with the sorter locked so that the shards are not drained meanwhile.
+    fn check_duplicated_timestamps(&self, data_points: &[DataPoint]) -> Result<()> {
+        let mut timestamps: Vec<TimestampNano> = data_points
+            .iter()
+            .map(|data_point| data_point.timestamp_nano)
+            .collect();
+        timestamps.sort();
+        DuplicatePolicy::Error.indices_to_keep(&timestamps)?;
+
+        let snapshot = self.snapshot();
+        for timestamp in timestamps.iter() {
+            if snapshot
+                .binary_search_by_key(timestamp, |datapoint| datapoint.timestamp_nano)
+                .is_ok()
+            {
+                return Err(DuplicatedTimestampError(*timestamp).into());
+            }
+        }
+        for shard in self.shards.iter() {
+            if let Some(duplicated) = shard
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|datapoint| timestamps.binary_search(&datapoint.timestamp_nano).is_ok())
+            {
+                return Err(DuplicatedTimestampError(duplicated.timestamp_nano).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// the latest snapshot. the datapoints pushed after the last refresh are not in it.
+    pub fn snapshot(&self) -> Arc<Vec<DataPoint>> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// the sorted datapoints. the pending datapoints are merged beforehand if `convert_dirty_to_sorted_on_read` is set.
+    pub async fn datapoints(&self) -> Result<Arc<Vec<DataPoint>>> {
+        if self.convert_dirty_to_sorted_on_read {
+            self.refresh().await
+        } else {
+            Ok(self.snapshot())
+        }
+    }
+
+    /// merges the datapoints in the shards into a new snapshot.
+    pub async fn refresh(&self) -> Result<Arc<Vec<DataPoint>>> {
+        let mut sorter = self.sorter.lock().await;
+        let dirty_datapoints = self.drain_shards();
+        self.merge_into_snapshot(dirty_datapoints, &mut sorter)
+    }
+
+    fn drain_shards(&self) -> Vec<DataPoint> {
+        let mut dirty_datapoints = Vec::<DataPoint>::new();
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().unwrap();
+            let mut drained = std::mem::take(&mut *shard);
+            self.shard_datapoint_num
+                .fetch_sub(drained.len(), AtomicOrdering::Relaxed);
+            self.shard_bytes
+                .fetch_sub(estimated_bytes(&drained), AtomicOrdering::Relaxed);
+            dirty_datapoints.append(&mut drained);
+        }
+        dirty_datapoints
+    }
+
+    /// seals the wal and drains the shards at once, so that all the datapoints in the sealed records are merged.
+    async fn seal_and_drain_shards(&self) -> Result<(WalCheckpoint, Vec<DataPoint>)> {
+        let mut wal = self.wal.lock().await;
+        Ok((wal.seal()?, self.drain_shards()))
+    }
+
+    /// must be called with the sorter locked.
+    /// only the datapoints of the snapshot at or after the earliest dirty datapoint are merged and deduplicated.
+    fn merge_into_snapshot(
+        &self,
+        mut dirty_datapoints: Vec<DataPoint>,
+        sorter: &mut S,
+    ) -> Result<Arc<Vec<DataPoint>>> {
+        let earliest = match dirty_datapoints
+            .iter()
+            .map(|datapoint| datapoint.timestamp_nano)
+            .min()
+        {
+            Some(earliest) => earliest,
+            None => return Ok(self.snapshot()),
+        };
+
+        let duplicate_policy = self.duplicate_policy;
+        let (snapshot, (tail_bytes, merged_tail_bytes)) =
+            self.modify_snapshot(|sorted_datapoints| {
+                let from = sorted_datapoints
+                    .partition_point(|datapoint| datapoint.timestamp_nano < earliest);
+                let mut tail = sorted_datapoints.split_off(from);
+                let tail_bytes = estimated_bytes(&tail);
+                let merged =
+                    merge_dirties(&mut tail, &mut dirty_datapoints, sorter, duplicate_policy);
+                let merged_tail_bytes = estimated_bytes(&tail);
+                sorted_datapoints.append(&mut tail);
+                merged.map(|()| (tail_bytes, merged_tail_bytes))
+            })?;
+        self.snapshot_bytes
+            .fetch_add(merged_tail_bytes, AtomicOrdering::Relaxed);
+        self.snapshot_bytes
+            .fetch_sub(tail_bytes, AtomicOrdering::Relaxed);
+        Ok(snapshot)
+    }
+
+    /// modifies the snapshot in place, or a copy of it if any reader holds it.
+    /// must be called with the sorter locked.
+    fn modify_snapshot<T>(
+        &self,
+        modify: impl FnOnce(&mut Vec<DataPoint>) -> Result<T>,
+    ) -> Result<(Arc<Vec<DataPoint>>, T)> {
+        let mut snapshot = self.snapshot.write().unwrap();
+        let modified = modify(Arc::make_mut(&mut snapshot))?;
+        Ok((snapshot.clone(), modified))
+    }
+
+    /// removes the datapoints at the sorted indices from the snapshot.
+    /// must be called with the sorter locked.
+    fn remove_from_snapshot(&self, indices: &[usize]) -> Result<Arc<Vec<DataPoint>>> {
+        let (snapshot, removed_bytes) = self.modify_snapshot(|sorted_datapoints| {
+            let removed_bytes = indices
+                .iter()
+                .map(|idx| estimated_bytes(std::slice::from_ref(&sorted_datapoints[*idx])))
+                .sum::<usize>();
+            let mut indices = indices.iter().peekable();
+            let mut idx = 0;
+            sorted_datapoints.retain(|_| {
+                let remove = indices.peek() == Some(&&idx);
+                if remove {
+                    indices.next();
+                }
+                idx += 1;
+                !remove
+            });
+            Ok(removed_bytes)
+        })?;
+        self.snapshot_bytes
+            .fetch_sub(removed_bytes, AtomicOrdering::Relaxed);
+        Ok(snapshot)
+    }
+
+    /// resets the status with the datapoints in the snapshot written to the wal again and the ones in the shards.
+    /// must be called with the wal locked so that no datapoints are pushed meanwhile.
+    fn reset_unpersisted(
+        &self,
+        unpersisted_num_in_snapshot: usize,
+        unpersisted_bytes_in_snapshot: usize,
+        since: Option<DateTime<Utc>>,
+    ) {
+        let datapoint_num =
+            unpersisted_num_in_snapshot + self.shard_datapoint_num.load(AtomicOrdering::Relaxed);
+        let bytes = unpersisted_bytes_in_snapshot + self.shard_bytes.load(AtomicOrdering::Relaxed);
+        *self.unpersisted.lock().unwrap() = UnpersistedStatus {
+            datapoint_num,
+            bytes,
+            since: if datapoint_num == 0 {
+                None
+            } else {
+                since.or_else(|| Some(Utc::now()))
+            },
+        };
+    }
+
+    /// the number and the bytes of the datapoints in the snapshot. must be called with the sorter locked.
+    fn snapshot_status(&self) -> (usize, usize) {
+        (
+            self.snapshot().len(),
+            self.snapshot_bytes.load(AtomicOrdering::Relaxed),
+        )
+    }
+
+    /// locks the persistence until the guard is dropped. see `purge_locked`.
+    pub async fn lock_persistence(&self) -> MutexGuard<'_, ()> {
+        self.persistence_lock.lock().await
+    }
+
+    /// removes the datapoints in the range, then rewrites the wal with the datapoints left
+    /// so that the removed ones are never replayed.
+    pub async fn purge(&self, datapoint_search_condition: DatapointsRange) -> Result<()> {
+        let persistence = self.lock_persistence().await;
+        self.purge_locked(&persistence, datapoint_search_condition)
+            .await
+    }
+
+    /// `purge` with the persistence locked by `lock_persistence`.
+    pub async fn purge_locked(
+        &self,
+        _persistence: &MutexGuard<'_, ()>,
+        datapoint_search_condition: DatapointsRange,
+    ) -> Result<()> {
+        let mut sorter = self.sorter.lock().await;
+        // the records sealed are removed by the next checkpoint if nothing is purged
+        let (checkpoint, dirty_datapoints) = self.seal_and_drain_shards().await?;
+        let snapshot = self.merge_into_snapshot(dirty_datapoints, &mut sorter)?;
+
+        let indices = DatapointSearcher::new(&snapshot)
+            .search_with_indices(&datapoint_search_condition)
+            .await
+            .map(|(_, indices)| indices);
+        if let Some((start, end)) = indices {
+            drop(snapshot);
+            let indices: Vec<usize> = (start..=end).collect();
+            let snapshot = self.remove_from_snapshot(&indices)?;
+            self.released.notify_waiters();
+
+            let mut wal = self.wal.lock().await;
+            wal.write(&snapshot).await?;
+            wal.checkpoint(checkpoint)?;
+            let since = self.unpersisted.lock().unwrap().since;
+            let (num, bytes) = self.snapshot_status();
+            self.reset_unpersisted(num, bytes, since);
+        }
+        Ok(())
+    }
+
+    /// persist on disk and to cloud. the writers are blocked only while the wal is sealed,
+    /// and neither the readers nor the merges are blocked while the datapoints are written to the storage.
+    pub async fn persist(&self, condition: PersistCondition) -> Result<Option<()>> {
+        let (db_dir, cloud_storage_and_setting) = match self.persistence.clone() {
+            Persistence::Storage(db_dir, cloud_storage_and_setting) => {
+                (db_dir, cloud_storage_and_setting)
+            }
+            Persistence::OnMemory => return Err(StoreError::NoPersistenceSettingError),
+        };
+
+        let _persistence = self.lock_persistence().await;
+        let (checkpoint, snapshot, (start, end)) = {
+            let mut sorter = self.sorter.lock().await;
+            let (checkpoint, dirty_datapoints) = self.seal_and_drain_shards().await?;
+            let snapshot = self.merge_into_snapshot(dirty_datapoints, &mut sorter)?;
+
+            let indices = DatapointSearcher::new(&snapshot)
+                .search_with_indices(&condition.datapoint_search_condition)
+                .await
+                .map(|(_, indices)| indices);
+            match indices {
+                Some(indices) => (checkpoint, snapshot, indices),
+                None => {
+                    // nothing is persisted. the datapoints are regarded as pushed now as well
+                    let _wal = self.wal.lock().await;
+                    let (num, bytes) = self.snapshot_status();
+                    self.reset_unpersisted(num, bytes, Some(Utc::now()));
+                    return Ok(None);
+                }
+            }
+        };
+
+        let persisted_datapoints = &snapshot[start..=end];
+        storage_api::write::write_datas(
+            db_dir,
+            &self.store_id,
+            &self.metrics,
+            persisted_datapoints,
+            cloud_storage_and_setting
+                .as_ref()
+                .map(|(cloud_strorage, cloud_setting)| (cloud_strorage, cloud_setting)),
+        )
+        .await?;
+
+        // the datapoints pushed after the seal are already in the wal records after the checkpoint
+        let mut unpersisted_datapoints = snapshot[..start].to_vec();
+        unpersisted_datapoints.extend_from_slice(&snapshot[end + 1..]);
+
+        // the snapshot may have got the other datapoints merged while persisting
+        let _sorter = self.sorter.lock().await;
+        let current_snapshot = self.snapshot();
+        let persisted_indices = persisted_indices(&current_snapshot, persisted_datapoints);
+        let (num, bytes) = if condition.remove_from_store_after_persisted {
+            // not to copy the snapshot on removing if no reader holds it
+            drop(snapshot);
+            drop(current_snapshot);
+            self.remove_from_snapshot(&persisted_indices)?;
+            self.released.notify_waiters();
+            self.snapshot_status()
+        } else {
+            let (num, bytes) = self.snapshot_status();
+            let persisted_bytes = persisted_indices
+                .iter()
+                .map(|idx| estimated_bytes(std::slice::from_ref(&current_snapshot[*idx])))
+                .sum::<usize>();
+            (num - persisted_indices.len(), bytes - persisted_bytes)
+        };
+
+        let mut wal = self.wal.lock().await;
+        wal.write(&unpersisted_datapoints).await?;
+        wal.checkpoint(checkpoint)?;
+        // the datapoints left are regarded as pushed now not to fire the age trigger again right away
+        self.reset_unpersisted(num, bytes, Some(Utc::now()));
+        Ok(Some(()))
+    }
+
+    /// persists the store when any of the conditions of the trigger fires first.
+    pub fn start_triggered_persist(
+        store: Arc<Self>,
+        trigger: PersistenceTrigger,
+        clear_after_persisted: bool,
+    ) -> PeriodicallyPeristenceShutdown {
+        start_triggered_persistence(store, trigger, clear_after_persisted)
+    }
+
+    /// syncs the wal on every tick. see `start_wal_sync`.
+    pub fn start_wal_sync(store: Arc<Self>, tick: Duration) -> PeriodicallyPeristenceShutdown {
+        start_wal_sync(store, tick)
+    }
+
+    pub async fn scavange_on_shutdown(&self) -> Result<()> {
+        if let Persistence::Storage(db_dir, cloud_storage_and_setting) = self.persistence.clone() {
+            storage_api::write::remove_local_lock_file_if_same_writer(
+                db_dir,
+                &self.store_id,
+                &self.metrics,
+            )
+            .await?;
+
+            storage_api::write::remove_cloud_lock_file_if_same_writer(
+                &self.store_id,
+                &self.metrics,
+                cloud_storage_and_setting
+                    .as_ref()
+                    .map(|(cloud_strorage, cloud_setting)| (cloud_strorage, cloud_setting)),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// the indices of the persisted datapoints in the sorted datapoints that may have got the other datapoints merged after persisted.
+/// the persisted datapoints that were dropped as duplicates meanwhile are skipped.
+fn persisted_indices(
+    sorted_datapoints: &[DataPoint],
+    persisted_datapoints: &[DataPoint],
+) -> Vec<usize> {
+    let from = match persisted_datapoints.first() {
+        Some(first) => sorted_datapoints
+            .partition_point(|datapoint| datapoint.timestamp_nano < first.timestamp_nano),
+        None => return vec![],
+    };
+
+    let mut indices = Vec::<usize>::new();
+    let mut persisted_datapoints = persisted_datapoints.iter().peekable();
+    for (idx, datapoint) in sorted_datapoints.iter().enumerate().skip(from) {
+        while persisted_datapoints
+            .next_if(|persisted| persisted.timestamp_nano < datapoint.timestamp_nano)
+            .is_some()
+        {}
+        match persisted_datapoints.peek() {
+            None => break,
+            Some(persisted) if is_same_datapoint(persisted, datapoint) => {
+                indices.push(idx);
+                persisted_datapoints.next();
+            }
+            Some(_) => {}
+        }
+    }
+    indices
+}
+
+/// same as `==` except that a NaN equals to itself.
+fn is_same_datapoint(l: &DataPoint, r: &DataPoint) -> bool {
+    l.timestamp_nano == r.timestamp_nano
+        && l.field_values.len() == r.field_values.len()
+        && l.field_values
+            .iter()
+            .zip(r.field_values.iter())
+            .all(|(l, r)| match (l, r) {
+                (FieldValue::Float64(l), FieldValue::Float64(r)) => l.to_bits() == r.to_bits(),
+                (l, r) => l == r,
+            })
+}
+
+#[async_trait]
+impl<S, Wal> PersistentStore for ShardedWritableStore<S, Wal>
+where
+    S: DatapointSorter + 'static,
+    Wal: WalWriter + 'static,
+{
+    async fn store_metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    async fn set_persistence_signal(&self, signal: PersistenceSignal) {
+        *self.persistence_signal.lock().unwrap() = Some(signal);
+    }
+
+    async fn persist_store(&self, condition: PersistCondition) -> Result<Option<()>> {
+        self.persist(condition).await
+    }
+
+    async fn unpersisted_status(&self) -> UnpersistedStatus {
+        ShardedWritableStore::unpersisted_status(self)
+    }
+
+    async fn sync_store_wal_due(&self) -> Result<()> {
+        Ok(self.wal.lock().await.sync_due()?)
+    }
+
+    async fn scavange_store(&self) -> Result<()> {
+        self.scavange_on_shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::WritableStore;
+    use super::*;
+    use crate::tsdb::storage::wal::{EmptyWal, SegmentedWal};
+    use crate::tsdb::TimestampNano;
+
+    fn datapoint(sec: u64) -> DataPoint {
+        DataPoint::new(
+            TimestampNano::new(sec * 1_000_000_000),
+            vec![FieldValue::Float64(sec as f64)],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_push_and_snapshot() {
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .build_sharded(4)
+        .await
+        .unwrap();
+
+        let mut handles = vec![];
+        for writer in 0..8u64 {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                for i in 0..10u64 {
+                    store
+                        .push_multi(vec![datapoint(i * 8 + writer)])
+                        .await
+                        .unwrap();
+                }
+            }));
+        }
+
+        // a snapshot taken before the refresh does not change
+        let before = store.snapshot();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        let after = store.refresh().await.unwrap();
+        assert!(before.is_empty());
+        assert_eq!(*after, (0..80).map(datapoint).collect::<Vec<DataPoint>>());
+
+        store.push_multi(vec![datapoint(100)]).await.unwrap();
+        assert_eq!(store.snapshot().len(), 80);
+        assert_eq!(store.datapoints().await.unwrap().len(), 81);
+
+        store
+            .purge(DatapointsRange::until(TimestampNano::new(
+                50 * 1_000_000_000,
+            )))
+            .await
+            .unwrap();
+        assert_eq!(store.snapshot().first(), Some(&datapoint(50)));
+    }
+
+    #[tokio::test]
+    async fn test_merge_into_snapshot_in_place() {
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .duplicate_policy(DuplicatePolicy::KeepLast)
+        .build_sharded(2)
+        .await
+        .unwrap();
+
+        store
+            .push_multi(vec![datapoint(1), datapoint(3)])
+            .await
+            .unwrap();
+        let snapshot = store.refresh().await.unwrap();
+        let snapshot_ptr = Arc::as_ptr(&snapshot);
+        drop(snapshot);
+
+        // no reader holds the snapshot
+        store
+            .push_multi(vec![datapoint(4), datapoint(2)])
+            .await
+            .unwrap();
+        let snapshot = store.refresh().await.unwrap();
+        assert_eq!(Arc::as_ptr(&snapshot), snapshot_ptr);
+        assert_eq!(
+            *snapshot,
+            vec![datapoint(1), datapoint(2), datapoint(3), datapoint(4)]
+        );
+
+        // the snapshot held does not change
+        let mut replaced = datapoint(3);
+        replaced.field_values = vec![FieldValue::Float64(0f64)];
+        store.push_multi(vec![replaced.clone()]).await.unwrap();
+        let merged = store.refresh().await.unwrap();
+        assert_eq!(snapshot.len(), 4);
+        assert_eq!(snapshot[2], datapoint(3));
+        assert_eq!(
+            *merged,
+            vec![datapoint(1), datapoint(2), replaced, datapoint(4)]
+        );
+        assert_eq!(store.buffered_bytes(), estimated_bytes(&merged[..]));
+    }
+
+    #[test]
+    fn test_persisted_indices() {
+        let persisted = vec![datapoint(1), datapoint(2), datapoint(2), datapoint(3)];
+        let mut merged = datapoint(2);
+        merged.field_values = vec![FieldValue::Float64(0f64)];
+
+        // the datapoints merged after persisted
+        let sorted = vec![
+            datapoint(0),
+            datapoint(1),
+            datapoint(2),
+            datapoint(2),
+            merged.clone(),
+            datapoint(3),
+            datapoint(4),
+        ];
+        assert_eq!(persisted_indices(&sorted, &persisted), vec![1, 2, 3, 5]);
+
+        // the persisted ones dropped as the duplicates
+        let sorted = vec![datapoint(1), merged, datapoint(3)];
+        assert_eq!(persisted_indices(&sorted, &persisted), vec![0, 2]);
+
+        let mut nan = datapoint(5);
+        nan.field_values = vec![FieldValue::Float64(f64::NAN)];
+        assert_eq!(
+            persisted_indices(&[nan.clone()], std::slice::from_ref(&nan)),
+            vec![0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_persist_sharded_store() {
+        let temp_data_dir = tempdir::TempDir::new("sharded_store_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics.clone(), vec![FieldType::Float64], wal)
+            .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+            .build_sharded(2)
+            .await
+            .unwrap();
+        store
+            .push_multi(vec![datapoint(1), datapoint(2), datapoint(3)])
+            .await
+            .unwrap();
+        store
+            .persist(PersistCondition::new(
+                DatapointsRange::until(TimestampNano::new(3_000_000_000)),
+                true,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(*store.snapshot(), vec![datapoint(3)]);
+        drop(store);
+
+        // only the datapoint not persisted is replayed
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics, vec![FieldType::Float64], wal)
+            .build_sharded(2)
+            .await
+            .unwrap();
+        assert_eq!(*store.datapoints().await.unwrap(), vec![datapoint(3)]);
+    }
+
+    #[tokio::test]
+    async fn test_error_duplicate_policy() {
+        let store = WritableStore::builder(
+            Metrics::new("test").unwrap(),
+            vec![FieldType::Float64],
+            EmptyWal,
+        )
+        .duplicate_policy(DuplicatePolicy::Error)
+        .build_sharded(2)
+        .await
+        .unwrap();
+
+        store
+            .push_multi(vec![datapoint(1), datapoint(2)])
+            .await
+            .unwrap();
+        // in the shards
+        assert!(store.push_multi(vec![datapoint(2)]).await.is_err());
+        // in the snapshot
+        store.refresh().await.unwrap();
+        assert!(store.push_multi(vec![datapoint(1)]).await.is_err());
+        // in the datapoints pushed
+        assert!(store
+            .push_multi(vec![datapoint(3), datapoint(3)])
+            .await
+            .is_err());
+        store.push_multi(vec![datapoint(3)]).await.unwrap();
+        assert_eq!(
+            *store.datapoints().await.unwrap(),
+            vec![datapoint(1), datapoint(2), datapoint(3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_rewrites_wal() {
+        let temp_data_dir = tempdir::TempDir::new("sharded_store_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics.clone(), vec![FieldType::Float64], wal)
+            .build_sharded(2)
+            .await
+            .unwrap();
+        store
+            .push_multi(vec![datapoint(1), datapoint(2), datapoint(3)])
+            .await
+            .unwrap();
+        store
+            .purge(DatapointsRange::new(
+                Some(TimestampNano::new(2_000_000_000)),
+                Some(TimestampNano::new(3_000_000_000)),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(store.unpersisted_status().datapoint_num, 2);
+        drop(store);
+
+        // the purged datapoint is not replayed
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics, vec![FieldType::Float64], wal)
+            .build_sharded(2)
+            .await
+            .unwrap();
+        assert_eq!(
+            *store.datapoints().await.unwrap(),
+            vec![datapoint(1), datapoint(3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sharded_high_water_mark() {
+        let temp_data_dir = tempdir::TempDir::new("sharded_store_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+
+        let store = WritableStore::builder(metrics.clone(), vec![FieldType::Float64], EmptyWal)
+            .high_water_mark(HighWaterMark::new(HighWaterMarkAction::Reject).with_max_datapoints(2))
+            .build_sharded(2)
+            .await
+            .unwrap();
+        store
+            .push_multi(vec![datapoint(1), datapoint(2)])
+            .await
+            .unwrap();
+        assert!(store.push_multi(vec![datapoint(3)]).await.is_err());
+
+        // the early persistence makes room
+        let store = WritableStore::builder(metrics, vec![FieldType::Float64], EmptyWal)
+            .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+            .high_water_mark(HighWaterMark::new(HighWaterMarkAction::Reject).with_max_datapoints(2))
+            .build_sharded(2)
+            .await
+            .unwrap();
+        store
+            .push_multi(vec![datapoint(1), datapoint(2)])
+            .await
+            .unwrap();
+        store.push_multi(vec![datapoint(3)]).await.unwrap();
+        assert_eq!(*store.datapoints().await.unwrap(), vec![datapoint(3)]);
+        assert_eq!(store.unpersisted_status().datapoint_num, 1);
+    }
+}
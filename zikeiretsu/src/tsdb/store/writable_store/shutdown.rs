@@ -0,0 +1,244 @@
+use super::{storage_api, DatapointSorter, PersistCondition, Persistence, WritableStore};
+use crate::tsdb::datapoint::DatapointsRange;
+use crate::tsdb::metrics::Metrics;
+use crate::tsdb::storage::api::{PendingUpload, UploadRetryBackoff};
+use crate::tsdb::storage::block_list::BlockTimestamp;
+use crate::tsdb::storage::wal::WalWriter;
+use crate::tsdb::CloudStorage;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// the store that the datapoints are left in after the shutdown. they are still in the wal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnflushedStore {
+    pub metrics: Metrics,
+    pub datapoint_num: usize,
+    pub reason: String,
+}
+
+/// what was done and what could not be done on the graceful shutdown.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShutdownReport {
+    pub flushed: Vec<Metrics>,
+    pub unflushed: Vec<UnflushedStore>,
+    pub uploaded: Vec<(Metrics, BlockTimestamp)>,
+    /// the uploads that failed until the deadline. they are left in the error files.
+    pub pending_uploads: Vec<PendingUpload>,
+    pub lock_release_failures: Vec<(Metrics, String)>,
+}
+
+impl ShutdownReport {
+    /// whether all the datapoints are persisted and uploaded, and all the lock files are released.
+    pub fn is_clean(&self) -> bool {
+        self.unflushed.is_empty()
+            && self.pending_uploads.is_empty()
+            && self.lock_release_failures.is_empty()
+    }
+
+    /// retries the uploads recorded in the error files with backoff until all of them succeed or the deadline.
+    /// only the uploads of the metrics are retried if it's given.
+    pub(crate) async fn retry_uploads(
+        &mut self,
+        db_dir: &Path,
+        writer_id: &Uuid,
+        metrics: Option<&Metrics>,
+        cloud_storage: Option<&CloudStorage>,
+        deadline: Duration,
+    ) {
+        let list_pending_uploads = || match metrics {
+            Some(metrics) => storage_api::list_pending_uploads_of_metrics(db_dir, metrics),
+            None => storage_api::list_pending_uploads(db_dir),
+        };
+        let cloud_storage = match cloud_storage {
+            Some(cloud_storage) => cloud_storage,
+            None => {
+                match list_pending_uploads() {
+                    Ok(mut pending_uploads) => self.pending_uploads.append(&mut pending_uploads),
+                    Err(e) => log::error!("failed to list the pending uploads: {e}"),
+                }
+                return;
+            }
+        };
+
+        let started_at = Instant::now();
+        let backoff = UploadRetryBackoff::default();
+        let mut failures = 0;
+        loop {
+            let retried = match metrics {
+                Some(metrics) => {
+                    storage_api::retry_failed_uploads_of_metrics(
+                        db_dir,
+                        writer_id,
+                        metrics,
+                        cloud_storage,
+                    )
+                    .await
+                }
+                None => storage_api::retry_failed_uploads(db_dir, writer_id, cloud_storage).await,
+            };
+            let mut pending_uploads = match retried {
+                Ok(mut summary) => {
+                    self.uploaded.append(&mut summary.uploaded);
+                    summary.pending
+                }
+                Err(e) => {
+                    log::error!("failed to retry the uploads: {e}");
+                    list_pending_uploads().unwrap_or_default()
+                }
+            };
+
+            let remaining = deadline.saturating_sub(started_at.elapsed());
+            if pending_uploads.is_empty() || remaining.is_zero() {
+                self.pending_uploads.append(&mut pending_uploads);
+                return;
+            }
+            failures += 1;
+            tokio::time::sleep(backoff.wait(failures).min(remaining)).await;
+        }
+    }
+}
+
+impl<S, Wal> WritableStore<S, Wal>
+where
+    S: DatapointSorter + 'static,
+    Wal: WalWriter + 'static,
+{
+    /// persists all the datapoints, retries the failed uploads until the deadline, then releases the lock files.
+    pub async fn graceful_shutdown(&mut self, upload_deadline: Duration) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        self.flush_on_shutdown(&mut report).await;
+        if let Persistence::Storage(db_dir, cloud_storage_and_setting) = self.persistence.clone() {
+            report
+                .retry_uploads(
+                    &db_dir,
+                    &self.store_id,
+                    Some(&self.metrics),
+                    cloud_storage_and_setting
+                        .as_ref()
+                        .map(|(cloud_storage, _)| cloud_storage),
+                    upload_deadline,
+                )
+                .await;
+        }
+        self.release_locks_on_shutdown(&mut report).await;
+        report
+    }
+
+    /// persists all the datapoints including the ones in the future.
+    /// the wal is synced regardless of the sync policy so that the datapoints left can be replayed.
+    pub(crate) async fn flush_on_shutdown(&mut self, report: &mut ShutdownReport) {
+        let persisted = self
+            .persist(PersistCondition::new(DatapointsRange::all(), true))
+            .await;
+        if let Err(e) = self.wal.sync() {
+            log::error!("failed to sync the wal of {}: {e}", self.metrics);
+        }
+
+        let datapoint_num = self.datapoint_num();
+        match persisted {
+            Ok(_) if datapoint_num == 0 => report.flushed.push(self.metrics.clone()),
+            Ok(_) => report.unflushed.push(UnflushedStore {
+                metrics: self.metrics.clone(),
+                datapoint_num,
+                reason: "datapoints are left after the persistence".to_string(),
+            }),
+            Err(e) => {
+                log::error!(
+                    "failed to persist the store of {} on shutdown: {e}",
+                    self.metrics
+                );
+                report.unflushed.push(UnflushedStore {
+                    metrics: self.metrics.clone(),
+                    datapoint_num,
+                    reason: format!("{e}"),
+                })
+            }
+        }
+    }
+
+    pub(crate) async fn release_locks_on_shutdown(&self, report: &mut ShutdownReport) {
+        if let Err(e) = self.scavange_on_shutdown().await {
+            log::error!("failed to release the lock files of {}: {e}", self.metrics);
+            report
+                .lock_release_failures
+                .push((self.metrics.clone(), format!("{e}")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tsdb::datapoint::DataPoint;
+    use crate::tsdb::field::{FieldType, FieldValue};
+    use crate::tsdb::storage::persisted_error::PersistedErrorType;
+    use crate::tsdb::storage::wal::SegmentedWal;
+    use crate::tsdb::{TimestampNano, TimestampSec};
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let temp_data_dir = tempdir::TempDir::new("graceful_shutdown_test").unwrap();
+        let db_dir = temp_data_dir.path();
+        let metrics = Metrics::new("test").unwrap();
+
+        let wal = SegmentedWal::open_or_create(db_dir, &metrics).unwrap();
+        let store = WritableStore::builder(metrics.clone(), vec![FieldType::Float64], wal)
+            .persistence(Persistence::Storage(db_dir.to_path_buf(), None))
+            .build()
+            .await
+            .unwrap();
+        let mut store = store.lock().await;
+
+        // a datapoint in the future is flushed as well
+        let future = TimestampNano::now().as_inner() + 3_600_000_000_000;
+        store
+            .push_multi(vec![
+                DataPoint::new(
+                    TimestampNano::new(1_000_000_000),
+                    vec![FieldValue::Float64(1.0)],
+                ),
+                DataPoint::new(TimestampNano::new(future), vec![FieldValue::Float64(2.0)]),
+            ])
+            .await
+            .unwrap();
+
+        let report = store.graceful_shutdown(Duration::from_millis(10)).await;
+        assert_eq!(
+            report,
+            ShutdownReport {
+                flushed: vec![metrics.clone()],
+                ..ShutdownReport::default()
+            }
+        );
+        assert!(report.is_clean());
+        assert!(!db_dir.join(format!("{metrics}.lock")).exists());
+
+        // the uploads failed before can't be retried with no cloud storage
+        storage_api::write::write_error_file(
+            db_dir,
+            TimestampNano::new(10),
+            &metrics,
+            PersistedErrorType::FailedToUploadBlockOrBLockList,
+            BlockTimestamp::new(TimestampSec::new(1), TimestampSec::new(2)),
+            None,
+        )
+        .await
+        .unwrap();
+        // the ones of the other metrics are not
+        storage_api::write::write_error_file(
+            db_dir,
+            TimestampNano::new(20),
+            &Metrics::new("other").unwrap(),
+            PersistedErrorType::FailedToUploadBlockOrBLockList,
+            BlockTimestamp::new(TimestampSec::new(1), TimestampSec::new(2)),
+            None,
+        )
+        .await
+        .unwrap();
+        let report = store.graceful_shutdown(Duration::from_millis(10)).await;
+        assert_eq!(report.pending_uploads.len(), 1);
+        assert_eq!(report.pending_uploads[0].metrics, Some(metrics));
+        assert!(!report.is_clean());
+    }
+}
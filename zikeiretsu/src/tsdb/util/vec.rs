@@ -109,6 +109,15 @@ pub fn trim_values<V>(
     Ok((removed_prefix, removed_suffix))
 }
 
+/// pick_values([10,11,12,13],[0,2,5]) -> [10,12]
+/// the indices out of range are ignored.
+pub fn pick_values<T: Clone>(values: &[T], indices: &[usize]) -> Vec<T> {
+    indices
+        .iter()
+        .filter_map(|idx| values.get(*idx).cloned())
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
 
@@ -379,4 +388,11 @@ mod test {
             datas
         );
     }
+
+    #[test]
+    fn pick_values_1() {
+        let values = vec![10, 11, 12, 13];
+        assert_eq!(pick_values(&values, &[0, 2, 5]), vec![10, 12]);
+        assert_eq!(pick_values(&values, &[]), Vec::<i32>::new());
+    }
 }